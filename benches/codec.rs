@@ -0,0 +1,149 @@
+//! Codec throughput over an in-memory loopback transport: decoding
+//! telegrams, receiving binary data in chunks and sending files, each with a
+//! few different buffer and chunk sizes.
+//!
+//! Requires the `testing` feature:
+//!
+//! ```text
+//! cargo bench --features testing --bench codec
+//! ```
+
+use std::path::PathBuf;
+
+use bytes::{BufMut, BytesMut};
+
+use criterion::{
+  criterion_group, criterion_main, BenchmarkId, Criterion, Throughput
+};
+
+use tokio::runtime::Runtime;
+
+use tokio_stream::StreamExt;
+
+use tokio_util::codec::Framed;
+
+use tokio_ddmw::clntif::{Codec, Input};
+use tokio_ddmw::msg::{self, Channel, MsgInfo, SendOpts, Transport};
+use tokio_ddmw::testing::Loopback;
+
+
+const TELEGRAMS: usize = 1000;
+const BIN_SIZE: usize = 8 * 1024 * 1024;
+
+
+fn runtime() -> Runtime {
+  tokio::runtime::Builder::new_current_thread()
+    .build()
+    .unwrap()
+}
+
+
+fn decode(c: &mut Criterion) {
+  let mut input = BytesMut::new();
+  for i in 0..TELEGRAMS {
+    input.put_slice(b"Msg\n_Ch 2\nLen 1048576\nMetaLen 128\n");
+    input.put_slice(format!("XferId {:016x}\n\n", i).as_bytes());
+  }
+  let input = input.freeze();
+  let rt = runtime();
+
+  let mut group = c.benchmark_group("decode_telegrams");
+  group.throughput(Throughput::Bytes(input.len() as u64));
+  for read_size in [4096, 65536] {
+    group.bench_with_input(
+      BenchmarkId::from_parameter(read_size),
+      &read_size,
+      |b, &read_size| {
+        b.iter(|| {
+          let io = Loopback::new(input.clone()).read_size(read_size);
+          let mut conn = Framed::new(io, Codec::new());
+          rt.block_on(async {
+            let mut n = 0;
+            while let Some(input) = conn.next().await {
+              if let Input::Telegram(_) = input.unwrap() {
+                n += 1;
+              }
+            }
+            assert_eq!(n, TELEGRAMS);
+          })
+        })
+      }
+    );
+  }
+  group.finish();
+}
+
+
+fn chunked_receive(c: &mut Criterion) {
+  let input = bytes::Bytes::from(vec![0x5a; BIN_SIZE]);
+  let rt = runtime();
+
+  let mut group = c.benchmark_group("chunked_receive");
+  group.throughput(Throughput::Bytes(BIN_SIZE as u64));
+  for chunk_size in [8192, 65536, 1024 * 1024] {
+    group.bench_with_input(
+      BenchmarkId::from_parameter(chunk_size),
+      &chunk_size,
+      |b, &chunk_size| {
+        b.iter(|| {
+          let io = Loopback::new(input.clone()).read_size(65536);
+          let mut codec = Codec::new();
+          codec.set_max_chunk_size(chunk_size);
+          codec.expect_chunks(BIN_SIZE).unwrap();
+          let mut conn = Framed::new(io, codec);
+          rt.block_on(async {
+            while let Some(input) = conn.next().await {
+              if let Input::Chunk(_, 0) = input.unwrap() {
+                break;
+              }
+            }
+          })
+        })
+      }
+    );
+  }
+  group.finish();
+}
+
+
+fn file_send(c: &mut Criterion) {
+  let fname: PathBuf =
+    std::env::temp_dir().join(format!("ddmw-bench-{}", std::process::id()));
+  std::fs::write(&fname, vec![0x5a; BIN_SIZE]).unwrap();
+  let replies: &[u8] = b"Ok\nXferId 1\n\nOk\n\n";
+  let xfer = Transport { ch: Channel::Id(1) };
+  let rt = runtime();
+
+  let mut group = c.benchmark_group("file_send");
+  group.throughput(Throughput::Bytes(BIN_SIZE as u64));
+  for buf_size in [8192, 65536, 1024 * 1024] {
+    let opts = SendOpts {
+      buf_size,
+      ..SendOpts::default()
+    };
+    group.bench_with_input(
+      BenchmarkId::from_parameter(buf_size),
+      &opts,
+      |b, opts| {
+        b.iter(|| {
+          let mi = MsgInfo::builder().payload_file(&fname).build().unwrap();
+          let mut conn =
+            Framed::new(Loopback::new(replies), blather::Codec::new());
+          rt.block_on(async {
+            msg::send_with(&mut conn, &xfer, &mi, opts).await.unwrap();
+          });
+          assert!(conn.get_ref().written() >= BIN_SIZE as u64);
+        })
+      }
+    );
+  }
+  group.finish();
+
+  let _ = std::fs::remove_file(&fname);
+}
+
+
+criterion_group!(benches, decode, chunked_receive, file_send);
+criterion_main!(benches);
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,45 @@
+//! Compare building and encoding per-chunk progress telegrams using
+//! `blather::Telegram` and the low-allocation `SmallTelegram`.
+
+use bytes::BytesMut;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use tokio_util::codec::Encoder;
+
+use tokio_ddmw::blather::Telegram;
+use tokio_ddmw::clntif::{Codec, SmallTelegram};
+
+
+fn progress(c: &mut Criterion) {
+  let mut codec = Codec::new();
+  let mut buf = BytesMut::with_capacity(4096);
+
+  c.bench_function("progress_telegram", |b| {
+    b.iter(|| {
+      let mut tg = Telegram::new_topic("Progress").unwrap();
+      tg.add_str("XferId", "0123456789abcdef").unwrap();
+      tg.add_param("Received", black_box(123_456_789u64)).unwrap();
+      tg.add_param("Total", black_box(987_654_321u64)).unwrap();
+      codec.encode(&tg, &mut buf).unwrap();
+      buf.clear();
+    })
+  });
+
+  let mut tg = SmallTelegram::new("Progress");
+  c.bench_function("progress_small_telegram", |b| {
+    b.iter(|| {
+      tg.params.clear();
+      tg.add("XferId", "0123456789abcdef").unwrap();
+      tg.add("Received", black_box(123_456_789u64)).unwrap();
+      tg.add("Total", black_box(987_654_321u64)).unwrap();
+      codec.encode(&tg, &mut buf).unwrap();
+      buf.clear();
+    })
+  });
+}
+
+criterion_group!(benches, progress);
+criterion_main!(benches);
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
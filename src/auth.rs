@@ -1,29 +1,56 @@
-use std::fs;
+use std::fmt;
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use tokio::io::{AsyncRead, AsyncWrite};
 
 use tokio_util::codec::Framed;
 
-use blather::Telegram;
+use blather::{Params, Telegram};
 
+use crate::clntif::ClntIfFramed;
+use crate::cmd::Command;
 use crate::utils;
 use crate::Error;
 
 /// Used to choose where an authentication token is fetched from.
-#[derive(Clone)]
+///
+/// With the `serde` feature enabled tokens stored in strings are redacted
+/// when serialized.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Token {
   /// Token is stored in a string.
-  Buf(String),
+  Buf(
+    #[cfg_attr(
+      feature = "serde",
+      serde(serialize_with = "ser_redacted", deserialize_with = "de_secret")
+    )]
+    String
+  ),
 
   /// Token is stored in a file.
   File(PathBuf)
 }
 
-#[derive(Clone)]
+/// Authentication settings.
+///
+/// With the `serde` feature enabled the password, and any token stored in a
+/// string, are redacted when serialized.  Serialized settings can therefore
+/// be logged or shown safely, but can't be used to authenticate unless the
+/// secrets are filled back in; deserializing a redacted secret fails.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AuthInfo {
+  #[cfg_attr(
+    feature = "serde",
+    serde(
+      default,
+      serialize_with = "ser_accpass",
+      deserialize_with = "de_accpass"
+    )
+  )]
   pub accpass: Option<(String, String)>,
   pub itkn: Option<Token>,
   pub otkn: Option<PathBuf>
@@ -37,6 +64,222 @@ impl AuthInfo {
       otkn: None
     }
   }
+
+  /// Construct authentication information from application settings,
+  /// loading the passphrase from `pass_file` if no passphrase is set
+  /// explicitly.
+  ///
+  /// Unlike the `From` conversion, which leaves out a passphrase it can't
+  /// load, this fails with `Error::Secret` if the passphrase file is
+  /// missing, unreadable or empty.
+  pub fn try_from_app(auth: &ddmw_util::app::Auth) -> Result<Self, Error> {
+    let accpass = match (&auth.name, &auth.pass, &auth.pass_file) {
+      (Some(name), Some(pass), _) => Some((name.clone(), pass.clone())),
+      (Some(name), None, Some(fname)) => Some((
+        name.clone(),
+        utils::read_secret_line(fname, Trim::default())?
+      )),
+      _ => None
+    };
+    Ok(AuthInfo {
+      accpass,
+      ..AuthInfo::tokens_from_app(auth)
+    })
+  }
+
+  /// Authentication information holding only the token settings of `auth`.
+  fn tokens_from_app(auth: &ddmw_util::app::Auth) -> Self {
+    // Attempt to get token (if not raw, then a filename to one)
+    let itkn = if let Some(ref tkn) = auth.token {
+      Some(Token::Buf(tkn.to_string()))
+    } else {
+      auth
+        .token_file
+        .as_ref()
+        .map(|tknfile| Token::File(PathBuf::from(tknfile)))
+    };
+
+    // If a token filename was specified, then use it as the output token
+    // filename as well.  This will cause the authenticate() to, if
+    // authenticating using username and passphrase, to request an authtoken
+    // and save it to this file.
+    let otkn = if let Some(Token::File(ref fname)) = itkn {
+      Some(fname.to_path_buf())
+    } else {
+      None
+    };
+
+    AuthInfo {
+      accpass: None,
+      itkn,
+      otkn
+    }
+  }
+}
+
+
+/// Whitespace to strip from a secret read by [`read_secret_line_with()`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Trim {
+  /// Keep the line as it is, except for its line terminator.
+  None,
+
+  /// Strip trailing whitespace.
+  #[default]
+  End,
+
+  /// Strip leading and trailing whitespace.
+  Both
+}
+
+impl Trim {
+  pub(crate) fn apply(self, line: &str) -> &str {
+    match self {
+      Trim::None => line
+        .strip_suffix('\n')
+        .map_or(line, |l| l.strip_suffix('\r').unwrap_or(l)),
+      Trim::End => line.trim_end(),
+      Trim::Both => line.trim()
+    }
+  }
+}
+
+
+/// The reason a secret couldn't be read from a file.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SecretError {
+  /// The file doesn't exist.
+  NotFound,
+
+  /// The file can't be opened because of its permissions.
+  PermissionDenied,
+
+  /// The file's first line is empty.
+  Empty,
+
+  /// Reading the file failed for another reason.
+  IO(std::io::Error)
+}
+
+impl fmt::Display for SecretError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      SecretError::NotFound => write!(f, "file not found"),
+      SecretError::PermissionDenied => write!(f, "permission denied"),
+      SecretError::Empty => write!(f, "file is empty"),
+      SecretError::IO(e) => write!(f, "{}", e)
+    }
+  }
+}
+
+
+/// Read a passphrase or token from the first line of the file `path`,
+/// stripping trailing whitespace.
+///
+/// Fails with `Error::Secret`, telling a missing file apart from one which
+/// can't be read or is empty, rather than treating any problem as there
+/// being no secret.
+pub async fn read_secret_line(
+  path: impl AsRef<Path>
+) -> Result<String, Error> {
+  read_secret_line_with(path, Trim::default()).await
+}
+
+/// Like [`read_secret_line()`], but strip the whitespace selected by `trim`.
+pub async fn read_secret_line_with(
+  path: impl AsRef<Path>,
+  trim: Trim
+) -> Result<String, Error> {
+  utils::read_secret_line_async(path, trim).await
+}
+
+
+/// Tokens stored in strings are redacted.
+impl fmt::Debug for Token {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Token::Buf(_) => f.debug_tuple("Buf").field(&REDACTED).finish(),
+      Token::File(fname) => f.debug_tuple("File").field(fname).finish()
+    }
+  }
+}
+
+/// The password is redacted.
+impl fmt::Debug for AuthInfo {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("AuthInfo")
+      .field(
+        "accpass",
+        &self
+          .accpass
+          .as_ref()
+          .map(|(accname, _)| (accname, REDACTED))
+      )
+      .field("itkn", &self.itkn)
+      .field("otkn", &self.otkn)
+      .finish()
+  }
+}
+
+
+const REDACTED: &str = "<redacted>";
+
+#[cfg(feature = "serde")]
+fn ser_redacted<S>(_secret: &str, ser: S) -> Result<S::Ok, S::Error>
+where
+  S: serde::Serializer
+{
+  ser.serialize_str(REDACTED)
+}
+
+#[cfg(feature = "serde")]
+fn ser_accpass<S>(
+  accpass: &Option<(String, String)>,
+  ser: S
+) -> Result<S::Ok, S::Error>
+where
+  S: serde::Serializer
+{
+  use serde::Serialize;
+  accpass
+    .as_ref()
+    .map(|(accname, _)| (accname, REDACTED))
+    .serialize(ser)
+}
+
+/// Reject secrets which were redacted when serialized, rather than
+/// authenticating with the placeholder.
+#[cfg(feature = "serde")]
+fn de_secret<'de, D>(de: D) -> Result<String, D::Error>
+where
+  D: serde::Deserializer<'de>
+{
+  use serde::Deserialize;
+  let secret = String::deserialize(de)?;
+  if secret == REDACTED {
+    return Err(serde::de::Error::custom(
+      "secret is redacted; the real value must be filled back in"
+    ));
+  }
+  Ok(secret)
+}
+
+#[cfg(feature = "serde")]
+fn de_accpass<'de, D>(de: D) -> Result<Option<(String, String)>, D::Error>
+where
+  D: serde::Deserializer<'de>
+{
+  use serde::Deserialize;
+  let accpass = Option::<(String, String)>::deserialize(de)?;
+  if let Some((_, ref pass)) = accpass {
+    if pass == REDACTED {
+      return Err(serde::de::Error::custom(
+        "passphrase is redacted; the real value must be filled back in"
+      ));
+    }
+  }
+  Ok(accpass)
 }
 
 
@@ -69,54 +312,12 @@ impl From<&ddmw_util::app::Config> for AuthInfo {
 }
 
 
+/// A passphrase which can't be loaded from `pass_file` is left out.  Use
+/// [`AuthInfo::try_from_app()`] to have problems reported instead.
 impl From<&ddmw_util::app::Auth> for AuthInfo {
   fn from(auth: &ddmw_util::app::Auth) -> AuthInfo {
-    // Attempt to get account name and passphrase (from file, if not set
-    // explictly).
-    let accpass = match &auth.name {
-      Some(name) => {
-        // Got an account name, see if there's a passphrase.
-        if let Some(ref pass) = auth.pass {
-          // Got a raw passphrase -- use it
-          Some((name.clone(), pass.clone()))
-        } else if let Some(ref fname) = auth.pass_file {
-          // No raw passphrase, attempt to load from file
-          if let Some(pass) = utils::read_single_line(fname) {
-            Some((name.to_string(), pass.to_string()))
-          } else {
-            None
-          }
-        } else {
-          None
-        }
-      }
-      None => None
-    };
-
-    // Attempt to get token (if not raw, then a filename to one)
-    let itkn = if let Some(ref tkn) = auth.token {
-      Some(Token::Buf(tkn.to_string()))
-    } else if let Some(ref tknfile) = auth.token_file {
-      Some(Token::File(PathBuf::from(tknfile)))
-    } else {
-      None
-    };
-
-    // If a token filename was specified, then use it as the output token
-    // filename as well.  This will cause the authenticate() to, if
-    // authenticating using username and passphrase, to request an authtoken
-    // and save it to this file.
-    let otkn = if let Some(Token::File(ref fname)) = itkn {
-      Some(fname.to_path_buf())
-    } else {
-      None
-    };
-
-    AuthInfo {
-      accpass,
-      itkn,
-      otkn
-    }
+    AuthInfo::try_from_app(auth)
+      .unwrap_or_else(|_| AuthInfo::tokens_from_app(auth))
   }
 }
 
@@ -128,48 +329,117 @@ impl From<&ddmw_util::app::Auth> for AuthInfo {
 pub async fn token<T: AsyncRead + AsyncWrite + Unpin>(
   conn: &mut Framed<T, blather::Codec>,
   tkn: &Token
+) -> Result<(), Error> {
+  token_via(conn, tkn).await
+}
+
+async fn token_via<C: Requester>(
+  conn: &mut C,
+  tkn: &Token
 ) -> Result<(), Error> {
   let buf = match tkn {
     Token::Buf(s) => s.clone(),
     Token::File(fname) => {
-      let mut buf = fs::read_to_string(&fname)?;
-      buf.truncate(32);
+      let mut buf = read_secret_line(fname).await?;
+      // Tokens are at most 32 bytes; cut any excess at a character boundary.
+      let mut end = buf.len().min(32);
+      while !buf.is_char_boundary(end) {
+        end -= 1;
+      }
+      buf.truncate(end);
       buf
     }
   };
-  let mut tg = Telegram::new_topic("Auth")?;
-  tg.add_param("Tkn", buf)?;
-  crate::sendrecv(conn, &tg).await?;
-  Ok(())
+  conn.call(&AuthToken { tkn: buf }).await
 }
 
 
 /// Attempt to authenticate using an account name and a passphrase.
 /// Optionally request an authentication token if the authentication was
 /// successful.
+#[allow(clippy::ptr_arg)]
 pub async fn accpass<T: AsyncRead + AsyncWrite + Unpin>(
   conn: &mut Framed<T, blather::Codec>,
   accname: &String,
   pass: &String,
   reqtkn: bool
 ) -> Result<Option<String>, Error> {
-  let mut tg = Telegram::new_topic("Auth")?;
-  tg.add_param("AccName", accname)?;
-  tg.add_param("Pass", pass)?;
-  if reqtkn {
-    tg.add_param("ReqTkn", "True")?;
+  let cmd = AuthPass {
+    accname: accname.clone(),
+    pass: pass.clone(),
+    reqtkn
+  };
+  crate::cmd::call(conn, &cmd).await
+}
+
+
+/// Authenticate using an authentication token, as a [`Command`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct AuthToken {
+  pub tkn: String
+}
+
+impl fmt::Debug for AuthToken {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("AuthToken").field("tkn", &"***").finish()
+  }
+}
+
+impl Command for AuthToken {
+  const TOPIC: &'static str = "Auth";
+  type Reply = ();
+
+  fn to_telegram(&self) -> Result<Telegram, Error> {
+    let mut tg = Telegram::new_topic(Self::TOPIC)?;
+    tg.add_param("Tkn", &self.tkn)?;
+    Ok(tg)
   }
-  let params = crate::sendrecv(conn, &tg).await?;
 
-  if reqtkn {
-    let s = params.get_str("Tkn");
-    if let Some(s) = s {
-      Ok(Some(s.to_string()))
-    } else {
-      Ok(None)
+  fn parse_reply(_params: Params) -> Result<(), Error> {
+    Ok(())
+  }
+}
+
+
+/// Authenticate using an account name and a passphrase, as a [`Command`].
+///
+/// The reply is the authentication token, if `reqtkn` was set and the server
+/// returned one.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AuthPass {
+  pub accname: String,
+  pub pass: String,
+
+  /// Request an authentication token.
+  pub reqtkn: bool
+}
+
+impl fmt::Debug for AuthPass {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("AuthPass")
+      .field("accname", &self.accname)
+      .field("pass", &"***")
+      .field("reqtkn", &self.reqtkn)
+      .finish()
+  }
+}
+
+impl Command for AuthPass {
+  const TOPIC: &'static str = "Auth";
+  type Reply = Option<String>;
+
+  fn to_telegram(&self) -> Result<Telegram, Error> {
+    let mut tg = Telegram::new_topic(Self::TOPIC)?;
+    tg.add_param("AccName", &self.accname)?;
+    tg.add_param("Pass", &self.pass)?;
+    if self.reqtkn {
+      tg.add_param("ReqTkn", "True")?;
     }
-  } else {
-    Ok(None)
+    Ok(tg)
+  }
+
+  fn parse_reply(params: Params) -> Result<Option<String>, Error> {
+    Ok(params.get_str("Tkn").map(|s| s.to_string()))
   }
 }
 
@@ -183,9 +453,35 @@ pub async fn accpass<T: AsyncRead + AsyncWrite + Unpin>(
 ///    passphrase.
 /// 3. If an output token file name was supplied, then save the returned
 ///    authentication to that file.
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(level = "debug", skip_all, err)
+)]
 pub async fn authenticate<T: AsyncRead + AsyncWrite + Unpin>(
   conn: &mut Framed<T, blather::Codec>,
   ai: &AuthInfo
+) -> Result<Option<String>, Error> {
+  authenticate_via(conn, ai).await
+}
+
+
+/// Authenticate a connection framed using the client interface
+/// [`Codec`](crate::clntif::Codec).  See [`authenticate()`].
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(level = "debug", skip_all, err)
+)]
+pub async fn authenticate_clntif<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut ClntIfFramed<T>,
+  ai: &AuthInfo
+) -> Result<Option<String>, Error> {
+  authenticate_via(conn, ai).await
+}
+
+
+async fn authenticate_via<C: Requester>(
+  conn: &mut C,
+  ai: &AuthInfo
 ) -> Result<Option<String>, Error> {
   //
   // If an input token was specified, then try to authenticate with it.
@@ -214,7 +510,7 @@ pub async fn authenticate<T: AsyncRead + AsyncWrite + Unpin>(
     };
 
     if do_tknauth {
-      match token(conn, &tkn).await {
+      match token_via(conn, tkn).await {
         Ok(_) => {
           // Everything went ok, and since it was a token authentication
           // there's no token to return.
@@ -222,11 +518,16 @@ pub async fn authenticate<T: AsyncRead + AsyncWrite + Unpin>(
         }
         Err(e) => {
           match e {
-            Error::ServerError(_) => {
+            ref e if e.is_server_error() => {
               // Ignore server errors, because it may just mean that the token
-              // is outdated.
+              // is outdated (which newer servers report as AuthExpired).
               // Could be more granular about the errors here.
             }
+            Error::Secret { .. } if ai.accpass.is_some() => {
+              // The token file exists but couldn't be used, for instance
+              // because it is empty; a new token can be requested using the
+              // passphrase.
+            }
             _ => {
               // Return any error that isn't a server error.
               return Err(e);
@@ -243,19 +544,18 @@ pub async fn authenticate<T: AsyncRead + AsyncWrite + Unpin>(
   // which suggests that a password authentication is an acceptable fallback.
   //
   if let Some((acc, pass)) = &ai.accpass {
-    let reqtkn = if let Some(_otkn) = &ai.otkn {
-      true
-    } else {
-      false
+    let cmd = AuthPass {
+      accname: acc.clone(),
+      pass: pass.clone(),
+      reqtkn: ai.otkn.is_some()
     };
 
-    let tkn = accpass(conn, &acc, &pass, reqtkn).await;
-    if let Ok(tkn) = &tkn {
-      if let Some(tkn) = tkn {
-        if let Some(fname) = &ai.otkn {
-          let mut f = File::create(fname)?;
-          f.write(tkn.as_bytes())?;
-        }
+    let tkn = conn.call(&cmd).await;
+    if let Ok(Some(tkn)) = &tkn {
+      if let Some(fname) = &ai.otkn {
+        let local = |e| Error::local_io(fname, e);
+        let mut f = File::create(fname).map_err(local)?;
+        f.write_all(tkn.as_bytes()).map_err(local)?;
       }
     }
     return tkn;
@@ -264,20 +564,62 @@ pub async fn authenticate<T: AsyncRead + AsyncWrite + Unpin>(
 
   // Token authetication failed and no account name/password was passed, so
   // error out.
-  return Err(Error::InvalidCredentials);
+  Err(Error::InvalidCredentials)
 }
 
 
 /// Return ownership of a connection to the built-in _unauthenticated_ account.
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(level = "debug", skip_all, err)
+)]
 pub async fn unauthenticate<T: AsyncRead + AsyncWrite + Unpin>(
   conn: &mut Framed<T, blather::Codec>
 ) -> Result<(), Error> {
-  let tg = Telegram::new_topic("Unauth")?;
+  crate::cmd::call(conn, &Unauth).await
+}
+
+
+/// Return ownership of a connection to the built-in _unauthenticated_
+/// account, as a [`Command`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Unauth;
 
-  crate::sendrecv(conn, &tg).await?;
+impl Command for Unauth {
+  const TOPIC: &'static str = "Unauth";
+  type Reply = ();
 
-  Ok(())
+  fn parse_reply(_params: Params) -> Result<(), Error> {
+    Ok(())
+  }
 }
 
 
+/// A connection requests can be sent over, regardless of its framing.
+pub(crate) trait Requester {
+  /// Send a request and wait for an `Ok` reply.
+  async fn sendrecv(&mut self, tg: &Telegram) -> Result<Params, Error>;
+
+  /// Run a command.  See [`cmd::call()`](crate::cmd::call).
+  async fn call<C: Command>(&mut self, cmd: &C) -> Result<C::Reply, Error> {
+    let tg = cmd.to_telegram()?;
+    let params = self.sendrecv(&tg).await?;
+    C::parse_reply(params).map_err(|e| e.with_command(&tg))
+  }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Requester
+  for Framed<T, blather::Codec>
+{
+  async fn sendrecv(&mut self, tg: &Telegram) -> Result<Params, Error> {
+    crate::sendrecv(self, tg).await
+  }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> Requester for ClntIfFramed<T> {
+  async fn sendrecv(&mut self, tg: &Telegram) -> Result<Params, Error> {
+    crate::clntif::util::sendrecv(self, tg).await
+  }
+}
+
 // vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
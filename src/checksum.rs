@@ -0,0 +1,141 @@
+//! Checksums used to verify the integrity of transferred data.
+//!
+//! Checksums are represented in text form as `<algorithm>:<hex digest>`, for
+//! instance `sha256:e3b0c442...`.  A bare hex digest is interpreted as a
+//! SHA-256 digest.
+
+use std::fmt;
+use std::str::FromStr;
+
+use sha2::{Digest, Sha256};
+
+use crate::err::Error;
+
+
+/// A checksum of a block of data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Checksum {
+  Sha256([u8; 32])
+}
+
+impl Checksum {
+  /// Calculate the SHA-256 checksum of a buffer.
+  pub fn sha256(data: &[u8]) -> Self {
+    let mut h = Hasher::new_sha256();
+    h.update(data);
+    h.finish()
+  }
+
+  /// Name of the checksum's algorithm.
+  pub fn algorithm(&self) -> &'static str {
+    match self {
+      Checksum::Sha256(_) => "sha256"
+    }
+  }
+
+  /// Raw digest.
+  pub fn digest(&self) -> &[u8] {
+    match self {
+      Checksum::Sha256(d) => d
+    }
+  }
+
+  /// Create a hasher which calculates a checksum using the same algorithm as
+  /// this one.
+  pub(crate) fn hasher(&self) -> Hasher {
+    match self {
+      Checksum::Sha256(_) => Hasher::new_sha256()
+    }
+  }
+
+  /// Make sure that `actual` matches this (expected) checksum.
+  pub(crate) fn verify(&self, actual: &Checksum) -> Result<(), Error> {
+    if self != actual {
+      return Err(Error::ChecksumMismatch {
+        expected: self.clone(),
+        actual: actual.clone()
+      });
+    }
+    Ok(())
+  }
+}
+
+impl fmt::Display for Checksum {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}:", self.algorithm())?;
+    for b in self.digest() {
+      write!(f, "{:02x}", b)?;
+    }
+    Ok(())
+  }
+}
+
+impl FromStr for Checksum {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (alg, hex) = match s.find(':') {
+      Some(idx) => (&s[..idx], &s[idx + 1..]),
+      None => ("sha256", s)
+    };
+    match alg.to_ascii_lowercase().as_str() {
+      "sha256" => {
+        let mut d = [0u8; 32];
+        parse_hex(hex, &mut d)?;
+        Ok(Checksum::Sha256(d))
+      }
+      _ => Err(Error::UnknownData {
+        what: "checksum algorithm".to_string(),
+        value: alg.to_string()
+      })
+    }
+  }
+}
+
+
+/// Incremental checksum calculation.
+pub(crate) enum Hasher {
+  Sha256(Sha256)
+}
+
+impl Hasher {
+  pub(crate) fn new_sha256() -> Self {
+    Hasher::Sha256(Sha256::new())
+  }
+
+  pub(crate) fn update(&mut self, data: &[u8]) {
+    match self {
+      Hasher::Sha256(h) => h.update(data)
+    }
+  }
+
+  pub(crate) fn finish(self) -> Checksum {
+    match self {
+      Hasher::Sha256(h) => Checksum::Sha256(h.finalize().into())
+    }
+  }
+}
+
+
+fn parse_hex(s: &str, out: &mut [u8]) -> Result<(), Error> {
+  if !s.is_ascii() || s.len() != out.len() * 2 {
+    return Err(Error::bad_format(
+      "checksum",
+      format!("expected {} hex digits, got {}", out.len() * 2, s.len())
+    ));
+  }
+  for (i, b) in out.iter_mut().enumerate() {
+    *b = match u8::from_str_radix(&s[i * 2..i * 2 + 2], 16) {
+      Ok(b) => b,
+      Err(_) => {
+        return Err(Error::bad_format(
+          "checksum",
+          format!("invalid hex digest '{}'", s)
+        ))
+      }
+    };
+  }
+  Ok(())
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,355 @@
+//! High-level client.
+//!
+//! [`Client`] bundles an authenticated connection with what it needs to
+//! reestablish it, and caches the server's [`NodeInfo`] so it only needs to
+//! be queried once per connection.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use blather::{Params, Telegram};
+
+use crate::auth::AuthInfo;
+use crate::cmd::{self, Command};
+use crate::deadline::Deadline;
+use crate::err::Error;
+use crate::mgmt::ch::ChCache;
+use crate::msg::{
+  self, Conn, ConnTransport, Endpoint, MsgInfo, SendReport, Transport
+};
+use crate::retry::{self, Policy};
+use crate::NodeInfo;
+
+
+/// A connection to a server, with cached information about the node.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), tokio_ddmw::Error> {
+/// use tokio_ddmw::client::Client;
+///
+/// let mut client = Client::connect("127.0.0.1:5000".parse()?, None).await?;
+/// println!("Connected to a {} node", client.node().nodetype);
+/// # Ok(())
+/// # }
+/// ```
+pub struct Client {
+  msgif: Endpoint,
+  authinfo: Option<AuthInfo>,
+  connect_timeout: Option<Duration>,
+  request_timeout: Option<Duration>,
+  retry: Policy,
+  chcache: Arc<ChCache>,
+  conn: Conn,
+  node: NodeInfo,
+  /// Set when the connection failed or a request timed out, which may leave
+  /// a late reply in the connection.  The client reconnects before it's
+  /// used again.
+  stale: bool
+}
+
+impl Client {
+  /// Return a builder for connecting to `msgif`.
+  pub fn builder(msgif: Endpoint) -> ClientBuilder {
+    ClientBuilder {
+      msgif,
+      authinfo: None,
+      connect_timeout: None,
+      request_timeout: None,
+      retry: Policy::none()
+    }
+  }
+
+  /// Connect to `msgif`, authenticate using `authinfo` if supplied, and
+  /// fetch the node's information.
+  pub async fn connect(
+    msgif: Endpoint,
+    authinfo: Option<AuthInfo>
+  ) -> Result<Self, Error> {
+    let mut builder = Self::builder(msgif);
+    builder.authinfo = authinfo;
+    builder.connect().await
+  }
+
+  /// Connect to the message interface of `xfer`, authenticating using its
+  /// authentication information if it has any.
+  pub async fn connect_transport(xfer: &ConnTransport) -> Result<Self, Error> {
+    Self::connect(xfer.msgif.clone(), xfer.authinfo.clone()).await
+  }
+
+  /// Information about the node, as fetched when the connection was
+  /// established or last refreshed.
+  pub fn node(&self) -> &NodeInfo {
+    &self.node
+  }
+
+  /// Fetch the node's information again, for instance after it has been
+  /// upgraded.
+  pub async fn refresh_node(&mut self) -> Result<&NodeInfo, Error> {
+    self.node = self.call(&crate::GetNodeInfo).await?;
+    Ok(&self.node)
+  }
+
+  /// Replace the connection with a new one, authenticated the same way, and
+  /// refresh the node's information.
+  ///
+  /// Connecting and authenticating is retried according to the client's
+  /// [retry policy](ClientBuilder::retry).  The reconnect is reported to the
+  /// installed [`Metrics`](crate::metrics::Metrics) implementation.
+  pub async fn reconnect(&mut self) -> Result<(), Error> {
+    let (conn, node) = retry::run(&self.retry, || {
+      establish(&self.msgif, self.authinfo.as_ref(), self.connect_timeout)
+    })
+    .await?;
+    self.replace(conn, node);
+    Ok(())
+  }
+
+  fn replace(&mut self, conn: Conn, node: NodeInfo) {
+    self.conn = conn;
+    self.node = node;
+    self.stale = false;
+    crate::metrics::global().reconnected();
+  }
+
+  /// Reconnect if the connection has been marked as stale.
+  async fn freshen(&mut self) -> Result<(), Error> {
+    if self.stale {
+      self.reconnect().await?;
+    }
+    Ok(())
+  }
+
+  /// Mark the connection as stale if `res` is a connection error, such as a
+  /// request timing out.
+  fn check<T>(&mut self, res: Result<T, Error>) -> Result<T, Error> {
+    if let Err(ref e) = res {
+      self.stale = self.stale || e.is_connection_error();
+    }
+    res
+  }
+
+  /// Fail with `Error::BadState` unless the node is a sender.
+  pub fn require_sender(&self) -> Result<(), Error> {
+    if self.node.is_sender() {
+      Ok(())
+    } else {
+      Err(Error::BadState {
+        expected: "a sender node".to_string(),
+        found: Some(format!("a {} node", self.node.nodetype))
+      })
+    }
+  }
+
+  /// Fail with `Error::BadState` unless the node is a receiver.
+  pub fn require_receiver(&self) -> Result<(), Error> {
+    if self.node.is_receiver() {
+      Ok(())
+    } else {
+      Err(Error::BadState {
+        expected: "a receiver node".to_string(),
+        found: Some(format!("a {} node", self.node.nodetype))
+      })
+    }
+  }
+
+  /// Run a command and return its parsed reply.  See [`cmd::call()`].
+  ///
+  /// If a [request timeout](ClientBuilder::request_timeout) has been set the
+  /// command fails with `Error::DeadlineExceeded` if it doesn't complete in
+  /// time.  Since the reply may still arrive, the client then reconnects
+  /// before it's next used, as it does after connection errors.
+  pub async fn call<C: Command>(
+    &mut self,
+    cmd: &C
+  ) -> Result<C::Reply, Error> {
+    self.freshen().await?;
+    let res = match self.request_timeout {
+      Some(timeout) => cmd::call_timeout(&mut self.conn, cmd, timeout).await,
+      None => cmd::call(&mut self.conn, cmd).await
+    };
+    self.check(res)
+  }
+
+  /// Send a telegram and wait for a reply.  See
+  /// [`sendrecv()`](crate::sendrecv).
+  ///
+  /// The [request timeout](ClientBuilder::request_timeout) applies as it does
+  /// to [`call()`](Self::call).
+  pub async fn sendrecv(&mut self, tg: &Telegram) -> Result<Params, Error> {
+    self.freshen().await?;
+    let res = match self.request_timeout {
+      Some(timeout) => {
+        let step = tg.get_topic().unwrap_or("request");
+        Deadline::after(timeout)
+          .run(step, crate::sendrecv(&mut self.conn, tg))
+          .await
+      }
+      None => crate::sendrecv(&mut self.conn, tg).await
+    };
+    self.check(res)
+  }
+
+  /// Send a message, failing without sending anything unless the node is a
+  /// sender.  See [`msg::send()`].
+  ///
+  /// Channel names are resolved through the [`ChCache`] of the client's
+  /// endpoint.
+  pub async fn send(
+    &mut self,
+    xfer: &Transport,
+    mi: &MsgInfo
+  ) -> Result<SendReport, Error> {
+    self.require_sender()?;
+    self.freshen().await?;
+    let res =
+      msg::send_cached(&mut self.conn, xfer, mi, self.chcache.clone()).await;
+    self.check(res)
+  }
+
+  /// Send a message, retrying according to the client's
+  /// [retry policy](ClientBuilder::retry).  The client reconnects before
+  /// retrying if the connection failed.
+  ///
+  /// If the connection fails after the server has accepted the message, but
+  /// before the client learns of it, retrying sends the message again.  Set a
+  /// [deduplication key](crate::msg::MsgInfoBuilder::dedup_key) to allow the
+  /// server to discard such duplicates.
+  pub async fn send_retry(
+    &mut self,
+    xfer: &Transport,
+    mi: &MsgInfo
+  ) -> Result<SendReport, Error> {
+    self.require_sender()?;
+    let policy = self.retry.clone();
+    let mut attempts = policy.start();
+    loop {
+      let res = if self.stale {
+        match establish(
+          &self.msgif,
+          self.authinfo.as_ref(),
+          self.connect_timeout
+        )
+        .await
+        {
+          Ok((conn, node)) => {
+            self.replace(conn, node);
+            msg::send_cached(&mut self.conn, xfer, mi, self.chcache.clone())
+              .await
+          }
+          Err(e) => Err(e)
+        }
+      } else {
+        msg::send_cached(&mut self.conn, xfer, mi, self.chcache.clone()).await
+      };
+      let err = match self.check(res) {
+        Ok(report) => return Ok(report),
+        Err(e) => e
+      };
+      match attempts.next_delay(&err) {
+        Some(delay) => tokio::time::sleep(delay).await,
+        None => return Err(err)
+      }
+    }
+  }
+
+  /// Get the connection, for use with the crate's lower level helpers.
+  ///
+  /// The connection isn't reestablished here if it's stale, as it is by the
+  /// client's own methods; use [`reconnect()`](Self::reconnect) first if a
+  /// request has failed.
+  pub fn conn(&mut self) -> &mut Conn {
+    &mut self.conn
+  }
+
+  /// Get the connection, discarding the client.
+  pub fn into_conn(self) -> Conn {
+    self.conn
+  }
+}
+
+
+/// Builder for a [`Client`].
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+  msgif: Endpoint,
+  authinfo: Option<AuthInfo>,
+  connect_timeout: Option<Duration>,
+  request_timeout: Option<Duration>,
+  retry: Policy
+}
+
+impl ClientBuilder {
+  /// Authenticate using `authinfo` once connected.
+  pub fn authinfo(mut self, authinfo: AuthInfo) -> Self {
+    self.authinfo = Some(authinfo);
+    self
+  }
+
+  /// Fail with an `Error::IO` of kind `TimedOut` if a connection can't be
+  /// established within `timeout`.  Also applies when reconnecting.
+  pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+    self.connect_timeout = Some(timeout);
+    self
+  }
+
+  /// Fail commands run using [`Client::call()`] and requests made using
+  /// [`Client::sendrecv()`] which don't complete within `timeout`.
+  pub fn request_timeout(mut self, timeout: Duration) -> Self {
+    self.request_timeout = Some(timeout);
+    self
+  }
+
+  /// Retry connecting and authenticating, and sends made using
+  /// [`Client::send_retry()`], according to `policy`.  By default no
+  /// attempts are retried.
+  pub fn retry(mut self, policy: Policy) -> Self {
+    self.retry = policy;
+    self
+  }
+
+  /// Connect, authenticate if authentication information was supplied, and
+  /// fetch the node's information.
+  pub async fn connect(self) -> Result<Client, Error> {
+    let (conn, node) = retry::run(&self.retry, || {
+      establish(&self.msgif, self.authinfo.as_ref(), self.connect_timeout)
+    })
+    .await?;
+    Ok(Client {
+      chcache: ChCache::for_endpoint(&self.msgif),
+      msgif: self.msgif,
+      authinfo: self.authinfo,
+      connect_timeout: self.connect_timeout,
+      request_timeout: self.request_timeout,
+      retry: self.retry,
+      conn,
+      node,
+      stale: false
+    })
+  }
+}
+
+
+async fn establish(
+  msgif: &Endpoint,
+  authinfo: Option<&AuthInfo>,
+  connect_timeout: Option<Duration>
+) -> Result<(Conn, NodeInfo), Error> {
+  let mut conn = match connect_timeout {
+    Some(timeout) => tokio::time::timeout(timeout, msgif.connect())
+      .await
+      .map_err(|_| {
+      Error::IO(std::io::Error::new(
+        std::io::ErrorKind::TimedOut,
+        "Timed out connecting to the message interface"
+      ))
+    })??,
+    None => msgif.connect().await?
+  };
+  if let Some(ai) = authinfo {
+    crate::auth::authenticate(&mut conn, ai).await?;
+  }
+  let node = crate::get_nodeinfo(&mut conn).await?;
+  Ok((conn, node))
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,51 @@
+//! Client interface protocol support.
+//!
+//! The helpers in the rest of this crate operate on connections framed using
+//! [`blather::Codec`], which is re-exported as [`crate::blather`].  That
+//! codec is sufficient for the plain request/reply exchanges most
+//! integrations perform.
+//!
+//! This module provides a client interface specific [`Codec`] which speaks
+//! the same wire protocol, but reports errors using this crate's
+//! [`Error`](crate::Error) type and is where client interface specific
+//! decoder features are implemented.  Use it, through [`ClntIfFramed`], when
+//! those features are needed or when a single error type is preferred over
+//! converting from `blather::Error`.
+//!
+//! The message, receiver and management helpers, in [`msg`](crate::msg),
+//! [`recv`](crate::recv) and [`mgmt`](crate::mgmt), only work on
+//! `blather::Codec` connections, so the decoder features of [`Codec`], such
+//! as line limits, compression and asynchronous file writing, don't apply
+//! to them.  Authentication and requests are available for `ClntIfFramed`
+//! connections through
+//! [`auth::authenticate_clntif()`](crate::auth::authenticate_clntif) and
+//! [`DdmwFramedExt`](crate::framed::DdmwFramedExt).
+//!
+//! Servers which support it can be switched over to the length-prefixed
+//! framing implemented by [`BinCodec`], which avoids scanning for line
+//! terminators.
+
+pub mod bincodec;
+pub mod codec;
+pub mod smallparams;
+pub mod util;
+
+use tokio_util::codec::Framed;
+
+pub use bincodec::{BinCodec, Frame};
+pub use codec::capture::Capture;
+pub use codec::tap::{Direction, TapData};
+pub use codec::{
+  Codec, CodecBuilder, CodecMetrics, Compression, DuplicateParams, Expect,
+  FileOpts, Input, InvalidUtf8, LineLimits, LineMode, Resumed, State
+};
+pub use smallparams::{SmallParams, SmallTelegram};
+
+
+/// A connection framed using the client interface [`Codec`].
+pub type ClntIfFramed<T> = Framed<T, Codec>;
+
+/// A connection framed using the protocol v2 [`BinCodec`].
+pub type BinFramed<T> = Framed<T, BinCodec>;
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,244 @@
+//! Length-prefixed framing of the client interface protocol (protocol v2).
+//!
+//! The line based [`Codec`](super::Codec) has to scan all received data for
+//! line terminators, which dominates the decoding cost of telegram heavy
+//! workloads.  [`BinCodec`] instead prefixes each frame with its type and
+//! length, so a decoder only needs to wait for the announced number of bytes.
+//!
+//! Each frame consists of a five byte header followed by the payload:
+//!
+//! | Offset | Size | Description                           |
+//! |--------|------|---------------------------------------|
+//! | 0      | 1    | Frame type (`1` telegram, `2` data)   |
+//! | 1      | 4    | Payload length, big endian            |
+//! | 5      | n    | Payload                               |
+//!
+//! A telegram payload consists of the topic, followed by each parameter's
+//! key and value.  Topics and keys are prefixed by a big endian 16-bit
+//! length, values by a big endian 32-bit length.  A data payload is the raw
+//! data.
+//!
+//! Servers which support protocol v2 are switched over to it using
+//! [`negotiate_v2()`](super::util::negotiate_v2).
+
+use std::fmt;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use tokio_util::codec::{Decoder, Encoder};
+
+use blather::Telegram;
+
+use crate::err::Error;
+
+/// Size of a frame header.
+const HDR_LEN: usize = 5;
+
+const FRAME_TELEGRAM: u8 = 1;
+const FRAME_DATA: u8 = 2;
+
+
+/// A frame received over a protocol v2 connection.
+pub enum Frame {
+  Telegram(Telegram),
+  Data(BytesMut)
+}
+
+
+/// A Codec which encodes and decodes length-prefixed client interface
+/// frames.
+pub struct BinCodec {
+  max_frame_size: usize
+}
+
+impl fmt::Debug for BinCodec {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("BinCodec")
+      .field("max_frame_size", &self.max_frame_size)
+      .finish()
+  }
+}
+
+impl Default for BinCodec {
+  fn default() -> Self {
+    BinCodec::new()
+  }
+}
+
+impl BinCodec {
+  pub fn new() -> Self {
+    BinCodec {
+      max_frame_size: u32::MAX as usize
+    }
+  }
+
+  /// Set the largest payload size the decoder will accept.  Frames which
+  /// announce a larger payload cause the decoder to fail with
+  /// `Error::TooLarge` before any of the payload has been buffered.
+  pub fn set_max_frame_size(&mut self, size: usize) {
+    self.max_frame_size = size;
+  }
+
+  /// Get the largest payload size the decoder will accept.
+  pub fn max_frame_size(&self) -> usize {
+    self.max_frame_size
+  }
+}
+
+
+/// Read a `len` byte UTF-8 string off the front of `buf`.
+fn take_str(buf: &mut BytesMut, len: usize) -> Result<String, Error> {
+  if buf.len() < len {
+    return Err(Error::bad_format("telegram frame", "truncated"));
+  }
+  let s = buf.split_to(len);
+  match std::str::from_utf8(&s) {
+    Ok(s) => Ok(s.to_string()),
+    Err(_) => Err(Error::bad_format(
+      "telegram frame",
+      "field is not valid UTF-8"
+    ))
+  }
+}
+
+fn take_len(buf: &mut BytesMut, size: usize) -> Result<usize, Error> {
+  if buf.len() < size {
+    return Err(Error::bad_format("telegram frame", "truncated"));
+  }
+  Ok(match size {
+    2 => buf.get_u16() as usize,
+    _ => buf.get_u32() as usize
+  })
+}
+
+fn parse_telegram(mut payload: BytesMut) -> Result<Telegram, Error> {
+  let len = take_len(&mut payload, 2)?;
+  let topic = take_str(&mut payload, len)?;
+  let mut tg = Telegram::new_topic(&topic)?;
+  while !payload.is_empty() {
+    let len = take_len(&mut payload, 2)?;
+    let key = take_str(&mut payload, len)?;
+    let len = take_len(&mut payload, 4)?;
+    let value = take_str(&mut payload, len)?;
+    tg.add_param(key, value)?;
+  }
+  Ok(tg)
+}
+
+
+impl Decoder for BinCodec {
+  type Item = Frame;
+  type Error = Error;
+
+  fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Frame>, Error> {
+    if buf.len() < HDR_LEN {
+      // Need more data
+      return Ok(None);
+    }
+
+    let len = u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]) as usize;
+    if len > self.max_frame_size {
+      return Err(Error::TooLarge {
+        what: "Frame payload".to_string(),
+        size: Some(len as u64),
+        limit: self.max_frame_size as u64
+      });
+    }
+    if buf.len() < HDR_LEN + len {
+      // Make room for the rest of the frame up front.
+      buf.reserve(HDR_LEN + len - buf.len());
+      return Ok(None);
+    }
+
+    let ftype = buf[0];
+    buf.advance(HDR_LEN);
+    let payload = buf.split_to(len);
+    match ftype {
+      FRAME_TELEGRAM => Ok(Some(Frame::Telegram(parse_telegram(payload)?))),
+      FRAME_DATA => Ok(Some(Frame::Data(payload))),
+      _ => Err(Error::UnknownData {
+        what: "frame type".to_string(),
+        value: ftype.to_string()
+      })
+    }
+  }
+}
+
+
+fn put_hdr(buf: &mut BytesMut, ftype: u8, len: usize) -> Result<(), Error> {
+  if len > u32::MAX as usize {
+    return Err(Error::TooLarge {
+      what: "Frame payload".to_string(),
+      size: Some(len as u64),
+      limit: u32::MAX as u64
+    });
+  }
+  buf.reserve(HDR_LEN + len);
+  buf.put_u8(ftype);
+  buf.put_u32(len as u32);
+  Ok(())
+}
+
+
+impl Encoder<&Telegram> for BinCodec {
+  type Error = Error;
+
+  fn encode(
+    &mut self,
+    tg: &Telegram,
+    buf: &mut BytesMut
+  ) -> Result<(), Error> {
+    let topic = match tg.get_topic() {
+      Some(topic) if topic.len() <= u16::MAX as usize => topic,
+      Some(_) => return Err(Error::bad_format("telegram", "topic too long")),
+      None => return Err(Error::bad_format("telegram", "missing topic"))
+    };
+
+    let params = tg.get_params_inner();
+    let mut len = 2 + topic.len();
+    for (k, v) in params {
+      if k.len() > u16::MAX as usize || v.len() > u32::MAX as usize {
+        return Err(Error::bad_format(
+          "telegram",
+          format!("parameter '{}' too long", k)
+        ));
+      }
+      len += 2 + k.len() + 4 + v.len();
+    }
+
+    put_hdr(buf, FRAME_TELEGRAM, len)?;
+    buf.put_u16(topic.len() as u16);
+    buf.put(topic.as_bytes());
+    for (k, v) in params {
+      buf.put_u16(k.len() as u16);
+      buf.put(k.as_bytes());
+      buf.put_u32(v.len() as u32);
+      buf.put(v.as_bytes());
+    }
+    Ok(())
+  }
+}
+
+
+impl Encoder<&[u8]> for BinCodec {
+  type Error = Error;
+
+  fn encode(&mut self, data: &[u8], buf: &mut BytesMut) -> Result<(), Error> {
+    put_hdr(buf, FRAME_DATA, data.len())?;
+    buf.put(data);
+    Ok(())
+  }
+}
+
+
+impl Encoder<Bytes> for BinCodec {
+  type Error = Error;
+
+  fn encode(&mut self, data: Bytes, buf: &mut BytesMut) -> Result<(), Error> {
+    put_hdr(buf, FRAME_DATA, data.len())?;
+    buf.put(data);
+    Ok(())
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
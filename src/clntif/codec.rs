@@ -0,0 +1,2621 @@
+//! A [`tokio_util::codec`] Codec that is used to encode and decode the DDMW
+//! client interface protocol.
+//!
+//! The wire format is the same as that of [`blather::Codec`], but errors are
+//! reported using this crate's [`Error`] type and the decoder is extended
+//! with features specific to the client interfaces.
+
+mod backlog;
+mod blockingwriter;
+mod builder;
+pub mod capture;
+mod decompress;
+mod filewriter;
+pub mod tap;
+mod worker;
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{cmp, mem};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use futures::channel::mpsc;
+use futures::future::BoxFuture;
+use futures::stream::StreamExt;
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use tokio_util::codec::Decoder;
+use tokio_util::codec::Encoder;
+
+use blather::{KVLines, Params, Telegram};
+
+use crate::checksum::{Checksum, Hasher};
+use crate::err::Error;
+use crate::metrics::Metrics;
+
+use super::smallparams::{SmallParams, SmallTelegram};
+
+use backlog::Backlog;
+pub use backlog::Resumed;
+use blockingwriter::BlockingWriter;
+pub use builder::CodecBuilder;
+use decompress::Decompressor;
+use filewriter::FileWriter;
+use tap::{Direction, Tap, TapData};
+
+/// Maximum number of bytes of offending input included in decode errors.
+const ERR_PREVIEW_LEN: usize = 16;
+
+
+/// Current state of decoder
+/// Controls what, if anything, will be returned to the application.
+#[derive(Clone, Debug, PartialEq)]
+enum CodecState {
+  Telegram,
+  Params,
+  KVLines,
+  SmallParams,
+  Chunks,
+  Buf,
+  File,
+  Writer,
+  AsyncWriter,
+  Skip,
+  Resync
+}
+
+/// Data returned to the application when the Codec's Decode iterator is
+/// called and the decoder has a complete entity to return.
+///
+/// New variants may be added as the protocol evolves, so matches on `Input`
+/// must include a wildcard arm.
+#[non_exhaustive]
+pub enum Input {
+  Telegram(Telegram),
+
+  /// An unsolicited telegram pushed by the server, as opposed to a reply to
+  /// a request.  See [`Codec::add_push_topic()`].
+  Push(Telegram),
+
+  KVLines(KVLines),
+  Params(Params),
+  SmallParams(SmallParams),
+  Chunk(BytesMut, usize),
+  Buf(BytesMut),
+  File(PathBuf),
+  WriteDone,
+  SkipDone,
+
+  /// The target of a binary transfer has fallen too far behind, or is
+  /// still finishing up after all data has been received, and the decoder
+  /// has stopped consuming data.  Await [`Codec::resumed()`] before reading
+  /// from the connection again.
+  ///
+  /// See [`Codec::set_async_writer_backlog()`], [`Codec::expect_file()`] and
+  /// [`Codec::expect_writer()`].
+  Paused
+}
+
+
+/// Options controlling how files received using [`Codec::expect_file_with()`]
+/// are created.
+#[derive(Clone, Debug)]
+pub struct FileOpts {
+  /// Permission bits to create the file with.  Unlike the permissions
+  /// normally used when creating files these are not subject to the process'
+  /// umask.
+  #[cfg(unix)]
+  pub mode: Option<u32>,
+
+  /// User to set as the file's owner.
+  #[cfg(unix)]
+  pub uid: Option<u32>,
+
+  /// Group to set as the file's group.
+  #[cfg(unix)]
+  pub gid: Option<u32>,
+
+  /// Replace the file if it already exists.  If not set the transfer fails if
+  /// the file exists when the transfer is started or completed.  Defaults to
+  /// `true`.
+  ///
+  /// The final check is atomic on filesystems which support hard links.  On
+  /// others a file created just as the transfer completes may be replaced.
+  pub overwrite: bool,
+
+  /// Create the file's parent directories if they don't exist.
+  pub create_dirs: bool
+}
+
+impl Default for FileOpts {
+  fn default() -> Self {
+    FileOpts {
+      #[cfg(unix)]
+      mode: None,
+      #[cfg(unix)]
+      uid: None,
+      #[cfg(unix)]
+      gid: None,
+      overwrite: true,
+      create_dirs: false
+    }
+  }
+}
+
+
+/// Compression formats the decoder can decompress received data from.
+///
+/// Each format can only be decompressed if the crate feature of the same
+/// name is enabled.  Otherwise starting a transfer which should be
+/// decompressed fails with `Error::UnknownData`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Compression {
+  Gzip,
+  Zstd
+}
+
+impl FromStr for Compression {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.to_ascii_lowercase().as_str() {
+      "gzip" => Ok(Compression::Gzip),
+      "zstd" => Ok(Compression::Zstd),
+      _ => Err(Error::UnknownData {
+        what: "compression".to_string(),
+        value: s.to_string()
+      })
+    }
+  }
+}
+
+
+/// What the decoder currently expects to receive.
+///
+/// Binary expectations include the number of bytes which remain to be
+/// received.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Expect {
+  Telegram,
+  Params,
+  KVLines,
+  Chunks(usize),
+  Buf(usize),
+  File(usize),
+  Writer(usize),
+  Skip(usize)
+}
+
+
+/// What the decoder does when a key appears more than once in a telegram or
+/// parameters block.
+///
+/// `KVLines` are ordered lists in which keys may repeat, so they aren't
+/// subject to this policy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateParams {
+  /// The last value replaces earlier ones.  This is the default.
+  #[default]
+  LastWins,
+
+  /// The first value is kept and later ones are ignored.
+  FirstWins,
+
+  /// Fail decoding the entity with an `Error::BadFormat`.
+  Error,
+
+  /// The last value is stored in the entity, as with `LastWins`, and all of
+  /// the values are made available through [`Codec::duplicates()`].
+  Collect
+}
+
+
+/// Coarse view of what the decoder expects to receive, for drivers which
+/// only need to distinguish line based entities from binary data.
+///
+/// See [`Expect`] for a more detailed view.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum State {
+  Telegram,
+  Params,
+  KVLines,
+  Binary { remaining: usize }
+}
+
+impl State {
+  /// Returns `true` if `self` and `other` are the same kind of state,
+  /// disregarding the number of remaining binary bytes.
+  pub fn same_kind(&self, other: &State) -> bool {
+    mem::discriminant(self) == mem::discriminant(other)
+  }
+}
+
+
+/// Callback which is notified when the decoder changes state.
+pub type StateHook = Box<dyn FnMut(State, State) + Send + Sync>;
+
+
+/// Counters maintained by the codec.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CodecMetrics {
+  /// Number of telegrams decoded, including pushed telegrams.
+  pub telegrams: u64,
+
+  /// Number of parameter blocks decoded.
+  pub params: u64,
+
+  /// Number of key/value line lists decoded.
+  pub kvlines: u64,
+
+  /// Number of binary transfers completed, regardless of their target.
+  pub bin_transfers: u64,
+
+  /// Number of bytes consumed by the decoder.
+  pub bytes_in: u64,
+
+  /// Number of bytes produced by the encoder.
+  pub bytes_out: u64,
+
+  /// Number of errors returned by the decoder.
+  pub decode_errors: u64
+}
+
+
+/// Callback which is notified of the progress of binary transfers.
+pub type ProgressHook = Box<dyn FnMut(u64, u64) + Send + Sync>;
+
+
+/// Line based entities the decoder can receive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineMode {
+  Telegram,
+  Params,
+  KVLines
+}
+
+
+/// How the decoder treats parameter values which aren't valid UTF-8.
+///
+/// Topics and keys must always be valid UTF-8.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InvalidUtf8 {
+  /// Fail decoding the entity.  This is the default.
+  #[default]
+  Fail,
+
+  /// Replace invalid sequences with `U+FFFD REPLACEMENT CHARACTER`.
+  Replace,
+
+  /// Replace invalid sequences like `Replace`, but also keep the value's
+  /// original bytes.  They are available through [`Codec::raw_values()`]
+  /// after the entity has been returned.
+  Preserve
+}
+
+
+/// Limits applied while receiving a line based entity.
+///
+/// All limits are disabled by default.  Applications which may be connected
+/// to untrusted peers should set limits to keep a peer from exhausting the
+/// process' memory with oversized entities.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineLimits {
+  /// Maximum length of a single line, excluding the line terminator.
+  pub max_line_length: usize,
+
+  /// Maximum number of lines in a single entity, excluding the terminating
+  /// empty line.  For telegrams the topic line is included.
+  pub max_lines: usize,
+
+  /// Maximum number of key/value parameters in a single entity.
+  pub max_params: usize,
+
+  /// Maximum total size, in bytes, of a single entity, including line
+  /// terminators.
+  pub max_size: usize
+}
+
+impl Default for LineLimits {
+  fn default() -> Self {
+    LineLimits {
+      max_line_length: usize::MAX,
+      max_lines: usize::MAX,
+      max_params: usize::MAX,
+      max_size: usize::MAX
+    }
+  }
+}
+
+
+/// The Codec is used to keep track of the state of the inbound and outbound
+/// communication.
+pub struct Codec {
+  next_line_index: usize,
+  num_lines: usize,
+  num_params: usize,
+  entity_size: usize,
+  invalid_utf8: InvalidUtf8,
+  strict: bool,
+  auto_resync: bool,
+  resync_line_start: bool,
+  duplicate_params: DuplicateParams,
+  duplicates: HashMap<String, Vec<String>>,
+  push_topics: HashSet<String>,
+  tap: Option<Tap>,
+  state_hook: Option<StateHook>,
+  progress_hook: Option<ProgressHook>,
+  metrics_sink: Option<Arc<dyn Metrics>>,
+  bin_total: usize,
+  bin_idle_timeout: Option<Duration>,
+  bin_timeout: Option<Duration>,
+  bin_started: Instant,
+  bin_activity: Instant,
+  tap_buf: BytesMut,
+  metrics: CodecMetrics,
+  raw_values: HashMap<String, Bytes>,
+  tg_limits: LineLimits,
+  params_limits: LineLimits,
+  kvlines_limits: LineLimits,
+  max_bin_size: usize,
+  tg: Telegram,
+  params: Params,
+  small_params: SmallParams,
+  kvlines: KVLines,
+  state: CodecState,
+  bin_remain: usize,
+  pathname: Option<PathBuf>,
+  writer: Option<BlockingWriter>,
+  file: Option<FileWriter>,
+  remove_partial: bool,
+  async_writer: Option<mpsc::UnboundedSender<Bytes>>,
+  backlog: Option<Arc<Backlog>>,
+  max_backlog: usize,
+  buf: BytesMut,
+  buf_verify: Option<(Hasher, Checksum)>,
+  pending_compression: Option<Compression>,
+  decompressor: Option<Decompressor>,
+  chunk_pool: Vec<BytesMut>,
+  chunk_pool_max: usize,
+  max_chunk_size: usize,
+  offset: u64
+}
+
+impl fmt::Debug for Codec {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Codec").field("state", &self.state).finish()
+  }
+}
+
+impl Default for Codec {
+  fn default() -> Self {
+    Codec::new()
+  }
+}
+
+
+/// A Codec used to encode and decode the client interface protocol.
+///
+/// # Notes
+/// Normally the Codec object is hidden inside a [`Framed`] object. In order to
+/// call methods in the codec it must be accessed through the Framed object:
+///
+/// ```no_run
+/// # use tokio::io::{AsyncRead, AsyncWrite};
+/// # fn example<T: AsyncRead + AsyncWrite>(socket: T, len: usize) {
+/// use tokio_util::codec::Framed;
+/// use tokio_ddmw::clntif::Codec;
+///
+/// let mut conn = Framed::new(socket, Codec::new());
+/// // ...
+/// conn.codec_mut().expect_chunks(len);
+/// # }
+/// ```
+///
+/// [`Framed`]: tokio_util::codec::Framed
+impl Codec {
+  pub fn new() -> Codec {
+    Codec {
+      next_line_index: 0,
+      num_lines: 0,
+      num_params: 0,
+      entity_size: 0,
+      invalid_utf8: InvalidUtf8::Fail,
+      strict: false,
+      auto_resync: false,
+      resync_line_start: true,
+      duplicate_params: DuplicateParams::LastWins,
+      duplicates: HashMap::new(),
+      push_topics: HashSet::new(),
+      tap: None,
+      state_hook: None,
+      progress_hook: None,
+      metrics_sink: None,
+      bin_total: 0,
+      bin_idle_timeout: None,
+      bin_timeout: None,
+      bin_started: Instant::now(),
+      bin_activity: Instant::now(),
+      tap_buf: BytesMut::new(),
+      metrics: CodecMetrics::default(),
+      raw_values: HashMap::new(),
+      tg_limits: LineLimits::default(),
+      params_limits: LineLimits::default(),
+      kvlines_limits: LineLimits::default(),
+      max_bin_size: usize::MAX,
+      tg: Telegram::new(),
+      params: Params::new(),
+      small_params: SmallParams::new(),
+      kvlines: KVLines::new(),
+      state: CodecState::Telegram,
+      bin_remain: 0,
+      pathname: None,
+      writer: None,
+      file: None,
+      remove_partial: false,
+      async_writer: None,
+      backlog: None,
+      max_backlog: usize::MAX,
+      buf: BytesMut::new(),
+      buf_verify: None,
+      pending_compression: None,
+      decompressor: None,
+      chunk_pool: Vec::new(),
+      chunk_pool_max: 0,
+      max_chunk_size: usize::MAX,
+      offset: 0
+    }
+  }
+
+  /// Create a codec which limits the line length of all line based entities
+  /// to `max_line_length`.
+  pub fn new_with_max_length(max_line_length: usize) -> Self {
+    let limits = LineLimits {
+      max_line_length,
+      ..Default::default()
+    };
+    Codec {
+      tg_limits: limits,
+      params_limits: limits,
+      kvlines_limits: limits,
+      ..Codec::new()
+    }
+  }
+
+  /// Get a builder for configuring a codec before it is handed over to a
+  /// `Framed`.
+  pub fn builder() -> CodecBuilder {
+    CodecBuilder::new()
+  }
+
+  /// Get the maximum line length of telegrams.
+  pub fn max_line_length(&self) -> usize {
+    self.tg_limits.max_line_length
+  }
+
+  /// Set the maximum line length of all line based entities, keeping their
+  /// other limits.
+  ///
+  /// The limit can be changed at any time through `Framed::codec_mut()`, for
+  /// instance to raise it for a specific large reply and lower it again
+  /// afterwards.  A new limit applies from the next line on.
+  pub fn set_max_line_length(&mut self, max: usize) {
+    self.tg_limits.max_line_length = max;
+    self.params_limits.max_line_length = max;
+    self.kvlines_limits.max_line_length = max;
+  }
+
+  /// Set the limits applied when receiving a specific kind of line based
+  /// entity.
+  ///
+  /// This allows, for instance, large `Params` replies to be accepted
+  /// without also accepting equally large telegrams.  The limits can be
+  /// changed at any time; they are applied to lines as they are received.
+  pub fn set_line_limits(&mut self, mode: LineMode, limits: LineLimits) {
+    match mode {
+      LineMode::Telegram => self.tg_limits = limits,
+      LineMode::Params => self.params_limits = limits,
+      LineMode::KVLines => self.kvlines_limits = limits
+    }
+  }
+
+  /// Get the limits applied when receiving a specific kind of line based
+  /// entity.
+  pub fn line_limits(&self, mode: LineMode) -> LineLimits {
+    match mode {
+      LineMode::Telegram => self.tg_limits,
+      LineMode::Params => self.params_limits,
+      LineMode::KVLines => self.kvlines_limits
+    }
+  }
+
+  /// Limits applicable to the current decoder state.
+  fn cur_limits(&self) -> &LineLimits {
+    match self.state {
+      CodecState::Params | CodecState::SmallParams => &self.params_limits,
+      CodecState::KVLines => &self.kvlines_limits,
+      _ => &self.tg_limits
+    }
+  }
+
+  /// Account for a received (non-empty) line, and make sure the current
+  /// entity hasn't exceeded its line count limit.
+  fn count_line(&mut self) -> Result<(), Error> {
+    self.num_lines += 1;
+    let max = self.cur_limits().max_lines;
+    if self.num_lines > max {
+      return Err(Error::TooLarge {
+        what: "Number of lines".to_string(),
+        size: None,
+        limit: max as u64
+      });
+    }
+    Ok(())
+  }
+
+  /// Account for a received key/value parameter, and make sure the current
+  /// entity hasn't exceeded its parameter count limit.
+  fn count_param(&mut self) -> Result<(), Error> {
+    self.num_params += 1;
+    let max = self.cur_limits().max_params;
+    if self.num_params > max {
+      return Err(Error::TooLarge {
+        what: "Number of parameters".to_string(),
+        size: None,
+        limit: max as u64
+      });
+    }
+    Ok(())
+  }
+
+  /// Account for `len` received bytes, and make sure the current entity
+  /// hasn't exceeded its size limit.
+  fn count_size(&mut self, len: usize) -> Result<(), Error> {
+    self.entity_size = self.entity_size.saturating_add(len);
+    let max = self.cur_limits().max_size;
+    if self.entity_size > max {
+      return Err(Error::TooLarge {
+        what: "Entity size".to_string(),
+        size: None,
+        limit: max as u64
+      });
+    }
+    Ok(())
+  }
+
+  /// Clear the per-entity counters once an entity has been completed.
+  fn end_entity(&mut self) {
+    self.num_lines = 0;
+    self.num_params = 0;
+    self.entity_size = 0;
+    if let Some(ref mut tap) = self.tap {
+      if !self.tap_buf.is_empty() {
+        tap(Direction::Inbound, &TapData::Lines(&self.tap_buf));
+      }
+    }
+    self.tap_buf.clear();
+  }
+
+  /// Set the largest binary transfer the codec will accept.
+  ///
+  /// Requests to receive more data than this, using any of the `expect_*()`
+  /// methods, fail with `Error::TooLarge`.  This protects applications from
+  /// peers announcing absurd lengths.  There is no limit by default.
+  pub fn set_max_bin_size(&mut self, max: usize) {
+    self.max_bin_size = max;
+  }
+
+  pub fn max_bin_size(&self) -> usize {
+    self.max_bin_size
+  }
+
+  /// Check that a binary transfer of `size` bytes does not exceed the
+  /// configured maximum.
+  ///
+  /// Applications can use this to validate lengths received in telegrams
+  /// before acting on them.
+  pub fn check_bin_size(&self, size: u64) -> Result<(), Error> {
+    if size > self.max_bin_size as u64 {
+      return Err(Error::TooLarge {
+        what: "Transfer size".to_string(),
+        size: Some(size),
+        limit: self.max_bin_size as u64
+      });
+    }
+    Ok(())
+  }
+
+  /// Register a telegram topic which the server uses for unsolicited events.
+  ///
+  /// Telegrams with a registered topic are returned as `Input::Push` rather
+  /// than `Input::Telegram`, which allows them to be routed separately from
+  /// request replies.
+  pub fn add_push_topic(&mut self, topic: &str) {
+    self.push_topics.insert(topic.to_string());
+  }
+
+  /// Unregister a push topic.  Returns `true` if the topic was registered.
+  pub fn remove_push_topic(&mut self, topic: &str) -> bool {
+    self.push_topics.remove(topic)
+  }
+
+  /// Enable or disable strict parsing of parameter lines.
+  ///
+  /// Parameter lines which lack the space separating the key from the value
+  /// are ignored by default.  In strict mode they cause the decoder to fail
+  /// with an `Error::BadFormat` which identifies the offending line.
+  pub fn set_strict(&mut self, strict: bool) {
+    self.strict = strict;
+  }
+
+  /// Returns `true` if strict parsing of parameter lines is enabled.
+  pub fn is_strict(&self) -> bool {
+    self.strict
+  }
+
+  /// Choose what happens when a key appears more than once in a telegram or
+  /// parameters block.
+  pub fn set_duplicate_params(&mut self, policy: DuplicateParams) {
+    self.duplicate_params = policy;
+  }
+
+  /// Get the current duplicate parameter policy.
+  pub fn duplicate_params(&self) -> DuplicateParams {
+    self.duplicate_params
+  }
+
+  /// All values of the keys which appeared more than once in the most
+  /// recently decoded telegram or parameters block, in the order they were
+  /// received.
+  ///
+  /// Only populated when the [`DuplicateParams::Collect`] policy is used.
+  /// The values are cleared when the decoder starts receiving the next
+  /// entity.
+  pub fn duplicates(&self) -> &HashMap<String, Vec<String>> {
+    &self.duplicates
+  }
+
+  /// Choose how parameter values which aren't valid UTF-8 are handled.
+  ///
+  /// Legacy producers may put, for instance, Latin-1 encoded filenames into
+  /// metadata.  By default such entities fail to decode.
+  pub fn set_invalid_utf8(&mut self, mode: InvalidUtf8) {
+    self.invalid_utf8 = mode;
+  }
+
+  /// Get the current handling of parameter values which aren't valid UTF-8.
+  pub fn invalid_utf8(&self) -> InvalidUtf8 {
+    self.invalid_utf8
+  }
+
+  /// Original bytes of the parameter values of the most recently decoded
+  /// line based entity which weren't valid UTF-8, keyed by parameter name.
+  ///
+  /// Only populated in [`InvalidUtf8::Preserve`] mode.  The values are
+  /// cleared when the decoder starts receiving the next entity.
+  pub fn raw_values(&self) -> &HashMap<String, Bytes> {
+    &self.raw_values
+  }
+
+  /// Choose whether the temporary file of a file transfer should be removed
+  /// if the transfer is interrupted, for instance because the connection was
+  /// dropped.
+  ///
+  /// Partial files are kept by default.
+  pub fn remove_partial_files(&mut self, remove: bool) {
+    self.remove_partial = remove;
+  }
+
+  /// Limit the size of the buffers returned in `Input::Chunk`.
+  ///
+  /// By default each chunk contains whatever was available in the read
+  /// buffer.  With a limit set, larger amounts of data are returned as
+  /// several consecutive chunks of at most `max` bytes each.  Passing zero
+  /// removes the limit.
+  pub fn set_max_chunk_size(&mut self, max: usize) {
+    self.max_chunk_size = if max == 0 { usize::MAX } else { max };
+  }
+
+  pub fn max_chunk_size(&self) -> usize {
+    self.max_chunk_size
+  }
+
+  /// Enable pooling of the buffers returned in `Input::Chunk`.
+  ///
+  /// Normally each chunk is split off the connection's read buffer, which
+  /// keeps the read buffer's allocation alive for as long as the chunk is
+  /// and causes a steady stream of allocations on sustained high-rate
+  /// receives.  With pooling enabled each chunk is instead copied into a
+  /// buffer taken from the pool, and the application hands chunks it's done
+  /// with back to the pool using [`Codec::recycle()`].
+  ///
+  /// At most `max_bufs` buffers are retained by the pool.  Passing zero
+  /// disables pooling (the default).
+  pub fn set_chunk_pool(&mut self, max_bufs: usize) {
+    self.chunk_pool_max = max_bufs;
+    self.chunk_pool.truncate(max_bufs);
+  }
+
+  /// Return a chunk buffer to the pool so it can be reused for a later
+  /// chunk.
+  ///
+  /// The buffer is simply dropped if pooling is disabled or the pool is full.
+  pub fn recycle(&mut self, mut buf: BytesMut) {
+    if self.chunk_pool.len() < self.chunk_pool_max {
+      buf.clear();
+      self.chunk_pool.push(buf);
+    }
+  }
+
+  /// Take `len` bytes off the front of the read buffer `buf` for an
+  /// `Input::Chunk`, copying them into a pooled buffer if pooling is enabled.
+  fn take_chunk(&mut self, buf: &mut BytesMut, len: usize) -> BytesMut {
+    self.count_in(len);
+    self.bin_activity = Instant::now();
+    self.observe_binary(Direction::Inbound, &buf[..len]);
+    if self.chunk_pool_max == 0 {
+      return buf.split_to(len);
+    }
+    let mut chunk = match self.chunk_pool.pop() {
+      Some(chunk) => chunk,
+      None => BytesMut::with_capacity(len)
+    };
+    chunk.extend_from_slice(&buf[..len]);
+    buf.advance(len);
+    chunk
+  }
+
+
+  /// Decompress the data of the next binary transfer.
+  ///
+  /// The next call to [`Codec::expect_buf()`], one of the `expect_file*()`
+  /// methods (except [`Codec::expect_file_at()`]), [`Codec::expect_writer()`]
+  /// or [`Codec::expect_async_writer()`] will run the received data through
+  /// a decompressor before handing it to its target.  The size passed to
+  /// those methods is the size of the compressed data, as sent on the wire.
+  ///
+  /// The decompressed size is also subject to the codec's maximum binary
+  /// size.  Checksums are calculated over the decompressed data.  If the
+  /// data can't be decompressed, or decompresses to more than the maximum
+  /// binary size, the codec is [reset](Codec::reset) and the decoder fails.
+  pub fn decompress_next(&mut self, compression: Compression) {
+    self.pending_compression = Some(compression);
+  }
+
+  /// Set up decompression, if it was requested, for a binary transfer which
+  /// is being started.
+  fn begin_bin(&mut self) -> Result<(), Error> {
+    self.decompressor = match self.pending_compression.take() {
+      Some(c) => Some(Decompressor::new(c, self.max_bin_size as u64)?),
+      None => None
+    };
+    Ok(())
+  }
+
+  /// Pass received binary data through the decompressor, if one is active.
+  fn bin_data(&mut self, data: BytesMut) -> Result<Bytes, Error> {
+    let res = match self.decompressor {
+      Some(ref mut d) => d.feed(&data),
+      None => return Ok(data.freeze())
+    };
+    if res.is_err() {
+      // The decompressor can't continue after a failure, so neither can the
+      // transfer.
+      self.reset();
+    }
+    res
+  }
+
+  /// Get any remaining decompressed data once all binary data has been
+  /// received.
+  fn bin_finish(&mut self) -> Result<Bytes, Error> {
+    let res = match self.decompressor.take() {
+      Some(d) => d.finish(),
+      None => return Ok(Bytes::new())
+    };
+    if res.is_err() {
+      self.reset();
+    }
+    res
+  }
+
+  /// Return the received file once its writer has finished, or pause until
+  /// it has.
+  fn poll_file(&mut self) -> Result<Option<Input>, Error> {
+    let res = match self.file {
+      Some(ref mut f) => match f.poll_done() {
+        Some(res) => res,
+        None => return Ok(Some(Input::Paused))
+      },
+      None => Err(Error::bad_state("a file writer"))
+    };
+    self.file = None;
+
+    // Revert to the default of expecting a telegram.
+    self.set_state(CodecState::Telegram);
+    res?;
+
+    match self.pathname.take() {
+      Some(pathname) => Ok(Some(Input::File(pathname))),
+      None => Err(Error::bad_state("a pathname"))
+    }
+  }
+
+  /// Report the transfer to a writer as done once the writer has flushed,
+  /// or pause until it has.
+  fn poll_writer(&mut self) -> Result<Option<Input>, Error> {
+    let res = match self.writer {
+      Some(ref mut w) => match w.poll_done() {
+        Some(res) => res,
+        None => return Ok(Some(Input::Paused))
+      },
+      None => Err(Error::bad_state("a writer"))
+    };
+    self.writer = None;
+
+    // Revert to the default of expecting a telegram.
+    self.set_state(CodecState::Telegram);
+    res?;
+
+    Ok(Some(Input::WriteDone))
+  }
+
+  /// Hand a chunk of data over to the asynchronous writer future.
+  fn send_async(&mut self, data: Bytes) -> Result<(), Error> {
+    if data.is_empty() {
+      return Ok(());
+    }
+    if let Some(ref tx) = self.async_writer {
+      let len = data.len();
+      if tx.unbounded_send(data).is_err() {
+        return Err(Error::LocalIO {
+          path: None,
+          error: io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            "Asynchronous writer was dropped"
+          )
+        });
+      }
+      if let Some(ref backlog) = self.backlog {
+        backlog.add(len);
+      }
+    }
+    Ok(())
+  }
+
+  /// Limit the amount of data which may be handed over to the future
+  /// returned by [`Codec::expect_async_writer()`] before it has been
+  /// written.
+  ///
+  /// Once the limit is exceeded the decoder stops consuming data, and
+  /// returns `Input::Paused` until the writer has caught up.  The
+  /// application should then await [`Codec::resumed()`] before reading from
+  /// the connection again, which lets the socket's receive window apply
+  /// backpressure to the sender.
+  ///
+  /// There is no limit by default.
+  pub fn set_async_writer_backlog(&mut self, max: usize) {
+    self.max_backlog = max;
+  }
+
+  /// Returns `true` if the decoder has paused because the writer of a
+  /// binary transfer has fallen behind, or is finishing up.
+  pub fn is_paused(&self) -> bool {
+    match self.state {
+      CodecState::AsyncWriter => match self.backlog {
+        Some(ref backlog) => backlog.queued() > self.max_backlog,
+        None => false
+      },
+      CodecState::File => self.file.as_ref().is_some_and(|f| f.is_paused()),
+      CodecState::Writer => {
+        self.writer.as_ref().is_some_and(|w| w.is_paused())
+      }
+      _ => false
+    }
+  }
+
+  /// Get a future which resolves once the decoder is ready to consume data
+  /// again, after having paused.
+  ///
+  /// An asynchronous writer's transfer resumes once the writer's backlog has
+  /// shrunk to half of the limit set using
+  /// [`Codec::set_async_writer_backlog()`].  The future resolves immediately
+  /// if there's no binary transfer in progress.
+  pub fn resumed(&self) -> Resumed {
+    match (&self.state, &self.file, &self.writer) {
+      (CodecState::AsyncWriter, _, _) => {
+        Resumed::new(self.backlog.clone(), self.max_backlog / 2)
+      }
+      (CodecState::File, Some(f), _) => f.resumed(),
+      (CodecState::Writer, _, Some(w)) => w.resumed(),
+      _ => Resumed::new(None, 0)
+    }
+  }
+
+  /// Get what the decoder currently expects to receive.
+  pub fn expecting(&self) -> Expect {
+    match self.state {
+      CodecState::Telegram | CodecState::Resync => Expect::Telegram,
+      CodecState::Params | CodecState::SmallParams => Expect::Params,
+      CodecState::KVLines => Expect::KVLines,
+      CodecState::Chunks => Expect::Chunks(self.bin_remain),
+      CodecState::Buf => Expect::Buf(self.bin_remain),
+      CodecState::File => Expect::File(self.bin_remain),
+      CodecState::Writer | CodecState::AsyncWriter => {
+        Expect::Writer(self.bin_remain)
+      }
+      CodecState::Skip => Expect::Skip(self.bin_remain)
+    }
+  }
+
+  /// Get what the decoder currently expects to receive.
+  pub fn state(&self) -> State {
+    match self.state {
+      CodecState::Telegram | CodecState::Resync => State::Telegram,
+      CodecState::Params | CodecState::SmallParams => State::Params,
+      CodecState::KVLines => State::KVLines,
+      _ => State::Binary {
+        remaining: self.bin_remain
+      }
+    }
+  }
+
+  /// Install a callback which is called with the previous and the new state
+  /// whenever the decoder switches between kinds of [`State`].
+  ///
+  /// The callback is not called as the number of remaining bytes of a
+  /// binary transfer decreases.  It is called synchronously, both from the
+  /// decoder and from the codec's `expect_*()` methods.
+  pub fn on_state_change<F>(&mut self, hook: F)
+  where
+    F: FnMut(State, State) + Send + Sync + 'static
+  {
+    self.state_hook = Some(Box::new(hook));
+  }
+
+  /// Fail binary transfers during which no data has been received for
+  /// `timeout`.  `None`, the default, disables the inactivity timeout.
+  ///
+  /// The decoder only runs when data arrives, so it can't detect that a
+  /// peer has stopped sending on its own.  Timeouts are enforced by
+  /// [`next_input()`](super::util::next_input), which should be used to
+  /// receive from connections which use them.
+  pub fn set_bin_idle_timeout(&mut self, timeout: Option<Duration>) {
+    self.bin_idle_timeout = timeout;
+  }
+
+  /// Fail binary transfers which haven't completed within `timeout`.
+  /// `None`, the default, disables the overall timeout.
+  ///
+  /// See [`Codec::set_bin_idle_timeout()`] for how timeouts are enforced.
+  pub fn set_bin_timeout(&mut self, timeout: Option<Duration>) {
+    self.bin_timeout = timeout;
+  }
+
+  /// Get the point in time at which the current binary transfer times out,
+  /// if the decoder is in a binary state and a timeout has been configured.
+  pub fn receive_deadline(&self) -> Option<Instant> {
+    if let State::Binary { .. } = self.state() {
+      let idle = self.bin_idle_timeout.map(|t| self.bin_activity + t);
+      let total = self.bin_timeout.map(|t| self.bin_started + t);
+      match (idle, total) {
+        (Some(a), Some(b)) => Some(cmp::min(a, b)),
+        (a, b) => a.or(b)
+      }
+    } else {
+      None
+    }
+  }
+
+  /// Install a callback which is called with the number of bytes received so
+  /// far and the total size each time data has been received for a binary
+  /// transfer to a buffer, file or writer.
+  ///
+  /// This allows progress to be reported for large transfers without
+  /// resorting to [`Codec::expect_chunks()`].  Sizes refer to the data as
+  /// received, before any decompression.
+  pub fn on_progress<F>(&mut self, hook: F)
+  where
+    F: FnMut(u64, u64) + Send + Sync + 'static
+  {
+    self.progress_hook = Some(Box::new(hook));
+  }
+
+  fn report_progress(&mut self) {
+    if let Some(ref mut hook) = self.progress_hook {
+      let total = self.bin_total as u64;
+      hook(total.saturating_sub(self.bin_remain as u64), total);
+    }
+  }
+
+  /// Switch to the binary state `state` for a transfer of `size` bytes.
+  ///
+  /// The transfer's size and timing are reset even if the decoder already is
+  /// in the same state, since each `expect_*()` call starts a new transfer.
+  fn start_bin(&mut self, state: CodecState, size: usize) {
+    self.bin_remain = size;
+    self.bin_total = size;
+    self.bin_started = Instant::now();
+    self.bin_activity = self.bin_started;
+    self.set_state(state);
+  }
+
+  /// Switch the decoder state, notifying the state change hook if the kind
+  /// of state changed.
+  ///
+  /// Binary states are entered through `start_bin()`.
+  fn set_state(&mut self, state: CodecState) {
+    if state != self.state {
+      // The new state may have a different line length limit, so any
+      // partial line in the read buffer is searched anew.
+      self.next_line_index = 0;
+    }
+    if self.state_hook.is_none() {
+      self.state = state;
+      return;
+    }
+    let prev = self.state();
+    self.state = state;
+    let new = self.state();
+    if let Some(ref mut hook) = self.state_hook {
+      if !prev.same_kind(&new) {
+        hook(prev, new);
+      }
+    }
+  }
+
+  /// Discard any partially received entity and return to expecting a
+  /// telegram.
+  ///
+  /// Any file, writer or buffer target of an unfinished binary transfer is
+  /// dropped; a partially received file is handled as if the transfer was
+  /// interrupted.
+  ///
+  /// Note that this only resets the decoder; it doesn't affect the data on
+  /// the wire.  If the peer is still sending data of an abandoned binary
+  /// transfer the application needs to account for it, for instance by
+  /// calling [`Codec::skip()`] with the number of remaining bytes reported by
+  /// [`Codec::expecting()`] prior to the reset.
+  pub fn reset(&mut self) {
+    self.next_line_index = 0;
+    self.tap_buf.clear();
+    self.end_entity();
+    self.tg = Telegram::new();
+    self.params = Params::new();
+    self.small_params = SmallParams::new();
+    self.kvlines = KVLines::new();
+    self.pathname = None;
+    self.writer = None;
+    self.file = None;
+    self.async_writer = None;
+    self.backlog = None;
+    self.buf = BytesMut::new();
+    self.buf_verify = None;
+    self.pending_compression = None;
+    self.decompressor = None;
+    self.bin_remain = 0;
+    self.set_state(CodecState::Telegram);
+  }
+
+
+  /// Get the codec's counters.
+  pub fn metrics(&self) -> &CodecMetrics {
+    &self.metrics
+  }
+
+  /// Reset all of the codec's counters to zero.
+  pub fn reset_metrics(&mut self) {
+    self.metrics = CodecMetrics::default();
+  }
+
+  /// Report bytes received and sent through the codec to `sink`, in
+  /// addition to the codec's own counters.
+  pub fn set_metrics_sink(&mut self, sink: Arc<dyn Metrics>) {
+    self.metrics_sink = Some(sink);
+  }
+
+  /// Discard the rest of the current line based entity, and anything else
+  /// received up to the next empty line, then resume decoding telegrams.
+  ///
+  /// After a decode error the position of the decoder relative to the
+  /// protocol's framing is unknown.  Since entities are terminated by an
+  /// empty line, skipping to the next one is likely to reach the start of a
+  /// new telegram, which allows long-lived connections to survive a single
+  /// malformed entity.  Data of any binary transfer in progress when the
+  /// error occurred can't be told apart from lines, so this is only
+  /// suitable for errors in line based entities.
+  ///
+  /// Note that a `Framed` returns `None` once after the decoder has failed.
+  /// The connection can still be used; calling `next()` again resumes
+  /// reading.
+  pub fn resync(&mut self) {
+    self.reset();
+    self.resync_line_start = true;
+    self.set_state(CodecState::Resync);
+  }
+
+  /// Automatically [resynchronize](Codec::resync) after errors while
+  /// decoding line based entities.  The error is still returned to the
+  /// application.
+  ///
+  /// Disabled by default.
+  pub fn set_auto_resync(&mut self, enable: bool) {
+    self.auto_resync = enable;
+  }
+
+  /// Discard input up to and including the next empty line.  Returns `true`
+  /// if an empty line was found.
+  ///
+  /// Resynchronization always starts at the beginning of a line, either
+  /// because the offending line has been consumed or because it is still at
+  /// the front of the buffer.
+  fn discard_until_blank(&mut self, buf: &mut BytesMut) -> bool {
+    let mut line_start = self.resync_line_start;
+    for (i, b) in buf.iter().enumerate() {
+      match *b {
+        b'\n' if line_start => {
+          self.consume(buf, i + 1);
+          self.resync_line_start = true;
+          return true;
+        }
+        b'\n' => line_start = true,
+        b'\r' => {}
+        _ => line_start = false
+      }
+    }
+    let len = buf.len();
+    self.consume(buf, len);
+    self.resync_line_start = line_start;
+    false
+  }
+
+  /// Number of bytes the decoder has consumed from the input stream since
+  /// the codec was created.
+  ///
+  /// Decode errors report the offset of the offending data relative to the
+  /// same origin.
+  pub fn offset(&self) -> u64 {
+    self.offset
+  }
+
+  /// Take `len` bytes off the front of the read buffer `buf`, accounting for
+  /// them in the stream offset.
+  fn consume(&mut self, buf: &mut BytesMut, len: usize) -> BytesMut {
+    self.count_in(len);
+    buf.split_to(len)
+  }
+
+  /// Account for `len` bytes taken off the read buffer.
+  fn count_in(&mut self, len: usize) {
+    self.offset += len as u64;
+    self.metrics.bytes_in += len as u64;
+    if let Some(ref sink) = self.metrics_sink {
+      sink.bytes_received(len as u64);
+    }
+  }
+
+  /// Account for `len` bytes produced by the encoder.
+  fn count_out(&mut self, len: usize) {
+    self.metrics.bytes_out += len as u64;
+    if let Some(ref sink) = self.metrics_sink {
+      sink.bytes_sent(len as u64);
+    }
+  }
+
+  /// Take `len` bytes of binary data off the front of the read buffer `buf`.
+  fn consume_bin(&mut self, buf: &mut BytesMut, len: usize) -> BytesMut {
+    self.bin_activity = Instant::now();
+    self.observe_binary(Direction::Inbound, &buf[..len]);
+    self.consume(buf, len)
+  }
+
+  /// Install a tap which observes all raw data passing through the codec.
+  ///
+  /// Line based entities are passed to the tap as-is once they are complete.
+  /// Binary data is summarized by its length and SHA-256 digest, which makes
+  /// the tap fairly expensive for binary heavy workloads.
+  pub fn set_tap<F>(&mut self, tap: F)
+  where
+    F: FnMut(Direction, &TapData<'_>) + Send + Sync + 'static
+  {
+    self.tap = Some(Box::new(tap));
+  }
+
+  /// Record all data passing through the codec to the file `path`, in the
+  /// format described in [`capture`].
+  ///
+  /// The capture is implemented as a tap, and replaces any tap which has
+  /// already been installed.
+  pub fn capture_to<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+    let mut capture = capture::Capture::create(path)?;
+    self.set_tap(move |dir, data| capture.record(dir, data));
+    Ok(())
+  }
+
+  /// Remove the codec's tap, if one has been installed.
+  pub fn clear_tap(&mut self) {
+    self.tap = None;
+    self.tap_buf.clear();
+  }
+
+  /// Pass data to the tap, and account for outbound data in the metrics.
+  fn observe_lines(&mut self, dir: Direction, data: &[u8]) {
+    if dir == Direction::Outbound {
+      self.count_out(data.len());
+    }
+    if let Some(ref mut tap) = self.tap {
+      tap(dir, &TapData::Lines(data));
+    }
+  }
+
+  fn observe_binary(&mut self, dir: Direction, data: &[u8]) {
+    if dir == Direction::Outbound {
+      self.count_out(data.len());
+    }
+    if let Some(ref mut tap) = self.tap {
+      tap(dir, &TapData::binary(data));
+    }
+  }
+
+  /// Construct a decode error which describes where in the input stream the
+  /// offending `data`, starting at stream offset `offset`, was encountered.
+  fn decode_error(&self, msg: &str, offset: u64, data: &[u8]) -> Error {
+    Error::bad_format(
+      "input",
+      format!(
+        "{} (state: {:?}, offset: {}, data: [{}])",
+        msg,
+        self.state,
+        offset,
+        hex_preview(data)
+      )
+    )
+  }
+
+  /// Take a complete line, including its terminator, off the front of the
+  /// read buffer and account for it in the current entity.
+  fn take_line(
+    &mut self,
+    buf: &mut BytesMut,
+    len: usize
+  ) -> Result<BytesMut, Error> {
+    if self.entity_size == 0 {
+      // First line of a new entity
+      self.raw_values.clear();
+      self.duplicates.clear();
+    }
+    let line = self.consume(buf, len);
+    if self.tap.is_some() {
+      self.tap_buf.extend_from_slice(&line);
+    }
+    self.count_size(len)?;
+    Ok(line)
+  }
+
+  /// Interpret a line which started at stream offset `offset` as UTF-8.
+  ///
+  /// Invalid parameter values are handled according to the codec's
+  /// [`InvalidUtf8`] mode.
+  fn utf8<'a>(
+    &mut self,
+    line: &'a [u8],
+    offset: u64
+  ) -> Result<Cow<'a, str>, Error> {
+    let e = match std::str::from_utf8(line) {
+      Ok(s) => return Ok(Cow::Borrowed(s)),
+      Err(e) => e
+    };
+    let fail = |codec: &Self, pos: usize| {
+      codec.decode_error(
+        "Unable to decode input as UTF-8",
+        offset + pos as u64,
+        &line[pos..]
+      )
+    };
+    if self.invalid_utf8 == InvalidUtf8::Fail {
+      return Err(fail(self, e.valid_up_to()));
+    }
+
+    // Only the value may be recovered; the key must be intact.
+    let key = match line.iter().position(|b| *b == b' ') {
+      Some(idx) if idx <= e.valid_up_to() => {
+        std::str::from_utf8(&line[..idx]).map_err(|_| fail(self, 0))?
+      }
+      _ => return Err(fail(self, e.valid_up_to()))
+    };
+    let value = &line[key.len() + 1..];
+    if self.invalid_utf8 == InvalidUtf8::Preserve {
+      self
+        .raw_values
+        .insert(key.to_string(), Bytes::copy_from_slice(value));
+    }
+    Ok(Cow::Owned(format!(
+      "{} {}",
+      key,
+      String::from_utf8_lossy(value)
+    )))
+  }
+
+
+  /// Determine how far into the buffer we'll search for a newline. If
+  /// there's no max_length set, we'll read to the end of the buffer.
+  ///
+  /// Returns the end of the searched range and the index of the newline in
+  /// the buffer, if one was found.
+  fn find_newline(&self, buf: &BytesMut) -> (usize, Option<usize>) {
+    let max_line_length = self.cur_limits().max_line_length;
+    let read_to = cmp::min(max_line_length.saturating_add(1), buf.len());
+    // The limit may have been lowered since the search was last resumed.
+    // The bytes before the resume point hold no newline either way.
+    let start = cmp::min(self.next_line_index, read_to);
+    let newline_offset = buf[start..read_to].iter().position(|b| *b == b'\n');
+
+    (read_to, newline_offset.map(|offset| offset + start))
+  }
+
+
+  /// `decode_telegram_lines` has encountered an eol, determined that the
+  /// string is longer than zero characters, and thus passed the line to this
+  /// function to process it.
+  ///
+  /// The first line received is a telegram topic.  This is a required line.
+  /// Following lines are parameter lines, which are a single space character
+  /// separated key/value pairs.
+  fn decode_telegram_line(
+    &mut self,
+    line: &str,
+    start: u64
+  ) -> Result<(), Error> {
+    if self.tg.get_topic().is_none() {
+      self.tg.set_topic(line)?;
+    } else {
+      if let Some((k, v)) = self.split_param(line, start)? {
+        self.count_param()?;
+        let prev = self.tg.get_str(k).map(str::to_string);
+        if self.keep_param(k, v, prev, start)? {
+          self.tg.add_param(k, v)?;
+        }
+      }
+    }
+    Ok(())
+  }
+
+
+  /// Split a parameter line, which started at stream offset `start`, into
+  /// its key and value.
+  ///
+  /// Lines without a separator are ignored, unless strict parsing is
+  /// enabled.
+  fn split_param<'a>(
+    &self,
+    line: &'a str,
+    start: u64
+  ) -> Result<Option<(&'a str, &'a str)>, Error> {
+    match line.find(' ') {
+      Some(idx) => Ok(Some((&line[..idx], &line[idx + 1..]))),
+      None if self.strict => Err(self.decode_error(
+        "Parameter line lacks a key/value separator",
+        start,
+        line.as_bytes()
+      )),
+      None => Ok(None)
+    }
+  }
+
+
+  /// Apply the duplicate parameter policy to the parameter `key`, whose
+  /// line started at stream offset `start`.  `prev` is the key's current
+  /// value in the entity being received, if it has one.
+  ///
+  /// Returns `true` if `value` should be stored in the entity.
+  fn keep_param(
+    &mut self,
+    key: &str,
+    value: &str,
+    prev: Option<String>,
+    start: u64
+  ) -> Result<bool, Error> {
+    let prev = match prev {
+      Some(prev) => prev,
+      None => return Ok(true)
+    };
+    match self.duplicate_params {
+      DuplicateParams::LastWins => Ok(true),
+      DuplicateParams::FirstWins => Ok(false),
+      DuplicateParams::Error => Err(self.decode_error(
+        &format!("Duplicate parameter '{}'", key),
+        start,
+        key.as_bytes()
+      )),
+      DuplicateParams::Collect => {
+        self
+          .duplicates
+          .entry(key.to_string())
+          .or_insert_with(|| vec![prev])
+          .push(value.to_string());
+        Ok(true)
+      }
+    }
+  }
+
+
+  fn get_eol_idx(&mut self, buf: &BytesMut) -> Result<Option<usize>, Error> {
+    let (read_to, newline_index) = self.find_newline(buf);
+    match newline_index {
+      Some(index) => {
+        // Found an eol
+        self.next_line_index = 0;
+        Ok(Some(index + 1))
+      }
+      None if buf.len() > self.cur_limits().max_line_length => Err(
+        self.decode_error("Exceeded maximum line length", self.offset, buf)
+      ),
+      None => {
+        // Didn't find a line or reach the length limit, so the next
+        // call will resume searching at the current offset.
+        self.next_line_index = read_to;
+
+        // Returning Ok(None) instructs the FramedRead that more data is
+        // needed.
+        Ok(None)
+      }
+    }
+  }
+
+  /// (New) data is available in the input buffer.
+  /// Try to parse lines until an empty line as been encountered, at which
+  /// point the buffer is parsed and returned in an [`Telegram`] buffer.
+  ///
+  /// If the buffer doesn't contain enough data to finalize a complete telegram
+  /// buffer return `Ok(None)` to inform the calling FramedRead that more data
+  /// is needed.
+  fn decode_telegram_lines(
+    &mut self,
+    buf: &mut BytesMut
+  ) -> Result<Option<Telegram>, Error> {
+    loop {
+      if let Some(idx) = self.get_eol_idx(buf)? {
+        let start = self.offset;
+        let line = self.take_line(buf, idx)?;
+        let line = &line[..line.len() - 1];
+        let line = self.utf8(without_carriage_return(line), start)?;
+
+        // Empty line marks end of Telegram
+        if line.is_empty() {
+          self.end_entity();
+
+          // mem::take() can replace a member of a struct.
+          // (This requires Default to be implemented for the object being
+          // taken).
+          return Ok(Some(mem::take(&mut self.tg)));
+        } else {
+          self.count_line()?;
+          self.decode_telegram_line(&line, start)?;
+        }
+      } else {
+        // Returning Ok(None) instructs the FramedRead that more data is
+        // needed.
+        return Ok(None);
+      }
+    }
+  }
+
+
+  /// Read buffer line-by-line, split each line at the first space character
+  /// and store the left part as a key and the right part as a value in a
+  /// Params structure.
+  fn decode_params_lines(
+    &mut self,
+    buf: &mut BytesMut
+  ) -> Result<Option<Params>, Error> {
+    loop {
+      if let Some(idx) = self.get_eol_idx(buf)? {
+        // Found an eol
+        let start = self.offset;
+        let line = self.take_line(buf, idx)?;
+        let line = &line[..line.len() - 1];
+        let line = self.utf8(without_carriage_return(line), start)?;
+
+        // Empty line marks end of Params
+        if line.is_empty() {
+          self.end_entity();
+
+          // Revert to expecting a telegram once a Params has been completed.
+          // The application can override this when needed.
+          self.set_state(CodecState::Telegram);
+
+          // mem::take() can replace a member of a struct.
+          // (This requires Default to be implemented for the object being
+          // taken).
+          return Ok(Some(mem::take(&mut self.params)));
+        } else {
+          self.count_line()?;
+          if let Some((k, v)) = self.split_param(&line, start)? {
+            self.count_param()?;
+            let prev = self.params.get_str(k).map(str::to_string);
+            if self.keep_param(k, v, prev, start)? {
+              self.params.add_param(k, v)?;
+            }
+          }
+        }
+      } else {
+        // Need more data
+        return Ok(None);
+      }
+    }
+  }
+
+  fn decode_small_params_lines(
+    &mut self,
+    buf: &mut BytesMut
+  ) -> Result<Option<SmallParams>, Error> {
+    loop {
+      if let Some(idx) = self.get_eol_idx(buf)? {
+        // Found an eol
+        let start = self.offset;
+        let line = self.take_line(buf, idx)?;
+        let line = &line[..line.len() - 1];
+        let line = self.utf8(without_carriage_return(line), start)?;
+
+        // Empty line marks end of Params
+        if line.is_empty() {
+          self.end_entity();
+          self.set_state(CodecState::Telegram);
+          return Ok(Some(mem::take(&mut self.small_params)));
+        } else {
+          self.count_line()?;
+          if let Some((k, v)) = self.split_param(&line, start)? {
+            self.count_param()?;
+            let prev = self.small_params.get_str(k).map(str::to_string);
+            if self.keep_param(k, v, prev, start)? {
+              self.small_params.add_line(k, v)?;
+            }
+          }
+        }
+      } else {
+        // Need more data
+        return Ok(None);
+      }
+    }
+  }
+
+  fn decode_kvlines(
+    &mut self,
+    buf: &mut BytesMut
+  ) -> Result<Option<KVLines>, Error> {
+    loop {
+      if let Some(idx) = self.get_eol_idx(buf)? {
+        // Found an eol
+        let start = self.offset;
+        let line = self.take_line(buf, idx)?;
+        let line = &line[..line.len() - 1];
+        let line = self.utf8(without_carriage_return(line), start)?;
+
+        // Empty line marks end of Params
+        if line.is_empty() {
+          self.end_entity();
+
+          // Revert to expecting a telegram once a KVLines  has been
+          // completed.
+          // The application can override this when needed.
+          self.set_state(CodecState::Telegram);
+
+          // mem::take() can replace a member of a struct.
+          // (This requires Default to be implemented for the object being
+          // taken).
+          return Ok(Some(mem::take(&mut self.kvlines)));
+        } else {
+          self.count_line()?;
+          if let Some((k, v)) = self.split_param(&line, start)? {
+            self.count_param()?;
+            self.kvlines.append(k, v);
+          }
+        }
+      } else {
+        // Need more data
+        return Ok(None);
+      }
+    }
+  }
+
+
+  /// Set the decoder to treat the next `size` bytes as raw bytes to be
+  /// received in chunks.
+  ///
+  /// # Decoder behavior
+  /// The decoder will return an `Input::Chunk(buf, remain)` to the application
+  /// each time a new chunk has been received. In addition to the actual
+  /// chunk number of bytes remaining will be returned.  The remaining bytes
+  /// value is adjusted to subtract the currently returned chunk, which means
+  /// that the application can detect the end of the buffer by checking if
+  /// the remaining value is zero.
+  ///
+  /// Fails with `Error::TooLarge` if `size` exceeds the codec's maximum
+  /// binary size.
+  pub fn expect_chunks(&mut self, size: usize) -> Result<(), Error> {
+    self.check_bin_size(size as u64)?;
+    self.pending_compression = None;
+    self.start_bin(CodecState::Chunks, size);
+    Ok(())
+  }
+
+  /// Expect a buffer of a certain size to be received.
+  /// The returned buffer will be stored in process memory.
+  ///
+  /// # Decoder behavior
+  /// One a complete buffer has been successfully reaceived the `Decoder` will
+  /// return an `Input::Buf(b)` where `b` is a `bytes::BytesMut` containing the
+  /// entire buffer.
+  ///
+  /// Once the entire buffer has been received by the `Decoder` it will revert
+  /// to expect an `Input::Telegram`.
+  pub fn expect_buf(&mut self, size: usize) -> Result<(), Error> {
+    if size == 0 {
+      return Err(Error::InvalidSize {
+        what: "transfer".to_string(),
+        expected: None,
+        actual: 0
+      });
+    }
+    self.check_bin_size(size as u64)?;
+    self.begin_bin()?;
+    self.start_bin(CodecState::Buf, size);
+    self.buf = BytesMut::with_capacity(size);
+    self.buf_verify = None;
+    Ok(())
+  }
+
+  /// Same as [`Codec::expect_buf()`], but the received data is hashed as it
+  /// arrives and its checksum is compared to `expected`.
+  ///
+  /// # Decoder behavior
+  /// If the checksum of the received buffer does not match the expected one
+  /// the Decoder returns an `Error::ChecksumMismatch` (and reverts to
+  /// expecting a telegram) instead of returning the buffer.
+  pub fn expect_buf_verified(
+    &mut self,
+    size: usize,
+    expected: Checksum
+  ) -> Result<(), Error> {
+    self.expect_buf(size)?;
+    self.buf_verify = Some((expected.hasher(), expected));
+    Ok(())
+  }
+
+  /// Expects a certain amount of bytes of data to arrive from the peer, and
+  /// that data should be stored to a file.
+  ///
+  /// The file is written by a background worker, so a slow disk does not
+  /// stall the runtime while the data is being received.  If the worker
+  /// falls behind, and while it syncs the file and moves it into place after
+  /// all data has been received, the decoder returns `Input::Paused` instead
+  /// of waiting for it.
+  ///
+  /// The data is written to a temporary file named after `pathname` with a
+  /// `.part` suffix.  Once all of it has been received the temporary file is
+  /// synced to disk and atomically renamed to `pathname`.  If the transfer is
+  /// interrupted the temporary file is left behind, unless the codec has been
+  /// told to remove partial files using [`Codec::remove_partial_files()`].
+  ///
+  /// # Decoder behavior
+  /// On successful completion the Decoder will return an Input::File(pathname)
+  /// once the entire file length has successfully been received and moved
+  /// into place, where the pathname is a PathBuf which matches the pathname
+  /// parameter passed to this function.
+  pub fn expect_file<P: Into<PathBuf>>(
+    &mut self,
+    pathname: P,
+    size: usize
+  ) -> Result<(), Error> {
+    self.expect_file_with(pathname, size, &FileOpts::default())
+  }
+
+  /// Same as [`Codec::expect_file()`], but allows the application to control
+  /// how the file is created.
+  ///
+  /// Permissions and ownership are applied to the temporary file before any
+  /// data is written to it, so the file is never accessible with other
+  /// permissions.
+  pub fn expect_file_with<P: Into<PathBuf>>(
+    &mut self,
+    pathname: P,
+    size: usize,
+    opts: &FileOpts
+  ) -> Result<(), Error> {
+    self.start_file(pathname.into(), size, opts, None)
+  }
+
+  /// Same as [`Codec::expect_file()`], but the received data is hashed as it
+  /// is written and its checksum is compared to `expected`.
+  ///
+  /// # Decoder behavior
+  /// If the checksum of the received file does not match the expected one
+  /// the temporary file is removed and the Decoder returns an
+  /// `Error::ChecksumMismatch` (and reverts to expecting a telegram) instead
+  /// of an `Input::File`.
+  pub fn expect_file_verified<P: Into<PathBuf>>(
+    &mut self,
+    pathname: P,
+    size: usize,
+    expected: Checksum
+  ) -> Result<(), Error> {
+    self.start_file(
+      pathname.into(),
+      size,
+      &FileOpts::default(),
+      Some(expected)
+    )
+  }
+
+  fn start_file(
+    &mut self,
+    pathname: PathBuf,
+    size: usize,
+    opts: &FileOpts,
+    verify: Option<Checksum>
+  ) -> Result<(), Error> {
+    if size == 0 {
+      return Err(Error::InvalidSize {
+        what: "transfer".to_string(),
+        expected: None,
+        actual: 0
+      });
+    }
+    self.check_bin_size(size as u64)?;
+    self.begin_bin()?;
+    self.file = Some(FileWriter::create(
+      &pathname,
+      opts,
+      self.remove_partial,
+      verify
+    )?);
+    self.pathname = Some(pathname);
+    self.start_bin(CodecState::File, size);
+
+    Ok(())
+  }
+
+  /// Continue receiving a file whose transfer was previously interrupted.
+  ///
+  /// The data received so far is expected to be in the temporary `.part`
+  /// file left behind by [`Codec::expect_file()`].  Writing continues at
+  /// `offset` (normally the size of the temporary file, as reported by
+  /// [`Codec::partial_file_size()`]), and `remaining` more bytes are
+  /// expected to arrive from the peer.  Once they have been received the file
+  /// is synced and moved into place just like in `expect_file()`.
+  ///
+  /// # Decoder behavior
+  /// Same as for [`Codec::expect_file()`].
+  pub fn expect_file_at<P: Into<PathBuf>>(
+    &mut self,
+    pathname: P,
+    offset: u64,
+    remaining: usize
+  ) -> Result<(), Error> {
+    if remaining == 0 {
+      return Err(Error::InvalidSize {
+        what: "transfer".to_string(),
+        expected: None,
+        actual: 0
+      });
+    }
+    self.check_bin_size(offset.saturating_add(remaining as u64))?;
+    if self.pending_compression.is_some() {
+      return Err(Error::BadState {
+        expected: "a transfer from the start of the stream".to_string(),
+        found: Some("a resumed compressed transfer".to_string())
+      });
+    }
+    let pathname = pathname.into();
+    self.file =
+      Some(FileWriter::resume(&pathname, offset, self.remove_partial)?);
+    self.pathname = Some(pathname);
+    self.start_bin(CodecState::File, remaining);
+
+    Ok(())
+  }
+
+  /// Get the size of the data received so far by an interrupted transfer to
+  /// `pathname`.
+  ///
+  /// Returns `None` if there's no partial file for `pathname`.
+  pub fn partial_file_size<P: AsRef<Path>>(pathname: P) -> Option<u64> {
+    let partname = filewriter::part_path(pathname.as_ref());
+    std::fs::metadata(partname).ok().map(|md| md.len())
+  }
+
+  /// Called from an application to request that data should be written to a
+  /// supplied writer.
+  ///
+  /// The writer's ownership will be transferred to the `Decoder` and will
+  /// automatically be dropped once the entire buffer has been written.
+  ///
+  /// Writes are performed in batches on the runtime's blocking thread pool,
+  /// so a slow writer doesn't block the runtime thread the decoder runs on.
+  /// If the writer falls behind, and while it's being flushed after all data
+  /// has been received, the decoder returns `Input::Paused` instead of
+  /// waiting for it.
+  ///
+  /// # Decoder behavior
+  /// On successful completion the Decoder will return an Input::WriteDone to
+  /// signal that the entire buffer has been received and written to the
+  /// `Writer`.
+  ///
+  /// Once the complete `Params` buffer has been received the Decoder will
+  /// revert back to waiting for a `Telegram`.
+  pub fn expect_writer<W: 'static + Write + Send + Sync>(
+    &mut self,
+    writer: W,
+    size: usize
+  ) -> Result<(), Error> {
+    if size == 0 {
+      return Err(Error::InvalidSize {
+        what: "transfer".to_string(),
+        expected: None,
+        actual: 0
+      });
+    }
+    self.check_bin_size(size as u64)?;
+    self.begin_bin()?;
+    self.writer = Some(BlockingWriter::new(writer)?);
+    self.start_bin(CodecState::Writer, size);
+    Ok(())
+  }
+
+  /// Called from an application to request that data should be written to a
+  /// supplied asynchronous writer.
+  ///
+  /// The decoder can't wait for a writer, so rather than writing to it
+  /// directly the decoder hands each received chunk over to the returned
+  /// future, which performs the actual writes.  The application must drive
+  /// the future (for instance by spawning it) while it keeps receiving from
+  /// the connection.  Chunks which the writer has not yet caught up with are
+  /// buffered in memory; use [`Codec::set_async_writer_backlog()`] to bound
+  /// the amount of buffered data.
+  ///
+  /// The future resolves once all `size` bytes have been written and the
+  /// writer has been flushed.  It fails if the writer fails, or if the codec
+  /// stops handing over data before all of it has been received.
+  ///
+  /// # Decoder behavior
+  /// Once the entire buffer has been handed over to the writer future the
+  /// Decoder will return an Input::WriteDone and revert back to waiting for a
+  /// `Telegram`.  Note that the data may not have been written at this point;
+  /// await the returned future to make sure it has.
+  pub fn expect_async_writer<W>(
+    &mut self,
+    writer: W,
+    size: usize
+  ) -> Result<BoxFuture<'static, Result<(), Error>>, Error>
+  where
+    W: 'static + AsyncWrite + Send + Unpin
+  {
+    if size == 0 {
+      return Err(Error::InvalidSize {
+        what: "transfer".to_string(),
+        expected: None,
+        actual: 0
+      });
+    }
+    self.check_bin_size(size as u64)?;
+    self.begin_bin()?;
+    let (tx, mut rx) = mpsc::unbounded::<Bytes>();
+    let backlog = Arc::new(Backlog::default());
+    self.async_writer = Some(tx);
+    self.backlog = Some(Arc::clone(&backlog));
+    self.start_bin(CodecState::AsyncWriter, size);
+
+    // Data chunks are never empty; an empty chunk marks the end of the
+    // transfer.
+    let mut writer = writer;
+    Ok(Box::pin(async move {
+      let mut complete = false;
+      while let Some(chunk) = rx.next().await {
+        if chunk.is_empty() {
+          complete = true;
+          break;
+        }
+        writer.write_all(&chunk).await.map_err(local_io)?;
+        backlog.remove(chunk.len());
+      }
+      writer.flush().await.map_err(local_io)?;
+      if !complete {
+        return Err(Error::BadState {
+          expected: "the rest of the transfer".to_string(),
+          found: Some("the end of the transfer".to_string())
+        });
+      }
+      Ok(())
+    }))
+  }
+
+  /// Tell the Decoder to expect lines of key/value pairs.
+  ///
+  /// # Decoder behavior
+  /// On successful completion the Framed StreamExt next() will return an
+  /// Input::Params(params) once a complete `Params` buffer has been received.
+  ///
+  /// Once the complete `Params` buffer has been received the Decoder will
+  /// revert back to waiting for a `Telegram`.
+  pub fn expect_params(&mut self) {
+    self.set_state(CodecState::Params);
+  }
+
+  /// Tell the Decoder to expect lines of key/value pairs, to be returned in
+  /// a low-allocation [`SmallParams`] container.
+  ///
+  /// # Decoder behavior
+  /// Same as [`Codec::expect_params()`], except that the Decoder returns an
+  /// Input::SmallParams(params).
+  pub fn expect_small_params(&mut self) {
+    self.set_state(CodecState::SmallParams);
+  }
+
+  /// Tell the Decoder to expect lines ordered key/value pairs.
+  ///
+  /// # Decoder behavior
+  /// On successful completion the Framed StreamExt next() will return an
+  /// Input::KVLines(kvlines) once a complete `KVLines` buffer has been
+  /// received.
+  ///
+  /// Once the complete `KVLines` buffer has been received the Decoder will
+  /// revert back to waiting for a `Telegram`.
+  pub fn expect_kvlines(&mut self) {
+    self.set_state(CodecState::KVLines);
+  }
+
+  /// Skip bytes.
+  ///
+  /// # Decoder behavior
+  /// Simply ignore the number of specified bytes, then revert back to waiting
+  /// for a Telegram.
+  pub fn skip(&mut self, size: usize) -> Result<(), Error> {
+    if size == 0 {
+      return Err(Error::InvalidSize {
+        what: "transfer".to_string(),
+        expected: None,
+        actual: 0
+      });
+    }
+    self.pending_compression = None;
+    self.start_bin(CodecState::Skip, size);
+    Ok(())
+  }
+}
+
+/// Format up to `ERR_PREVIEW_LEN` bytes of `data` as space-separated hex
+/// octets.
+fn hex_preview(data: &[u8]) -> String {
+  let mut s = data
+    .iter()
+    .take(ERR_PREVIEW_LEN)
+    .map(|b| format!("{:02x}", b))
+    .collect::<Vec<_>>()
+    .join(" ");
+  if data.len() > ERR_PREVIEW_LEN {
+    s.push_str(" ..");
+  }
+  s
+}
+
+fn without_carriage_return(s: &[u8]) -> &[u8] {
+  if let Some(&b'\r') = s.last() {
+    &s[..s.len() - 1]
+  } else {
+    s
+  }
+}
+
+
+impl Codec {
+  /// Decode according to the current decoder state.
+  fn decode_state(
+    &mut self,
+    buf: &mut BytesMut
+  ) -> Result<Option<Input>, Error> {
+    // The codec's internal decoder state denotes whether lines or binary data
+    // is currently being expected.
+    match self.state {
+      CodecState::Resync => {
+        if !self.discard_until_blank(buf) {
+          return Ok(None); // Need more data
+        }
+        self.set_state(CodecState::Telegram);
+        self.decode_state(buf)
+      }
+      CodecState::Telegram => {
+        // If decode_telegram_lines returns Some(value) it means that a
+        // complete buffer has been received.
+        let tg = self.decode_telegram_lines(buf)?;
+        if let Some(tg) = tg {
+          // A complete Telegram was received
+          let push = match tg.get_topic() {
+            Some(topic) => self.push_topics.contains(topic),
+            None => false
+          };
+          if push {
+            return Ok(Some(Input::Push(tg)));
+          }
+          return Ok(Some(Input::Telegram(tg)));
+        }
+
+        // Returning Ok(None) tells the caller that we need more data
+        Ok(None)
+      }
+      CodecState::Params => {
+        // If decode_telegram_lines returns Some(value) it means that a
+        // complete buffer has been received.
+        let params = self.decode_params_lines(buf)?;
+        if let Some(params) = params {
+          // A complete Params buffer was received
+          return Ok(Some(Input::Params(params)));
+        }
+
+        // Returning Ok(None) tells the caller that we need more data
+        Ok(None)
+      }
+      CodecState::SmallParams => {
+        let params = self.decode_small_params_lines(buf)?;
+        if let Some(params) = params {
+          return Ok(Some(Input::SmallParams(params)));
+        }
+        Ok(None)
+      }
+      CodecState::KVLines => {
+        // If decode_telegram_lines returns Some(value) it means that a
+        // complete buffer has been received.
+        let kvlines = self.decode_kvlines(buf)?;
+        if let Some(kvlines) = kvlines {
+          // A complete Params buffer was received
+          return Ok(Some(Input::KVLines(kvlines)));
+        }
+
+        // Returning Ok(None) tells the caller that we need more data
+        Ok(None)
+      }
+
+      CodecState::Chunks => {
+        if buf.is_empty() {
+          // Need more data
+          return Ok(None);
+        }
+
+        let read_to = cmp::min(self.bin_remain, buf.len());
+        let read_to = cmp::min(read_to, self.max_chunk_size);
+        self.bin_remain -= read_to;
+
+        if self.bin_remain == 0 {
+          // When no more data is expected for this binary part, revert to
+          // expecting Telegram lines
+          self.set_state(CodecState::Telegram);
+        }
+
+        // Return a buffer and the amount of data remaining, this buffer
+        // included.  The application can check if remain is 0 to determine
+        // if it has received all the expected binary data.
+        let chunk = self.take_chunk(buf, read_to);
+        Ok(Some(Input::Chunk(chunk, self.bin_remain)))
+      }
+      CodecState::Buf => {
+        if buf.is_empty() {
+          // Need more data
+          return Ok(None);
+        }
+        let read_to = cmp::min(self.bin_remain, buf.len());
+
+        // Transfer data from input to output buffer.  The data is accounted
+        // for as soon as it's been consumed, so the counters remain accurate
+        // if it can't be processed.
+        let data = self.consume_bin(buf, read_to);
+        self.bin_remain -= read_to;
+        self.report_progress();
+        let data = self.bin_data(data)?;
+        if let Some((ref mut h, _)) = self.buf_verify {
+          h.update(&data);
+        }
+        self.buf.put(data);
+
+        if self.bin_remain != 0 {
+          // Need more data
+          return Ok(None);
+        }
+
+        let data = self.bin_finish()?;
+        if let Some((ref mut h, _)) = self.buf_verify {
+          h.update(&data);
+        }
+        self.buf.put(data);
+
+        // When no more data is expected for this binary part, revert to
+        // expecting Telegram lines
+        self.set_state(CodecState::Telegram);
+
+        if let Some((h, expected)) = self.buf_verify.take() {
+          if let Err(e) = expected.verify(&h.finish()) {
+            self.buf = BytesMut::new();
+            return Err(e);
+          }
+        }
+
+        // Return a buffer and the amount of data remaining, this buffer
+        // included.  The application can check if remain is 0 to determine
+        // if it has received all the expected binary data.
+        Ok(Some(Input::Buf(mem::take(&mut self.buf))))
+      }
+      CodecState::File => {
+        if self.bin_remain == 0 {
+          // All data has been received; waiting for the writer to finish.
+          return self.poll_file();
+        }
+        if buf.is_empty() {
+          return Ok(None); // Need more data
+        }
+
+        if self.is_paused() {
+          // Leave the data in the read buffer until the writer catches up.
+          return Ok(Some(Input::Paused));
+        }
+
+        // Hand as much data as available or requested over to the file
+        // writer.
+        let read_to = cmp::min(self.bin_remain, buf.len());
+        let data = self.consume_bin(buf, read_to);
+        self.bin_remain -= read_to;
+        self.report_progress();
+        let data = self.bin_data(data)?;
+        if let Some(ref mut f) = self.file {
+          if !data.is_empty() {
+            f.write(data)?;
+          }
+        }
+
+        if self.bin_remain != 0 {
+          return Ok(None); // Need more data
+        }
+
+        let data = self.bin_finish()?;
+        if let Some(ref mut f) = self.file {
+          if !data.is_empty() {
+            f.write(data)?;
+          }
+        }
+
+        // At this point the entire expected buffer has been received.  Have
+        // the writer sync the file and move it into place.
+        if let Some(ref mut f) = self.file {
+          f.finish()?;
+        }
+        self.poll_file()
+      } // CodecState::File
+      CodecState::Writer => {
+        if self.bin_remain == 0 {
+          // All data has been received; waiting for the writer to finish.
+          return self.poll_writer();
+        }
+        if buf.is_empty() {
+          return Ok(None); // Need more data
+        }
+
+        if self.is_paused() {
+          // Leave the data in the read buffer until the writer catches up.
+          return Ok(Some(Input::Paused));
+        }
+
+        // Read as much data as available or requested and write it to our
+        // output.
+        let read_to = cmp::min(self.bin_remain, buf.len());
+        let data = self.consume_bin(buf, read_to);
+        self.bin_remain -= read_to;
+        self.report_progress();
+        let data = self.bin_data(data)?;
+        if let Some(ref mut w) = self.writer {
+          w.write(&data)?;
+        }
+
+        if self.bin_remain != 0 {
+          return Ok(None); // Need more data
+        }
+
+        let data = self.bin_finish()?;
+
+        // At this point the entire expected buffer has been received.  Have
+        // the writer write and flush it.
+        if let Some(ref mut w) = self.writer {
+          w.write(&data)?;
+          w.finish()?;
+        }
+        self.poll_writer()
+      } // CodecState::Writer
+      CodecState::AsyncWriter => {
+        if buf.is_empty() {
+          return Ok(None); // Need more data
+        }
+
+        if self.is_paused() {
+          // Leave the data in the read buffer until the writer catches up.
+          return Ok(Some(Input::Paused));
+        }
+
+        // Hand over as much data as available or requested to the writer
+        // future.
+        let read_to = cmp::min(self.bin_remain, buf.len());
+        let data = self.consume_bin(buf, read_to);
+        self.bin_remain -= read_to;
+        self.report_progress();
+        let data = self.bin_data(data)?;
+        self.send_async(data)?;
+
+        if self.bin_remain != 0 {
+          return Ok(None); // Need more data
+        }
+
+        let data = self.bin_finish()?;
+        self.send_async(data)?;
+
+        // Tell the writer future that all data has been handed over.
+        if let Some(tx) = self.async_writer.take() {
+          let _ = tx.unbounded_send(Bytes::new());
+        }
+        self.backlog = None;
+
+        // Revert to the default of expecting a telegram.
+        self.set_state(CodecState::Telegram);
+
+        Ok(Some(Input::WriteDone))
+      } // CodecState::AsyncWriter
+      CodecState::Skip => {
+        if buf.is_empty() {
+          return Ok(None); // Need more data
+        }
+
+        // Read as much data as available or requested and write it to our
+        // output.
+        let read_to = cmp::min(self.bin_remain, buf.len());
+        self.consume_bin(buf, read_to);
+
+        self.bin_remain -= read_to;
+
+        if self.bin_remain != 0 {
+          return Ok(None); // Need more data
+        }
+
+        // Revert to the default of expecting a telegram.
+        self.set_state(CodecState::Telegram);
+
+        Ok(Some(Input::SkipDone))
+      } // CodecState::Skip
+    } // match self.state
+  }
+}
+
+
+/// A Decoder implementation that is used to assist in decoding data arriving
+/// over a DDM client interface.
+///
+/// The default behavior for the Decoder is to wait for a Telegram buffer.  It
+/// will, on success, return an `Input::Telegram(tg)`, where `tg` is a
+/// `blather::Telegram` object.
+impl Decoder for Codec {
+  type Item = Input;
+  type Error = crate::err::Error;
+
+  fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Input>, Error> {
+    let lines = !matches!(self.state(), State::Binary { .. });
+    let res = self.decode_state(buf);
+    match res {
+      Ok(Some(ref input)) => {
+        let m = &mut self.metrics;
+        match input {
+          Input::Telegram(_) | Input::Push(_) => m.telegrams += 1,
+          Input::Params(_) | Input::SmallParams(_) => m.params += 1,
+          Input::KVLines(_) => m.kvlines += 1,
+          Input::Chunk(_, 0)
+          | Input::Buf(_)
+          | Input::File(_)
+          | Input::WriteDone
+          | Input::SkipDone => m.bin_transfers += 1,
+          _ => {}
+        }
+      }
+      Ok(None) => {}
+      Err(_) => {
+        self.metrics.decode_errors += 1;
+        if lines && self.auto_resync {
+          self.resync();
+        }
+      }
+    }
+    res
+  }
+
+  /// Called once the connection has been closed.
+  ///
+  /// If the connection was closed in the middle of a binary transfer the
+  /// codec is reset, which handles a partially received file as an
+  /// interrupted transfer, and `Error::TruncatedTransfer` is returned.
+  fn decode_eof(
+    &mut self,
+    buf: &mut BytesMut
+  ) -> Result<Option<Input>, Error> {
+    if let Some(input) = self.decode(buf)? {
+      return Ok(Some(input));
+    }
+    if let State::Binary { remaining } = self.state() {
+      let expected = self.bin_total as u64;
+      self.reset();
+      self.metrics.decode_errors += 1;
+      return Err(Error::TruncatedTransfer {
+        expected,
+        received: expected.saturating_sub(remaining as u64)
+      });
+    }
+    if buf.is_empty() {
+      Ok(None)
+    } else {
+      self.metrics.decode_errors += 1;
+      Err(Error::IO(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "Bytes remaining on stream"
+      )))
+    }
+  }
+}
+
+
+/// Wrap an error returned by an application supplied writer.
+fn local_io(error: io::Error) -> Error {
+  Error::LocalIO { path: None, error }
+}
+
+
+impl Encoder<&Telegram> for Codec {
+  type Error = crate::err::Error;
+
+  fn encode(
+    &mut self,
+    tg: &Telegram,
+    buf: &mut BytesMut
+  ) -> Result<(), Error> {
+    let start = buf.len();
+    tg.encoder_write(buf)?;
+    self.observe_lines(Direction::Outbound, &buf[start..]);
+    Ok(())
+  }
+}
+
+
+impl Encoder<Telegram> for Codec {
+  type Error = crate::err::Error;
+
+  fn encode(&mut self, tg: Telegram, buf: &mut BytesMut) -> Result<(), Error> {
+    self.encode(&tg, buf)
+  }
+}
+
+
+impl Encoder<&Params> for Codec {
+  type Error = crate::err::Error;
+
+  fn encode(
+    &mut self,
+    params: &Params,
+    buf: &mut BytesMut
+  ) -> Result<(), Error> {
+    let start = buf.len();
+    params.encoder_write(buf)?;
+    self.observe_lines(Direction::Outbound, &buf[start..]);
+    Ok(())
+  }
+}
+
+
+impl Encoder<Params> for Codec {
+  type Error = crate::err::Error;
+
+  fn encode(
+    &mut self,
+    params: Params,
+    buf: &mut BytesMut
+  ) -> Result<(), Error> {
+    self.encode(&params, buf)
+  }
+}
+
+
+impl Encoder<&SmallParams> for Codec {
+  type Error = crate::err::Error;
+
+  fn encode(
+    &mut self,
+    params: &SmallParams,
+    buf: &mut BytesMut
+  ) -> Result<(), Error> {
+    buf.reserve(params.calc_buf_size());
+    let start = buf.len();
+    put_small_params(params, buf);
+    self.observe_lines(Direction::Outbound, &buf[start..]);
+    Ok(())
+  }
+}
+
+
+impl Encoder<&SmallTelegram> for Codec {
+  type Error = crate::err::Error;
+
+  fn encode(
+    &mut self,
+    tg: &SmallTelegram,
+    buf: &mut BytesMut
+  ) -> Result<(), Error> {
+    if tg.topic.is_empty() || tg.topic.contains(char::is_whitespace) {
+      return Err(Error::bad_format(
+        "telegram",
+        format!("invalid topic '{}'", tg.topic)
+      ));
+    }
+    buf.reserve(tg.calc_buf_size());
+    let start = buf.len();
+    buf.put(tg.topic.as_bytes());
+    buf.put_u8(b'\n');
+    put_small_params(&tg.params, buf);
+    self.observe_lines(Direction::Outbound, &buf[start..]);
+    Ok(())
+  }
+}
+
+
+fn put_small_params(params: &SmallParams, buf: &mut BytesMut) {
+  for (k, v) in params.iter() {
+    buf.put(k.as_bytes());
+    buf.put_u8(b' ');
+    buf.put(v.as_bytes());
+    buf.put_u8(b'\n');
+  }
+  buf.put_u8(b'\n');
+}
+
+
+impl Encoder<&HashMap<String, String>> for Codec {
+  type Error = crate::err::Error;
+
+  fn encode(
+    &mut self,
+    data: &HashMap<String, String>,
+    buf: &mut BytesMut
+  ) -> Result<(), Error> {
+    // Calculate the amount of space required
+    let mut sz = 0;
+    for (k, v) in data.iter() {
+      // key space + whitespace + value space + eol
+      sz += k.len() + 1 + v.len() + 1;
+    }
+
+    // Terminating empty line
+    sz += 1;
+
+    buf.reserve(sz);
+
+    let start = buf.len();
+    for (k, v) in data.iter() {
+      buf.put(k.as_bytes());
+      buf.put_u8(b' ');
+      buf.put(v.as_bytes());
+      buf.put_u8(b'\n');
+    }
+    buf.put_u8(b'\n');
+    self.observe_lines(Direction::Outbound, &buf[start..]);
+
+    Ok(())
+  }
+}
+
+
+impl Encoder<&KVLines> for Codec {
+  type Error = crate::err::Error;
+
+  fn encode(
+    &mut self,
+    kvlines: &KVLines,
+    buf: &mut BytesMut
+  ) -> Result<(), Error> {
+    let start = buf.len();
+    kvlines.encoder_write(buf)?;
+    self.observe_lines(Direction::Outbound, &buf[start..]);
+    Ok(())
+  }
+}
+
+
+impl Encoder<KVLines> for Codec {
+  type Error = crate::err::Error;
+
+  fn encode(
+    &mut self,
+    kvlines: KVLines,
+    buf: &mut BytesMut
+  ) -> Result<(), Error> {
+    self.encode(&kvlines, buf)
+  }
+}
+
+
+/// Note that the buffer is copied into the codec's write buffer; use
+/// [`send_frame_and_body()`](super::util::send_frame_and_body) to send large
+/// buffers without copying them.
+impl Encoder<Bytes> for Codec {
+  type Error = crate::err::Error;
+
+  fn encode(
+    &mut self,
+    data: Bytes,
+    buf: &mut BytesMut
+  ) -> Result<(), crate::err::Error> {
+    self.observe_binary(Direction::Outbound, &data);
+    buf.reserve(data.len());
+    buf.put(data);
+    Ok(())
+  }
+}
+
+
+impl Encoder<&[u8]> for Codec {
+  type Error = crate::err::Error;
+
+  fn encode(
+    &mut self,
+    data: &[u8],
+    buf: &mut BytesMut
+  ) -> Result<(), crate::err::Error> {
+    self.observe_binary(Direction::Outbound, data);
+    buf.reserve(data.len());
+    buf.put(data);
+    Ok(())
+  }
+}
+
+
+impl Encoder<Vec<u8>> for Codec {
+  type Error = crate::err::Error;
+
+  fn encode(
+    &mut self,
+    data: Vec<u8>,
+    buf: &mut BytesMut
+  ) -> Result<(), crate::err::Error> {
+    self.observe_binary(Direction::Outbound, &data);
+    buf.reserve(data.len());
+    buf.put(data.as_slice());
+    Ok(())
+  }
+}
+
+
+impl Encoder<BytesMut> for Codec {
+  type Error = crate::err::Error;
+
+  fn encode(
+    &mut self,
+    data: BytesMut,
+    buf: &mut BytesMut
+  ) -> Result<(), crate::err::Error> {
+    self.observe_binary(Direction::Outbound, &data);
+    if buf.is_empty() {
+      // Nothing queued; take over the data's buffer rather than copying it.
+      buf.unsplit(data);
+    } else {
+      buf.reserve(data.len());
+      buf.put(data);
+    }
+    Ok(())
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,110 @@
+//! Accounting of data handed over to an asynchronous writer, but not yet
+//! written by it.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::task::AtomicWaker;
+
+
+/// Number of bytes queued for an asynchronous writer.  Shared between the
+/// codec, which adds to it, and the writer, which subtracts from it.
+#[derive(Default)]
+pub(super) struct Backlog {
+  queued: AtomicUsize,
+  done: AtomicBool,
+  waker: AtomicWaker
+}
+
+impl Backlog {
+  pub(super) fn queued(&self) -> usize {
+    self.queued.load(Ordering::Acquire)
+  }
+
+  pub(super) fn add(&self, len: usize) {
+    self.queued.fetch_add(len, Ordering::AcqRel);
+  }
+
+  /// Account for `len` bytes having been written, and wake up anyone waiting
+  /// for the backlog to shrink.
+  pub(super) fn remove(&self, len: usize) {
+    self.queued.fetch_sub(len, Ordering::AcqRel);
+    self.waker.wake();
+  }
+
+  pub(super) fn is_done(&self) -> bool {
+    self.done.load(Ordering::Acquire)
+  }
+
+  /// Mark the writer as having terminated, and wake up anyone waiting for
+  /// it.
+  pub(super) fn set_done(&self) {
+    self.done.store(true, Ordering::Release);
+    self.waker.wake();
+  }
+}
+
+
+/// Future returned by [`Codec::resumed()`](super::Codec::resumed).
+///
+/// Resolves once the backlog of the writer the decoder is feeding has shrunk
+/// to half of the configured limit, or, if the decoder is waiting for the
+/// writer to finish, once it has.
+pub struct Resumed {
+  backlog: Option<Arc<Backlog>>,
+  low: Option<usize>
+}
+
+impl Resumed {
+  /// Wait for the backlog to shrink to `low` bytes.
+  pub(super) fn new(backlog: Option<Arc<Backlog>>, low: usize) -> Self {
+    Resumed {
+      backlog,
+      low: Some(low)
+    }
+  }
+
+  /// Wait for the writer to terminate.
+  pub(super) fn done(backlog: Arc<Backlog>) -> Self {
+    Resumed {
+      backlog: Some(backlog),
+      low: None
+    }
+  }
+
+  fn is_ready(&self, backlog: &Backlog) -> bool {
+    // A writer which has terminated won't reduce its backlog any further.
+    if backlog.is_done() {
+      return true;
+    }
+    match self.low {
+      Some(low) => backlog.queued() <= low,
+      None => false
+    }
+  }
+}
+
+impl Future for Resumed {
+  type Output = ();
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+    let backlog = match self.backlog {
+      Some(ref backlog) => backlog,
+      None => return Poll::Ready(())
+    };
+    if self.is_ready(backlog) {
+      return Poll::Ready(());
+    }
+    backlog.waker.register(cx.waker());
+    // The writer may have caught up before the waker was registered.
+    if self.is_ready(backlog) {
+      return Poll::Ready(());
+    }
+    Poll::Pending
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
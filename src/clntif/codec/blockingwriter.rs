@@ -0,0 +1,104 @@
+//! Background writer for application supplied synchronous writers.
+//!
+//! Like the file writer, this keeps the decoder from waiting for a slow
+//! writer: decoded data is collected into batches which are queued to a
+//! blocking worker that performs the actual writes.
+
+use std::io::{self, Write};
+use std::sync::mpsc::Receiver;
+
+use bytes::{Bytes, BytesMut};
+
+use crate::err::Error;
+
+use super::backlog::{Backlog, Resumed};
+use super::worker::Worker;
+
+/// Number of bytes collected before a batch is handed over to the worker.
+const BATCH_SIZE: usize = 64 * 1024;
+
+/// Maximum number of batches which may be queued for the worker before the
+/// decoder pauses to let it catch up.
+const QUEUE_DEPTH: usize = 16;
+
+
+pub(super) struct BlockingWriter {
+  worker: Worker<Bytes>,
+  batch: BytesMut
+}
+
+impl BlockingWriter {
+  /// Start a worker which writes to `writer`.
+  pub(super) fn new<W>(mut writer: W) -> Result<Self, Error>
+  where
+    W: 'static + Write + Send
+  {
+    let work = move |rx: &Receiver<Bytes>, backlog: &Backlog| {
+      for batch in rx.iter() {
+        writer.write_all(&batch).map_err(local_io)?;
+        backlog.remove(batch.len());
+      }
+      writer.flush().map_err(local_io)?;
+      Ok(())
+    };
+
+    Ok(BlockingWriter {
+      worker: Worker::spawn("ddmw-writer", BATCH_SIZE * QUEUE_DEPTH, work)?,
+      batch: BytesMut::new()
+    })
+  }
+
+  /// Add data to the current batch, handing the batch over to the worker
+  /// once it is large enough.
+  ///
+  /// This never waits for the worker; use [`BlockingWriter::is_paused()`] to
+  /// find out if it has fallen behind.
+  pub(super) fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+    self.batch.extend_from_slice(data);
+    if self.batch.len() >= BATCH_SIZE {
+      self.send_batch()?;
+    }
+    Ok(())
+  }
+
+  /// Hand the last batch over to the worker, and let it flush the writer
+  /// once it has been written.
+  ///
+  /// This doesn't wait for the worker; poll [`BlockingWriter::poll_done()`]
+  /// to find out when it's done.
+  pub(super) fn finish(&mut self) -> Result<(), Error> {
+    if !self.batch.is_empty() {
+      self.send_batch()?;
+    }
+    self.worker.close();
+    Ok(())
+  }
+
+  /// Returns `true` if the worker has fallen behind, or is finishing.
+  pub(super) fn is_paused(&self) -> bool {
+    self.worker.is_paused()
+  }
+
+  /// Get a future which resolves once the worker is no longer paused.
+  pub(super) fn resumed(&self) -> Resumed {
+    self.worker.resumed()
+  }
+
+  /// Get the outcome of the transfer, once the worker has terminated.
+  pub(super) fn poll_done(&mut self) -> Option<Result<(), Error>> {
+    self.worker.poll_done()
+  }
+
+  fn send_batch(&mut self) -> Result<(), Error> {
+    let batch = self.batch.split().freeze();
+    let len = batch.len();
+    self.worker.send(batch, len)
+  }
+}
+
+
+fn local_io(error: io::Error) -> Error {
+  Error::LocalIO { path: None, error }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,132 @@
+//! Builder for configuring a [`Codec`].
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::metrics::Metrics;
+
+use super::{Codec, DuplicateParams, InvalidUtf8, LineLimits, LineMode};
+
+
+/// Builder for a [`Codec`].
+///
+/// All settings can also be changed on an existing codec; the builder allows
+/// a codec to be configured in a single expression:
+///
+/// ```
+/// use tokio_ddmw::clntif::Codec;
+///
+/// let codec = Codec::builder()
+///   .max_line_length(4096)
+///   .max_bin_size(1 << 30)
+///   .strict(true)
+///   .build();
+/// ```
+#[derive(Debug, Default)]
+pub struct CodecBuilder {
+  codec: Codec
+}
+
+impl CodecBuilder {
+  pub fn new() -> Self {
+    CodecBuilder {
+      codec: Codec::new()
+    }
+  }
+
+  /// See [`Codec::set_max_line_length()`].
+  pub fn max_line_length(mut self, max: usize) -> Self {
+    self.codec.set_max_line_length(max);
+    self
+  }
+
+  /// See [`Codec::set_line_limits()`].
+  pub fn line_limits(mut self, mode: LineMode, limits: LineLimits) -> Self {
+    self.codec.set_line_limits(mode, limits);
+    self
+  }
+
+  /// See [`Codec::set_max_bin_size()`].
+  pub fn max_bin_size(mut self, max: usize) -> Self {
+    self.codec.set_max_bin_size(max);
+    self
+  }
+
+  /// See [`Codec::set_max_chunk_size()`].
+  pub fn max_chunk_size(mut self, max: usize) -> Self {
+    self.codec.set_max_chunk_size(max);
+    self
+  }
+
+  /// See [`Codec::set_chunk_pool()`].
+  pub fn chunk_pool(mut self, max_bufs: usize) -> Self {
+    self.codec.set_chunk_pool(max_bufs);
+    self
+  }
+
+  /// See [`Codec::remove_partial_files()`].
+  pub fn remove_partial_files(mut self, remove: bool) -> Self {
+    self.codec.remove_partial_files(remove);
+    self
+  }
+
+  /// See [`Codec::set_strict()`].
+  pub fn strict(mut self, strict: bool) -> Self {
+    self.codec.set_strict(strict);
+    self
+  }
+
+  /// See [`Codec::set_duplicate_params()`].
+  pub fn duplicate_params(mut self, policy: DuplicateParams) -> Self {
+    self.codec.set_duplicate_params(policy);
+    self
+  }
+
+  /// See [`Codec::set_invalid_utf8()`].
+  pub fn invalid_utf8(mut self, mode: InvalidUtf8) -> Self {
+    self.codec.set_invalid_utf8(mode);
+    self
+  }
+
+  /// See [`Codec::set_auto_resync()`].
+  pub fn auto_resync(mut self, enable: bool) -> Self {
+    self.codec.set_auto_resync(enable);
+    self
+  }
+
+  /// See [`Codec::set_async_writer_backlog()`].
+  pub fn async_writer_backlog(mut self, max: usize) -> Self {
+    self.codec.set_async_writer_backlog(max);
+    self
+  }
+
+  /// See [`Codec::set_bin_idle_timeout()`].
+  pub fn bin_idle_timeout(mut self, timeout: Duration) -> Self {
+    self.codec.set_bin_idle_timeout(Some(timeout));
+    self
+  }
+
+  /// See [`Codec::set_bin_timeout()`].
+  pub fn bin_timeout(mut self, timeout: Duration) -> Self {
+    self.codec.set_bin_timeout(Some(timeout));
+    self
+  }
+
+  /// See [`Codec::add_push_topic()`].
+  pub fn push_topic(mut self, topic: &str) -> Self {
+    self.codec.add_push_topic(topic);
+    self
+  }
+
+  /// See [`Codec::set_metrics_sink()`].
+  pub fn metrics_sink(mut self, sink: Arc<dyn Metrics>) -> Self {
+    self.codec.set_metrics_sink(sink);
+    self
+  }
+
+  pub fn build(self) -> Codec {
+    self.codec
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
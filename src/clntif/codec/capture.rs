@@ -0,0 +1,112 @@
+//! Recording of the protocol exchange to a file, for inclusion in support
+//! cases.
+//!
+//! A capture is started using
+//! [`Codec::capture_to()`](super::Codec::capture_to), which installs a
+//! [tap](super::tap) that appends everything passing through the codec to a
+//! file.  Values of parameters carrying credentials are redacted, and binary
+//! data is only recorded as its length and digest.
+//!
+//! # File format
+//! The file is UTF-8 text.  The first line identifies the format and its
+//! version:
+//!
+//! ```text
+//! ddmw-capture 1
+//! ```
+//!
+//! Each following line is a record consisting of four space separated
+//! fields, the last of which extends to the end of the line:
+//!
+//! ```text
+//! <time> <dir> <kind> <data>
+//! ```
+//!
+//! - `time` is the number of seconds since the Unix epoch, with microsecond
+//!   precision, at which the data was observed.
+//! - `dir` is `<` for data received from the peer and `>` for data sent to it.
+//! - `kind` is `L` for a line of a line based entity, in which case `data` is
+//!   the line without its terminator.  Each entity is terminated by a record
+//!   with an empty `data` field.
+//! - `kind` is `B` for a block of binary data, in which case `data` is the
+//!   block's length in bytes followed by its digest, for instance `4
+//!   sha256:<hex>`.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::tap::{Direction, TapData};
+use crate::err::{Error, REDACTED_PARAMS};
+
+
+/// Version of the capture file format.
+pub const FORMAT_VERSION: u32 = 1;
+
+
+/// Writer of capture files.
+pub struct Capture {
+  out: BufWriter<File>
+}
+
+impl Capture {
+  /// Create (or truncate) the capture file `path` and write its header.
+  pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "ddmw-capture {}", FORMAT_VERSION)?;
+    out.flush()?;
+    Ok(Capture { out })
+  }
+
+  /// Append a record of `data` to the capture file.
+  ///
+  /// Write errors are ignored, since a failing capture must not affect the
+  /// connection it is observing.
+  pub fn record(&mut self, dir: Direction, data: &TapData<'_>) {
+    let _ = self.write_record(dir, data);
+  }
+
+  fn write_record(
+    &mut self,
+    dir: Direction,
+    data: &TapData<'_>
+  ) -> std::io::Result<()> {
+    let now = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap_or_default();
+    let time = format!("{}.{:06}", now.as_secs(), now.subsec_micros());
+    let dir = match dir {
+      Direction::Inbound => '<',
+      Direction::Outbound => '>'
+    };
+
+    match data {
+      TapData::Lines(buf) => {
+        let text = String::from_utf8_lossy(buf);
+        let text = text.strip_suffix('\n').unwrap_or(&text);
+        for line in text.split('\n') {
+          let line = line.strip_suffix('\r').unwrap_or(line);
+          writeln!(self.out, "{} {} L {}", time, dir, redact(line))?;
+        }
+      }
+      TapData::Binary { len, digest } => {
+        writeln!(self.out, "{} {} B {} {}", time, dir, len, digest)?;
+      }
+    }
+    self.out.flush()
+  }
+}
+
+
+/// Replace the value of a `key value` line if the key carries credentials.
+fn redact(line: &str) -> String {
+  match line.split_once(' ') {
+    Some((key, _)) if REDACTED_PARAMS.contains(&key) => {
+      format!("{} <redacted>", key)
+    }
+    _ => line.to_string()
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
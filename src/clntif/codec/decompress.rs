@@ -0,0 +1,157 @@
+//! Streaming decompression of received binary data.
+//!
+//! The decompressors are push based: compressed data is fed to them as it is
+//! received and whatever decompressed output is available is returned.
+//!
+//! Output is collected in a buffer which refuses to grow beyond the total
+//! amount of decompressed data the codec accepts, so a small amount of
+//! compressed data can't expand into an unbounded amount of memory before
+//! the limit is checked.
+
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+use std::io::{self, Write};
+
+use bytes::Bytes;
+
+use crate::err::Error;
+
+use super::Compression;
+
+
+pub(super) enum Decompressor {
+  #[cfg(feature = "gzip")]
+  Gzip(flate2::write::GzDecoder<Sink>),
+  #[cfg(feature = "zstd")]
+  Zstd(zstd::stream::write::Decoder<'static, Sink>)
+}
+
+impl Decompressor {
+  /// Create a decompressor for `c` which fails once more than `limit` bytes
+  /// of decompressed data have been produced.
+  ///
+  /// Fails if support for `c` hasn't been enabled.
+  #[allow(unused_variables)]
+  pub(super) fn new(c: Compression, limit: u64) -> Result<Self, Error> {
+    match c {
+      #[cfg(feature = "gzip")]
+      Compression::Gzip => Ok(Decompressor::Gzip(
+        flate2::write::GzDecoder::new(Sink::new(limit))
+      )),
+      #[cfg(feature = "zstd")]
+      Compression::Zstd => Ok(Decompressor::Zstd(
+        zstd::stream::write::Decoder::new(Sink::new(limit))?
+      )),
+      #[allow(unreachable_patterns)]
+      c => Err(Error::UnknownData {
+        what: "decompression".to_string(),
+        value: format!("{:?}", c)
+      })
+    }
+  }
+
+  /// Feed compressed data to the decompressor and return the decompressed
+  /// data which is available.
+  ///
+  /// The decompressor can't be used after it has failed.
+  #[allow(unused_variables)]
+  pub(super) fn feed(&mut self, data: &[u8]) -> Result<Bytes, Error> {
+    match *self {
+      #[cfg(feature = "gzip")]
+      Decompressor::Gzip(ref mut d) => {
+        if let Err(e) = d.write_all(data) {
+          return Err(d.get_ref().error(e));
+        }
+        Ok(d.get_mut().take())
+      }
+      #[cfg(feature = "zstd")]
+      Decompressor::Zstd(ref mut d) => {
+        if let Err(e) = d.write_all(data).and_then(|_| d.flush()) {
+          return Err(d.get_ref().error(e));
+        }
+        Ok(d.get_mut().take())
+      }
+    }
+  }
+
+  /// Signal that all compressed data has been fed to the decompressor and
+  /// return any remaining decompressed data.
+  pub(super) fn finish(self) -> Result<Bytes, Error> {
+    match self {
+      #[cfg(feature = "gzip")]
+      Decompressor::Gzip(mut d) => {
+        if let Err(e) = d.try_finish() {
+          return Err(d.get_ref().error(e));
+        }
+        Ok(d.get_mut().take())
+      }
+      #[cfg(feature = "zstd")]
+      Decompressor::Zstd(mut d) => {
+        if let Err(e) = d.flush() {
+          return Err(d.get_ref().error(e));
+        }
+        Ok(d.get_mut().take())
+      }
+    }
+  }
+}
+
+
+/// Output buffer of a decompressor, which fails writes once the total amount
+/// of data written to it would exceed its limit.
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+pub(super) struct Sink {
+  buf: Vec<u8>,
+  total: u64,
+  limit: u64,
+  exceeded: bool
+}
+
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+impl Sink {
+  fn new(limit: u64) -> Self {
+    Sink {
+      buf: Vec::new(),
+      total: 0,
+      limit,
+      exceeded: false
+    }
+  }
+
+  /// Take the data written since the last call.
+  fn take(&mut self) -> Bytes {
+    Bytes::from(std::mem::take(&mut self.buf))
+  }
+
+  /// Translate an error returned by the decompressor, which may have been
+  /// caused by the limit being exceeded.
+  fn error(&self, e: io::Error) -> Error {
+    if self.exceeded {
+      return Error::TooLarge {
+        what: "Decompressed data".to_string(),
+        size: None,
+        limit: self.limit
+      };
+    }
+    Error::IO(e)
+  }
+}
+
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+impl Write for Sink {
+  fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+    let total = self.total.saturating_add(data.len() as u64);
+    if total > self.limit {
+      self.exceeded = true;
+      return Err(io::Error::other("Decompressed data exceeds limit"));
+    }
+    self.total = total;
+    self.buf.extend_from_slice(data);
+    Ok(data.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,307 @@
+//! Background file writer used by the decoder.
+//!
+//! The decoder runs inside the runtime's reactor and must not wait for the
+//! disk.  Received chunks are instead queued to a blocking worker which
+//! performs the actual writes, and which also syncs the file and moves it
+//! into place once the transfer is complete.
+//!
+//! Data is written to a temporary `<name>.part` file, which is synced to disk
+//! and atomically renamed to the requested pathname once all of it has been
+//! received.  A file at the requested pathname is therefore always complete.
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+
+use bytes::Bytes;
+
+use crate::checksum::{Checksum, Hasher};
+use crate::err::Error;
+
+use super::backlog::{Backlog, Resumed};
+use super::worker::Worker;
+use super::FileOpts;
+
+/// Maximum number of bytes which may be queued for the writer before the
+/// decoder pauses to let it catch up.
+const MAX_QUEUED: usize = 4 * 1024 * 1024;
+
+
+enum Cmd {
+  Data(Bytes),
+  Finish
+}
+
+
+pub(super) struct FileWriter {
+  worker: Worker<Cmd>
+}
+
+impl FileWriter {
+  /// Create (or truncate) the temporary file for `pathname` and start a
+  /// writer thread for it.
+  ///
+  /// If `remove_partial` is set the temporary file is removed if the writer
+  /// is dropped before the transfer is finished.
+  ///
+  /// If an expected checksum is passed in `verify` the data is hashed as it
+  /// is written.  If the final checksum doesn't match the expected one the
+  /// temporary file is removed and the transfer fails.
+  pub(super) fn create(
+    pathname: &Path,
+    opts: &FileOpts,
+    remove_partial: bool,
+    verify: Option<Checksum>
+  ) -> Result<Self, Error> {
+    if !opts.overwrite && pathname.exists() {
+      return Err(already_exists(pathname));
+    }
+    if opts.create_dirs {
+      if let Some(parent) = pathname.parent() {
+        fs::create_dir_all(parent).map_err(|e| Error::local_io(parent, e))?;
+      }
+    }
+
+    let partname = part_path(pathname);
+    let local = |e| Error::local_io(&partname, e);
+
+    let mut oo = OpenOptions::new();
+    oo.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if let Some(mode) = opts.mode {
+      use std::os::unix::fs::OpenOptionsExt;
+      oo.mode(mode);
+    }
+    let f = oo.open(&partname).map_err(local)?;
+
+    // Apply the permissions and ownership before any data is written.  The
+    // mode is set explicitly since the one passed when creating the file is
+    // subject to the process' umask.
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      if let Some(mode) = opts.mode {
+        f.set_permissions(fs::Permissions::from_mode(mode))
+          .map_err(local)?;
+      }
+      if opts.uid.is_some() || opts.gid.is_some() {
+        std::os::unix::fs::fchown(&f, opts.uid, opts.gid).map_err(local)?;
+      }
+    }
+
+    Self::start(pathname, f, opts.overwrite, remove_partial, verify)
+  }
+
+  /// Open the temporary file of a previously interrupted transfer to
+  /// `pathname` and start a writer thread which continues writing at
+  /// `offset`.
+  ///
+  /// Anything beyond `offset` in the temporary file is discarded.  If
+  /// `offset` is zero the temporary file is created if it doesn't exist.
+  pub(super) fn resume(
+    pathname: &Path,
+    offset: u64,
+    remove_partial: bool
+  ) -> Result<Self, Error> {
+    let partname = part_path(pathname);
+    let local = |e| Error::local_io(&partname, e);
+    let mut f = OpenOptions::new()
+      .write(true)
+      .create(offset == 0)
+      .truncate(false)
+      .open(&partname)
+      .map_err(local)?;
+    let len = f.metadata().map_err(local)?.len();
+    if len < offset {
+      return Err(Error::InvalidSize {
+        what: "partial file".to_string(),
+        expected: Some(offset),
+        actual: len
+      });
+    }
+    f.set_len(offset).map_err(local)?;
+    f.seek(SeekFrom::Start(offset)).map_err(local)?;
+    Self::start(pathname, f, true, remove_partial, None)
+  }
+
+  fn start(
+    pathname: &Path,
+    f: File,
+    overwrite: bool,
+    remove_partial: bool,
+    verify: Option<Checksum>
+  ) -> Result<Self, Error> {
+    let target = Target {
+      partname: part_path(pathname),
+      pathname: pathname.to_path_buf(),
+      overwrite,
+      remove_partial,
+      verify
+    };
+    let work =
+      move |rx: &Receiver<Cmd>, backlog: &Backlog| target.run(rx, backlog, f);
+
+    Ok(FileWriter {
+      worker: Worker::spawn("ddmw-filewriter", MAX_QUEUED, work)?
+    })
+  }
+
+  /// Queue a chunk of data to be written.
+  ///
+  /// This never waits for the writer; use [`FileWriter::is_paused()`] to
+  /// find out if it has fallen behind.
+  pub(super) fn write(&mut self, chunk: Bytes) -> Result<(), Error> {
+    let len = chunk.len();
+    self.worker.send(Cmd::Data(chunk), len)
+  }
+
+  /// Ask the writer to sync the file to disk and move it into place once
+  /// all queued data has been written.
+  ///
+  /// This doesn't wait for the writer; poll [`FileWriter::poll_done()`] to
+  /// find out when it's done.
+  pub(super) fn finish(&mut self) -> Result<(), Error> {
+    self.worker.send(Cmd::Finish, 0)?;
+    self.worker.close();
+    Ok(())
+  }
+
+  /// Returns `true` if the writer has fallen behind, or is finishing.
+  pub(super) fn is_paused(&self) -> bool {
+    self.worker.is_paused()
+  }
+
+  /// Get a future which resolves once the writer is no longer paused.
+  pub(super) fn resumed(&self) -> Resumed {
+    self.worker.resumed()
+  }
+
+  /// Get the outcome of the transfer, once the writer has terminated.
+  pub(super) fn poll_done(&mut self) -> Option<Result<(), Error>> {
+    self.worker.poll_done()
+  }
+}
+
+
+/// Where, and how, the writer puts the received file.
+struct Target {
+  pathname: PathBuf,
+  partname: PathBuf,
+  overwrite: bool,
+  remove_partial: bool,
+  verify: Option<Checksum>
+}
+
+impl Target {
+  fn run(
+    self,
+    rx: &Receiver<Cmd>,
+    backlog: &Backlog,
+    mut f: File
+  ) -> Result<(), Error> {
+    let mut hasher = self.verify.as_ref().map(|c| c.hasher());
+    let res = loop {
+      match rx.recv() {
+        Ok(Cmd::Data(chunk)) => {
+          if let Some(ref mut h) = hasher {
+            h.update(&chunk);
+          }
+          if let Err(e) = f.write_all(&chunk) {
+            break Err(Error::local_io(&self.partname, e));
+          }
+          backlog.remove(chunk.len());
+        }
+        Ok(Cmd::Finish) => {
+          let res = self.finish(f, hasher);
+          if res.is_err() {
+            // The original error is more useful than a failure to clean up.
+            let _ = self.remove_partial();
+          }
+          return res;
+        }
+        // The writer was dropped before the transfer was finished.
+        Err(_) => break Ok(())
+      }
+    };
+
+    // The transfer was interrupted, or a write failed.
+    drop(f);
+    let removed = self.remove_partial();
+    res.and(removed)
+  }
+
+  /// Sync the file to disk and move it into place.
+  fn finish(&self, mut f: File, hasher: Option<Hasher>) -> Result<(), Error> {
+    let part = |e| Error::local_io(&self.partname, e);
+    let target = |e| Error::local_io(&self.pathname, e);
+    f.flush().map_err(part)?;
+    f.sync_all().map_err(part)?;
+    drop(f);
+    if let (Some(ref expected), Some(h)) = (&self.verify, hasher) {
+      if let Err(e) = expected.verify(&h.finish()) {
+        fs::remove_file(&self.partname).map_err(part)?;
+        return Err(e);
+      }
+    }
+    if self.overwrite {
+      fs::rename(&self.partname, &self.pathname).map_err(target)?;
+      return Ok(());
+    }
+    // Linking fails if the target exists, which makes the existence check
+    // and the move a single atomic operation.
+    match fs::hard_link(&self.partname, &self.pathname) {
+      Ok(()) => {
+        fs::remove_file(&self.partname).map_err(part)?;
+        Ok(())
+      }
+      Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(target(e)),
+      // Some filesystems, like FAT, don't support hard links at all.  Fall
+      // back to checking for the target before renaming the file, which
+      // leaves a small window during which the target may appear.
+      Err(_) => {
+        if fs::symlink_metadata(&self.pathname).is_ok() {
+          return Err(already_exists(&self.pathname));
+        }
+        fs::rename(&self.partname, &self.pathname).map_err(target)?;
+        Ok(())
+      }
+    }
+  }
+
+  /// Remove the temporary file, if the codec has been told to remove partial
+  /// files.
+  fn remove_partial(&self) -> Result<(), Error> {
+    if !self.remove_partial {
+      return Ok(());
+    }
+    match fs::remove_file(&self.partname) {
+      Ok(()) => Ok(()),
+      // A failed checksum verification has already removed it.
+      Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+      Err(e) => Err(Error::local_io(&self.partname, e))
+    }
+  }
+}
+
+
+fn already_exists(pathname: &Path) -> Error {
+  Error::local_io(
+    pathname,
+    io::Error::new(
+      io::ErrorKind::AlreadyExists,
+      format!("{} already exists", pathname.display())
+    )
+  )
+}
+
+
+/// Get the pathname of the temporary file used while receiving `pathname`.
+pub(super) fn part_path(pathname: &Path) -> PathBuf {
+  let mut s = OsString::from(pathname.as_os_str());
+  s.push(".part");
+  PathBuf::from(s)
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,43 @@
+//! Observation of the raw data passing through the codec.
+//!
+//! A tap is installed using [`Codec::set_tap()`](super::Codec::set_tap) and
+//! is intended for protocol debugging.  It is called synchronously from the
+//! encoder and decoder, so it should be cheap.
+
+use crate::checksum::Checksum;
+
+
+/// Direction of the data passed to a tap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+  Inbound,
+  Outbound
+}
+
+
+/// Data passed to a tap.
+#[derive(Debug)]
+pub enum TapData<'a> {
+  /// A complete line based entity, including its line terminators and the
+  /// terminating empty line.
+  Lines(&'a [u8]),
+
+  /// A block of binary data, summarized by its length and digest rather
+  /// than passed as-is.
+  Binary { len: usize, digest: Checksum }
+}
+
+impl<'a> TapData<'a> {
+  pub(super) fn binary(data: &[u8]) -> Self {
+    TapData::Binary {
+      len: data.len(),
+      digest: Checksum::sha256(data)
+    }
+  }
+}
+
+
+/// Callback which observes data passing through a codec.
+pub type Tap = Box<dyn FnMut(Direction, &TapData<'_>) + Send + Sync>;
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,176 @@
+//! Blocking workers which perform the writes of the decoder's file and
+//! writer targets.
+//!
+//! Work is queued to the worker without waiting, and the amount of queued
+//! data is tracked in a [`Backlog`].  The decoder pauses, rather than block,
+//! once a worker has fallen too far behind, and waits for the worker to
+//! terminate the same way before reporting a transfer as complete.
+
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+use crate::err::Error;
+
+use super::backlog::{Backlog, Resumed};
+
+
+type Outcome = Arc<Mutex<Option<Result<(), Error>>>>;
+
+
+pub(super) struct Worker<T> {
+  tx: Option<Sender<T>>,
+  backlog: Arc<Backlog>,
+  outcome: Outcome,
+  limit: usize
+}
+
+impl<T: Send + 'static> Worker<T> {
+  /// Run `work` on the runtime's blocking thread pool, or on a dedicated
+  /// thread if the `rt` feature is disabled or there's no runtime to run it
+  /// on.
+  ///
+  /// `work` receives the queued items, and must remove each item's length
+  /// from the backlog once it has been written.  The worker is considered
+  /// to have fallen behind once more than `limit` bytes are queued.
+  pub(super) fn spawn<F>(
+    name: &'static str,
+    limit: usize,
+    work: F
+  ) -> Result<Self, Error>
+  where
+    F: FnOnce(&Receiver<T>, &Backlog) -> Result<(), Error> + Send + 'static
+  {
+    let (tx, rx) = channel::<T>();
+    let backlog = Arc::new(Backlog::default());
+    let outcome: Outcome = Arc::new(Mutex::new(None));
+
+    let job = {
+      let backlog = Arc::clone(&backlog);
+      let outcome = Arc::clone(&outcome);
+      move || {
+        let res = match panic::catch_unwind(AssertUnwindSafe(|| {
+          work(&rx, &backlog)
+        })) {
+          Ok(res) => res,
+          Err(_) => Err(Error::LocalIO {
+            path: None,
+            error: io::Error::other("Writer panicked")
+          })
+        };
+        *lock(&outcome) = Some(res);
+        backlog.set_done();
+        // The receiver is dropped only after the outcome has been stored, so
+        // a failed send always finds it.
+        drop(rx);
+      }
+    };
+    run_blocking(name, job)?;
+
+    Ok(Worker {
+      tx: Some(tx),
+      backlog,
+      outcome,
+      limit
+    })
+  }
+
+  /// Queue `item`, which accounts for `len` bytes of the backlog.
+  ///
+  /// Fails if the worker has terminated, which it only does early if a write
+  /// failed.
+  pub(super) fn send(&mut self, item: T, len: usize) -> Result<(), Error> {
+    if self.backlog.is_done() {
+      return Err(self.failure());
+    }
+    let res = match self.tx {
+      Some(ref tx) => tx.send(item),
+      None => {
+        return Err(Error::BadState {
+          expected: "an open writer".to_string(),
+          found: Some("a closed writer".to_string())
+        })
+      }
+    };
+    if res.is_err() {
+      return Err(self.failure());
+    }
+    self.backlog.add(len);
+    Ok(())
+  }
+
+  /// Close the queue, letting the worker know that no more items will
+  /// arrive.
+  pub(super) fn close(&mut self) {
+    self.tx = None;
+  }
+
+  /// Returns `true` if the decoder should stop handing over data until the
+  /// worker has caught up, or, once the queue has been closed, until it has
+  /// finished.
+  pub(super) fn is_paused(&self) -> bool {
+    if self.tx.is_none() {
+      return !self.backlog.is_done();
+    }
+    self.backlog.queued() > self.limit
+  }
+
+  /// Get a future which resolves once the worker is no longer paused.
+  pub(super) fn resumed(&self) -> Resumed {
+    if self.tx.is_none() {
+      return Resumed::done(Arc::clone(&self.backlog));
+    }
+    Resumed::new(Some(Arc::clone(&self.backlog)), self.limit / 2)
+  }
+
+  /// Get the worker's outcome, if it has terminated.
+  pub(super) fn poll_done(&mut self) -> Option<Result<(), Error>> {
+    if !self.backlog.is_done() {
+      return None;
+    }
+    match lock(&self.outcome).take() {
+      Some(res) => Some(res),
+      None => Some(Err(Error::BadState {
+        expected: "an open writer".to_string(),
+        found: Some("a closed writer".to_string())
+      }))
+    }
+  }
+
+  fn failure(&mut self) -> Error {
+    self.tx = None;
+    match self.poll_done() {
+      Some(Err(e)) => e,
+      _ => Error::BadState {
+        expected: "a running writer".to_string(),
+        found: Some("a terminated writer".to_string())
+      }
+    }
+  }
+}
+
+
+fn run_blocking<F>(name: &'static str, job: F) -> Result<(), Error>
+where
+  F: FnOnce() + Send + 'static
+{
+  #[cfg(feature = "rt")]
+  if tokio::runtime::Handle::try_current().is_ok() {
+    crate::task::spawn_blocking(name, job)?;
+    return Ok(());
+  }
+  thread::Builder::new().name(name.to_string()).spawn(job)?;
+  Ok(())
+}
+
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+  match m.lock() {
+    Ok(guard) => guard,
+    Err(poisoned) => poisoned.into_inner()
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
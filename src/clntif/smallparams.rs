@@ -0,0 +1,237 @@
+//! Low-allocation parameter containers.
+//!
+//! [`blather::Params`] stores each parameter as a pair of owned strings in a
+//! hash map, which means at least two allocations per parameter and another
+//! for the map itself.  That is fine for most exchanges, but is noticeable on
+//! hot paths such as per-chunk progress telegrams.
+//!
+//! [`SmallParams`] stores up to four parameters inline, accepts `'static`
+//! keys without copying them and keeps all values in a single shared string
+//! buffer.  A container which is cleared and reused therefore doesn't
+//! allocate at all once its buffer has grown large enough.
+
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::fmt::{self, Write};
+use std::ops::Range;
+use std::str::FromStr;
+
+use smallvec::SmallVec;
+
+use blather::{Params, Telegram};
+
+use crate::err::Error;
+
+/// Number of parameters stored without allocating.
+const INLINE_PARAMS: usize = 4;
+
+
+/// A parameter container optimized for few parameters with static keys.
+#[derive(Clone, Debug, Default)]
+pub struct SmallParams {
+  entries: SmallVec<[(Cow<'static, str>, Range<u32>); INLINE_PARAMS]>,
+  values: String
+}
+
+impl SmallParams {
+  pub fn new() -> Self {
+    SmallParams::default()
+  }
+
+  /// Create a container whose value buffer can hold `values_len` bytes
+  /// without reallocating.
+  pub fn with_capacity(values_len: usize) -> Self {
+    SmallParams {
+      entries: SmallVec::new(),
+      values: String::with_capacity(values_len)
+    }
+  }
+
+  /// Remove all parameters, but keep the allocated buffers for reuse.
+  pub fn clear(&mut self) {
+    self.entries.clear();
+    self.values.clear();
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+
+  /// Add a parameter.  The value is formatted directly into the shared
+  /// value buffer.
+  ///
+  /// Keys and values must not contain whitespace or newlines respectively,
+  /// since they couldn't be represented on the wire.  If a key is added more
+  /// than once, the last value is the one returned by the accessors.
+  pub fn add<K, V>(&mut self, key: K, value: V) -> Result<(), Error>
+  where
+    K: Into<Cow<'static, str>>,
+    V: fmt::Display
+  {
+    let key = key.into();
+    if key.is_empty() || key.contains(char::is_whitespace) {
+      return Err(Error::bad_format(
+        "parameter",
+        format!("invalid key '{}'", key)
+      ));
+    }
+    let start = self.values.len();
+    if write!(self.values, "{}", value).is_err() {
+      self.values.truncate(start);
+      return Err(Error::SerializeError {
+        what: format!("value of '{}'", key),
+        reason: "formatting failed".to_string()
+      });
+    }
+    if self.values[start..].contains(['\r', '\n']) {
+      self.values.truncate(start);
+      return Err(Error::bad_format(
+        format!("value of '{}'", key),
+        "contains a newline"
+      ));
+    }
+    self.push_entry(key, start)
+  }
+
+  /// Record an entry whose value was appended to the value buffer at
+  /// `start`.
+  fn push_entry(
+    &mut self,
+    key: Cow<'static, str>,
+    start: usize
+  ) -> Result<(), Error> {
+    let end = self.values.len();
+    if end > u32::MAX as usize {
+      self.values.truncate(start);
+      return Err(Error::TooLarge {
+        what: "Parameter values".to_string(),
+        size: Some(end as u64),
+        limit: u32::MAX as u64
+      });
+    }
+    self.entries.push((key, start as u32..end as u32));
+    Ok(())
+  }
+
+  fn value(&self, r: &Range<u32>) -> &str {
+    &self.values[r.start as usize..r.end as usize]
+  }
+
+  /// Get a parameter's value as a string.
+  pub fn get_str(&self, key: &str) -> Option<&str> {
+    self
+      .entries
+      .iter()
+      .rev()
+      .find(|(k, _)| k == key)
+      .map(|(_, r)| self.value(r))
+  }
+
+  /// Get a parameter's value parsed into a type.
+  pub fn get_param<T: FromStr>(&self, key: &str) -> Result<T, Error> {
+    match self.get_str(key) {
+      Some(v) => match v.parse::<T>() {
+        Ok(v) => Ok(v),
+        Err(_) => Err(Error::bad_format(
+          format!("value of '{}'", key),
+          "unable to parse"
+        ))
+      },
+      None => Err(Error::missing(key))
+    }
+  }
+
+  /// Iterate over the parameters in the order they were added.
+  pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+    self
+      .entries
+      .iter()
+      .map(move |(k, r)| (k.as_ref(), self.value(r)))
+  }
+
+  /// Number of bytes required to serialize the parameters, including the
+  /// terminating empty line.
+  pub fn calc_buf_size(&self) -> usize {
+    // key + space + eol per parameter, plus all values
+    let keys: usize = self.entries.iter().map(|(k, _)| k.len() + 2).sum();
+    keys + self.values.len() + 1
+  }
+
+  /// Add a parameter line received from the wire.
+  pub(crate) fn add_line(
+    &mut self,
+    key: &str,
+    value: &str
+  ) -> Result<(), Error> {
+    let start = self.values.len();
+    self.values.push_str(value);
+    self.push_entry(Cow::Owned(key.to_string()), start)
+  }
+
+  /// Convert into a regular `Params` buffer.
+  pub fn to_params(&self) -> Result<Params, Error> {
+    let mut params = Params::new();
+    for (k, v) in self.iter() {
+      params.add_str(k, v)?;
+    }
+    Ok(params)
+  }
+}
+
+impl TryFrom<&Params> for SmallParams {
+  type Error = Error;
+
+  fn try_from(params: &Params) -> Result<Self, Self::Error> {
+    let mut sp = SmallParams::new();
+    for (k, v) in params.get_inner().iter() {
+      sp.add_line(k, v)?;
+    }
+    Ok(sp)
+  }
+}
+
+
+/// A telegram whose parameters are stored in a [`SmallParams`] container.
+#[derive(Clone, Debug, Default)]
+pub struct SmallTelegram {
+  pub topic: Cow<'static, str>,
+  pub params: SmallParams
+}
+
+impl SmallTelegram {
+  pub fn new<T: Into<Cow<'static, str>>>(topic: T) -> Self {
+    SmallTelegram {
+      topic: topic.into(),
+      params: SmallParams::new()
+    }
+  }
+
+  /// Add a parameter.  See [`SmallParams::add()`].
+  pub fn add<K, V>(&mut self, key: K, value: V) -> Result<(), Error>
+  where
+    K: Into<Cow<'static, str>>,
+    V: fmt::Display
+  {
+    self.params.add(key, value)
+  }
+
+  /// Number of bytes required to serialize the telegram.
+  pub fn calc_buf_size(&self) -> usize {
+    self.topic.len() + 1 + self.params.calc_buf_size()
+  }
+
+  /// Convert into a regular `Telegram`.
+  pub fn to_telegram(&self) -> Result<Telegram, Error> {
+    let mut tg = Telegram::new_topic(&self.topic)?;
+    for (k, v) in self.params.iter() {
+      tg.add_str(k, v)?;
+    }
+    Ok(tg)
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,307 @@
+//! Request/reply helpers for connections using the client interface
+//! [`Codec`](super::Codec).
+
+use std::time::Instant;
+
+use futures::sink::SinkExt;
+
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use tokio_stream::StreamExt;
+
+use tokio_util::codec::{Framed, FramedParts};
+
+use bytes::{Buf, BufMut, BytesMut};
+
+use blather::{Params, Telegram};
+
+use super::{BinCodec, BinFramed, ClntIfFramed, Input, State};
+
+use crate::auth::{AuthInfo, AuthPass, AuthToken, Token};
+use crate::err::Error;
+
+
+/// Credentials used to authenticate a client interface connection.
+///
+/// Superseded by [`AuthInfo`], which is used by the rest of the crate and
+/// converts from this type.
+#[deprecated(
+  since = "0.9.5",
+  note = "use auth::AuthInfo with auth::authenticate_clntif() instead"
+)]
+#[derive(Clone)]
+pub enum Auth {
+  /// Authenticate using an account name and a passphrase.
+  AccPass(String, String),
+
+  /// Authenticate using an authentication token.
+  Token(String)
+}
+
+
+#[allow(deprecated)]
+impl From<&Auth> for AuthInfo {
+  fn from(auth: &Auth) -> AuthInfo {
+    match auth {
+      Auth::AccPass(accname, pass) => {
+        AuthInfo::from_accpass(accname.clone(), pass.clone())
+      }
+      Auth::Token(tkn) => AuthInfo {
+        accpass: None,
+        itkn: Some(Token::Buf(tkn.clone())),
+        otkn: None
+      }
+    }
+  }
+}
+
+#[allow(deprecated)]
+impl From<Auth> for AuthInfo {
+  fn from(auth: Auth) -> AuthInfo {
+    AuthInfo::from(&auth)
+  }
+}
+
+
+/// Result of a protocol negotiation.
+///
+/// The variants differ in size since each holds its codec's buffers inline;
+/// a negotiation result is short-lived, so this isn't worth boxing.
+#[allow(clippy::large_enum_variant)]
+pub enum Negotiated<T> {
+  /// The server doesn't support protocol v2; the connection remains line
+  /// based.
+  V1(ClntIfFramed<T>),
+
+  /// The connection has been switched over to protocol v2.
+  V2(BinFramed<T>)
+}
+
+
+/// Receive the next input from the connection, enforcing the codec's
+/// binary transfer timeouts.
+///
+/// If a binary transfer times out the codec is [reset](super::Codec::reset)
+/// and `Error::TransferTimeout` is returned.  The connection should
+/// generally be closed at this point, since the peer may yet send the rest
+/// of the transfer.
+pub async fn next_input<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut ClntIfFramed<T>
+) -> Option<Result<Input, Error>> {
+  loop {
+    let deadline = match conn.codec().receive_deadline() {
+      Some(deadline) => deadline,
+      None => return conn.next().await
+    };
+    let deadline = tokio::time::Instant::from_std(deadline);
+    if let Ok(res) = tokio::time::timeout_at(deadline, conn.next()).await {
+      return res;
+    }
+
+    // Data may have arrived in the meantime, which moves the deadline.
+    match conn.codec().receive_deadline() {
+      Some(deadline) if deadline > Instant::now() => continue,
+      _ => {}
+    }
+    let remain = match conn.codec().state() {
+      State::Binary { remaining } => remaining,
+      _ => 0
+    };
+    conn.codec_mut().reset();
+    return Some(Err(Error::TransferTimeout {
+      remaining: remain as u64
+    }));
+  }
+}
+
+
+/// Send a telegram and wait for a reply.
+///
+/// Errors are wrapped in an [`Error::Command`] identifying the request.
+/// If [request identifiers](crate::reqid) are enabled the request is stamped
+/// with one before it is sent.
+pub async fn sendrecv<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut ClntIfFramed<T>,
+  tg: &Telegram
+) -> Result<Params, Error> {
+  let tg = crate::reqid::stamp(tg);
+  let tg = tg.as_ref();
+  crate::instrument::request(tg, false, async {
+    conn.send(tg).await.map_err(|e| e.with_command(tg))?;
+    expect_okfail(conn).await.map_err(|e| e.with_command(tg))
+  })
+  .await
+}
+
+
+/// Waits for a message and ensures that it's Ok or Fail.
+/// Converts Fail state to an error using [`Error::from_fail()`].
+/// Returns a Params buffer containig the Ok parameters on success.
+pub async fn expect_okfail<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut ClntIfFramed<T>
+) -> Result<Params, Error> {
+  match conn.next().await {
+    Some(Ok(Input::Telegram(tg))) => crate::okfail(tg),
+    Some(Ok(_)) => Err(crate::unexpected_reply()),
+    Some(Err(e)) => Err(e),
+    None => Err(Error::Disconnected)
+  }
+}
+
+
+/// Send a telegram followed by a binary body.
+///
+/// Sending the body through the `Framed` sink would copy all of it into the
+/// codec's write buffer first.  Instead the telegram is serialized on its
+/// own, and the serialized telegram and the body are written directly to the
+/// underlying stream, using vectored writes where the stream supports them.
+///
+/// The body can be any [`Buf`], such as `Bytes`, a slice, or several buffers
+/// chained together.
+pub async fn send_frame_and_body<T, B>(
+  conn: &mut ClntIfFramed<T>,
+  tg: &Telegram,
+  body: B
+) -> Result<(), Error>
+where
+  T: AsyncRead + AsyncWrite + Unpin,
+  B: Buf
+{
+  let mut hdr = BytesMut::with_capacity(tg.calc_buf_size());
+  tg.encoder_write(&mut hdr)?;
+
+  // Anything already queued in the Framed must go out first.
+  SinkExt::<&Telegram>::flush(conn).await?;
+
+  let stream = conn.get_mut();
+  let mut data = hdr.chain(body);
+  stream.write_all_buf(&mut data).await?;
+  stream.flush().await?;
+
+  Ok(())
+}
+
+
+/// Number of bytes of parameter lines [`send_params_batched()`] collects
+/// before writing them to the connection.
+const PARAMS_BATCH_SIZE: usize = 64 * 1024;
+
+
+/// Send a parameters block without serializing all of it up front.
+///
+/// Encoding a `Params` through the `Framed` sink requires the entire block
+/// to be serialized into the codec's write buffer.  For blocks with a very
+/// large number of entries, such as file manifests, this function instead
+/// serializes the entries in batches of about `PARAMS_BATCH_SIZE` bytes and
+/// writes each batch to the underlying stream before serializing the next,
+/// keeping peak memory use bounded.  Since `params` can be any iterator of
+/// pairs, the entries don't need to be collected in memory at all.
+///
+/// Keys must be non-empty and must not contain whitespace, and values must
+/// not contain line breaks.  The block is sent up to the offending entry if
+/// one doesn't, so the connection should be considered broken on error.
+pub async fn send_params_batched<T, I, K, V>(
+  conn: &mut ClntIfFramed<T>,
+  params: I
+) -> Result<(), Error>
+where
+  T: AsyncRead + AsyncWrite + Unpin,
+  I: IntoIterator<Item = (K, V)>,
+  K: AsRef<str>,
+  V: AsRef<str>
+{
+  // Anything already queued in the Framed must go out first.
+  SinkExt::<&Telegram>::flush(conn).await?;
+
+  let stream = conn.get_mut();
+  let mut batch = BytesMut::with_capacity(PARAMS_BATCH_SIZE);
+  for (k, v) in params {
+    let (k, v) = (k.as_ref(), v.as_ref());
+    if k.is_empty() || k.contains(char::is_whitespace) {
+      return Err(Error::bad_format(
+        "parameter",
+        format!("invalid key '{}'", k)
+      ));
+    }
+    if v.contains(['\n', '\r']) {
+      return Err(Error::bad_format(
+        format!("value of '{}'", k),
+        "contains a line break"
+      ));
+    }
+    batch.put(k.as_bytes());
+    batch.put_u8(b' ');
+    batch.put(v.as_bytes());
+    batch.put_u8(b'\n');
+    if batch.len() >= PARAMS_BATCH_SIZE {
+      stream.write_all_buf(&mut batch).await?;
+      batch.clear();
+    }
+  }
+  batch.put_u8(b'\n');
+  stream.write_all_buf(&mut batch).await?;
+  stream.flush().await?;
+
+  Ok(())
+}
+
+
+/// Authenticate a connection.
+///
+/// When authenticating using an account name and passphrase an
+/// authentication token can be requested by setting `reqtkn`.  The token, if
+/// one was returned, is returned to the caller.
+#[deprecated(
+  since = "0.9.5",
+  note = "use auth::authenticate_clntif() with an auth::AuthInfo instead"
+)]
+#[allow(deprecated)]
+pub async fn authenticate<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut ClntIfFramed<T>,
+  auth: &Auth,
+  reqtkn: bool
+) -> Result<Option<String>, Error> {
+  use crate::auth::Requester;
+
+  match auth {
+    Auth::AccPass(accname, pass) => {
+      let cmd = AuthPass {
+        accname: accname.clone(),
+        pass: pass.clone(),
+        reqtkn
+      };
+      conn.call(&cmd).await
+    }
+    Auth::Token(tkn) => {
+      conn.call(&AuthToken { tkn: tkn.clone() }).await?;
+      Ok(None)
+    }
+  }
+}
+
+/// Ask the server to switch the connection over to the length-prefixed
+/// protocol v2 framing implemented by [`BinCodec`].
+///
+/// This should be done directly after connecting, before any other requests
+/// are issued.  If the server rejects the request the line based connection
+/// is returned as-is, so callers can fall back to it.
+pub async fn negotiate_v2<T: AsyncRead + AsyncWrite + Unpin>(
+  mut conn: ClntIfFramed<T>
+) -> Result<Negotiated<T>, Error> {
+  let mut tg = Telegram::new_topic("Proto")?;
+  tg.add_param("Version", 2)?;
+  match sendrecv(&mut conn, &tg).await {
+    Ok(_) => {}
+    Err(e) if e.is_server_error() => return Ok(Negotiated::V1(conn)),
+    Err(e) => return Err(e)
+  }
+
+  // Carry over anything which has already been buffered.
+  let old = conn.into_parts();
+  let mut parts = FramedParts::new::<&Telegram>(old.io, BinCodec::new());
+  parts.read_buf = old.read_buf;
+  parts.write_buf = old.write_buf;
+  Ok(Negotiated::V2(Framed::from_parts(parts)))
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,124 @@
+//! Typed request/reply definitions.
+//!
+//! A [`Command`] describes one request of the client interface protocol: the
+//! telegram it is sent as and how the parameters of its `Ok` reply are
+//! parsed.  The crate's own requests, such as
+//! [`GetNodeInfo`](crate::GetNodeInfo) and
+//! [`mgmt::ch::LsCh`](crate::mgmt::ch::LsCh), are defined this way, and
+//! applications can define further server commands in their own crates:
+//!
+//! ```
+//! use tokio_ddmw::blather::{Params, Telegram};
+//! use tokio_ddmw::cmd::Command;
+//! use tokio_ddmw::Error;
+//!
+//! /// Get the number of messages queued on a channel.
+//! struct QueueLen {
+//!   ch: u8
+//! }
+//!
+//! impl Command for QueueLen {
+//!   const TOPIC: &'static str = "QueueLen";
+//!   type Reply = u64;
+//!
+//!   fn to_telegram(&self) -> Result<Telegram, Error> {
+//!     let mut tg = Telegram::new_topic(Self::TOPIC)?;
+//!     tg.add_param("Ch", self.ch)?;
+//!     Ok(tg)
+//!   }
+//!
+//!   fn parse_reply(params: Params) -> Result<u64, Error> {
+//!     Ok(params.get_int::<u64>("Len")?)
+//!   }
+//! }
+//! ```
+//!
+//! Commands are run using [`call()`], or
+//! [`Client::call()`](crate::client::Client::call), which send the request,
+//! map `Fail` replies to errors and report the exchange to the installed
+//! metrics and tracing subscriber the same way for all commands.
+
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use tokio_util::codec::Framed;
+
+use blather::{Params, Telegram};
+
+use crate::deadline::Deadline;
+use crate::err::Error;
+
+
+/// A request and the reply it expects.
+pub trait Command {
+  /// The topic of the request telegram.
+  const TOPIC: &'static str;
+
+  /// The parsed reply.
+  type Reply;
+
+  /// Build the request telegram.  The default implementation builds a
+  /// telegram with no parameters.
+  fn to_telegram(&self) -> Result<Telegram, Error> {
+    Ok(Telegram::new_topic(Self::TOPIC)?)
+  }
+
+  /// Parse the parameters of the server's `Ok` reply.  `Fail` replies are
+  /// handled before this is called.
+  fn parse_reply(params: Params) -> Result<Self::Reply, Error>;
+}
+
+
+/// Check that the server is responding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Ping;
+
+impl Command for Ping {
+  const TOPIC: &'static str = "Ping";
+  type Reply = ();
+
+  fn parse_reply(_params: Params) -> Result<(), Error> {
+    Ok(())
+  }
+}
+
+
+/// Send the request of `cmd` and parse its reply.
+///
+/// Errors, including errors parsing the reply, are wrapped in an
+/// [`Error::Command`] identifying the request.  See
+/// [`sendrecv()`](crate::sendrecv).
+pub async fn call<T, C>(
+  conn: &mut Framed<T, blather::Codec>,
+  cmd: &C
+) -> Result<C::Reply, Error>
+where
+  T: AsyncRead + AsyncWrite + Unpin,
+  C: Command
+{
+  let tg = cmd.to_telegram()?;
+  let params = crate::sendrecv(conn, &tg).await?;
+  C::parse_reply(params).map_err(|e| e.with_command(&tg))
+}
+
+
+/// Like [`call()`], but fail with `Error::DeadlineExceeded`, naming the
+/// command's topic, if it doesn't complete within `timeout`.
+///
+/// A command which times out may leave the connection in an undefined state.
+pub async fn call_timeout<T, C>(
+  conn: &mut Framed<T, blather::Codec>,
+  cmd: &C,
+  timeout: Duration
+) -> Result<C::Reply, Error>
+where
+  T: AsyncRead + AsyncWrite + Unpin,
+  C: Command
+{
+  Deadline::after(timeout)
+    .run(C::TOPIC, call(conn, cmd))
+    .await
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,50 @@
+//! Use of the crate from applications built on other runtimes.
+//!
+//! Enabled by the `compat` feature.  The codecs and helpers are written
+//! against tokio's `AsyncRead` and `AsyncWrite` traits, which don't require
+//! the tokio runtime.  Streams implementing the `futures::io` traits, as used
+//! by async-std and smol, can be adapted to them using [`framed()`], or by
+//! calling [`compat()`](FuturesAsyncReadCompatExt::compat) on the stream:
+//!
+//! ```no_run
+//! # async fn example<S>(stream: S) -> Result<(), tokio_ddmw::Error>
+//! # where S: futures::io::AsyncRead + futures::io::AsyncWrite + Unpin {
+//! let mut conn = tokio_ddmw::compat::framed(stream);
+//! let node = tokio_ddmw::get_nodeinfo(&mut conn).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Parts of the crate still depend on the tokio runtime being available:
+//! connecting to an [`Endpoint`](crate::msg::Endpoint) (the `tokio-net`
+//! feature), sending and receiving file contents, which uses `tokio::fs`,
+//! and anything involving timeouts or deadlines, which uses `tokio::time`.
+
+use futures::io::{AsyncRead, AsyncWrite};
+
+use tokio_util::codec::Framed;
+
+pub use tokio_util::compat::{
+  Compat, FuturesAsyncReadCompatExt, FuturesAsyncWriteCompatExt,
+  TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt
+};
+
+#[cfg(feature = "msg")]
+use crate::msg::AsyncStream;
+
+
+/// Frame a `futures::io` stream for use with the crate's helpers.
+pub fn framed<T>(io: T) -> Framed<Compat<T>, blather::Codec>
+where
+  T: AsyncRead + AsyncWrite
+{
+  Framed::new(io.compat(), blather::Codec::new())
+}
+
+
+/// Allows adapted streams to be used as a [`Conn`](crate::msg::Conn).
+#[cfg(feature = "msg")]
+impl<T> AsyncStream for Compat<T> where T: AsyncRead + AsyncWrite + Unpin + Send
+{}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
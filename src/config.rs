@@ -0,0 +1,382 @@
+//! Integration configuration.
+//!
+//! Enabled by the `config` feature.  [`IntegrationConfig`] collects the
+//! settings most integrations need -- which interfaces to connect to, which
+//! channel to use, how to authenticate and which timeouts to apply -- from a
+//! TOML file, with environment variables taking precedence:
+//!
+//! ```toml
+//! msgif = "127.0.0.1:5000"
+//! mgmtif = "127.0.0.1:5001"
+//! channel = "reports"
+//!
+//! [auth]
+//! name = "reporter"
+//! pass-file = "/etc/reporter/pass"
+//!
+//! [timeouts]
+//! connect = 10
+//! ```
+//!
+//! Channels may be given by identifier or by name, and timeouts are given in
+//! seconds.  The [`ConnTransport`], [`AuthInfo`] and
+//! [`ClientBuilder`](crate::client::ClientBuilder) needed to get going are
+//! then constructed from the configuration.  The management interface is
+//! connected to the same way, using
+//! [`mgmt_client_builder()`](IntegrationConfig::mgmt_client_builder):
+#![cfg_attr(feature = "tokio-net", doc = "```no_run")]
+#![cfg_attr(not(feature = "tokio-net"), doc = "```ignore")]
+//! # async fn example() -> Result<(), tokio_ddmw::Error> {
+//! use tokio_ddmw::config::IntegrationConfig;
+//!
+//! let cfg = IntegrationConfig::load(None)?;
+//! let mut client = cfg.client_builder()?.connect().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer};
+
+use figment::{
+  providers::{Format, Toml},
+  Figment
+};
+
+use crate::auth::AuthInfo;
+use crate::err::Error;
+use crate::msg::{Channel, ConnTransport, Endpoint};
+
+
+/// Environment variable used to locate the configuration file if none is
+/// passed to [`IntegrationConfig::load()`].
+pub const CONF_ENV: &str = "DDMW_APPCONF";
+
+/// Configuration file used if neither a file name nor [`CONF_ENV`] is
+/// supplied.
+pub const DEFAULT_CONF: &str = "ddmwapp.toml";
+
+
+/// Settings shared by DDMW integrations.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct IntegrationConfig {
+  /// Message interface of the node.
+  #[serde(default, deserialize_with = "de_parse")]
+  pub msgif: Option<Endpoint>,
+
+  /// Management interface of the node.
+  #[serde(default, deserialize_with = "de_parse")]
+  pub mgmtif: Option<Endpoint>,
+
+  /// Channel messages are sent on.
+  pub channel: Option<Channel>,
+
+  pub auth: Option<AuthConfig>,
+
+  #[serde(default)]
+  pub timeouts: Timeouts
+}
+
+/// Authentication settings, as used by `ddmw_util::app::Auth`.
+#[derive(Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AuthConfig {
+  pub name: Option<String>,
+  pub pass: Option<String>,
+  pub pass_file: Option<PathBuf>,
+  pub token: Option<String>,
+  pub token_file: Option<PathBuf>
+}
+
+/// Timeouts, given in seconds in configuration files.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Timeouts {
+  /// Connecting to the message or management interface.
+  #[serde(default, deserialize_with = "de_secs")]
+  pub connect: Option<Duration>
+}
+
+
+impl IntegrationConfig {
+  /// Load the configuration file and apply environment overrides using
+  /// [`apply_env()`](Self::apply_env).
+  ///
+  /// The file is looked for in the same order as
+  /// `ddmw_util::app::load_conf()`: `fname` if supplied, otherwise the file
+  /// named by [`CONF_ENV`] if it is set, otherwise [`DEFAULT_CONF`] in the
+  /// current directory.  A missing file is not an error; the configuration
+  /// is then taken from the environment alone.
+  pub fn load(fname: Option<&Path>) -> Result<Self, Error> {
+    let fname = match fname {
+      Some(p) => p.to_path_buf(),
+      None => match std::env::var_os(CONF_ENV) {
+        Some(val) => PathBuf::from(val),
+        None => PathBuf::from(DEFAULT_CONF)
+      }
+    };
+
+    let mut cfg = if fname.exists() {
+      extract(Figment::new().merge(Toml::file(fname)))?
+    } else {
+      IntegrationConfig::default()
+    };
+    cfg.apply_env()?;
+    Ok(cfg)
+  }
+
+  /// Parse a configuration from a TOML string.  Environment overrides are
+  /// not applied.
+  pub fn parse(s: &str) -> Result<Self, Error> {
+    extract(Figment::new().merge(Toml::string(s)))
+  }
+
+  /// Override settings using the environment variables which are set:
+  ///
+  /// | Variable                | Setting              |
+  /// |-------------------------|----------------------|
+  /// | `DDMW_MSGIF`            | `msgif`              |
+  /// | `DDMW_MGMTIF`           | `mgmtif`             |
+  /// | `DDMW_CHANNEL`          | `channel`            |
+  /// | `DDMW_AUTH_NAME`        | `auth.name`          |
+  /// | `DDMW_AUTH_PASS`        | `auth.pass`          |
+  /// | `DDMW_AUTH_PASS_FILE`   | `auth.pass-file`     |
+  /// | `DDMW_AUTH_TOKEN`       | `auth.token`         |
+  /// | `DDMW_AUTH_TOKEN_FILE`  | `auth.token-file`    |
+  /// | `DDMW_CONNECT_TIMEOUT`  | `timeouts.connect`   |
+  pub fn apply_env(&mut self) -> Result<(), Error> {
+    self.apply_overrides(|key| std::env::var(key).ok())
+  }
+
+  fn apply_overrides<F>(&mut self, var: F) -> Result<(), Error>
+  where
+    F: Fn(&str) -> Option<String>
+  {
+    if let Some(v) = var("DDMW_MSGIF") {
+      self.msgif = Some(v.parse()?);
+    }
+    if let Some(v) = var("DDMW_MGMTIF") {
+      self.mgmtif = Some(v.parse()?);
+    }
+    if let Some(v) = var("DDMW_CHANNEL") {
+      self.channel = Some(match v.parse::<u8>() {
+        Ok(id) => Channel::Id(id),
+        Err(_) => Channel::Name(v)
+      });
+    }
+
+    let auth_vars = [
+      "DDMW_AUTH_NAME",
+      "DDMW_AUTH_PASS",
+      "DDMW_AUTH_PASS_FILE",
+      "DDMW_AUTH_TOKEN",
+      "DDMW_AUTH_TOKEN_FILE"
+    ];
+    if auth_vars.iter().any(|k| var(k).is_some()) {
+      let auth = self.auth.get_or_insert_with(AuthConfig::default);
+      if let Some(v) = var("DDMW_AUTH_NAME") {
+        auth.name = Some(v);
+      }
+      if let Some(v) = var("DDMW_AUTH_PASS") {
+        auth.pass = Some(v);
+      }
+      if let Some(v) = var("DDMW_AUTH_PASS_FILE") {
+        auth.pass_file = Some(PathBuf::from(v));
+      }
+      if let Some(v) = var("DDMW_AUTH_TOKEN") {
+        auth.token = Some(v);
+      }
+      if let Some(v) = var("DDMW_AUTH_TOKEN_FILE") {
+        auth.token_file = Some(PathBuf::from(v));
+      }
+    }
+
+    if let Some(v) = var("DDMW_CONNECT_TIMEOUT") {
+      self.timeouts.connect = Some(parse_secs("DDMW_CONNECT_TIMEOUT", &v)?);
+    }
+
+    Ok(())
+  }
+
+  /// Authentication information, if an `auth` section was supplied.
+  ///
+  /// Fails with `Error::Secret` if `pass_file` is used and the passphrase
+  /// can't be read from it.
+  pub fn authinfo(&self) -> Result<Option<AuthInfo>, Error> {
+    self.auth.as_ref().map(AuthConfig::authinfo).transpose()
+  }
+
+  /// Construct a `ConnTransport` from the `msgif`, `channel` and `auth`
+  /// settings.
+  ///
+  /// Fails with `Error::MissingData` if `msgif` or `channel` isn't set.
+  pub fn conn_transport(&self) -> Result<ConnTransport, Error> {
+    Ok(ConnTransport {
+      msgif: self.require_msgif()?.clone(),
+      authinfo: self.authinfo()?,
+      ch: self
+        .channel
+        .clone()
+        .ok_or_else(|| Error::missing("channel"))?
+    })
+  }
+
+  /// A client builder for the message interface, with the configured
+  /// authentication information and connect timeout applied.
+  ///
+  /// Fails with `Error::MissingData` if `msgif` isn't set.
+  #[cfg(feature = "tokio-net")]
+  pub fn client_builder(&self) -> Result<crate::client::ClientBuilder, Error> {
+    self.builder_for(self.require_msgif()?)
+  }
+
+  /// A client builder for the management interface, with the configured
+  /// authentication information and connect timeout applied.
+  ///
+  /// Fails with `Error::MissingData` if `mgmtif` isn't set.
+  #[cfg(feature = "tokio-net")]
+  pub fn mgmt_client_builder(
+    &self
+  ) -> Result<crate::client::ClientBuilder, Error> {
+    let mgmtif = self
+      .mgmtif
+      .as_ref()
+      .ok_or_else(|| Error::missing("mgmtif"))?;
+    self.builder_for(mgmtif)
+  }
+
+  #[cfg(feature = "tokio-net")]
+  fn builder_for(
+    &self,
+    endpoint: &Endpoint
+  ) -> Result<crate::client::ClientBuilder, Error> {
+    let mut builder = crate::client::Client::builder(endpoint.clone());
+    if let Some(ai) = self.authinfo()? {
+      builder = builder.authinfo(ai);
+    }
+    if let Some(timeout) = self.timeouts.connect {
+      builder = builder.connect_timeout(timeout);
+    }
+    Ok(builder)
+  }
+
+  fn require_msgif(&self) -> Result<&Endpoint, Error> {
+    self.msgif.as_ref().ok_or_else(|| Error::missing("msgif"))
+  }
+}
+
+
+/// Convert an application configuration, using the sender's interfaces.
+impl TryFrom<&ddmw_util::app::Config> for IntegrationConfig {
+  type Error = Error;
+
+  fn try_from(cfg: &ddmw_util::app::Config) -> Result<Self, Self::Error> {
+    let sender = cfg.sender.as_ref();
+    let endpoint = |s: Option<&String>| s.map(|s| s.parse()).transpose();
+    Ok(IntegrationConfig {
+      msgif: endpoint(sender.and_then(|s| s.msgif.as_ref()))?,
+      mgmtif: endpoint(sender.and_then(|s| s.mgmtif.as_ref()))?,
+      channel: cfg.channel.map(Channel::Id),
+      auth: cfg.auth.as_ref().map(|auth| AuthConfig {
+        name: auth.name.clone(),
+        pass: auth.pass.clone(),
+        pass_file: auth.pass_file.as_ref().map(PathBuf::from),
+        token: auth.token.clone(),
+        token_file: auth.token_file.as_ref().map(PathBuf::from)
+      }),
+      timeouts: Timeouts::default()
+    })
+  }
+}
+
+
+/// The password and any token are redacted.
+impl std::fmt::Debug for AuthConfig {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    let redact = |s: &Option<String>| s.as_ref().map(|_| "<redacted>");
+    f.debug_struct("AuthConfig")
+      .field("name", &self.name)
+      .field("pass", &redact(&self.pass))
+      .field("pass_file", &self.pass_file)
+      .field("token", &redact(&self.token))
+      .field("token_file", &self.token_file)
+      .finish()
+  }
+}
+
+
+impl AuthConfig {
+  /// Authentication information for these settings, loading the passphrase
+  /// from `pass_file` if no passphrase is set explicitly.
+  ///
+  /// Fails with `Error::Secret` if the passphrase can't be read.
+  pub fn authinfo(&self) -> Result<AuthInfo, Error> {
+    AuthInfo::try_from_app(&self.to_app())
+  }
+
+  fn to_app(&self) -> ddmw_util::app::Auth {
+    let path = |p: &Option<PathBuf>| {
+      p.as_ref().map(|p| p.to_string_lossy().into_owned())
+    };
+    ddmw_util::app::Auth {
+      name: self.name.clone(),
+      pass: self.pass.clone(),
+      pass_file: path(&self.pass_file),
+      token: self.token.clone(),
+      token_file: path(&self.token_file)
+    }
+  }
+}
+
+/// A passphrase which can't be loaded from `pass_file` is left out.  Use
+/// [`AuthConfig::authinfo()`] to have problems reported instead.
+impl From<&AuthConfig> for AuthInfo {
+  fn from(auth: &AuthConfig) -> AuthInfo {
+    AuthInfo::from(&auth.to_app())
+  }
+}
+
+
+fn extract(figment: Figment) -> Result<IntegrationConfig, Error> {
+  figment
+    .extract()
+    .map_err(|e| Error::bad_format("configuration", e.to_string()))
+}
+
+fn parse_secs(what: &str, s: &str) -> Result<Duration, Error> {
+  s.trim()
+    .parse::<f64>()
+    .ok()
+    .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+    .ok_or_else(|| {
+      Error::bad_format(what, format!("invalid number of seconds '{}'", s))
+    })
+}
+
+fn de_parse<'de, D, T>(de: D) -> Result<Option<T>, D::Error>
+where
+  D: Deserializer<'de>,
+  T: FromStr<Err = Error>
+{
+  Option::<String>::deserialize(de)?
+    .map(|s| s.parse().map_err(serde::de::Error::custom))
+    .transpose()
+}
+
+fn de_secs<'de, D>(de: D) -> Result<Option<Duration>, D::Error>
+where
+  D: Deserializer<'de>
+{
+  Option::<f64>::deserialize(de)?
+    .map(|secs| {
+      Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+    })
+    .transpose()
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
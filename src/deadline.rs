@@ -0,0 +1,71 @@
+//! Overall deadlines for operations made up of several steps.
+//!
+//! A [`Deadline`] is started once and then passed to each step of an
+//! operation, so the steps share a single time budget rather than each
+//! having a timeout of its own.  If the budget runs out the operation fails
+//! with `Error::DeadlineExceeded`, which names the step which was running
+//! and how much of the budget had been consumed.
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::err::Error;
+
+
+/// A point in time by which an operation must complete.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline {
+  start: Instant,
+  budget: Duration
+}
+
+impl Deadline {
+  /// Start a deadline which expires once `budget` has passed.
+  pub fn after(budget: Duration) -> Self {
+    Deadline {
+      start: Instant::now(),
+      budget
+    }
+  }
+
+  /// The total time budget.
+  pub fn budget(&self) -> Duration {
+    self.budget
+  }
+
+  /// The time consumed since the deadline was started.
+  pub fn elapsed(&self) -> Duration {
+    self.start.elapsed()
+  }
+
+  /// The time left before the deadline expires.
+  pub fn remaining(&self) -> Duration {
+    self.budget.saturating_sub(self.elapsed())
+  }
+
+  /// Returns `true` if the deadline has passed.
+  pub fn is_expired(&self) -> bool {
+    self.remaining().is_zero()
+  }
+
+  /// Run `fut`, a step of the operation named `step`, failing with
+  /// `Error::DeadlineExceeded` if the deadline passes before it completes.
+  ///
+  /// Steps which are abandoned part way through may leave the connection
+  /// they were using in an undefined state.
+  pub async fn run<T, F>(&self, step: &str, fut: F) -> Result<T, Error>
+  where
+    F: Future<Output = Result<T, Error>>
+  {
+    match tokio::time::timeout(self.remaining(), fut).await {
+      Ok(res) => res,
+      Err(_) => Err(Error::DeadlineExceeded {
+        step: step.to_string(),
+        elapsed: self.elapsed(),
+        budget: self.budget
+      })
+    }
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
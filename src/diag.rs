@@ -0,0 +1,461 @@
+//! Connectivity diagnostics.
+//!
+//! [`verify_connectivity()`] runs through the steps an integration goes
+//! through when it starts -- connecting, querying the node, authenticating --
+//! and reports how each of them went, rather than stopping at the first
+//! error.  It is intended for smoke tests of new deployments and for
+//! troubleshooting:
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), tokio_ddmw::Error> {
+//! use std::time::Duration;
+//! use tokio_ddmw::diag;
+//!
+//! let msgif = "127.0.0.1:5000".parse()?;
+//! let report =
+//!   diag::verify_connectivity(&msgif, None, Duration::from_secs(5)).await;
+//! print!("{}", report);
+//! if !report.is_ok() {
+//!   std::process::exit(1);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! For monitoring, a [`HealthReport`] classifies the outcome of the check as
+//! [`Severity::Ok`], [`Severity::Warn`] or [`Severity::Fail`], with the
+//! reasons for the classification.  With the `serde` feature the report,
+//! and the [`NodeStats`] and [`LinkStatus`] it can carry, serialize
+//! directly, so exporters don't need to map them to types of their own.
+
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use crate::auth::AuthInfo;
+use crate::cmd::Ping;
+use crate::err::Error;
+use crate::metrics::NodeStats;
+use crate::msg::{Conn, Endpoint};
+
+
+/// Steps that are considered slow if they take longer than this.
+const SLOW_STEP: Duration = Duration::from_secs(1);
+
+
+/// A step of the connectivity check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Step {
+  Connect,
+  NodeInfo,
+  Authenticate,
+  Ping
+}
+
+impl fmt::Display for Step {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let s = match self {
+      Step::Connect => "connect",
+      Step::NodeInfo => "node info",
+      Step::Authenticate => "authenticate",
+      Step::Ping => "ping"
+    };
+    f.write_str(s)
+  }
+}
+
+
+/// How a step of the connectivity check went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Outcome {
+  Ok,
+
+  /// The step failed; contains the error.
+  Failed(String),
+
+  /// The step was not attempted, either because it wasn't requested or
+  /// because an earlier step failed.
+  Skipped
+}
+
+
+/// The result of one step of the connectivity check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StepReport {
+  pub step: Step,
+  pub outcome: Outcome,
+
+  /// Time taken by the step.  Zero for skipped steps.
+  pub latency: Duration
+}
+
+
+/// The result of [`verify_connectivity()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConnectivityReport {
+  /// The endpoint which was checked.
+  pub endpoint: Endpoint,
+
+  /// The steps of the check, in the order they were run.
+  pub steps: Vec<StepReport>,
+
+  /// The node type, if the node could be queried.
+  pub nodetype: Option<String>,
+
+  /// The server's version string, if the node could be queried.
+  pub version: Option<String>,
+
+  /// The node's diode link configuration, if reported.
+  pub link: Option<LinkStatus>,
+
+  /// Problems detected, including ones which did not cause any step to
+  /// fail, described in plain language.
+  pub problems: Vec<String>
+}
+
+impl ConnectivityReport {
+  /// Returns `true` if no step failed.
+  pub fn is_ok(&self) -> bool {
+    !self
+      .steps
+      .iter()
+      .any(|s| matches!(s.outcome, Outcome::Failed(_)))
+  }
+
+  /// Get the report of a step.
+  pub fn step(&self, step: Step) -> Option<&StepReport> {
+    self.steps.iter().find(|s| s.step == step)
+  }
+
+  fn record(&mut self, step: Step, outcome: Outcome, latency: Duration) {
+    if let Outcome::Ok = outcome {
+      if latency > SLOW_STEP {
+        self.problems.push(format!(
+          "The {} step took {:.1}s",
+          step,
+          latency.as_secs_f64()
+        ));
+      }
+    }
+    self.steps.push(StepReport {
+      step,
+      outcome,
+      latency
+    });
+  }
+
+  fn skip(&mut self, step: Step) {
+    self.record(step, Outcome::Skipped, Duration::ZERO);
+  }
+}
+
+impl fmt::Display for ConnectivityReport {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let endpoint = match self.endpoint {
+      Endpoint::TcpSockAddr(ref sa) => sa.clone(),
+      #[cfg(unix)]
+      Endpoint::UdsPath(ref p) => p.display().to_string()
+    };
+    writeln!(f, "Connectivity check of {}", endpoint)?;
+    for s in &self.steps {
+      match s.outcome {
+        Outcome::Ok => writeln!(
+          f,
+          "  {:<12} ok ({:.1} ms)",
+          s.step.to_string(),
+          s.latency.as_secs_f64() * 1000.0
+        )?,
+        Outcome::Failed(ref e) => {
+          writeln!(f, "  {:<12} FAILED: {}", s.step.to_string(), e)?
+        }
+        Outcome::Skipped => {
+          writeln!(f, "  {:<12} skipped", s.step.to_string())?
+        }
+      }
+    }
+    if let (Some(nodetype), Some(version)) = (&self.nodetype, &self.version) {
+      writeln!(f, "  Node: {} version {}", nodetype, version)?;
+    }
+    if let Some(ref link) = self.link {
+      writeln!(f, "  Link: {}", link)?;
+    }
+    for p in &self.problems {
+      writeln!(f, "  Problem: {}", p)?;
+    }
+    Ok(())
+  }
+}
+
+
+/// A node's diode link configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LinkStatus {
+  pub engine: String,
+  pub protocol: String,
+  pub protimpl: String
+}
+
+impl fmt::Display for LinkStatus {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} ({} {})", self.engine, self.protocol, self.protimpl)
+  }
+}
+
+
+/// How healthy a checked node is, in increasing order of severity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Severity {
+  Ok,
+  Warn,
+  Fail
+}
+
+impl Severity {
+  /// The process exit code used by Nagios compatible plugins to report this
+  /// severity.
+  pub fn exit_code(&self) -> i32 {
+    match self {
+      Severity::Ok => 0,
+      Severity::Warn => 1,
+      Severity::Fail => 2
+    }
+  }
+}
+
+impl fmt::Display for Severity {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let s = match self {
+      Severity::Ok => "ok",
+      Severity::Warn => "warn",
+      Severity::Fail => "fail"
+    };
+    f.write_str(s)
+  }
+}
+
+
+/// A classified health report, for monitoring agents.
+///
+/// The severity is derived from the connectivity check: [`Severity::Fail`]
+/// if any step failed, with the failed steps as reasons, [`Severity::Warn`]
+/// if all steps that were run succeeded but problems were detected, with the
+/// problems as reasons, and [`Severity::Ok`] otherwise.  The statistics are
+/// informational and don't affect the severity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HealthReport {
+  pub severity: Severity,
+
+  /// Why the report has the severity it has.  Empty if the severity is
+  /// [`Severity::Ok`].
+  pub reasons: Vec<String>,
+
+  pub connectivity: ConnectivityReport,
+
+  /// Client side statistics, if supplied using [`HealthReport::stats()`].
+  pub stats: Option<NodeStats>
+}
+
+impl HealthReport {
+  /// Attach a snapshot of client side statistics to the report.
+  pub fn stats(mut self, stats: NodeStats) -> Self {
+    self.stats = Some(stats);
+    self
+  }
+}
+
+impl From<ConnectivityReport> for HealthReport {
+  fn from(connectivity: ConnectivityReport) -> Self {
+    let failed: Vec<String> = connectivity
+      .steps
+      .iter()
+      .filter_map(|s| match s.outcome {
+        Outcome::Failed(ref e) => Some(format!("{} failed; {}", s.step, e)),
+        _ => None
+      })
+      .collect();
+    let (severity, reasons) = if !failed.is_empty() {
+      (Severity::Fail, failed)
+    } else if !connectivity.problems.is_empty() {
+      (Severity::Warn, connectivity.problems.clone())
+    } else {
+      (Severity::Ok, Vec::new())
+    };
+    HealthReport {
+      severity,
+      reasons,
+      connectivity,
+      stats: None
+    }
+  }
+}
+
+impl fmt::Display for HealthReport {
+  /// Formats the report as a single line, in the form expected from Nagios
+  /// compatible plugins.
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let label = match self.severity {
+      Severity::Ok => "OK",
+      Severity::Warn => "WARNING",
+      Severity::Fail => "CRITICAL"
+    };
+    write!(f, "DDMW {}", label)?;
+    if let Some(ref version) = self.connectivity.version {
+      write!(f, " (version {})", version)?;
+    }
+    if !self.reasons.is_empty() {
+      write!(f, " - {}", self.reasons.join("; "))?;
+    }
+    Ok(())
+  }
+}
+
+
+/// Check that the message interface at `msgif` is reachable and working.
+///
+/// Connects, queries the node's information, authenticates if `authinfo` is
+/// supplied and sends a `Ping` request.  Each step must complete within
+/// `timeout`.  Steps which depend on a failed step are skipped; a failed
+/// authentication doesn't prevent the ping from being attempted.
+///
+/// Failures are reported in the returned report rather than as errors.
+pub async fn verify_connectivity(
+  msgif: &Endpoint,
+  authinfo: Option<&AuthInfo>,
+  timeout: Duration
+) -> ConnectivityReport {
+  let mut report = ConnectivityReport {
+    endpoint: msgif.clone(),
+    steps: Vec::new(),
+    nodetype: None,
+    version: None,
+    link: None,
+    problems: Vec::new()
+  };
+
+  let (res, latency) = timed(timeout, msgif.connect()).await;
+  let mut conn: Conn = match res {
+    Ok(conn) => {
+      report.record(Step::Connect, Outcome::Ok, latency);
+      conn
+    }
+    Err(e) => {
+      report
+        .problems
+        .push(format!("Unable to connect to the server; {}", e));
+      report.record(Step::Connect, Outcome::Failed(e.to_string()), latency);
+      report.skip(Step::NodeInfo);
+      report.skip(Step::Authenticate);
+      report.skip(Step::Ping);
+      return report;
+    }
+  };
+
+  let (res, latency) = timed(timeout, crate::get_nodeinfo(&mut conn)).await;
+  match res {
+    Ok(node) => {
+      report.record(Step::NodeInfo, Outcome::Ok, latency);
+      if node.semver.is_none() {
+        report.problems.push(format!(
+          "The server's version '{}' is not a semantic version",
+          node.version
+        ));
+      }
+      report.nodetype = Some(node.nodetype.to_string());
+      report.version = Some(node.version);
+      report.link = node.ddlnk.map(|l| LinkStatus {
+        engine: l.engine,
+        protocol: l.protocol.to_string(),
+        protimpl: l.protimpl.to_string()
+      });
+    }
+    Err(e) => {
+      if e.is_connection_error() {
+        report
+          .problems
+          .push(format!("The connection failed; {}", e));
+      } else {
+        report
+          .problems
+          .push(format!("The node information query failed; {}", e));
+      }
+      report.record(Step::NodeInfo, Outcome::Failed(e.to_string()), latency);
+      if e.is_connection_error() {
+        report.skip(Step::Authenticate);
+        report.skip(Step::Ping);
+        return report;
+      }
+    }
+  }
+
+  match authinfo {
+    Some(ai) => {
+      let (res, latency) =
+        timed(timeout, crate::auth::authenticate(&mut conn, ai)).await;
+      match res {
+        Ok(_) => report.record(Step::Authenticate, Outcome::Ok, latency),
+        Err(e) => {
+          if e.is_auth_failure() || e.is_server_error() {
+            report
+              .problems
+              .push(format!("The server rejected the credentials; {}", e));
+          } else {
+            report
+              .problems
+              .push(format!("Authentication failed; {}", e));
+          }
+          let fatal = e.is_connection_error();
+          report.record(
+            Step::Authenticate,
+            Outcome::Failed(e.to_string()),
+            latency
+          );
+          if fatal {
+            report.skip(Step::Ping);
+            return report;
+          }
+        }
+      }
+    }
+    None => report.skip(Step::Authenticate)
+  }
+
+  let (res, latency) =
+    timed(timeout, crate::cmd::call(&mut conn, &Ping)).await;
+  match res {
+    Ok(()) => report.record(Step::Ping, Outcome::Ok, latency),
+    Err(e) => {
+      report.problems.push(format!("The ping failed; {}", e));
+      report.record(Step::Ping, Outcome::Failed(e.to_string()), latency);
+    }
+  }
+
+  report
+}
+
+
+/// Run a step, failing it if it doesn't complete within `timeout`, and
+/// measure how long it took.
+async fn timed<T, F>(timeout: Duration, fut: F) -> (Result<T, Error>, Duration)
+where
+  F: Future<Output = Result<T, Error>>
+{
+  let start = Instant::now();
+  let res = match tokio::time::timeout(timeout, fut).await {
+    Ok(res) => res,
+    Err(_) => Err(Error::IO(std::io::Error::new(
+      std::io::ErrorKind::TimedOut,
+      format!("No response within {:?}", timeout)
+    )))
+  };
+  (res, start.elapsed())
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
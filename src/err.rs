@@ -1,55 +1,402 @@
 use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use tokio::io;
 
-use blather::Params;
+use blather::{Params, Telegram};
 
+use crate::auth::SecretError;
+use crate::checksum::Checksum;
+
+/// Request parameters whose values are never included in errors or
+/// captures.
+pub(crate) const REDACTED_PARAMS: &[&str] = &["Pass", "Tkn"];
+
+
+/// Errors reported by this crate.
+///
+/// New variants may be added in minor releases; match on the classification
+/// helpers, such as [`Error::is_connection_error()`], where possible.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
-  Blather(String),
-  IO(String),
-  BadFormat(String),
-  SerializeError(String),
+  Blather(blather::Error),
+  IO(io::Error),
+  /// Accessing a local file, or an application supplied writer, failed.
+  /// `path` is the pathname of the file, if there is one.  Unlike
+  /// `Error::IO` this never indicates a problem with the connection.
+  LocalIO {
+    path: Option<PathBuf>,
+    error: io::Error
+  },
+  /// Data couldn't be parsed or isn't valid.  `what` describes the data,
+  /// and `reason` what's wrong with it.
+  BadFormat {
+    what: String,
+    reason: String
+  },
+  /// `what` couldn't be serialized, for the reason `reason`.
+  SerializeError {
+    what: String,
+    reason: String
+  },
   ServerError(Params),
-  BadState(String),
-  InvalidSize(String),
+  PermissionDenied(Params),
+  NotFound(Params),
+  AlreadyExists(Params),
+  QuotaExceeded(Params),
+  AuthExpired(Params),
+  /// Something other than `expected` was encountered, such as an
+  /// unexpected reply or input.  `found` describes what was encountered
+  /// instead, if known.
+  BadState {
+    expected: String,
+    found: Option<String>
+  },
+  /// `what` has a size of `actual` bytes, which is invalid.  `expected` is
+  /// the size it should have had, if a specific size was expected.
+  InvalidSize {
+    what: String,
+    expected: Option<u64>,
+    actual: u64
+  },
+  /// A size or count exceeded a limit.  `what` describes what was being
+  /// measured, and `size` the offending value, if known.
+  TooLarge {
+    what: String,
+    size: Option<u64>,
+    limit: u64
+  },
+  ChecksumMismatch {
+    expected: Checksum,
+    actual: Checksum
+  },
+  /// A binary transfer timed out with `remaining` bytes not yet received,
+  /// or, when sending, not yet written.
+  TransferTimeout {
+    remaining: u64
+  },
+  TruncatedTransfer {
+    expected: u64,
+    received: u64
+  },
+  /// An operation's [deadline](crate::deadline::Deadline) passed while
+  /// `step` was running, `elapsed` into its time `budget`.
+  DeadlineExceeded {
+    step: String,
+    elapsed: Duration,
+    budget: Duration
+  },
   InvalidCredentials,
+  /// A passphrase or token couldn't be read from the file `path`.
+  Secret {
+    path: PathBuf,
+    error: SecretError
+  },
   Disconnected,
-  MissingData(String),
-  UnknownData(String)
+  /// A request to the server failed.  Wraps the actual error together with
+  /// the request's topic, its [request identifier](crate::reqid), if it has
+  /// one, and a snapshot of its parameters, with sensitive values redacted.
+  Command {
+    topic: String,
+    request_id: Option<String>,
+    params: Params,
+    source: Box<Error>
+  },
+  /// The required value `key` is missing.
+  MissingData {
+    key: String
+  },
+  /// `value` isn't a recognized or supported `what`.
+  UnknownData {
+    what: String,
+    value: String
+  }
+}
+
+impl std::error::Error for Error {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Error::Blather(e) => Some(e),
+      Error::IO(e) => Some(e),
+      Error::LocalIO { error, .. } => Some(error),
+      Error::Secret {
+        error: SecretError::IO(e),
+        ..
+      } => Some(e),
+      Error::Command { source, .. } => Some(source.as_ref()),
+      _ => None
+    }
+  }
 }
 
-impl std::error::Error for Error {}
+impl Error {
+  /// Construct an `Error::LocalIO` for a failure to access the local file
+  /// `path`.
+  pub(crate) fn local_io<P: AsRef<Path>>(path: P, error: io::Error) -> Self {
+    Error::LocalIO {
+      path: Some(path.as_ref().to_path_buf()),
+      error
+    }
+  }
+
+  /// Construct an `Error::BadFormat` for the data `what`.
+  pub(crate) fn bad_format<W, R>(what: W, reason: R) -> Self
+  where
+    W: Into<String>,
+    R: Into<String>
+  {
+    Error::BadFormat {
+      what: what.into(),
+      reason: reason.into()
+    }
+  }
+
+  /// Construct an `Error::BadState` for when `expected` wasn't encountered,
+  /// and it isn't known what was encountered instead.
+  pub(crate) fn bad_state<E: Into<String>>(expected: E) -> Self {
+    Error::BadState {
+      expected: expected.into(),
+      found: None
+    }
+  }
+
+  /// Construct an `Error::MissingData` for the missing value `key`.
+  pub(crate) fn missing<K: Into<String>>(key: K) -> Self {
+    Error::MissingData { key: key.into() }
+  }
+
+  /// Construct an error from the parameters of a server's `Fail` reply.
+  ///
+  /// Well-known failure classes, identified by the reply's `ErrCode`
+  /// parameter, are mapped to dedicated variants.  Anything else, including
+  /// failure classes introduced by later server versions, is returned as an
+  /// `Error::ServerError`.
+  pub fn from_fail(params: Params) -> Self {
+    match params.get_str("ErrCode") {
+      Some("PermissionDenied") => Error::PermissionDenied(params),
+      Some("NotFound") => Error::NotFound(params),
+      Some("AlreadyExists") => Error::AlreadyExists(params),
+      Some("QuotaExceeded") => Error::QuotaExceeded(params),
+      Some("AuthExpired") => Error::AuthExpired(params),
+      _ => Error::ServerError(params)
+    }
+  }
+
+  /// Wrap the error in an `Error::Command` describing the request `tg` which
+  /// caused it.
+  pub fn with_command(self, tg: &Telegram) -> Self {
+    let mut params = Params::new();
+    for (k, v) in tg.get_params_inner() {
+      let v = if REDACTED_PARAMS.contains(&k.as_str()) {
+        "<redacted>"
+      } else {
+        v.as_str()
+      };
+      // Keys and values have already been validated by the telegram.
+      let _ = params.add_str(k, v);
+    }
+    Error::Command {
+      topic: tg.get_topic().unwrap_or_default().to_string(),
+      request_id: tg.get_str(crate::reqid::PARAM).map(str::to_string),
+      params,
+      source: Box::new(self)
+    }
+  }
+
+  /// Get the underlying error, looking through any `Error::Command`
+  /// wrappers.
+  pub fn root(&self) -> &Error {
+    match self {
+      Error::Command { source, .. } => source.root(),
+      e => e
+    }
+  }
+
+  /// Returns `true` if the error represents a `Fail` reply from the server,
+  /// regardless of its failure class.
+  pub fn is_server_error(&self) -> bool {
+    self.server_params().is_some()
+  }
+
+  /// Get the parameters of the server's `Fail` reply, if the error
+  /// represents one.
+  pub fn server_params(&self) -> Option<&Params> {
+    match self.root() {
+      Error::ServerError(p)
+      | Error::PermissionDenied(p)
+      | Error::NotFound(p)
+      | Error::AlreadyExists(p)
+      | Error::QuotaExceeded(p)
+      | Error::AuthExpired(p) => Some(p),
+      _ => None
+    }
+  }
+
+  /// Returns `true` if the error was caused by the connection to the server
+  /// failing, rather than by the request or its reply.
+  ///
+  /// Failures to access local files, reported as `Error::LocalIO`, are not
+  /// connection errors.
+  ///
+  /// Connections which have encountered such errors should be discarded.
+  pub fn is_connection_error(&self) -> bool {
+    matches!(
+      self.root(),
+      Error::IO(_)
+        | Error::Disconnected
+        | Error::TransferTimeout { .. }
+        | Error::TruncatedTransfer { .. }
+        | Error::DeadlineExceeded { .. }
+    )
+  }
+
+  /// Returns `true` if the server rejected the credentials used to
+  /// authenticate, either because they are invalid or because they have
+  /// expired.
+  pub fn is_auth_failure(&self) -> bool {
+    matches!(
+      self.root(),
+      Error::InvalidCredentials | Error::AuthExpired(_)
+    )
+  }
+
+  /// Returns `true` if repeating the operation, on a new connection, may
+  /// succeed.
+  ///
+  /// This is the case for connection errors.  Errors caused by the request
+  /// itself, including failure replies from the server, will not go away by
+  /// retrying.
+  pub fn is_retryable(&self) -> bool {
+    self.is_connection_error()
+  }
+}
 
 impl fmt::Display for Error {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    match &*self {
+    match self {
       Error::Blather(s) => write!(f, "Msg buffer error; {}", s),
       Error::IO(s) => write!(f, "I/O error; {}", s),
-      Error::BadFormat(s) => write!(f, "Bad format; {}", s),
-      Error::SerializeError(s) => write!(f, "Unable to serialize; {}", s),
-      Error::ServerError(p) => write!(f, "Server replied: {}", p),
-      Error::BadState(s) => {
-        write!(f, "Encountred an unexpected/bad state: {}", s)
+      Error::LocalIO {
+        path: Some(path),
+        error
+      } => write!(f, "I/O error on {}; {}", path.display(), error),
+      Error::LocalIO { path: None, error } => {
+        write!(f, "Local I/O error; {}", error)
+      }
+      Error::BadFormat { what, reason } => {
+        write!(f, "Bad format of {}; {}", what, reason)
       }
-      Error::InvalidSize(s) => write!(f, "Invalid size; {}", s),
+      Error::SerializeError { what, reason } => {
+        write!(f, "Unable to serialize {}; {}", what, reason)
+      }
+      Error::ServerError(p) => write!(f, "Server replied: {}", p),
+      Error::PermissionDenied(p) => write!(f, "Permission denied: {}", p),
+      Error::NotFound(p) => write!(f, "Not found: {}", p),
+      Error::AlreadyExists(p) => write!(f, "Already exists: {}", p),
+      Error::QuotaExceeded(p) => write!(f, "Quota exceeded: {}", p),
+      Error::AuthExpired(p) => write!(f, "Authentication expired: {}", p),
+      Error::BadState {
+        expected,
+        found: Some(found)
+      } => write!(
+        f,
+        "Unexpected state; expected {}, found {}",
+        expected, found
+      ),
+      Error::BadState {
+        expected,
+        found: None
+      } => write!(f, "Unexpected state; expected {}", expected),
+      Error::InvalidSize {
+        what,
+        expected: Some(expected),
+        actual
+      } => write!(
+        f,
+        "Invalid size; {} has {} bytes, expected {}",
+        what, actual, expected
+      ),
+      Error::InvalidSize {
+        what,
+        expected: None,
+        actual
+      } => write!(f, "Invalid size; {} of {} bytes", what, actual),
+      Error::TooLarge {
+        what,
+        size: Some(size),
+        limit
+      } => write!(
+        f,
+        "Too large; {} of {} exceeds the limit of {}",
+        what, size, limit
+      ),
+      Error::TooLarge {
+        what,
+        size: None,
+        limit
+      } => write!(f, "Too large; {} exceeds the limit of {}", what, limit),
+      Error::ChecksumMismatch { expected, actual } => write!(
+        f,
+        "Checksum mismatch; expected {}, got {}",
+        expected, actual
+      ),
+      Error::TransferTimeout { remaining } => write!(
+        f,
+        "Transfer timed out; {} bytes of binary transfer outstanding",
+        remaining
+      ),
+      Error::TruncatedTransfer { expected, received } => write!(
+        f,
+        "Connection closed after {} of {} bytes of a transfer",
+        received, expected
+      ),
+      Error::DeadlineExceeded {
+        step,
+        elapsed,
+        budget
+      } => write!(
+        f,
+        "Deadline exceeded during {}; {:?} of a {:?} budget consumed",
+        step, elapsed, budget
+      ),
       Error::InvalidCredentials => write!(f, "Invalid credentials"),
+      Error::Secret { path, error } => write!(
+        f,
+        "Unable to read secret from {}; {}",
+        path.display(),
+        error
+      ),
       Error::Disconnected => write!(f, "Disconnected"),
-      Error::MissingData(s) => write!(f, "Missing data; {}", s),
-      Error::UnknownData(s) => write!(f, "Unknown data; {}", s)
+      Error::Command {
+        topic,
+        request_id: Some(id),
+        source,
+        ..
+      } => write!(f, "{} request {} failed; {}", topic, id, source),
+      Error::Command { topic, source, .. } => {
+        write!(f, "{} request failed; {}", topic, source)
+      }
+      Error::MissingData { key } => {
+        write!(f, "Missing data; '{}' not found", key)
+      }
+      Error::UnknownData { what, value } => {
+        write!(f, "Unknown data; {} '{}' is not supported", what, value)
+      }
     }
   }
 }
 
 impl From<io::Error> for Error {
   fn from(err: io::Error) -> Self {
-    Error::IO(err.to_string())
+    Error::IO(err)
   }
 }
 
 impl From<blather::Error> for Error {
   fn from(err: blather::Error) -> Self {
-    Error::Blather(err.to_string())
+    Error::Blather(err)
   }
 }
 
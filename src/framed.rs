@@ -0,0 +1,240 @@
+//! Methods for performing common operations directly on a connection.
+//!
+//! [`DdmwFramedExt`] is implemented for [`DdmwFramed`](crate::DdmwFramed)
+//! and [`ClntIfFramed`] connections, and makes the crate's request/reply and
+//! file transfer helpers available as methods, for applications which drive
+//! the protocol themselves:
+#![cfg_attr(feature = "msg", doc = "```no_run")]
+#![cfg_attr(not(feature = "msg"), doc = "```ignore")]
+//! # async fn example(
+//! #   mut conn: tokio_ddmw::msg::Conn
+//! # ) -> Result<(), tokio_ddmw::Error> {
+//! use tokio_ddmw::framed::DdmwFramedExt;
+//! use tokio_ddmw::tg;
+//!
+//! let tg = tg!("Msg", "_Ch" => 1, "Len" => 4096)?;
+//! let params = conn.send_ok_expected(&tg).await?;
+//! conn.send_file("report.pdf").await?;
+//! conn.expect_okfail().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+#[cfg(feature = "msg")]
+use std::path::Path;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use tokio_util::codec::Framed;
+
+use futures::future::BoxFuture;
+
+use blather::{Params, Telegram};
+
+use crate::auth::Requester;
+use crate::clntif::ClntIfFramed;
+use crate::cmd::Command;
+use crate::err::Error;
+
+
+/// Request/reply and file transfer operations on a connection.
+pub trait DdmwFramedExt {
+  /// Send a telegram and wait for an `Ok` reply, returning its parameters.
+  /// See [`sendrecv()`](crate::sendrecv).
+  fn send_ok_expected<'a>(
+    &'a mut self,
+    tg: &'a Telegram
+  ) -> BoxFuture<'a, Result<Params, Error>>;
+
+  /// Wait for an `Ok` or `Fail` reply.  See
+  /// [`expect_okfail()`](crate::expect_okfail).
+  fn expect_okfail(&mut self) -> BoxFuture<'_, Result<Params, Error>>;
+
+  /// Run a command and return its parsed reply.  See
+  /// [`cmd::call()`](crate::cmd::call).
+  fn call<'a, C>(
+    &'a mut self,
+    cmd: &'a C
+  ) -> BoxFuture<'a, Result<C::Reply, Error>>
+  where
+    C: Command + Sync,
+    C::Reply: Send;
+
+  /// Send the contents of the file `path` as raw data, returning the number
+  /// of bytes sent.
+  ///
+  /// The peer must have been told how much data to expect, typically by a
+  /// preceding telegram.  Requires the `msg` feature.
+  #[cfg(feature = "msg")]
+  fn send_file<'a>(
+    &'a mut self,
+    path: impl AsRef<Path> + Send + 'a
+  ) -> BoxFuture<'a, Result<u64, Error>>;
+
+  /// Receive `len` bytes of raw data into the file `path`, which is created
+  /// or truncated.  Requires the `msg` feature.
+  #[cfg(feature = "msg")]
+  fn recv_to_file<'a>(
+    &'a mut self,
+    path: impl AsRef<Path> + Send + 'a,
+    len: usize
+  ) -> BoxFuture<'a, Result<(), Error>>;
+}
+
+impl<T> DdmwFramedExt for Framed<T, blather::Codec>
+where
+  T: AsyncRead + AsyncWrite + Unpin + Send
+{
+  fn send_ok_expected<'a>(
+    &'a mut self,
+    tg: &'a Telegram
+  ) -> BoxFuture<'a, Result<Params, Error>> {
+    Box::pin(crate::sendrecv(self, tg))
+  }
+
+  fn expect_okfail(&mut self) -> BoxFuture<'_, Result<Params, Error>> {
+    Box::pin(crate::expect_okfail(self))
+  }
+
+  fn call<'a, C>(
+    &'a mut self,
+    cmd: &'a C
+  ) -> BoxFuture<'a, Result<C::Reply, Error>>
+  where
+    C: Command + Sync,
+    C::Reply: Send
+  {
+    Box::pin(crate::cmd::call(self, cmd))
+  }
+
+  #[cfg(feature = "msg")]
+  fn send_file<'a>(
+    &'a mut self,
+    path: impl AsRef<Path> + Send + 'a
+  ) -> BoxFuture<'a, Result<u64, Error>> {
+    use futures::sink::SinkExt;
+    use tokio::io::AsyncWriteExt;
+
+    Box::pin(async move {
+      let path = path.as_ref();
+      let mut f = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| Error::local_io(path, e))?;
+      // Make sure anything buffered by the Framed is sent before the file
+      // contents, which bypass it.
+      SinkExt::<&Telegram>::flush(self).await?;
+      let stream = self.get_mut();
+      let n = tokio::io::copy(&mut f, stream).await?;
+      stream.flush().await?;
+      crate::metrics::global().bytes_sent(n);
+      Ok(n)
+    })
+  }
+
+  #[cfg(feature = "msg")]
+  fn recv_to_file<'a>(
+    &'a mut self,
+    path: impl AsRef<Path> + Send + 'a,
+    len: usize
+  ) -> BoxFuture<'a, Result<(), Error>> {
+    use tokio_stream::StreamExt;
+
+    Box::pin(async move {
+      let path = path.as_ref();
+      if len == 0 {
+        tokio::fs::File::create(path)
+          .await
+          .map_err(|e| Error::local_io(path, e))?;
+        return Ok(());
+      }
+      self.codec_mut().expect_file(path, len)?;
+      match self.next().await {
+        Some(Ok(blather::codec::Input::File(_))) => Ok(()),
+        Some(Ok(_)) => Err(Error::bad_state("file data")),
+        Some(Err(e)) => Err(e.into()),
+        None => Err(Error::Disconnected)
+      }
+    })
+  }
+}
+
+impl<T> DdmwFramedExt for ClntIfFramed<T>
+where
+  T: AsyncRead + AsyncWrite + Unpin + Send
+{
+  fn send_ok_expected<'a>(
+    &'a mut self,
+    tg: &'a Telegram
+  ) -> BoxFuture<'a, Result<Params, Error>> {
+    Box::pin(crate::clntif::util::sendrecv(self, tg))
+  }
+
+  fn expect_okfail(&mut self) -> BoxFuture<'_, Result<Params, Error>> {
+    Box::pin(crate::clntif::util::expect_okfail(self))
+  }
+
+  fn call<'a, C>(
+    &'a mut self,
+    cmd: &'a C
+  ) -> BoxFuture<'a, Result<C::Reply, Error>>
+  where
+    C: Command + Sync,
+    C::Reply: Send
+  {
+    Box::pin(Requester::call(self, cmd))
+  }
+
+  #[cfg(feature = "msg")]
+  fn send_file<'a>(
+    &'a mut self,
+    path: impl AsRef<Path> + Send + 'a
+  ) -> BoxFuture<'a, Result<u64, Error>> {
+    use futures::sink::SinkExt;
+    use tokio::io::AsyncWriteExt;
+
+    Box::pin(async move {
+      let path = path.as_ref();
+      let mut f = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| Error::local_io(path, e))?;
+      SinkExt::<&Telegram>::flush(self).await?;
+      let stream = self.get_mut();
+      let n = tokio::io::copy(&mut f, stream).await?;
+      stream.flush().await?;
+      crate::metrics::global().bytes_sent(n);
+      Ok(n)
+    })
+  }
+
+  #[cfg(feature = "msg")]
+  fn recv_to_file<'a>(
+    &'a mut self,
+    path: impl AsRef<Path> + Send + 'a,
+    len: usize
+  ) -> BoxFuture<'a, Result<(), Error>> {
+    use crate::clntif::Input;
+    use tokio_stream::StreamExt;
+
+    Box::pin(async move {
+      let path = path.as_ref();
+      if len == 0 {
+        tokio::fs::File::create(path)
+          .await
+          .map_err(|e| Error::local_io(path, e))?;
+        return Ok(());
+      }
+      self.codec_mut().expect_file(path, len)?;
+      loop {
+        match self.next().await {
+          Some(Ok(Input::File(_))) => return Ok(()),
+          Some(Ok(Input::Paused)) => self.codec().resumed().await,
+          Some(Ok(_)) => return Err(Error::bad_state("file data")),
+          Some(Err(e)) => return Err(e),
+          None => return Err(Error::Disconnected)
+        }
+      }
+    })
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
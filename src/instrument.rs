@@ -0,0 +1,106 @@
+//! Instrumentation of request/reply exchanges.
+//!
+//! Every exchange is reported to the installed
+//! [`Metrics`](crate::metrics::Metrics) implementation.  With the `tracing`
+//! feature enabled it is also wrapped in a `ddmw.request` span, recording the
+//! request's topic, the number of bytes sent and received, how long the
+//! exchange took and its outcome.
+
+use std::future::Future;
+use std::time::Instant;
+
+use blather::{Params, Telegram};
+
+use crate::err::Error;
+use crate::metrics::{self, Outcome};
+
+
+fn outcome(res: &Result<Params, Error>) -> Outcome {
+  match res {
+    Ok(_) => Outcome::Ok,
+    Err(e) if e.is_server_error() => Outcome::Fail,
+    Err(_) => Outcome::Error
+  }
+}
+
+
+/// Run the request/reply exchange `fut` for the request `tg`.
+///
+/// If `count_bytes` is set the sizes of the request and its reply are
+/// reported as sent and received bytes.  Connections whose codec reports the
+/// bytes passing through it should not set it.
+pub(crate) async fn request<F>(
+  tg: &Telegram,
+  count_bytes: bool,
+  fut: F
+) -> Result<Params, Error>
+where
+  F: Future<Output = Result<Params, Error>>
+{
+  let topic = tg.get_topic().unwrap_or_default();
+  let m = metrics::global();
+  m.request_started(topic);
+  let start = Instant::now();
+
+  let res = traced(tg, fut).await;
+
+  if count_bytes {
+    m.bytes_sent(tg.calc_buf_size() as u64);
+    if let Ok(params) = &res {
+      m.bytes_received(params.calc_buf_size() as u64);
+    }
+  }
+  m.request_finished(topic, outcome(&res), start.elapsed());
+  res
+}
+
+
+#[cfg(feature = "tracing")]
+async fn traced<F>(tg: &Telegram, fut: F) -> Result<Params, Error>
+where
+  F: Future<Output = Result<Params, Error>>
+{
+  use tracing::field::Empty;
+  use tracing::Instrument;
+
+  let span = tracing::debug_span!(
+    "ddmw.request",
+    topic = tg.get_topic().unwrap_or_default(),
+    request_id = tg.get_str(crate::reqid::PARAM),
+    bytes_out = tg.calc_buf_size(),
+    bytes_in = Empty,
+    elapsed_ms = Empty,
+    outcome = Empty
+  );
+  let start = Instant::now();
+  let res = fut.instrument(span.clone()).await;
+  span.record("elapsed_ms", start.elapsed().as_millis() as u64);
+
+  let _enter = span.enter();
+  match &res {
+    Ok(params) => {
+      span.record("bytes_in", params.calc_buf_size());
+      span.record("outcome", "ok");
+      tracing::debug!("request succeeded");
+    }
+    Err(e) if e.is_server_error() => {
+      span.record("outcome", "fail");
+      tracing::debug!(error = %e, "server rejected request");
+    }
+    Err(e) => {
+      span.record("outcome", "error");
+      tracing::warn!(error = %e, "request failed");
+    }
+  }
+  res
+}
+
+#[cfg(not(feature = "tracing"))]
+async fn traced<F>(_tg: &Telegram, fut: F) -> Result<Params, Error>
+where
+  F: Future<Output = Result<Params, Error>>
+{
+  fut.await
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
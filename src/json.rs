@@ -0,0 +1,86 @@
+//! Conversion of telegrams to and from JSON.
+//!
+//! Enabled by the `json` feature.  A telegram is represented as an object
+//! with its topic and an object holding its parameters:
+//!
+//! ```json
+//! { "topic": "Msg", "params": { "_Ch": "2", "Len": "1024" } }
+//! ```
+//!
+//! This allows gateways to expose the client interface over HTTP or
+//! WebSockets, and structured logs to include complete telegrams.  Note that
+//! parameters are converted as-is; credentials are not redacted.
+
+use serde_json::{Map, Value};
+
+use blather::Telegram;
+
+use crate::err::Error;
+
+
+/// Convert a telegram to its JSON representation.
+///
+/// Parameter values are represented as strings.
+pub fn telegram_to_json(tg: &Telegram) -> Value {
+  let params: Map<String, Value> = tg
+    .get_params()
+    .get_inner()
+    .iter()
+    .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+    .collect();
+
+  let mut obj = Map::new();
+  obj.insert(
+    "topic".to_string(),
+    tg.get_topic()
+      .map_or(Value::Null, |t| Value::String(t.to_string()))
+  );
+  obj.insert("params".to_string(), Value::Object(params));
+  Value::Object(obj)
+}
+
+
+/// Construct a telegram from its JSON representation.
+///
+/// The `params` object is optional.  Besides strings, parameter values may
+/// be numbers or booleans, which are converted to their string form.
+///
+/// Fails with `Error::BadFormat` if the value isn't an object with a string
+/// `topic` or if a parameter value is of any other type.
+pub fn telegram_from_json(v: &Value) -> Result<Telegram, Error> {
+  let obj = v
+    .as_object()
+    .ok_or_else(|| Error::bad_format("telegram", "must be a JSON object"))?;
+  let topic = obj.get("topic").and_then(Value::as_str).ok_or_else(|| {
+    Error::bad_format("telegram", "missing a 'topic' string")
+  })?;
+
+  let mut tg = Telegram::new_topic(topic)?;
+  match obj.get("params") {
+    None | Some(Value::Null) => {}
+    Some(Value::Object(params)) => {
+      for (k, v) in params {
+        match v {
+          Value::String(s) => tg.add_str(k, s)?,
+          Value::Number(n) => tg.add_param(k, n)?,
+          Value::Bool(b) => tg.add_bool(k, *b)?,
+          _ => {
+            return Err(Error::bad_format(
+              format!("parameter '{}'", k),
+              "unsupported value type"
+            ))
+          }
+        }
+      }
+    }
+    Some(_) => {
+      return Err(Error::bad_format(
+        "telegram",
+        "'params' must be a JSON object"
+      ))
+    }
+  }
+  Ok(tg)
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
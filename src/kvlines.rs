@@ -0,0 +1,97 @@
+//! Conversions and typed accessors for [`KVLines`].
+//!
+//! List-style replies, such as account listings or log excerpts, are
+//! received as `KVLines`: an ordered list of key/value pairs in which keys
+//! may repeat.  [`KVLinesExt`] makes their contents available as plain pairs
+//! and parsed values.
+
+use std::str::FromStr;
+
+use blather::KVLines;
+
+use crate::err::Error;
+
+
+/// Convenience methods for reading the contents of a [`KVLines`].
+pub trait KVLinesExt {
+  /// Get the key/value pairs, in order.
+  fn to_pairs(&self) -> Vec<(String, String)>;
+
+  /// Iterate over the key/value pairs, in order.
+  fn pairs(&self) -> std::vec::IntoIter<(String, String)> {
+    self.to_pairs().into_iter()
+  }
+
+  /// Iterate over the values of all pairs with the key `key`, in order.
+  fn values_of<'a>(
+    &self,
+    key: &'a str
+  ) -> Box<dyn Iterator<Item = String> + 'a> {
+    Box::new(self.pairs().filter(move |(k, _)| k == key).map(|(_, v)| v))
+  }
+
+  /// Get the first value with the key `key`, parsed as `T`.
+  ///
+  /// Returns `Ok(None)` if there's no such key.
+  fn get_first<T: FromStr>(&self, key: &str) -> Result<Option<T>, Error> {
+    match self.values_of(key).next() {
+      Some(v) => parse(key, &v).map(Some),
+      None => Ok(None)
+    }
+  }
+
+  /// Get all values with the key `key`, in order, parsed as `T`.
+  fn get_all<T: FromStr>(&self, key: &str) -> Result<Vec<T>, Error> {
+    self.values_of(key).map(|v| parse(key, &v)).collect()
+  }
+
+  /// Get the key/value pairs as an `IndexMap`, which retains the order in
+  /// which keys first appeared.
+  ///
+  /// If a key occurs more than once its last value is kept.  Use
+  /// [`KVLinesExt::to_multimap()`] to keep all values.
+  #[cfg(feature = "indexmap")]
+  fn to_indexmap(&self) -> indexmap::IndexMap<String, String> {
+    self.pairs().collect()
+  }
+
+  /// Get the values of each key as an `IndexMap`, which retains the order in
+  /// which keys first appeared.
+  #[cfg(feature = "indexmap")]
+  fn to_multimap(&self) -> indexmap::IndexMap<String, Vec<String>> {
+    let mut map = indexmap::IndexMap::<String, Vec<String>>::new();
+    for (k, v) in self.pairs() {
+      map.entry(k).or_default().push(v);
+    }
+    map
+  }
+}
+
+impl KVLinesExt for KVLines {
+  fn to_pairs(&self) -> Vec<(String, String)> {
+    // blather doesn't expose the individual entries, so extract them from
+    // the wire representation.  Keys received over the wire never contain
+    // spaces, and values never contain line breaks.
+    let buf = match self.serialize() {
+      Ok(buf) => buf,
+      Err(_) => return Vec::new()
+    };
+    String::from_utf8_lossy(&buf)
+      .lines()
+      .filter(|line| !line.is_empty())
+      .map(|line| match line.find(' ') {
+        Some(idx) => (line[..idx].to_string(), line[idx + 1..].to_string()),
+        None => (line.to_string(), String::new())
+      })
+      .collect()
+  }
+}
+
+
+fn parse<T: FromStr>(key: &str, value: &str) -> Result<T, Error> {
+  value.parse::<T>().map_err(|_| {
+    Error::bad_format(format!("value of '{}'", key), "unable to parse")
+  })
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
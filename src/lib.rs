@@ -4,14 +4,57 @@
 //! This library provides low level functions to perform arbitrary calls
 //! to the server nodes as well as a few high-level helper functions that are
 //! built on top of the low level functions.
+//!
+//! # Codec-only builds
+//! Building with `default-features = false` leaves out the message helpers
+//! in [`msg`] (the `msg` feature) and the connection helpers (the
+//! `tokio-net` feature), and with them tokio's `fs` and `net` features.
+//! What remains is the [client interface codec](clntif), the error types,
+//! the telegram helpers and the request/reply helpers, for applications
+//! which bring their own transport or run in constrained environments.
 
 pub mod auth;
+pub mod checksum;
+#[cfg(feature = "tokio-net")]
+pub mod client;
+pub mod clntif;
+pub mod cmd;
+#[cfg(feature = "compat")]
+pub mod compat;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod deadline;
+#[cfg(feature = "tokio-net")]
+pub mod diag;
 pub mod err;
+pub mod framed;
+#[cfg(feature = "json")]
+pub mod json;
+pub mod kvlines;
+pub mod meta;
+pub mod metrics;
 pub mod mgmt;
+#[cfg(feature = "msg")]
 pub mod msg;
+pub mod params;
+pub mod prelude;
+pub mod recv;
+pub mod reqid;
+pub mod retry;
+pub mod seq;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod tgbuilder;
+pub mod version;
 
+mod instrument;
+#[cfg(feature = "rt")]
+mod task;
 mod utils;
 
+use std::fmt;
+use std::str::FromStr;
+
 use futures::sink::SinkExt;
 
 use tokio::io::{AsyncRead, AsyncWrite};
@@ -20,65 +63,227 @@ use tokio_stream::StreamExt;
 
 use tokio_util::codec::Framed;
 
-use blather::{codec, Telegram};
+use blather::{codec, Params, Telegram};
+
+use params::ParamsExt;
+
+pub use blather;
 
+pub use clntif::ClntIfFramed;
 pub use err::Error;
+pub use version::Version;
+
+
+/// A connection framed using [`blather::Codec`], as expected by most of the
+/// helpers in this crate.
+pub type DdmwFramed<T> = Framed<T, codec::Codec>;
+
+
+/// How [`OptObjRef::Current`] is displayed and parsed.
+const CURRENT: &str = "(current)";
 
 
 /// Reference an account; with the option to implicitly reference self.
+///
+/// Parsing a string yields an `Id` if it is an integer and a `Name`
+/// otherwise, so command line arguments can be passed straight through.
+/// `(current)`, as `Current` is displayed, parses back to `Current`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
 pub enum OptObjRef {
+  #[default]
   Current,
   Id(i64),
   Name(String)
 }
 
 /// Explicitly reference an account, either by numeric identifier or name.
+///
+/// Parsing a string yields an `Id` if it is an integer and a `Name`
+/// otherwise.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ObjRef {
   Id(i64),
   Name(String)
 }
 
+impl ObjRef {
+  /// Add the reference to `tg` as an `Id` or `Name` parameter.
+  pub(crate) fn add_to(&self, tg: &mut Telegram) -> Result<(), Error> {
+    match self {
+      ObjRef::Id(id) => tg.add_param("Id", id)?,
+      ObjRef::Name(nm) => tg.add_str("Name", nm)?
+    }
+    Ok(())
+  }
+}
+
+impl OptObjRef {
+  /// Add the reference to `tg` as an `Id` or `Name` parameter.  Nothing is
+  /// added for `Current`.
+  pub(crate) fn add_to(&self, tg: &mut Telegram) -> Result<(), Error> {
+    match self {
+      OptObjRef::Current => Ok(()),
+      OptObjRef::Id(id) => ObjRef::Id(*id).add_to(tg),
+      OptObjRef::Name(nm) => {
+        tg.add_str("Name", nm)?;
+        Ok(())
+      }
+    }
+  }
+}
+
+impl FromStr for ObjRef {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    if s.is_empty() {
+      return Err(Error::bad_format("object reference", "empty"));
+    }
+    match s.parse::<i64>() {
+      Ok(id) => Ok(ObjRef::Id(id)),
+      Err(_) => Ok(ObjRef::Name(s.to_string()))
+    }
+  }
+}
+
+impl FromStr for OptObjRef {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    if s == CURRENT {
+      return Ok(OptObjRef::Current);
+    }
+    Ok(s.parse::<ObjRef>()?.into())
+  }
+}
+
+impl fmt::Display for ObjRef {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ObjRef::Id(id) => write!(f, "{}", id),
+      ObjRef::Name(nm) => write!(f, "{}", nm)
+    }
+  }
+}
+
+/// `Current` is displayed as `(current)`.
+impl fmt::Display for OptObjRef {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      OptObjRef::Current => f.write_str(CURRENT),
+      OptObjRef::Id(id) => write!(f, "{}", id),
+      OptObjRef::Name(nm) => write!(f, "{}", nm)
+    }
+  }
+}
+
+impl From<i64> for ObjRef {
+  fn from(id: i64) -> Self {
+    ObjRef::Id(id)
+  }
+}
+
+impl From<&str> for ObjRef {
+  fn from(name: &str) -> Self {
+    ObjRef::Name(name.to_string())
+  }
+}
+
+impl From<String> for ObjRef {
+  fn from(name: String) -> Self {
+    ObjRef::Name(name)
+  }
+}
+
+impl From<i64> for OptObjRef {
+  fn from(id: i64) -> Self {
+    OptObjRef::Id(id)
+  }
+}
+
+impl From<&str> for OptObjRef {
+  fn from(name: &str) -> Self {
+    OptObjRef::Name(name.to_string())
+  }
+}
+
+impl From<String> for OptObjRef {
+  fn from(name: String) -> Self {
+    OptObjRef::Name(name)
+  }
+}
+
+impl From<ObjRef> for OptObjRef {
+  fn from(r: ObjRef) -> Self {
+    match r {
+      ObjRef::Id(id) => OptObjRef::Id(id),
+      ObjRef::Name(nm) => OptObjRef::Name(nm)
+    }
+  }
+}
+
 
 /// Send a telegram and wait for a reply.
+///
+/// Errors are wrapped in an [`Error::Command`] identifying the request.
+/// If [request identifiers](crate::reqid) are enabled the request is stamped
+/// with one before it is sent.
 pub async fn sendrecv<T: AsyncRead + AsyncWrite + Unpin>(
   conn: &mut Framed<T, blather::Codec>,
   tg: &Telegram
 ) -> Result<blather::Params, Error> {
-  conn.send(tg).await?;
-  crate::expect_okfail(conn).await
+  let tg = reqid::stamp(tg);
+  let tg = tg.as_ref();
+  instrument::request(tg, true, async {
+    conn
+      .send(tg)
+      .await
+      .map_err(|e| Error::from(e).with_command(tg))?;
+    crate::expect_okfail(conn)
+      .await
+      .map_err(|e| e.with_command(tg))
+  })
+  .await
 }
 
 
 /// Waits for a message and ensures that it's Ok or Fail.
-/// Converts Fail state to an Error::ServerError.
+/// Converts Fail state to an error using [`Error::from_fail()`].
 /// Returns a Params buffer containig the Ok parameters on success.
+///
+/// For connections framed using the client interface codec use
+/// [`DdmwFramedExt::expect_okfail()`](framed::DdmwFramedExt::expect_okfail)
+/// instead.
 pub async fn expect_okfail<T: AsyncRead + AsyncWrite + Unpin>(
   conn: &mut Framed<T, blather::Codec>
 ) -> Result<blather::Params, Error> {
-  if let Some(o) = conn.next().await {
-    let o = o?;
-    match o {
-      codec::Input::Telegram(tg) => {
-        if let Some(topic) = tg.get_topic() {
-          if topic == "Ok" {
-            return Ok(tg.into_params());
-          } else if topic == "Fail" {
-            return Err(Error::ServerError(tg.into_params()));
-          }
-        }
-      }
-      _ => {
-        println!("unexpected reply");
-      }
-    }
-    return Err(Error::BadState("Unexpected reply from server.".to_string()));
+  match conn.next().await {
+    Some(Ok(codec::Input::Telegram(tg))) => okfail(tg),
+    Some(Ok(_)) => Err(unexpected_reply()),
+    Some(Err(e)) => Err(e.into()),
+    None => Err(Error::Disconnected)
   }
+}
+
+
+/// Interpret a reply telegram, which must be `Ok` or `Fail`.
+pub(crate) fn okfail(tg: Telegram) -> Result<Params, Error> {
+  match tg.get_topic() {
+    Some("Ok") => Ok(tg.into_params()),
+    Some("Fail") => Err(Error::from_fail(tg.into_params())),
+    _ => Err(unexpected_reply())
+  }
+}
 
-  Err(Error::Disconnected)
+pub(crate) fn unexpected_reply() -> Error {
+  Error::BadState {
+    expected: "an Ok or Fail reply".to_string(),
+    found: Some("another reply".to_string())
+  }
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct DDLinkInfo {
   pub engine: String,
   pub protocol: ddmw_types::node::ddlnk::Protocol,
@@ -86,89 +291,163 @@ pub struct DDLinkInfo {
 }
 
 
+/// Parameters of the `GetNodeInfo` reply which are parsed into the fields of
+/// [`NodeInfo`], whenever they are present.
+const NODEINFO_KEYS: &[&str] = &["ddmw.node", "ddmw.version", "os.name"];
+
+/// Parameters of the `GetNodeInfo` reply which make up the ddlink block.
+/// They are only parsed if all of them are present.
+const DDLINK_KEYS: &[&str] = &[
+  "ddmw.ddlink.engine",
+  "ddmw.ddlink.protocol",
+  "ddmw.ddlink.protimpl"
+];
+
 #[derive(Debug)]
 pub struct NodeInfo {
+  /// The version string, as reported by the server.
   pub version: String,
-  pub os_name: String,
+
+  /// The version, if the version string is a valid semantic version.
+  pub semver: Option<Version>,
+
+  /// The node's operating system, if reported.
+  pub os_name: Option<String>,
+
   pub nodetype: ddmw_types::node::Type,
-  pub ddlnk: DDLinkInfo
+
+  /// The node's diode link configuration, if reported.
+  pub ddlnk: Option<DDLinkInfo>,
+
+  /// Any reply parameters not parsed into the other fields, such as ones
+  /// added by newer servers.
+  pub extra: Params
 }
 
+impl NodeInfo {
+  /// Returns `true` if the server's version is `major.minor.0` or later.
+  ///
+  /// Returns `false` if the version string could not be parsed.
+  pub fn at_least(&self, major: u64, minor: u64) -> bool {
+    self
+      .semver
+      .as_ref()
+      .is_some_and(|v| v.at_least(major, minor))
+  }
 
+  /// Returns `true` if the node is a sender, which accepts messages.
+  pub fn is_sender(&self) -> bool {
+    self.nodetype == ddmw_types::node::Type::Sender
+  }
+
+  /// Returns `true` if the node is a receiver, which delivers messages.
+  pub fn is_receiver(&self) -> bool {
+    self.nodetype == ddmw_types::node::Type::Receiver
+  }
+}
+
+
+/// Get information about the node the connection is connected to.
+///
+/// Only the node type and version are required; the OS name and the
+/// ddlink block are set to `None` if the server omits them, as some hardened
+/// nodes do.  Use [`get_nodeinfo_strict()`] to require them.
 pub async fn get_nodeinfo<T: AsyncRead + AsyncWrite + Unpin>(
   conn: &mut Framed<T, blather::Codec>
 ) -> Result<NodeInfo, Error> {
-  let mut tg = Telegram::new();
-  tg.set_topic("GetNodeInfo")?;
-  let params = sendrecv(conn, &tg).await?;
+  cmd::call(conn, &GetNodeInfo).await
+}
 
-  let nodetype = match params.get_str("ddmw.node") {
-    Some(s) => s.parse::<ddmw_types::node::Type>(),
-    None => return Err(Error::MissingData("ddmw.node not found".to_string()))
-  };
-  let nodetype = match nodetype {
-    Ok(nt) => nt,
-    Err(_) => return Err(Error::UnknownData("Unknown node type".to_string()))
-  };
 
-  let version = match params.get_str("ddmw.version") {
-    Some(s) => s.to_string(),
-    None => {
-      return Err(Error::MissingData("ddmw.version not found".to_string()))
-    }
-  };
+/// Get information about the node the connection is connected to, failing
+/// with `Error::MissingData` if the server omits any of its fields.
+pub async fn get_nodeinfo_strict<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>
+) -> Result<NodeInfo, Error> {
+  cmd::call(conn, &GetNodeInfoStrict).await
+}
 
-  let os_name = match params.get_str("os.name") {
-    Some(s) => s.to_string(),
-    None => return Err(Error::MissingData("os.name not found".to_string()))
-  };
 
-  let engine = match params.get_str("ddmw.ddlink.engine") {
-    Some(s) => s.to_string(),
-    None => {
-      return Err(Error::MissingData(
-        "ddmw.ddlink.engine not found".to_string()
-      ))
-    }
-  };
-  let protocol = match params.get_str("ddmw.ddlink.protocol") {
-    Some(s) => s.parse::<ddmw_types::node::ddlnk::Protocol>(),
-    None => {
-      return Err(Error::MissingData(
-        "ddmw.ddlnk.protocol not found".to_string()
-      ))
-    }
-  };
-  let protocol = match protocol {
-    Ok(s) => s,
-    Err(_) => {
-      return Err(Error::UnknownData("Unknown protocol type".to_string()))
-    }
+/// Get information about the node, as a [`Command`](cmd::Command).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GetNodeInfo;
+
+impl cmd::Command for GetNodeInfo {
+  const TOPIC: &'static str = "GetNodeInfo";
+  type Reply = NodeInfo;
+
+  fn parse_reply(params: Params) -> Result<NodeInfo, Error> {
+    parse_nodeinfo(params, false)
+  }
+}
+
+
+/// Get information about the node, failing with `Error::MissingData` if the
+/// server omits any of its fields.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GetNodeInfoStrict;
+
+impl cmd::Command for GetNodeInfoStrict {
+  const TOPIC: &'static str = "GetNodeInfo";
+  type Reply = NodeInfo;
+
+  fn parse_reply(params: Params) -> Result<NodeInfo, Error> {
+    parse_nodeinfo(params, true)
+  }
+}
+
+
+fn parse_nodeinfo(params: Params, strict: bool) -> Result<NodeInfo, Error> {
+  let nodetype = params.get_enum::<ddmw_types::node::Type>("ddmw.node")?;
+  let version = params.require_str("ddmw.version")?.to_string();
+  let os_name = if strict {
+    Some(params.require_str("os.name")?.to_string())
+  } else {
+    params.get_str("os.name").map(str::to_string)
   };
-  let protimpl = match params.get_str("ddmw.ddlink.protimpl") {
-    Some(s) => s.parse::<ddmw_types::node::ddlnk::ProtImpl>(),
-    None => {
-      return Err(Error::MissingData(
-        "ddmw.ddlnk.protimpl not found".to_string()
-      ))
-    }
+
+  let have_ddlnk = DDLINK_KEYS.iter().all(|k| params.have(k));
+  let ddlnk = if strict || have_ddlnk {
+    Some(parse_ddlink(&params)?)
+  } else {
+    None
   };
-  let protimpl = match protimpl {
-    Ok(s) => s,
-    Err(_) => {
-      return Err(Error::UnknownData("Unknown protimpl type".to_string()))
+
+  let semver = version.parse::<Version>().ok();
+
+  let mut extra = params.into_inner();
+  for key in NODEINFO_KEYS {
+    extra.remove(*key);
+  }
+  // A partial ddlink block isn't parsed, so it's left for the caller.
+  if ddlnk.is_some() {
+    for key in DDLINK_KEYS {
+      extra.remove(*key);
     }
-  };
+  }
 
   Ok(NodeInfo {
     version,
+    semver,
     os_name,
     nodetype,
-    ddlnk: DDLinkInfo {
-      engine,
-      protocol,
-      protimpl
-    }
+    ddlnk,
+    extra: Params::from(extra)
+  })
+}
+
+
+fn parse_ddlink(params: &Params) -> Result<DDLinkInfo, Error> {
+  use ddmw_types::node::ddlnk::{ProtImpl, Protocol};
+
+  let engine = params.require_str("ddmw.ddlink.engine")?.to_string();
+  let protocol = params.get_enum::<Protocol>("ddmw.ddlink.protocol")?;
+  let protimpl = params.get_enum::<ProtImpl>("ddmw.ddlink.protimpl")?;
+
+  Ok(DDLinkInfo {
+    engine,
+    protocol,
+    protimpl
   })
 }
 
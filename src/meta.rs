@@ -0,0 +1,176 @@
+//! Conventional message metadata fields.
+//!
+//! The server does not interpret message metadata; it is simply passed to the
+//! receiving integration.  `MsgMeta` defines a set of commonly needed fields
+//! and the keys used to store them, so sender and receiver integrations can
+//! interoperate without agreeing on their own key names.
+
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use blather::Params;
+
+use crate::err::Error;
+
+/// Metadata key for the original file name.
+pub const FILENAME_KEY: &str = "FileName";
+
+/// Metadata key for the payload's content (MIME) type.
+pub const CONTENT_TYPE_KEY: &str = "ContentType";
+
+/// Metadata key for the creation time, in seconds since the unix epoch.
+pub const CREATED_KEY: &str = "Created";
+
+/// Metadata key for the name of the host the message originated from.
+pub const ORIGIN_KEY: &str = "Origin";
+
+/// Metadata key for the message's tags, stored as a comma-separated list.
+pub const TAGS_KEY: &str = "Tags";
+
+
+/// Standard message metadata.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MsgMeta {
+  /// Original file name (without any directory components).
+  pub filename: Option<String>,
+
+  /// Payload content type, such as `application/json`.
+  pub content_type: Option<String>,
+
+  /// Time at which the message, or the file it contains, was created.
+  pub created: Option<SystemTime>,
+
+  /// Name of the host the message originated from.
+  pub origin: Option<String>,
+
+  /// Free-form labels which receivers can use to select which messages they
+  /// handle.  Tags must not be empty or contain commas.
+  pub tags: Vec<String>
+}
+
+impl MsgMeta {
+  pub fn new() -> Self {
+    MsgMeta::default()
+  }
+
+  /// Store the fields which have been set in a new `Params` buffer.
+  pub fn to_params(&self) -> Result<Params, Error> {
+    let mut params = Params::new();
+    self.add_to_params(&mut params)?;
+    Ok(params)
+  }
+
+  /// Store the fields which have been set in an existing `Params` buffer,
+  /// which may contain application-specific keys as well.
+  pub fn add_to_params(&self, params: &mut Params) -> Result<(), Error> {
+    if let Some(ref fname) = self.filename {
+      params.add_str(FILENAME_KEY, fname)?;
+    }
+    if let Some(ref ct) = self.content_type {
+      params.add_str(CONTENT_TYPE_KEY, ct)?;
+    }
+    if let Some(created) = self.created {
+      let ts = match created.duration_since(UNIX_EPOCH) {
+        Ok(ts) => ts.as_secs(),
+        Err(_) => {
+          let e = "predates the unix epoch";
+          return Err(Error::bad_format("creation time", e));
+        }
+      };
+      params.add_param(CREATED_KEY, ts)?;
+    }
+    if let Some(ref origin) = self.origin {
+      params.add_str(ORIGIN_KEY, origin)?;
+    }
+    if !self.tags.is_empty() {
+      for tag in self.tags.iter() {
+        if tag.is_empty() || tag.contains(',') {
+          return Err(Error::bad_format(
+            "tag",
+            format!("invalid tag '{}'", tag)
+          ));
+        }
+      }
+      params.add_strit(TAGS_KEY, self.tags.iter())?;
+    }
+    Ok(())
+  }
+
+  /// Extract the standard fields from a metadata `Params` buffer.
+  ///
+  /// Fields which are not present are left unset.  Keys which are not part of
+  /// the standard schema are ignored.
+  pub fn from_params(params: &Params) -> Result<Self, Error> {
+    let created = match params.get_str(CREATED_KEY) {
+      Some(s) => match s.parse::<u64>() {
+        Ok(ts) => Some(UNIX_EPOCH + Duration::from_secs(ts)),
+        Err(_) => {
+          return Err(Error::bad_format(
+            format!("value of '{}'", CREATED_KEY),
+            format!("invalid timestamp '{}'", s)
+          ))
+        }
+      },
+      None => None
+    };
+
+    Ok(MsgMeta {
+      filename: params.get_str(FILENAME_KEY).map(|s| s.to_string()),
+      content_type: params.get_str(CONTENT_TYPE_KEY).map(|s| s.to_string()),
+      created,
+      origin: params.get_str(ORIGIN_KEY).map(|s| s.to_string()),
+      tags: split_tags(params).map(|t| t.to_string()).collect()
+    })
+  }
+}
+
+
+/// Get the set of tags stored in a metadata `Params` buffer.
+pub fn get_tags(params: &Params) -> HashSet<String> {
+  split_tags(params).map(|t| t.to_string()).collect()
+}
+
+
+fn split_tags(params: &Params) -> impl Iterator<Item = &str> {
+  params
+    .get_str(TAGS_KEY)
+    .unwrap_or("")
+    .split(',')
+    .filter(|t| !t.is_empty())
+}
+
+
+/// Select messages based on their tags.
+#[derive(Debug, Clone, Default)]
+pub enum TagFilter {
+  /// Accept all messages, tagged or not.
+  #[default]
+  All,
+
+  /// Accept messages which have at least one of the tags.
+  AnyOf(HashSet<String>),
+
+  /// Accept messages which have every one of the tags.
+  AllOf(HashSet<String>),
+
+  /// Accept messages which have none of the tags.
+  NoneOf(HashSet<String>)
+}
+
+impl TagFilter {
+  /// Check whether a message, identified by its metadata, passes the filter.
+  pub fn matches(&self, meta: &Params) -> bool {
+    if let TagFilter::All = self {
+      return true;
+    }
+    let tags = get_tags(meta);
+    match self {
+      TagFilter::All => true,
+      TagFilter::AnyOf(set) => !tags.is_disjoint(set),
+      TagFilter::AllOf(set) => set.is_subset(&tags),
+      TagFilter::NoneOf(set) => tags.is_disjoint(set)
+    }
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
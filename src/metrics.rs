@@ -0,0 +1,528 @@
+//! Hooks for collecting metrics.
+//!
+//! The helpers in this crate report requests and transferred bytes to a
+//! [`Metrics`] implementation.  By default the reports are discarded;
+//! applications which want to expose them install their own implementation
+//! using [`set_global()`], or use the bundled [`Counters`]:
+//!
+//! ```
+//! use std::sync::Arc;
+//! use tokio_ddmw::metrics::{self, Counters};
+//!
+//! let counters = Arc::new(Counters::new());
+//! metrics::set_global(counters.clone());
+//!
+//! // .. later, when scraped ..
+//! let text = counters.render("ddmw");
+//! ```
+//!
+//! Connections framed using [`clntif::Codec`](crate::clntif::Codec) report
+//! the bytes passing through the codec, which requires the metrics object to
+//! be attached to the codec using
+//! [`Codec::set_metrics_sink()`](crate::clntif::Codec::set_metrics_sink).
+//!
+//! [`ThroughputMeter`] keeps rolling averages of the message rates per
+//! channel, for applications which pace their sending or display live
+//! rates.  Several implementations can be installed together using
+//! [`Fanout`].
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+
+/// The outcome of a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+  /// The server replied `Ok`.
+  Ok,
+
+  /// The server replied `Fail`.
+  Fail,
+
+  /// The request failed without a reply from the server.
+  Error
+}
+
+
+/// Receiver of metrics reports.
+///
+/// All methods have no-op default implementations, so implementations only
+/// need to provide the reports they are interested in.  The methods are
+/// called from within the I/O paths and should not block.
+pub trait Metrics: Send + Sync {
+  /// A request with the topic `topic` is about to be sent.
+  fn request_started(&self, _topic: &str) {}
+
+  /// The request with the topic `topic` completed after `elapsed`.
+  fn request_finished(
+    &self,
+    _topic: &str,
+    _outcome: Outcome,
+    _elapsed: Duration
+  ) {
+  }
+
+  /// `n` bytes were sent to the server.
+  fn bytes_sent(&self, _n: u64) {}
+
+  /// `n` bytes were received from the server.
+  fn bytes_received(&self, _n: u64) {}
+
+  /// A connection to the server was reestablished.
+  fn reconnected(&self) {}
+
+  /// A message of `bytes` bytes, counting both metadata and payload, was
+  /// sent on channel `ch` and accepted by the server.
+  fn message_sent(&self, _ch: u8, _bytes: u64) {}
+
+  /// A message of `bytes` bytes, counting both metadata and payload, was
+  /// received on channel `ch`.
+  fn message_received(&self, _ch: u8, _bytes: u64) {}
+}
+
+
+/// [`Metrics`] implementation which discards all reports.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {}
+
+
+/// [`Metrics`] implementation which passes all reports on to several other
+/// implementations, in the order they were added.
+///
+/// ```
+/// use std::sync::Arc;
+/// use tokio_ddmw::metrics::{self, Counters, Fanout, ThroughputMeter};
+///
+/// let counters = Arc::new(Counters::new());
+/// let meter = Arc::new(ThroughputMeter::new());
+/// metrics::set_global(Arc::new(
+///   Fanout::new().with(counters.clone()).with(meter.clone())
+/// ));
+/// ```
+#[derive(Clone, Default)]
+pub struct Fanout {
+  sinks: Vec<Arc<dyn Metrics>>
+}
+
+impl Fanout {
+  pub fn new() -> Self {
+    Fanout::default()
+  }
+
+  /// Add `sink` to the implementations reports are passed on to.
+  pub fn with(mut self, sink: Arc<dyn Metrics>) -> Self {
+    self.push(sink);
+    self
+  }
+
+  /// Add `sink` to the implementations reports are passed on to.
+  pub fn push(&mut self, sink: Arc<dyn Metrics>) {
+    self.sinks.push(sink);
+  }
+}
+
+impl Metrics for Fanout {
+  fn request_started(&self, topic: &str) {
+    self.sinks.iter().for_each(|m| m.request_started(topic));
+  }
+
+  fn request_finished(
+    &self,
+    topic: &str,
+    outcome: Outcome,
+    elapsed: Duration
+  ) {
+    self
+      .sinks
+      .iter()
+      .for_each(|m| m.request_finished(topic, outcome, elapsed));
+  }
+
+  fn bytes_sent(&self, n: u64) {
+    self.sinks.iter().for_each(|m| m.bytes_sent(n));
+  }
+
+  fn bytes_received(&self, n: u64) {
+    self.sinks.iter().for_each(|m| m.bytes_received(n));
+  }
+
+  fn reconnected(&self) {
+    self.sinks.iter().for_each(|m| m.reconnected());
+  }
+
+  fn message_sent(&self, ch: u8, bytes: u64) {
+    self.sinks.iter().for_each(|m| m.message_sent(ch, bytes));
+  }
+
+  fn message_received(&self, ch: u8, bytes: u64) {
+    self
+      .sinks
+      .iter()
+      .for_each(|m| m.message_received(ch, bytes));
+  }
+}
+
+
+fn global_cell() -> &'static RwLock<Arc<dyn Metrics>> {
+  static GLOBAL: OnceLock<RwLock<Arc<dyn Metrics>>> = OnceLock::new();
+  GLOBAL.get_or_init(|| RwLock::new(Arc::new(NoopMetrics)))
+}
+
+/// Install the [`Metrics`] implementation the crate's helpers report to.
+pub fn set_global(metrics: Arc<dyn Metrics>) {
+  let mut global = match global_cell().write() {
+    Ok(global) => global,
+    Err(poisoned) => poisoned.into_inner()
+  };
+  *global = metrics;
+}
+
+/// Get the installed [`Metrics`] implementation.
+pub fn global() -> Arc<dyn Metrics> {
+  match global_cell().read() {
+    Ok(global) => Arc::clone(&global),
+    Err(poisoned) => Arc::clone(&poisoned.into_inner())
+  }
+}
+
+
+/// [`Metrics`] implementation which keeps a set of counters, and can render
+/// them in the Prometheus text exposition format.
+#[derive(Debug, Default)]
+pub struct Counters {
+  requests_ok: AtomicU64,
+  requests_fail: AtomicU64,
+  requests_error: AtomicU64,
+  request_micros: AtomicU64,
+  bytes_sent: AtomicU64,
+  bytes_received: AtomicU64,
+  reconnects: AtomicU64
+}
+
+impl Counters {
+  pub fn new() -> Self {
+    Counters::default()
+  }
+
+  /// Number of completed requests with the outcome `outcome`.
+  pub fn requests(&self, outcome: Outcome) -> u64 {
+    self.request_counter(outcome).load(Ordering::Relaxed)
+  }
+
+  /// Total time spent waiting for requests to complete.
+  pub fn request_time(&self) -> Duration {
+    Duration::from_micros(self.request_micros.load(Ordering::Relaxed))
+  }
+
+  pub fn sent_bytes(&self) -> u64 {
+    self.bytes_sent.load(Ordering::Relaxed)
+  }
+
+  pub fn received_bytes(&self) -> u64 {
+    self.bytes_received.load(Ordering::Relaxed)
+  }
+
+  pub fn reconnects(&self) -> u64 {
+    self.reconnects.load(Ordering::Relaxed)
+  }
+
+  /// Take a snapshot of the counters.
+  pub fn stats(&self) -> NodeStats {
+    NodeStats {
+      requests_ok: self.requests(Outcome::Ok),
+      requests_fail: self.requests(Outcome::Fail),
+      requests_error: self.requests(Outcome::Error),
+      request_time: self.request_time(),
+      sent_bytes: self.sent_bytes(),
+      received_bytes: self.received_bytes(),
+      reconnects: self.reconnects()
+    }
+  }
+
+  /// Render the counters in the Prometheus text exposition format, using
+  /// `prefix` as the metric name prefix.
+  pub fn render(&self, prefix: &str) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(out, "# TYPE {}_requests_total counter", prefix);
+    for (label, outcome) in &[
+      ("ok", Outcome::Ok),
+      ("fail", Outcome::Fail),
+      ("error", Outcome::Error)
+    ] {
+      let _ = writeln!(
+        out,
+        "{}_requests_total{{outcome=\"{}\"}} {}",
+        prefix,
+        label,
+        self.requests(*outcome)
+      );
+    }
+
+    let _ = writeln!(out, "# TYPE {}_request_seconds_total counter", prefix);
+    let _ = writeln!(
+      out,
+      "{}_request_seconds_total {}",
+      prefix,
+      self.request_time().as_secs_f64()
+    );
+
+    for (name, value) in &[
+      ("sent_bytes_total", self.sent_bytes()),
+      ("received_bytes_total", self.received_bytes()),
+      ("reconnects_total", self.reconnects())
+    ] {
+      let _ = writeln!(out, "# TYPE {}_{} counter", prefix, name);
+      let _ = writeln!(out, "{}_{} {}", prefix, name, value);
+    }
+
+    out
+  }
+
+  fn request_counter(&self, outcome: Outcome) -> &AtomicU64 {
+    match outcome {
+      Outcome::Ok => &self.requests_ok,
+      Outcome::Fail => &self.requests_fail,
+      Outcome::Error => &self.requests_error
+    }
+  }
+}
+
+impl Metrics for Counters {
+  fn request_finished(
+    &self,
+    _topic: &str,
+    outcome: Outcome,
+    elapsed: Duration
+  ) {
+    self
+      .request_counter(outcome)
+      .fetch_add(1, Ordering::Relaxed);
+    self
+      .request_micros
+      .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+  }
+
+  fn bytes_sent(&self, n: u64) {
+    self.bytes_sent.fetch_add(n, Ordering::Relaxed);
+  }
+
+  fn bytes_received(&self, n: u64) {
+    self.bytes_received.fetch_add(n, Ordering::Relaxed);
+  }
+
+  fn reconnected(&self) {
+    self.reconnects.fetch_add(1, Ordering::Relaxed);
+  }
+}
+
+
+/// A snapshot of a [`Counters`] object, as returned by [`Counters::stats()`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeStats {
+  pub requests_ok: u64,
+  pub requests_fail: u64,
+  pub requests_error: u64,
+
+  /// Total time spent waiting for requests to complete.
+  pub request_time: Duration,
+
+  pub sent_bytes: u64,
+  pub received_bytes: u64,
+  pub reconnects: u64
+}
+
+
+/// Resolution of the [`ThroughputMeter`] windows.
+const SLOT: Duration = Duration::from_millis(100);
+
+/// Number of slots in the longest window, one minute.
+const SLOTS: usize = 600;
+
+/// Number of slots in the shortest window, one second.
+const SHORT_SLOTS: usize = 10;
+
+
+/// The direction of the messages a rate applies to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Direction {
+  Sent,
+  Received
+}
+
+
+/// Rolling average message rates, as returned by [`ThroughputMeter::rates()`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rates {
+  /// Bytes per second, averaged over the last second.
+  pub bytes_1s: f64,
+
+  /// Messages per second, averaged over the last second.
+  pub msgs_1s: f64,
+
+  /// Bytes per second, averaged over the last minute.
+  pub bytes_1m: f64,
+
+  /// Messages per second, averaged over the last minute.
+  pub msgs_1m: f64
+}
+
+
+/// Message and byte counts of a channel, in 100ms slots covering the last
+/// minute.
+struct Window {
+  slots: Box<[(u64, u64); SLOTS]>,
+
+  /// Index, counted from the meter's epoch, of the most recent slot.
+  current: u64
+}
+
+impl Window {
+  fn new(current: u64) -> Self {
+    Window {
+      slots: Box::new([(0, 0); SLOTS]),
+      current
+    }
+  }
+
+  /// Move the window forward to slot `now`, clearing the slots passed.
+  fn advance(&mut self, now: u64) {
+    if now <= self.current {
+      return;
+    }
+    let passed = (now - self.current).min(SLOTS as u64);
+    for n in 1..=passed {
+      self.slots[((self.current + n) % SLOTS as u64) as usize] = (0, 0);
+    }
+    self.current = now;
+  }
+
+  fn add(&mut self, bytes: u64) {
+    let slot = &mut self.slots[(self.current % SLOTS as u64) as usize];
+    slot.0 += bytes;
+    slot.1 += 1;
+  }
+
+  fn sum(&self, nslots: usize) -> (u64, u64) {
+    (0..nslots as u64)
+      .map(|n| {
+        let idx = (self.current + SLOTS as u64 - n) % SLOTS as u64;
+        self.slots[idx as usize]
+      })
+      .fold((0, 0), |acc, s| (acc.0 + s.0, acc.1 + s.1))
+  }
+}
+
+
+/// [`Metrics`] implementation which keeps rolling averages of the rates at
+/// which messages are sent and received on each channel, over the last
+/// second and the last minute.
+///
+/// The averages have a resolution of 100ms, and count messages as they
+/// complete, so a single large message appears as a burst.  Rates are
+/// averaged over the whole window even shortly after the meter was created.
+/// Use [`Fanout`] to install a meter alongside another implementation.
+///
+/// ```
+/// use std::sync::Arc;
+/// use tokio_ddmw::metrics::{self, Direction, ThroughputMeter};
+///
+/// let meter = Arc::new(ThroughputMeter::new());
+/// metrics::set_global(meter.clone());
+///
+/// // .. later ..
+/// let rates = meter.rates(Direction::Sent, 1);
+/// println!("{:.0} bytes/s", rates.bytes_1s);
+/// ```
+pub struct ThroughputMeter {
+  epoch: Instant,
+  windows: Mutex<HashMap<(Direction, u8), Window>>
+}
+
+impl ThroughputMeter {
+  /// Create a meter with no messages recorded.  The windows start out
+  /// empty, so all rates are zero until messages are recorded.
+  pub fn new() -> Self {
+    ThroughputMeter {
+      epoch: Instant::now(),
+      windows: Mutex::new(HashMap::new())
+    }
+  }
+
+  /// Record a message of `bytes` bytes on channel `ch`.
+  pub fn record(&self, dir: Direction, ch: u8, bytes: u64) {
+    let now = self.slot();
+    let mut windows = self.lock();
+    let w = windows.entry((dir, ch)).or_insert_with(|| Window::new(now));
+    w.advance(now);
+    w.add(bytes);
+  }
+
+  /// Get the current rates of messages in direction `dir` on channel `ch`.
+  pub fn rates(&self, dir: Direction, ch: u8) -> Rates {
+    let now = self.slot();
+    let mut windows = self.lock();
+    let w = match windows.get_mut(&(dir, ch)) {
+      Some(w) => w,
+      None => return Rates::default()
+    };
+    w.advance(now);
+
+    let short = SLOT.as_secs_f64() * SHORT_SLOTS as f64;
+    let long = SLOT.as_secs_f64() * SLOTS as f64;
+    let (bytes_1s, msgs_1s) = w.sum(SHORT_SLOTS);
+    let (bytes_1m, msgs_1m) = w.sum(SLOTS);
+    Rates {
+      bytes_1s: bytes_1s as f64 / short,
+      msgs_1s: msgs_1s as f64 / short,
+      bytes_1m: bytes_1m as f64 / long,
+      msgs_1m: msgs_1m as f64 / long
+    }
+  }
+
+  /// The channels messages have been recorded for, and in which directions.
+  pub fn channels(&self) -> Vec<(Direction, u8)> {
+    let mut chans: Vec<_> = self.lock().keys().copied().collect();
+    chans.sort();
+    chans
+  }
+
+  fn slot(&self) -> u64 {
+    (self.epoch.elapsed().as_nanos() / SLOT.as_nanos()) as u64
+  }
+
+  fn lock(
+    &self
+  ) -> std::sync::MutexGuard<'_, HashMap<(Direction, u8), Window>> {
+    match self.windows.lock() {
+      Ok(windows) => windows,
+      Err(poisoned) => poisoned.into_inner()
+    }
+  }
+}
+
+impl Default for ThroughputMeter {
+  fn default() -> Self {
+    ThroughputMeter::new()
+  }
+}
+
+impl Metrics for ThroughputMeter {
+  fn message_sent(&self, ch: u8, bytes: u64) {
+    self.record(Direction::Sent, ch, bytes);
+  }
+
+  fn message_received(&self, ch: u8, bytes: u64) {
+    self.record(Direction::Received, ch, bytes);
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
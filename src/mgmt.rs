@@ -1,3 +1,4 @@
 pub mod acc;
+pub mod ch;
 
 // vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -4,24 +4,21 @@ use tokio::io::{AsyncRead, AsyncWrite};
 
 use tokio_util::codec::Framed;
 
+use blather::{Params, Telegram};
+
+use crate::cmd::Command;
+use crate::params::ParamsExt;
 use crate::Error;
 
 /// Reference an account; with the option to implicitly reference self.
-pub enum OptAccRef {
-  Current,
-  Id(i64),
-  Name(String)
-}
+pub type OptAccRef = crate::OptObjRef;
 
 
 /// Explicitly reference an account.
-pub enum AccRef {
-  Id(i64),
-  Name(String)
-}
+pub type AccRef = crate::ObjRef;
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Account {
   pub id: i64,
   pub name: String,
@@ -33,41 +30,57 @@ pub struct Account {
 /// Get information about an account.
 /// The `acc` parameter can be used to query the account by id, name or get
 /// information about the connection's current owner.
-pub async fn rd<T: AsyncRead + AsyncWrite + Unpin>(
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(level = "debug", skip_all, err)
+)]
+pub async fn rd<T, A>(
   conn: &mut Framed<T, blather::Codec>,
-  acc: OptAccRef
-) -> Result<Account, Error> {
-  let mut tg = blather::Telegram::new_topic("RdAcc")?;
+  acc: A
+) -> Result<Account, Error>
+where
+  T: AsyncRead + AsyncWrite + Unpin,
+  A: Into<OptAccRef>
+{
+  crate::cmd::call(conn, &RdAcc { acc: acc.into() }).await
+}
 
-  match acc {
-    OptAccRef::Id(id) => {
-      tg.add_param("Id", id)?;
-    }
-    OptAccRef::Name(nm) => {
-      tg.add_str("Name", &nm)?;
-    }
-    OptAccRef::Current => {}
-  }
 
-  let params = crate::sendrecv(conn, &tg).await?;
+/// Get information about an account, as a [`Command`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RdAcc {
+  pub acc: OptAccRef
+}
 
-  let id = params.get_int::<i64>("Id")?;
-  let name = params.get_param::<String>("Name")?;
-  let lock = params.get_bool("Lock")?;
-  let perms = params.get_hashset("Perms")?;
+impl Command for RdAcc {
+  const TOPIC: &'static str = "RdAcc";
+  type Reply = Account;
 
-  let acc = Account {
-    id,
-    name,
-    lock,
-    perms
-  };
+  fn to_telegram(&self) -> Result<Telegram, Error> {
+    let mut tg = Telegram::new_topic(Self::TOPIC)?;
+    self.acc.add_to(&mut tg)?;
+    Ok(tg)
+  }
 
-  Ok(acc)
+  fn parse_reply(params: Params) -> Result<Account, Error> {
+    let id = params.get_parsed::<i64>("Id")?;
+    let name = params.require_str("Name")?.to_string();
+    let lock = params.get_bool("Lock")?;
+    let perms = params.get_hashset("Perms")?;
+
+    let acc = Account {
+      id,
+      name,
+      lock,
+      perms
+    };
+
+    Ok(acc)
+  }
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LsEntry {
   pub id: i64,
   pub name: String
@@ -80,39 +93,60 @@ pub struct LsEntry {
 /// associated unique account name.  To get detailed information about each
 /// account the application needs to call [`rd`](self::rd) for each
 /// entry.
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(level = "debug", skip_all, err)
+)]
 pub async fn ls<T: AsyncRead + AsyncWrite + Unpin>(
   conn: &mut Framed<T, blather::Codec>,
   inclock: bool
 ) -> Result<Vec<LsEntry>, Error> {
-  let mut tg = blather::Telegram::new_topic("LsAcc")?;
+  crate::cmd::call(conn, &LsAcc { inclock }).await
+}
+
+
+/// Get a list of accounts, as a [`Command`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LsAcc {
+  /// Include locked accounts.
+  pub inclock: bool
+}
+
+impl Command for LsAcc {
+  const TOPIC: &'static str = "LsAcc";
+  type Reply = Vec<LsEntry>;
+
+  fn to_telegram(&self) -> Result<Telegram, Error> {
+    let mut tg = Telegram::new_topic(Self::TOPIC)?;
 
-  match inclock {
-    true => {
+    if self.inclock {
       tg.add_bool("All", true)?;
     }
-    _ => {}
+
+    Ok(tg)
   }
 
-  let params = crate::sendrecv(conn, &tg).await?;
+  fn parse_reply(params: Params) -> Result<Vec<LsEntry>, Error> {
+    let num_entries = params.get_int::<usize>("#")?;
 
-  let num_entries = params.get_int::<usize>("#")?;
+    let mut acclist = Vec::with_capacity(num_entries);
+    for i in 0..num_entries {
+      let id = format!("{}.Id", i);
+      let name = format!("{}.Name", i);
 
-  let mut acclist = Vec::with_capacity(num_entries);
-  for i in 0..num_entries {
-    let id = format!("{}.Id", i);
-    let name = format!("{}.Name", i);
+      acclist.push(LsEntry {
+        id: params.get_int::<i64>(&id)?,
+        name: params.get_param::<String>(&name)?
+      });
+    }
 
-    acclist.push(LsEntry {
-      id: params.get_int::<i64>(&id).unwrap(),
-      name: params.get_str(&name).unwrap().to_string()
-    });
+    Ok(acclist)
   }
-
-  Ok(acclist)
 }
 
 
 /// Enumeration of account permission change methods.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ModPerms {
   /// Reset the account's permissions to the ones passed in the supplied
   /// HashSet.
@@ -132,6 +166,7 @@ pub enum ModPerms {
 
 
 /// Account fields to update.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct WrAccount {
   /// New account name.
   /// This is currently not supported.
@@ -150,75 +185,116 @@ pub struct WrAccount {
 
 
 /// Update an account.
-pub async fn wr<T: AsyncRead + AsyncWrite + Unpin>(
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(level = "debug", skip_all, err)
+)]
+pub async fn wr<T, A>(
   conn: &mut Framed<T, blather::Codec>,
-  acc: AccRef,
+  acc: A,
   ai: WrAccount
-) -> Result<(), Error> {
-  let mut tg = blather::Telegram::new_topic("WrAcc")?;
+) -> Result<(), Error>
+where
+  T: AsyncRead + AsyncWrite + Unpin,
+  A: Into<AccRef>
+{
+  let cmd = WrAcc {
+    acc: acc.into(),
+    fields: ai
+  };
+  crate::cmd::call(conn, &cmd).await
+}
+
 
-  match acc {
-    AccRef::Id(id) => {
-      tg.add_param("Id", id)?;
+/// Update an account, as a [`Command`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WrAcc {
+  pub acc: AccRef,
+  pub fields: WrAccount
+}
+
+impl Command for WrAcc {
+  const TOPIC: &'static str = "WrAcc";
+  type Reply = ();
+
+  fn to_telegram(&self) -> Result<Telegram, Error> {
+    let mut tg = Telegram::new_topic(Self::TOPIC)?;
+    self.acc.add_to(&mut tg)?;
+
+    let ai = &self.fields;
+    if let Some(ref name) = ai.name {
+      tg.add_str("NewName", name)?;
     }
-    AccRef::Name(nm) => {
-      tg.add_str("Name", &nm)?;
+    if let Some(ref username) = ai.username {
+      tg.add_str("UserName", username)?;
+    }
+    if let Some(lck) = ai.lock {
+      tg.add_bool("Lock", lck)?;
     }
-  }
-
-  if let Some(name) = ai.name {
-    tg.add_str("NewName", &name)?;
-  }
-  if let Some(username) = ai.username {
-    tg.add_str("UserName", &username)?;
-  }
-  if let Some(lck) = ai.lock {
-    tg.add_bool("Lock", lck)?;
-  }
 
-  if let Some(perms) = ai.perms {
-    match perms {
-      ModPerms::Set(set) => {
-        tg.add_strit("Perms", set.iter())?;
-      }
-      ModPerms::Grant(set) => {
-        tg.add_strit("Grant", set.iter())?;
-      }
-      ModPerms::Revoke(set) => {
-        tg.add_strit("Revoke", set.iter())?;
-      }
-      ModPerms::GrantRevoke(grant, revoke) => {
-        tg.add_strit("Grant", grant.iter())?;
-        tg.add_strit("Revoke", revoke.iter())?;
+    if let Some(ref perms) = ai.perms {
+      match perms {
+        ModPerms::Set(set) => {
+          tg.add_strit("Perms", set.iter())?;
+        }
+        ModPerms::Grant(set) => {
+          tg.add_strit("Grant", set.iter())?;
+        }
+        ModPerms::Revoke(set) => {
+          tg.add_strit("Revoke", set.iter())?;
+        }
+        ModPerms::GrantRevoke(grant, revoke) => {
+          tg.add_strit("Grant", grant.iter())?;
+          tg.add_strit("Revoke", revoke.iter())?;
+        }
       }
     }
-  }
 
-  crate::sendrecv(conn, &tg).await?;
+    Ok(tg)
+  }
 
-  Ok(())
+  fn parse_reply(_params: Params) -> Result<(), Error> {
+    Ok(())
+  }
 }
 
 
 /// Remove an account.
-pub async fn rm<T: AsyncRead + AsyncWrite + Unpin>(
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(level = "debug", skip_all, err)
+)]
+pub async fn rm<T, A>(
   conn: &mut Framed<T, blather::Codec>,
-  acc: AccRef
-) -> Result<(), Error> {
-  let mut tg = blather::Telegram::new_topic("RmAcc")?;
+  acc: A
+) -> Result<(), Error>
+where
+  T: AsyncRead + AsyncWrite + Unpin,
+  A: Into<AccRef>
+{
+  crate::cmd::call(conn, &RmAcc { acc: acc.into() }).await
+}
 
-  match acc {
-    AccRef::Id(id) => {
-      tg.add_param("Id", id)?;
-    }
-    AccRef::Name(nm) => {
-      tg.add_str("Name", &nm)?;
-    }
-  }
 
-  crate::sendrecv(conn, &tg).await?;
+/// Remove an account, as a [`Command`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RmAcc {
+  pub acc: AccRef
+}
+
+impl Command for RmAcc {
+  const TOPIC: &'static str = "RmAcc";
+  type Reply = ();
 
-  Ok(())
+  fn to_telegram(&self) -> Result<Telegram, Error> {
+    let mut tg = Telegram::new_topic(Self::TOPIC)?;
+    self.acc.add_to(&mut tg)?;
+    Ok(tg)
+  }
+
+  fn parse_reply(_params: Params) -> Result<(), Error> {
+    Ok(())
+  }
 }
 
 
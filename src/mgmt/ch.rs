@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+#[cfg(feature = "msg")]
+use std::sync::{Arc, OnceLock};
+use std::sync::{Mutex, MutexGuard};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use tokio_util::codec::Framed;
+
+use blather::{Params, Telegram};
+
+use crate::cmd::Command;
+#[cfg(feature = "msg")]
+use crate::msg::Endpoint;
+use crate::Error;
+
+/// Explicitly reference a channel, either by numeric identifier or name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ChRef {
+  Id(u8),
+  Name(String)
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChInfo {
+  pub id: u8,
+  pub name: String
+}
+
+
+/// Get information about a channel.
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(level = "debug", skip_all, err)
+)]
+pub async fn rd<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  ch: ChRef
+) -> Result<ChInfo, Error> {
+  crate::cmd::call(conn, &RdCh { ch }).await
+}
+
+
+/// Get information about a channel, as a [`Command`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RdCh {
+  pub ch: ChRef
+}
+
+impl Command for RdCh {
+  const TOPIC: &'static str = "RdCh";
+  type Reply = ChInfo;
+
+  fn to_telegram(&self) -> Result<Telegram, Error> {
+    let mut tg = Telegram::new_topic(Self::TOPIC)?;
+
+    match self.ch {
+      ChRef::Id(id) => {
+        tg.add_param("Id", id)?;
+      }
+      ChRef::Name(ref nm) => {
+        tg.add_str("Name", nm)?;
+      }
+    }
+
+    Ok(tg)
+  }
+
+  fn parse_reply(params: Params) -> Result<ChInfo, Error> {
+    let id = params.get_int::<u8>("Id")?;
+    let name = params.get_param::<String>("Name")?;
+
+    Ok(ChInfo { id, name })
+  }
+}
+
+
+/// Get a list of channels.
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(level = "debug", skip_all, err)
+)]
+pub async fn ls<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>
+) -> Result<Vec<ChInfo>, Error> {
+  crate::cmd::call(conn, &LsCh).await
+}
+
+
+/// Get a list of channels, as a [`Command`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LsCh;
+
+impl Command for LsCh {
+  const TOPIC: &'static str = "LsCh";
+  type Reply = Vec<ChInfo>;
+
+  fn parse_reply(params: Params) -> Result<Vec<ChInfo>, Error> {
+    let num_entries = params.get_int::<usize>("#")?;
+
+    let mut chlist = Vec::with_capacity(num_entries);
+    for i in 0..num_entries {
+      let id = format!("{}.Id", i);
+      let name = format!("{}.Name", i);
+
+      chlist.push(ChInfo {
+        id: params.get_int::<u8>(&id)?,
+        name: params.get_param::<String>(&name)?
+      });
+    }
+
+    Ok(chlist)
+  }
+}
+
+
+/// Resolve a channel name to its numeric identifier.
+///
+/// The server is queried on each call; use a [`ChCache`] to avoid repeated
+/// lookups.
+pub async fn resolve<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  name: &str
+) -> Result<u8, Error> {
+  Ok(rd(conn, ChRef::Name(name.to_string())).await?.id)
+}
+
+
+/// A cache of the channel name resolutions of one server.
+///
+/// Channel names are only meaningful to the server they were resolved by,
+/// so a cache must not be shared between connections to different servers.
+/// [`ChCache::for_endpoint()`] returns the process-wide cache of a server.
+#[derive(Debug, Default)]
+pub struct ChCache {
+  ids: Mutex<HashMap<String, u8>>
+}
+
+impl ChCache {
+  pub fn new() -> Self {
+    ChCache::default()
+  }
+
+  /// Get the cache shared by all connections to the server at `ep`.
+  #[cfg(feature = "msg")]
+  pub fn for_endpoint(ep: &Endpoint) -> Arc<ChCache> {
+    lock(endpoint_caches())
+      .entry(ep.clone())
+      .or_default()
+      .clone()
+  }
+
+  /// Get the cached identifier of the channel `name`.
+  pub fn get(&self, name: &str) -> Option<u8> {
+    lock(&self.ids).get(name).copied()
+  }
+
+  /// Resolve a channel name to its numeric identifier, only querying the
+  /// server over `conn` if the name hasn't been resolved before.
+  pub async fn resolve<T: AsyncRead + AsyncWrite + Unpin>(
+    &self,
+    conn: &mut Framed<T, blather::Codec>,
+    name: &str
+  ) -> Result<u8, Error> {
+    if let Some(id) = self.get(name) {
+      return Ok(id);
+    }
+    let id = resolve(conn, name).await?;
+    lock(&self.ids).insert(name.to_string(), id);
+    Ok(id)
+  }
+
+  /// Forget all cached resolutions.
+  ///
+  /// Should be called if channels have been renamed or renumbered.
+  pub fn clear(&self) {
+    lock(&self.ids).clear();
+  }
+}
+
+
+#[cfg(feature = "msg")]
+fn endpoint_caches() -> &'static Mutex<HashMap<Endpoint, Arc<ChCache>>> {
+  static CACHES: OnceLock<Mutex<HashMap<Endpoint, Arc<ChCache>>>> =
+    OnceLock::new();
+  CACHES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+  match m.lock() {
+    Ok(guard) => guard,
+    Err(poisoned) => poisoned.into_inner()
+  }
+}
+
+
+/// Forget the cached channel name resolutions of all servers.
+///
+/// Should be called if channels have been renamed or renumbered.
+pub fn clear_cache() {
+  #[cfg(feature = "msg")]
+  for cache in lock(endpoint_caches()).values() {
+    cache.clear();
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
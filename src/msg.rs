@@ -1,10 +1,23 @@
+use std::convert::TryFrom;
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(all(unix, feature = "tokio-net"))]
+use std::os::unix::io::AsRawFd;
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+#[cfg(feature = "tokio-net")]
 use tokio::net::TcpStream;
 
-#[cfg(unix)]
+#[cfg(all(unix, feature = "tokio-net"))]
 use tokio::net::UnixStream;
 
 use tokio_util::codec::Framed;
@@ -15,16 +28,59 @@ use bytes::Bytes;
 
 use blather::{Params, Telegram};
 
+use crate::deadline::Deadline;
 use crate::err::Error;
+use crate::mgmt::ch::ChCache;
+
+pub mod source;
+#[cfg(feature = "watch")]
+pub mod watch;
+#[cfg(all(target_os = "linux", feature = "zerocopy"))]
+mod zerocopy;
+
+pub use source::PayloadSource;
+#[cfg(feature = "watch")]
+pub use watch::{watch_dir, AfterSend, WatchRules};
 
 
 pub enum InputType {
   Params(Params),
   File(PathBuf),
   VecBuf(Vec<u8>),
-  Bytes(Bytes)
+  Bytes(Bytes),
+
+  /// File which is memory mapped and written to the connection directly from
+  /// the mapping, rather than being read into an intermediate buffer.
+  ///
+  /// Pages of the file which aren't in the page cache are read in as they
+  /// are written to the connection, blocking the runtime's worker thread
+  /// while they are; prefer `File` for files on slow storage.
+  ///
+  /// Without the `mmap` feature the file is sent like a `File`.
+  Mmap(PathBuf),
+
+  /// Content streamed from an application-supplied source.
+  Source(Box<dyn PayloadSource>)
 }
 
+/// Buffers are shown by their length rather than their contents.
+impl fmt::Debug for InputType {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      InputType::Params(params) => {
+        f.debug_tuple("Params").field(params).finish()
+      }
+      InputType::File(fname) => f.debug_tuple("File").field(fname).finish(),
+      InputType::VecBuf(buf) => write!(f, "VecBuf({} bytes)", buf.len()),
+      InputType::Bytes(buf) => write!(f, "Bytes({} bytes)", buf.len()),
+      InputType::Mmap(fname) => f.debug_tuple("Mmap").field(fname).finish(),
+      InputType::Source(_) => write!(f, "Source(..)")
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Endpoint {
   TcpSockAddr(String),
 
@@ -32,63 +88,841 @@ pub enum Endpoint {
   UdsPath(PathBuf)
 }
 
+/// Reference a channel, either by numeric identifier or name.
+///
+/// Channel names are resolved to identifiers using
+/// [`mgmt::ch::resolve()`](crate::mgmt::ch::resolve).  [`connsend()`] and
+/// [`Client`](crate::client::Client) cache the resolutions of the server
+/// they are connected to in its [`ChCache`].
+///
+/// With the `serde` feature enabled channels are represented by their bare
+/// identifier or name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+  feature = "serde",
+  derive(serde::Serialize, serde::Deserialize),
+  serde(untagged)
+)]
+pub enum Channel {
+  Id(u8),
+  Name(String)
+}
+
+impl Channel {
+  /// Get the numeric channel identifier, resolving the channel name over
+  /// `conn` if needed.
+  pub async fn resolve<T: AsyncRead + AsyncWrite + Unpin>(
+    &self,
+    conn: &mut Framed<T, blather::Codec>
+  ) -> Result<u8, Error> {
+    match self {
+      Channel::Id(id) => Ok(*id),
+      Channel::Name(name) => crate::mgmt::ch::resolve(conn, name).await
+    }
+  }
+
+  /// Like [`Channel::resolve()`], but look the channel name up in `cache`
+  /// first.  `cache` must belong to the server `conn` is connected to.
+  pub async fn resolve_cached<T: AsyncRead + AsyncWrite + Unpin>(
+    &self,
+    conn: &mut Framed<T, blather::Codec>,
+    cache: &ChCache
+  ) -> Result<u8, Error> {
+    match self {
+      Channel::Id(id) => Ok(*id),
+      Channel::Name(name) => cache.resolve(conn, name).await
+    }
+  }
+}
+
+impl From<u8> for Channel {
+  fn from(id: u8) -> Self {
+    Channel::Id(id)
+  }
+}
+
+impl From<&str> for Channel {
+  fn from(name: &str) -> Self {
+    Channel::Name(name.to_string())
+  }
+}
+
+impl From<String> for Channel {
+  fn from(name: String) -> Self {
+    Channel::Name(name)
+  }
+}
+
+/// Parse an endpoint string.
+///
+/// On unix platforms strings which begin with `/` or `.` are treated as unix
+/// domain socket paths.  Anything else is treated as a TCP socket address.
+impl FromStr for Endpoint {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    if s.is_empty() {
+      return Err(Error::bad_format("endpoint", "empty"));
+    }
+
+    #[cfg(unix)]
+    if s.starts_with('/') || s.starts_with('.') {
+      return Ok(Endpoint::UdsPath(PathBuf::from(s)));
+    }
+
+    Ok(Endpoint::TcpSockAddr(s.to_string()))
+  }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ConnTransport {
   pub msgif: Endpoint,
   pub authinfo: Option<crate::auth::AuthInfo>,
-  pub ch: u8
+  pub ch: Channel
+}
+
+impl TryFrom<ddmw_util::app::Config> for ConnTransport {
+  type Error = Error;
+
+  fn try_from(cfg: ddmw_util::app::Config) -> Result<Self, Self::Error> {
+    ConnTransport::try_from(&cfg)
+  }
+}
+
+/// Construct a `ConnTransport` from the `sender.msgif`, `channel` and `auth`
+/// sections of an application configuration.
+impl TryFrom<&ddmw_util::app::Config> for ConnTransport {
+  type Error = Error;
+
+  fn try_from(cfg: &ddmw_util::app::Config) -> Result<Self, Self::Error> {
+    let msgif = match cfg.sender {
+      Some(ddmw_util::app::Sender {
+        msgif: Some(ref msgif),
+        ..
+      }) => msgif.parse::<Endpoint>()?,
+      _ => return Err(Error::missing("sender.msgif"))
+    };
+
+    let ch = match cfg.channel {
+      Some(ch) => Channel::Id(ch),
+      None => return Err(Error::missing("channel"))
+    };
+
+    let authinfo = cfg
+      .auth
+      .as_ref()
+      .map(crate::auth::AuthInfo::try_from_app)
+      .transpose()?;
+
+    Ok(ConnTransport {
+      msgif,
+      authinfo,
+      ch
+    })
+  }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Transport {
-  pub ch: u8
+  pub ch: Channel
 }
 
+/// Message expiry.
+///
+/// Messages which have not crossed the diode before they expire are dropped
+/// by the server rather than delivered late.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+  /// Message expires once it has been queued for the specified duration.
+  /// The server counts in whole seconds; fractions are rounded up, so a
+  /// message is never expired earlier than requested.
+  Duration(Duration),
+
+  /// Message expires at a specific point in time.
+  At(SystemTime)
+}
+
+/// Message transfer priority.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Priority {
+  Low,
+  Normal,
+  High
+}
+
+impl Priority {
+  fn as_str(&self) -> &'static str {
+    match self {
+      Priority::Low => "low",
+      Priority::Normal => "normal",
+      Priority::High => "high"
+    }
+  }
+}
+
+#[derive(Debug)]
 pub struct MsgInfo {
   pub cmd: u32,
   pub meta: Option<InputType>,
-  pub payload: Option<InputType>
+  pub payload: Option<InputType>,
+  pub expires: Option<Expiry>,
+
+  /// Client-supplied deduplication key.
+  ///
+  /// If a send is retried after an ambiguous failure (for instance if the
+  /// connection was lost after the `Msg` telegram was acknowledged but before
+  /// the payload was), reusing the same key allows the server to discard the
+  /// duplicate.
+  pub dedup_key: Option<String>,
+
+  /// Transfer priority.  If not set the server's default priority is used.
+  pub priority: Option<Priority>,
+
+  /// Hold the message in the server's queue and don't begin transferring it
+  /// before this point in time.
+  pub deliver_after: Option<SystemTime>
 }
 
+impl MsgInfo {
+  /// Return a builder for constructing a `MsgInfo`.
+  pub fn builder() -> MsgInfoBuilder {
+    MsgInfoBuilder::default()
+  }
+}
 
-/// Connect, optionally authenticate, send message and disconnect
-pub async fn connsend(
-  xfer: ConnTransport,
-  mi: &MsgInfo
-) -> Result<String, Error> {
-  match xfer.msgif {
-    Endpoint::TcpSockAddr(sa) => {
-      let stream = TcpStream::connect(sa).await?;
-      let mut framed = Framed::new(stream, blather::Codec::new());
-      if let Some(ref authinfo) = xfer.authinfo {
-        let _ = crate::auth::authenticate(&mut framed, authinfo).await?;
+
+/// Builder for [`MsgInfo`] objects.
+///
+/// ```no_run
+/// use tokio_ddmw::msg::{MsgInfo, Priority};
+/// # fn f() -> Result<(), tokio_ddmw::Error> {
+/// let mi = MsgInfo::builder()
+///   .cmd(7)
+///   .payload_file("/tmp/report.pdf")
+///   .priority(Priority::High)
+///   .build()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct MsgInfoBuilder {
+  cmd: u32,
+  meta: Option<InputType>,
+  payload: Option<InputType>,
+  expires: Option<Expiry>,
+  dedup_key: Option<String>,
+  priority: Option<Priority>,
+  deliver_after: Option<SystemTime>
+}
+
+impl MsgInfoBuilder {
+  pub fn cmd(mut self, cmd: u32) -> Self {
+    self.cmd = cmd;
+    self
+  }
+
+  pub fn meta(mut self, meta: InputType) -> Self {
+    self.meta = Some(meta);
+    self
+  }
+
+  pub fn meta_params(self, params: Params) -> Self {
+    self.meta(InputType::Params(params))
+  }
+
+  pub fn meta_file<P: Into<PathBuf>>(self, fname: P) -> Self {
+    self.meta(InputType::File(fname.into()))
+  }
+
+  pub fn payload(mut self, payload: InputType) -> Self {
+    self.payload = Some(payload);
+    self
+  }
+
+  pub fn payload_params(self, params: Params) -> Self {
+    self.payload(InputType::Params(params))
+  }
+
+  pub fn payload_file<P: Into<PathBuf>>(self, fname: P) -> Self {
+    self.payload(InputType::File(fname.into()))
+  }
+
+  pub fn payload_buf(self, buf: Vec<u8>) -> Self {
+    self.payload(InputType::VecBuf(buf))
+  }
+
+  pub fn payload_bytes(self, buf: Bytes) -> Self {
+    self.payload(InputType::Bytes(buf))
+  }
+
+  pub fn payload_source<S: PayloadSource + 'static>(self, src: S) -> Self {
+    self.payload(InputType::Source(Box::new(src)))
+  }
+
+  pub fn expires(mut self, expires: Expiry) -> Self {
+    self.expires = Some(expires);
+    self
+  }
+
+  pub fn dedup_key<S: Into<String>>(mut self, key: S) -> Self {
+    self.dedup_key = Some(key.into());
+    self
+  }
+
+  pub fn priority(mut self, priority: Priority) -> Self {
+    self.priority = Some(priority);
+    self
+  }
+
+  pub fn deliver_after(mut self, tm: SystemTime) -> Self {
+    self.deliver_after = Some(tm);
+    self
+  }
+
+  /// Validate the message and construct a [`MsgInfo`].
+  ///
+  /// Fails if a file referenced by the metadata or payload does not exist or
+  /// isn't a regular file, or if the metadata or payload is too large to be
+  /// sent.
+  pub fn build(self) -> Result<MsgInfo, Error> {
+    for input in [&self.meta, &self.payload].iter().copied().flatten() {
+      let fname = match input {
+        InputType::File(fname) | InputType::Mmap(fname) => fname,
+        _ => continue
+      };
+      let md =
+        std::fs::metadata(fname).map_err(|e| Error::local_io(fname, e))?;
+      if !md.is_file() {
+        let e = io::Error::new(io::ErrorKind::InvalidInput, "Not a file");
+        return Err(Error::local_io(fname, e));
       }
-      send(&mut framed, &Transport { ch: xfer.ch }, mi).await
     }
-    #[cfg(unix)]
-    Endpoint::UdsPath(sa) => {
-      let stream = UnixStream::connect(sa).await?;
-      let mut framed = Framed::new(stream, blather::Codec::new());
-      if let Some(ref authinfo) = xfer.authinfo {
-        let _ = crate::auth::authenticate(&mut framed, authinfo).await?;
+
+    let mi = MsgInfo {
+      cmd: self.cmd,
+      meta: self.meta,
+      payload: self.payload,
+      expires: self.expires,
+      dedup_key: self.dedup_key,
+      priority: self.priority,
+      deliver_after: self.deliver_after
+    };
+
+    // The sizes of application-supplied sources can only be determined
+    // asynchronously, when the message is sent.
+    if let Some(meta) = &mi.meta {
+      if let Some(sz) = known_size(meta)? {
+        check_meta_size(sz)?;
+      }
+    }
+    if let Some(payload) = &mi.payload {
+      known_size(payload)?;
+    }
+
+    Ok(mi)
+  }
+}
+
+/// Outcome of a message submission.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SendOutcome {
+  /// Transfer identifier assigned to the message.
+  pub xferid: String,
+
+  /// `true` if the server recognized the deduplication key and discarded the
+  /// message as a duplicate of one it has already accepted.
+  pub duplicate: bool
+}
+
+
+/// Summary of a completed message transfer.
+///
+/// Displays as a one-line summary suitable for logs and command line
+/// tools, such as
+/// `a1b2c3: 1.5 MiB (128 B metadata, 1.5 MiB payload) in 2.00s, 768.0 KiB/s`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SendReport {
+  /// Transfer identifier assigned to the message.
+  pub xferid: String,
+
+  /// Number of bytes of metadata sent.
+  pub bytes_meta: u64,
+
+  /// Number of bytes of payload sent.
+  pub bytes_payload: u64,
+
+  /// Time taken from announcing the message until the server acknowledged
+  /// its content.
+  pub duration: Duration,
+
+  /// Average number of bytes of metadata and payload sent per second.
+  pub throughput: f64,
+
+  /// `true` if the server recognized the deduplication key and discarded the
+  /// message as a duplicate of one it has already accepted.  No metadata or
+  /// payload is sent for duplicates.
+  pub duplicate: bool
+}
+
+impl SendReport {
+  fn new(
+    outcome: SendOutcome,
+    (metalen, payloadlen): (u64, u64),
+    duration: Duration
+  ) -> Self {
+    // Duplicates are discarded by the server before any content is sent.
+    let (bytes_meta, bytes_payload) = if outcome.duplicate {
+      (0, 0)
+    } else {
+      (metalen, payloadlen)
+    };
+    let secs = duration.as_secs_f64();
+    let throughput = if secs > 0.0 {
+      (bytes_meta + bytes_payload) as f64 / secs
+    } else {
+      0.0
+    };
+    SendReport {
+      xferid: outcome.xferid,
+      bytes_meta,
+      bytes_payload,
+      duration,
+      throughput,
+      duplicate: outcome.duplicate
+    }
+  }
+}
+
+impl fmt::Display for SendReport {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "{}: {} ({} metadata, {} payload) in {:.2}s, {}/s",
+      self.xferid,
+      HumanBytes((self.bytes_meta + self.bytes_payload) as f64),
+      HumanBytes(self.bytes_meta as f64),
+      HumanBytes(self.bytes_payload as f64),
+      self.duration.as_secs_f64(),
+      HumanBytes(self.throughput)
+    )?;
+    if self.duplicate {
+      write!(f, " (duplicate)")?;
+    }
+    Ok(())
+  }
+}
+
+
+/// Formats a byte count using binary units.
+struct HumanBytes(f64);
+
+impl fmt::Display for HumanBytes {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+    if self.0 < 1024.0 {
+      return write!(f, "{} B", self.0.round());
+    }
+    let mut n = self.0 / 1024.0;
+    let mut unit = UNITS[0];
+    for u in &UNITS[1..] {
+      if n < 1024.0 {
+        break;
       }
-      send(&mut framed, &Transport { ch: xfer.ch }, mi).await
+      n /= 1024.0;
+      unit = u;
     }
+    write!(f, "{:.1} {}", n, unit)
+  }
+}
+
+
+/// A bidirectional byte stream which a client interface connection can run
+/// over, such as a TCP or unix domain socket.
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {
+  /// Return the file descriptor of the underlying socket, if the stream is a
+  /// plain socket that data can be written to directly.
+  ///
+  /// Streams which transform the data (such as TLS streams) must return
+  /// `None`, which is the default.
+  #[cfg(unix)]
+  fn raw_socket(&self) -> Option<RawFd> {
+    None
   }
 }
 
+#[cfg(feature = "tokio-net")]
+impl AsyncStream for TcpStream {
+  #[cfg(unix)]
+  fn raw_socket(&self) -> Option<RawFd> {
+    Some(self.as_raw_fd())
+  }
+}
+
+#[cfg(all(unix, feature = "tokio-net"))]
+impl AsyncStream for UnixStream {
+  fn raw_socket(&self) -> Option<RawFd> {
+    Some(self.as_raw_fd())
+  }
+}
+
+/// A client interface connection, independent of the underlying transport.
+pub type Conn = Framed<Box<dyn AsyncStream>, blather::Codec>;
+
+
+#[cfg(feature = "tokio-net")]
+impl Endpoint {
+  /// Connect to the endpoint.
+  pub async fn connect(&self) -> Result<Conn, Error> {
+    let stream: Box<dyn AsyncStream> = match self {
+      Endpoint::TcpSockAddr(sa) => Box::new(TcpStream::connect(sa).await?),
+      #[cfg(unix)]
+      Endpoint::UdsPath(sa) => Box::new(UnixStream::connect(sa).await?)
+    };
+    Ok(Framed::new(stream, blather::Codec::new()))
+  }
+}
+
+
+/// Connect to the message interface and, if authentication information has
+/// been supplied, authenticate the connection.
+#[cfg(feature = "tokio-net")]
+pub async fn connect(xfer: &ConnTransport) -> Result<Conn, Error> {
+  let mut framed = xfer.msgif.connect().await?;
+  if let Some(ref authinfo) = xfer.authinfo {
+    let _ = crate::auth::authenticate(&mut framed, authinfo).await?;
+  }
+  Ok(framed)
+}
+
+
+/// Connect, optionally authenticate, send message and disconnect
+#[cfg(feature = "tokio-net")]
+pub async fn connsend(
+  xfer: ConnTransport,
+  mi: &MsgInfo
+) -> Result<SendReport, Error> {
+  let (report, _conn) = connsend_keep(xfer, mi).await?;
+  Ok(report)
+}
+
+
+/// Connect, optionally authenticate and send message, returning the
+/// transfer report along with the connection.
+///
+/// The returned connection remains authenticated, and can be used to send
+/// further messages using [`send()`] without having to reconnect.
+///
+/// If the `zerocopy` feature is enabled file contents are sent using
+/// `sendfile()` on Linux.
+#[cfg(feature = "tokio-net")]
+pub async fn connsend_keep(
+  xfer: ConnTransport,
+  mi: &MsgInfo
+) -> Result<(SendReport, Conn), Error> {
+  connsend_opts(xfer, mi, None).await
+}
+
+
+/// Connect, optionally authenticate and send message, with all steps
+/// sharing a single overall `deadline`.
+///
+/// If the deadline passes the function fails with
+/// `Error::DeadlineExceeded`, identifying the step which was running and
+/// how much of the budget had been consumed.
+#[cfg(feature = "tokio-net")]
+pub async fn connsend_deadline(
+  xfer: ConnTransport,
+  mi: &MsgInfo,
+  deadline: Deadline
+) -> Result<SendReport, Error> {
+  let (report, _conn) = connsend_opts(xfer, mi, Some(deadline)).await?;
+  Ok(report)
+}
+
+
+#[cfg(feature = "tokio-net")]
+async fn connsend_opts(
+  xfer: ConnTransport,
+  mi: &MsgInfo,
+  deadline: Option<Deadline>
+) -> Result<(SendReport, Conn), Error> {
+  #[allow(unused_mut)]
+  let mut opts = ContentOpts {
+    ch_cache: Some(ChCache::for_endpoint(&xfer.msgif)),
+    deadline,
+    ..Default::default()
+  };
+
+  let mut conn = opts.step("connect", xfer.msgif.connect()).await?;
+  if let Some(ref authinfo) = xfer.authinfo {
+    opts
+      .step(
+        "authentication",
+        crate::auth::authenticate(&mut conn, authinfo)
+      )
+      .await?;
+  }
+
+  #[cfg(all(target_os = "linux", feature = "zerocopy"))]
+  {
+    opts.sendfile_fd = conn.get_ref().raw_socket();
+  }
+
+  let report =
+    send_report(&mut conn, &Transport { ch: xfer.ch }, mi, opts).await?;
+  Ok((report, conn))
+}
+
 
 /// Send a message, including (if applicable) its metadata and payload.
 ///
-/// On successful completion returns the transfer identifier.
+/// On successful completion returns a report containing the transfer
+/// identifier and statistics about the transfer.
 pub async fn send<T: AsyncRead + AsyncWrite + Unpin>(
   conn: &mut Framed<T, blather::Codec>,
   xfer: &Transport,
   mi: &MsgInfo
-) -> Result<String, Error> {
-  let metalen = get_meta_size(&mi)?;
-  let payloadlen = get_payload_size(&mi)?;
+) -> Result<SendReport, Error> {
+  send_report(conn, xfer, mi, ContentOpts::default()).await
+}
+
+
+/// Send a message, resolving channel names through `cache`, which must
+/// belong to the server `conn` is connected to.
+#[cfg(feature = "tokio-net")]
+pub(crate) async fn send_cached<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  xfer: &Transport,
+  mi: &MsgInfo,
+  cache: Arc<ChCache>
+) -> Result<SendReport, Error> {
+  let opts = ContentOpts {
+    ch_cache: Some(cache),
+    ..Default::default()
+  };
+  send_report(conn, xfer, mi, opts).await
+}
+
+
+/// Send a message, including (if applicable) its metadata and payload, and
+/// report whether the server considered it a duplicate.
+///
+/// If the server reports that the message's deduplication key has already
+/// been seen it will not expect the metadata or payload, and none will be
+/// sent.  The returned report's `duplicate` field is then set.
+pub async fn send_dedup<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  xfer: &Transport,
+  mi: &MsgInfo
+) -> Result<SendReport, Error> {
+  send_report(conn, xfer, mi, ContentOpts::default()).await
+}
+
+
+/// Send a message, with the steps of the transfer sharing a single overall
+/// `deadline`.  See [`connsend_deadline()`] for how expiry is reported.
+///
+/// On successful completion returns a report of the transfer.
+pub async fn send_deadline<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  xfer: &Transport,
+  mi: &MsgInfo,
+  deadline: Deadline
+) -> Result<SendReport, Error> {
+  let opts = ContentOpts {
+    deadline: Some(deadline),
+    ..Default::default()
+  };
+  send_report(conn, xfer, mi, opts).await
+}
+
+
+/// Send a message, writing file contents directly from the file to the
+/// socket using `sendfile()` rather than copying them through userspace
+/// buffers.
+///
+/// The connection's stream must be a plain TCP or unix domain socket, since
+/// the data bypasses the stream object entirely.
+///
+/// On successful completion returns a report of the transfer.
+#[cfg(all(target_os = "linux", feature = "zerocopy"))]
+pub async fn send_zerocopy<T: AsyncRead + AsyncWrite + AsRawFd + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  xfer: &Transport,
+  mi: &MsgInfo
+) -> Result<SendReport, Error> {
+  let opts = ContentOpts {
+    sendfile_fd: Some(conn.get_ref().as_raw_fd()),
+    ..Default::default()
+  };
+  send_report(conn, xfer, mi, opts).await
+}
+
+
+/// Send a message, using the supplied options to control how file contents
+/// are written to the connection.
+///
+/// On successful completion returns a report of the transfer.
+pub async fn send_with<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  xfer: &Transport,
+  mi: &MsgInfo,
+  opts: &SendOpts
+) -> Result<SendReport, Error> {
+  send_report(conn, xfer, mi, ContentOpts::new(*opts)).await
+}
+
+
+async fn send_report<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  xfer: &Transport,
+  mi: &MsgInfo,
+  opts: ContentOpts
+) -> Result<SendReport, Error> {
+  let metalen = get_meta_size(mi).await?;
+  let payloadlen = get_payload_size(mi).await?;
+
+  let start = Instant::now();
+  let outcome = send_sized(
+    conn,
+    xfer,
+    mi,
+    (mi.meta.as_ref(), metalen),
+    (mi.payload.as_ref(), payloadlen),
+    opts
+  )
+  .await?;
+  Ok(SendReport::new(
+    outcome,
+    (metalen as u64, payloadlen),
+    start.elapsed()
+  ))
+}
+
+
+/// Send the same message to several channels over a single connection.
+///
+/// The metadata and payload sizes are only calculated once.  Metadata and
+/// payloads that are `Params` buffers, or files no larger than
+/// `MULTI_CACHE_LIMIT` bytes, are serialized/read into memory once and the
+/// cached buffer is sent to each channel.
+///
+/// Returns the outcome of each channel's transfer, in the order of
+/// `channels`.  An error is only returned by the function itself if the
+/// message could not be prepared.
+///
+/// If a transfer fails with a [connection
+/// error](Error::is_connection_error), the connection can't be used for the
+/// remaining channels; they are left out of the returned outcomes, the last
+/// of which is the connection error.
+pub async fn send_multi<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  channels: &[Channel],
+  mi: &MsgInfo
+) -> Result<Vec<(Channel, Result<String, Error>)>, Error> {
+  let metalen = get_meta_size(mi).await?;
+  let payloadlen = get_payload_size(mi).await?;
+
+  let meta_cache = match &mi.meta {
+    Some(input) => cache_input(input, metalen as u64).await?,
+    None => None
+  };
+  let payload_cache = match &mi.payload {
+    Some(input) => cache_input(input, payloadlen).await?,
+    None => None
+  };
+  let meta = meta_cache.as_ref().or(mi.meta.as_ref());
+  let payload = payload_cache.as_ref().or(mi.payload.as_ref());
+
+  let mut results = Vec::with_capacity(channels.len());
+  for ch in channels {
+    let res = send_sized(
+      conn,
+      &Transport { ch: ch.clone() },
+      mi,
+      (meta, metalen),
+      (payload, payloadlen),
+      ContentOpts::default()
+    )
+    .await
+    .map(|outcome| outcome.xferid);
+    let broken = matches!(res, Err(ref e) if e.is_connection_error());
+    results.push((ch.clone(), res));
+    if broken {
+      break;
+    }
+  }
+
+  Ok(results)
+}
+
+
+/// Maximum size of a file which [`send_multi()`] will cache in memory.
+pub const MULTI_CACHE_LIMIT: u64 = 16 * 1024 * 1024;
+
+
+/// Return an in-memory copy of an input, if it is worth caching.
+async fn cache_input(
+  input: &InputType,
+  size: u64
+) -> Result<Option<InputType>, Error> {
+  match input {
+    InputType::Params(params) => {
+      let buf = params.serialize()?;
+      Ok(Some(InputType::Bytes(Bytes::from(buf))))
+    }
+    InputType::File(fname) if size <= MULTI_CACHE_LIMIT => {
+      let buf = tokio::fs::read(fname)
+        .await
+        .map_err(|e| Error::local_io(fname, e))?;
+      Ok(Some(InputType::Bytes(Bytes::from(buf))))
+    }
+    _ => Ok(None)
+  }
+}
+
+
+/// Send a message whose metadata and payload sizes have already been
+/// determined.
+#[cfg_attr(
+  feature = "tracing",
+  tracing::instrument(
+    level = "debug",
+    skip_all,
+    err,
+    fields(
+      cmd = mi.cmd,
+      metalen,
+      payloadlen,
+      xferid = tracing::field::Empty
+    )
+  )
+)]
+async fn send_sized<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  xfer: &Transport,
+  mi: &MsgInfo,
+  (meta, metalen): (Option<&InputType>, u32),
+  (payload, payloadlen): (Option<&InputType>, u64),
+  opts: ContentOpts
+) -> Result<SendOutcome, Error> {
+  let ch = match opts.ch_cache {
+    Some(ref cache) => {
+      opts
+        .step("channel resolution", xfer.ch.resolve_cached(conn, cache))
+        .await?
+    }
+    None => {
+      opts
+        .step("channel resolution", xfer.ch.resolve(conn))
+        .await?
+    }
+  };
 
   let mut tg = Telegram::new_topic("Msg")?;
-  tg.add_param("_Ch", xfer.ch)?;
+  tg.add_param("_Ch", ch)?;
   if mi.cmd != 0 {
     tg.add_param("Cmd", mi.cmd)?;
   }
@@ -98,88 +932,600 @@ pub async fn send<T: AsyncRead + AsyncWrite + Unpin>(
   if payloadlen != 0 {
     tg.add_param("Len", payloadlen)?;
   }
-  let params = crate::sendrecv(conn, &tg).await?;
+  if let Some(expires) = &mi.expires {
+    add_expiry(&mut tg, expires)?;
+  }
+  if let Some(key) = &mi.dedup_key {
+    tg.add_str("DedupKey", key)?;
+  }
+  if let Some(prio) = &mi.priority {
+    tg.add_str("Prio", prio.as_str())?;
+  }
+  if let Some(tm) = mi.deliver_after {
+    tg.add_param("DeliverAfter", unix_secs(tm, "Delivery time")?)?;
+  }
+  let params = opts
+    .step("message announcement", crate::sendrecv(conn, &tg))
+    .await?;
 
   // Extract the transfer identifier assigned to this message
   let xferid = match params.get_str("XferId") {
     Some(xferid) => xferid.to_string(),
-    None => {
-      let e = "Missing expected transfer identifier";
-      return Err(Error::MissingData(String::from(e)));
-    }
+    None => return Err(Error::missing("XferId"))
   };
+  #[cfg(feature = "tracing")]
+  tracing::Span::current().record("xferid", xferid.as_str());
 
-  if let Some(meta) = &mi.meta {
-    send_content(conn, meta).await?;
-    crate::expect_okfail(conn).await?;
+  // The server has already accepted a message with this deduplication key,
+  // so it won't be expecting any content.
+  if params.get_bool_def("Dup", false)? {
+    return Ok(SendOutcome {
+      xferid,
+      duplicate: true
+    });
   }
 
-  if let Some(payload) = &mi.payload {
-    send_content(conn, payload).await?;
-    crate::expect_okfail(conn).await?;
+  if let Some(meta) = meta {
+    opts
+      .step("metadata", async {
+        send_content(conn, meta, metalen as u64, &opts).await?;
+        crate::metrics::global().bytes_sent(metalen as u64);
+        expect_ack(conn, &opts.file.keepalive).await
+      })
+      .await?;
   }
 
-  Ok(xferid)
+  if let Some(payload) = payload {
+    opts
+      .step("payload", async {
+        send_content(conn, payload, payloadlen, &opts).await?;
+        crate::metrics::global().bytes_sent(payloadlen);
+        expect_ack(conn, &opts.file.keepalive).await
+      })
+      .await?;
+  }
+
+  crate::metrics::global().message_sent(ch, metalen as u64 + payloadlen);
+
+  Ok(SendOutcome {
+    xferid,
+    duplicate: false
+  })
+}
+
+
+/// Send a string as a message payload, with optional metadata.
+///
+/// On successful completion returns a report of the transfer.
+pub async fn send_text<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  xfer: &Transport,
+  text: &str,
+  cmd: u32,
+  meta: Option<Params>
+) -> Result<SendReport, Error> {
+  send_bytes(
+    conn,
+    xfer,
+    Bytes::copy_from_slice(text.as_bytes()),
+    cmd,
+    meta
+  )
+  .await
 }
 
 
-fn get_meta_size(mi: &MsgInfo) -> Result<u32, Error> {
+/// Send a byte buffer as a message payload, with optional metadata.
+///
+/// On successful completion returns a report of the transfer.
+pub async fn send_bytes<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  xfer: &Transport,
+  buf: Bytes,
+  cmd: u32,
+  meta: Option<Params>
+) -> Result<SendReport, Error> {
+  let mut builder = MsgInfo::builder().cmd(cmd).payload_bytes(buf);
+  if let Some(meta) = meta {
+    builder = builder.meta_params(meta);
+  }
+  let mi = builder.build()?;
+
+  send(conn, xfer, &mi).await
+}
+
+
+/// Serialize a value to JSON and send it as a message payload.
+///
+/// The message's metadata will contain the content type
+/// `application/json`.
+///
+/// On successful completion returns a report of the transfer.
+#[cfg(feature = "serde_json")]
+pub async fn send_json<T, V>(
+  conn: &mut Framed<T, blather::Codec>,
+  xfer: &Transport,
+  value: &V,
+  cmd: u32
+) -> Result<SendReport, Error>
+where
+  T: AsyncRead + AsyncWrite + Unpin,
+  V: serde::Serialize + ?Sized
+{
+  let buf = match serde_json::to_vec(value) {
+    Ok(buf) => buf,
+    Err(e) => {
+      return Err(Error::SerializeError {
+        what: "JSON payload".to_string(),
+        reason: e.to_string()
+      })
+    }
+  };
+
+  let meta = crate::meta::MsgMeta {
+    content_type: Some(String::from("application/json")),
+    ..Default::default()
+  };
+
+  let mi = MsgInfo::builder()
+    .cmd(cmd)
+    .meta_params(meta.to_params()?)
+    .payload_buf(buf)
+    .build()?;
+
+  send(conn, xfer, &mi).await
+}
+
+
+/// Extra time allowed for the server's reply to a delivery wait, on top of
+/// the requested timeout.
+const DELIVERY_WAIT_GRACE: Duration = Duration::from_secs(5);
+
+
+/// Wait for the server to report that a message has been delivered on the
+/// far side of the diode.
+///
+/// This requires a deployment which has a return channel for delivery
+/// receipts.  The server waits up to `timeout` for the receipt of the
+/// message identified by the transfer identifier `xferid`.
+///
+/// Returns `Ok(true)` if the message was confirmed delivered and `Ok(false)`
+/// if no receipt arrived within `timeout`.  If the server doesn't reply at
+/// all the connection is left in an undefined state and
+/// `Error::BadState` is returned.
+pub async fn wait_delivered<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  xferid: &str,
+  timeout: Duration
+) -> Result<bool, Error> {
+  let tg = crate::tg!(
+    "WaitDelivered",
+    "XferId" => xferid,
+    "Timeout" => timeout.as_millis()
+  )?;
+
+  let res = tokio::time::timeout(
+    timeout + DELIVERY_WAIT_GRACE,
+    crate::sendrecv(conn, &tg)
+  )
+  .await;
+  let params = match res {
+    Ok(params) => params?,
+    Err(_) => {
+      return Err(Error::bad_state("a reply to the delivery wait request"))
+    }
+  };
+
+  Ok(params.get_bool_def("Delivered", false)?)
+}
+
+
+/// Translate an expiry into the telegram parameter the server expects.
+///
+/// Relative expiries are sent as a `TTL` in seconds, while absolute expiries
+/// are sent as an `Expires` timestamp in seconds since the unix epoch.
+fn add_expiry(tg: &mut Telegram, expires: &Expiry) -> Result<(), Error> {
+  match expires {
+    Expiry::Duration(dur) => {
+      let secs = dur
+        .as_secs()
+        .saturating_add(u64::from(dur.subsec_nanos() > 0));
+      tg.add_param("TTL", secs)?;
+    }
+    Expiry::At(tm) => {
+      tg.add_param("Expires", unix_secs(*tm, "Expiry time")?)?;
+    }
+  }
+  Ok(())
+}
+
+
+/// Convert a point in time to seconds since the unix epoch.
+fn unix_secs(tm: SystemTime, what: &str) -> Result<u64, Error> {
+  match tm.duration_since(UNIX_EPOCH) {
+    Ok(ts) => Ok(ts.as_secs()),
+    Err(_) => Err(Error::bad_format(what, "predates the unix epoch"))
+  }
+}
+
+
+/// Maximum size of a message's metadata.
+pub const MAX_META_SIZE: u64 = u32::MAX as u64;
+
+
+async fn get_meta_size(mi: &MsgInfo) -> Result<u32, Error> {
   let sz = match &mi.meta {
-    Some(meta) => match meta {
-      InputType::Params(params) => params.calc_buf_size(),
-      InputType::File(f) => {
-        let metadata = fs::metadata(&f)?;
-        metadata.len() as usize
-      }
-      InputType::VecBuf(v) => v.len(),
-      InputType::Bytes(b) => b.len()
-    },
+    Some(meta) => get_input_size(meta).await?,
     None => 0
   };
+  check_meta_size(sz)
+}
+
 
-  if sz > u32::MAX as usize {
-    // ToDo: Return out of bounds error
+fn check_meta_size(sz: u64) -> Result<u32, Error> {
+  if sz > MAX_META_SIZE {
+    return Err(Error::TooLarge {
+      what: "Metadata".to_string(),
+      size: Some(sz),
+      limit: MAX_META_SIZE
+    });
   }
 
   Ok(sz as u32)
 }
 
 
-fn get_payload_size(mi: &MsgInfo) -> Result<u64, Error> {
-  let sz = match &mi.payload {
-    Some(payload) => match payload {
-      InputType::Params(params) => params.calc_buf_size(),
-      InputType::File(f) => {
-        let metadata = fs::metadata(&f)?;
-        metadata.len() as usize
-      }
-      InputType::VecBuf(v) => v.len(),
-      InputType::Bytes(b) => b.len()
-    },
-    None => 0
+async fn get_payload_size(mi: &MsgInfo) -> Result<u64, Error> {
+  match &mi.payload {
+    Some(payload) => get_input_size(payload).await,
+    None => Ok(0)
+  }
+}
+
+
+/// Get the number of bytes an input will occupy on the wire.
+async fn get_input_size(input: &InputType) -> Result<u64, Error> {
+  match input {
+    InputType::Source(src) => src.size().await,
+    input => Ok(known_size(input)?.unwrap_or_default())
+  }
+}
+
+
+/// Get the number of bytes an input will occupy on the wire, unless it is
+/// an application-supplied source, whose size isn't known up front.
+fn known_size(input: &InputType) -> Result<Option<u64>, Error> {
+  let sz = match input {
+    InputType::Params(params) => params.calc_buf_size() as u64,
+    InputType::File(f) | InputType::Mmap(f) => {
+      fs::metadata(f).map_err(|e| Error::local_io(f, e))?.len()
+    }
+    InputType::VecBuf(v) => v.len() as u64,
+    InputType::Bytes(b) => b.len() as u64,
+    InputType::Source(_) => return Ok(None)
   };
+  Ok(Some(sz))
+}
+
 
-  Ok(sz as u64)
+/// When to flush the connection while sending file contents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlushPolicy {
+  /// Only flush once the entire file has been written.
+  AtEnd,
+
+  /// Flush each time the specified number of bytes has been written since
+  /// the last flush.
+  Bytes(u64),
+
+  /// Flush if the specified amount of time has passed since the last flush.
+  Interval(Duration)
 }
 
 
+/// Liveness checks for long running transfers.
+///
+/// Firewalls and NAT devices tend to drop connections which have been idle
+/// for a while.  A connection is idle, as far as they can tell, while the
+/// server is processing a large payload it has received, before it
+/// acknowledges it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Keepalive {
+  /// While waiting for the server to acknowledge metadata or a payload, send
+  /// a `Ping` request whenever this much time has passed without a reply.
+  ///
+  /// Only enable this for servers which support `Ping`.  Replies to the
+  /// pings, which the server sends after acknowledging the content, are
+  /// consumed; failure replies are ignored.
+  pub ping: Option<Duration>,
+
+  /// Fail with `Error::TransferTimeout` if writing content to the connection
+  /// makes no progress for this long.  Does not apply to content written
+  /// using `sendfile()` or from a memory mapping.
+  pub stall_timeout: Option<Duration>
+}
+
+
+/// Tuning options for sending file contents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SendOpts {
+  /// Size of the buffer used to copy file contents to the connection.
+  pub buf_size: usize,
+
+  /// When to flush the connection while the file is being sent.
+  pub flush: FlushPolicy,
+
+  /// Liveness checks while the content is being sent and acknowledged.
+  pub keepalive: Keepalive
+}
+
+impl Default for SendOpts {
+  fn default() -> Self {
+    SendOpts {
+      buf_size: 8 * 1024,
+      flush: FlushPolicy::AtEnd,
+      keepalive: Keepalive::default()
+    }
+  }
+}
+
+
+/// Options controlling how message content is written to the connection.
+#[derive(Clone, Default)]
+struct ContentOpts {
+  file: SendOpts,
+
+  /// If set, channel names are resolved through this cache.
+  ch_cache: Option<Arc<ChCache>>,
+
+  /// If set, file contents are written to this socket using `sendfile()`.
+  #[cfg(all(target_os = "linux", feature = "zerocopy"))]
+  sendfile_fd: Option<RawFd>,
+
+  /// If set, each step of the transfer must complete before the deadline.
+  deadline: Option<Deadline>
+}
+
+impl ContentOpts {
+  fn new(file: SendOpts) -> Self {
+    ContentOpts {
+      file,
+      ch_cache: None,
+      #[cfg(all(target_os = "linux", feature = "zerocopy"))]
+      sendfile_fd: None,
+      deadline: None
+    }
+  }
+
+  /// Run a step of the transfer, subject to the deadline if there is one.
+  async fn step<R, F>(&self, step: &str, fut: F) -> Result<R, Error>
+  where
+    F: std::future::Future<Output = Result<R, Error>>
+  {
+    match self.deadline {
+      Some(ref deadline) => deadline.run(step, fut).await,
+      None => fut.await
+    }
+  }
+}
+
+
+/// Write `data`, whose size has been announced to the server as `size`, to
+/// the connection.
 async fn send_content<T>(
   conn: &mut Framed<T, blather::Codec>,
-  data: &InputType
+  data: &InputType,
+  size: u64,
+  opts: &ContentOpts
 ) -> Result<(), Error>
 where
   T: AsyncRead + AsyncWrite + Unpin
 {
   match data {
     InputType::Params(params) => Ok(conn.send(params).await?),
-    InputType::File(fname) => {
-      let mut f = tokio::fs::File::open(fname).await?;
-      let _ = tokio::io::copy(&mut f, conn.get_mut()).await?;
+    #[cfg(all(target_os = "linux", feature = "zerocopy"))]
+    InputType::File(fname) if opts.sendfile_fd.is_some() => {
+      // Make sure nothing is left in the Framed's write buffer, since the
+      // file contents will bypass it.
+      SinkExt::<&Telegram>::flush(conn).await?;
+      let fd = opts.sendfile_fd.unwrap();
+      zerocopy::sendfile(fd, fname, size).await
+    }
+    InputType::File(fname) => send_file(conn, fname, size, opts).await,
+    InputType::Source(src) => {
+      // The size has already been announced to the server, and is not asked
+      // for again in case it has changed since.
+      let mut reader = src.open().await?.take(size);
+      let n = copy_file(&mut reader, conn.get_mut(), size, &opts.file).await?;
+      if n != size {
+        return Err(Error::InvalidSize {
+          what: "source".to_string(),
+          expected: Some(size),
+          actual: n
+        });
+      }
       Ok(())
     }
     InputType::VecBuf(v) => Ok(conn.send(v.as_slice()).await?),
-    InputType::Bytes(b) => Ok(conn.send(b.as_ref()).await?)
+    InputType::Bytes(b) => Ok(conn.send(b.as_ref()).await?),
+    #[cfg(not(feature = "mmap"))]
+    InputType::Mmap(fname) => send_file(conn, fname, size, opts).await,
+    #[cfg(feature = "mmap")]
+    InputType::Mmap(fname) => {
+      let local = |e| Error::local_io(fname, e);
+      let f = fs::File::open(fname).map_err(local)?;
+      let len = f.metadata().map_err(local)?.len();
+      if len < size {
+        return Err(truncated(fname, len, size));
+      }
+      if size == 0 {
+        // Zero-length files can't be mapped
+        return Ok(());
+      }
+      // Safety: The mapping is only read, but the file may be modified
+      // by other processes while it's mapped; this is the caller's
+      // responsibility to avoid.
+      let map = unsafe { memmap2::Mmap::map(&f).map_err(local)? };
+
+      // Write straight from the mapping rather than through the Framed's
+      // write buffer.
+      SinkExt::<&Telegram>::flush(conn).await?;
+      let stream = conn.get_mut();
+      // Only the announced size is sent, in case the file has grown since.
+      for chunk in map[..size as usize].chunks(MMAP_CHUNK_SIZE) {
+        stream.write_all(chunk).await?;
+      }
+      stream.flush().await?;
+      Ok(())
+    }
+  }
+}
+
+
+/// Write the first `size` bytes of the file `fname`, which is the size that
+/// has been announced to the server, to the connection.
+async fn send_file<T>(
+  conn: &mut Framed<T, blather::Codec>,
+  fname: &Path,
+  size: u64,
+  opts: &ContentOpts
+) -> Result<(), Error>
+where
+  T: AsyncRead + AsyncWrite + Unpin
+{
+  let f = tokio::fs::File::open(fname)
+    .await
+    .map_err(|e| Error::local_io(fname, e))?;
+  let mut reader = f.take(size);
+  let n = copy_file(&mut reader, conn.get_mut(), size, &opts.file).await?;
+  if n != size {
+    return Err(truncated(fname, n, size));
   }
+  Ok(())
 }
 
+
+/// The error reported when the file `fname` turned out to hold fewer bytes
+/// than the `size` announced to the server.
+fn truncated(fname: &Path, len: u64, size: u64) -> Error {
+  Error::InvalidSize {
+    what: format!("file '{}'", fname.display()),
+    expected: Some(size),
+    actual: len
+  }
+}
+
+
+/// Copy a file (or any other reader) of `size` bytes to a stream, flushing
+/// according to the flush policy.
+///
+/// Returns the number of bytes copied.
+async fn copy_file<R, W>(
+  f: &mut R,
+  stream: &mut W,
+  size: u64,
+  opts: &SendOpts
+) -> Result<u64, Error>
+where
+  R: AsyncRead + Unpin,
+  W: AsyncWrite + Unpin
+{
+  let mut buf = vec![0u8; opts.buf_size.max(1)];
+  let mut unflushed: u64 = 0;
+  let mut last_flush = Instant::now();
+  let mut total: u64 = 0;
+
+  loop {
+    let n = f
+      .read(&mut buf)
+      .await
+      .map_err(|error| Error::LocalIO { path: None, error })?;
+    if n == 0 {
+      break;
+    }
+    stalled(opts, size, total, stream.write_all(&buf[..n])).await?;
+    unflushed += n as u64;
+    total += n as u64;
+
+    let do_flush = match opts.flush {
+      FlushPolicy::AtEnd => false,
+      FlushPolicy::Bytes(limit) => unflushed >= limit,
+      FlushPolicy::Interval(dur) => last_flush.elapsed() >= dur
+    };
+    if do_flush {
+      stalled(opts, size, total, stream.flush()).await?;
+      unflushed = 0;
+      last_flush = Instant::now();
+    }
+  }
+  stalled(opts, size, total, stream.flush()).await?;
+
+  Ok(total)
+}
+
+
+/// Run a write to the connection, failing with `Error::TransferTimeout` if
+/// it doesn't complete within the stall timeout.
+async fn stalled<F>(
+  opts: &SendOpts,
+  size: u64,
+  done: u64,
+  fut: F
+) -> Result<(), Error>
+where
+  F: std::future::Future<Output = std::io::Result<()>>
+{
+  match opts.keepalive.stall_timeout {
+    Some(timeout) => match tokio::time::timeout(timeout, fut).await {
+      Ok(res) => Ok(res?),
+      Err(_) => Err(Error::TransferTimeout {
+        remaining: size.saturating_sub(done)
+      })
+    },
+    None => Ok(fut.await?)
+  }
+}
+
+
+/// Wait for the server to acknowledge content, sending pings while waiting
+/// if the keepalive settings ask for it.
+async fn expect_ack<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  keepalive: &Keepalive
+) -> Result<Params, Error> {
+  let interval = match keepalive.ping {
+    Some(interval) => interval,
+    None => return crate::expect_okfail(conn).await
+  };
+
+  // Waiting for the reply can be abandoned safely, since the Framed buffers
+  // partially received frames.
+  let mut pings = 0;
+  let res = loop {
+    match tokio::time::timeout(interval, crate::expect_okfail(conn)).await {
+      Ok(res) => break res,
+      Err(_) => {
+        conn.send(&Telegram::new_topic("Ping")?).await?;
+        pings += 1;
+      }
+    }
+  };
+
+  // The replies to the pings follow the acknowledgement.
+  for _ in 0..pings {
+    match crate::expect_okfail(conn).await {
+      Ok(_) => {}
+      Err(e) if e.is_server_error() => {}
+      Err(e) => return Err(e)
+    }
+  }
+
+  res
+}
+
+
+/// Number of bytes of a memory mapped file handed to the stream per write.
+#[cfg(feature = "mmap")]
+const MMAP_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
 // vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
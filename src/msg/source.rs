@@ -0,0 +1,76 @@
+//! Pluggable sources of message content.
+//!
+//! A [`PayloadSource`] allows message content to be streamed from anywhere
+//! an async reader can be produced from (object stores, HTTP responses,
+//! database blobs, ...) without first storing it in a local temporary file.
+
+use std::path::PathBuf;
+
+use tokio::io::AsyncRead;
+
+use futures::future::BoxFuture;
+
+use bytes::Bytes;
+
+use crate::err::Error;
+
+/// Async reader returned by a [`PayloadSource`].
+pub type SourceReader = Box<dyn AsyncRead + Send + Unpin>;
+
+
+/// A source of message content of a known length.
+pub trait PayloadSource: Send + Sync {
+  /// Return the number of bytes the source's reader will produce.
+  ///
+  /// The size is announced to the server before the content is sent, so
+  /// the reader must produce exactly this many bytes.  It is asked for once
+  /// per send.
+  fn size(&self) -> BoxFuture<'_, Result<u64, Error>>;
+
+  /// Open a reader for the source's content.
+  fn open(&self) -> BoxFuture<'_, Result<SourceReader, Error>>;
+}
+
+
+/// Content stored in a local file.
+pub struct FileSource(pub PathBuf);
+
+impl PayloadSource for FileSource {
+  fn size(&self) -> BoxFuture<'_, Result<u64, Error>> {
+    Box::pin(async move {
+      match tokio::fs::metadata(&self.0).await {
+        Ok(md) => Ok(md.len()),
+        Err(e) => Err(Error::local_io(&self.0, e))
+      }
+    })
+  }
+
+  fn open(&self) -> BoxFuture<'_, Result<SourceReader, Error>> {
+    Box::pin(async move {
+      let f = tokio::fs::File::open(&self.0)
+        .await
+        .map_err(|e| Error::local_io(&self.0, e))?;
+      Ok(Box::new(f) as SourceReader)
+    })
+  }
+}
+
+
+/// Content stored in memory.
+pub struct BufSource(pub Bytes);
+
+impl PayloadSource for BufSource {
+  fn size(&self) -> BoxFuture<'_, Result<u64, Error>> {
+    let size = self.0.len() as u64;
+    Box::pin(async move { Ok(size) })
+  }
+
+  fn open(&self) -> BoxFuture<'_, Result<SourceReader, Error>> {
+    let buf = self.0.clone();
+    Box::pin(
+      async move { Ok(Box::new(std::io::Cursor::new(buf)) as SourceReader) }
+    )
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
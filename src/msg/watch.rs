@@ -0,0 +1,382 @@
+//! Sending files dropped into a directory.
+//!
+//! Enabled by the `watch` feature.  [`watch_dir()`] implements the common
+//! "file drop" integration: files which appear in a directory are sent as
+//! messages, with their name stored in the message metadata under
+//! [`meta::FILENAME_KEY`](crate::meta::FILENAME_KEY), and are deleted or
+//! moved away once the server has accepted them.
+//!
+//! The directory is watched using the platform's file system notifications,
+//! where they are available, so new files are picked up promptly.  It is
+//! also polled, so files are still found on platforms and (network) file
+//! systems which don't deliver notifications.  A file is only sent once its
+//! size and modification time have remained unchanged for
+//! [`WatchRules::settle`], so files which are still being written are left
+//! alone.  Producers which can should still write files under a hidden
+//! (`.`-prefixed) name and rename them into place once complete; hidden
+//! files are never sent.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::time::{Duration, Instant, SystemTime};
+
+use futures::channel::mpsc;
+use futures::future;
+use futures::StreamExt;
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::{Interval, MissedTickBehavior};
+
+use tokio_util::codec::Framed;
+
+use crate::err::Error;
+use crate::meta::MsgMeta;
+
+use super::{Channel, MsgInfo, Transport};
+
+
+/// What to do with a file once it has been handed off to the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AfterSend {
+  /// Delete the file.
+  Delete,
+
+  /// Move the file into the specified directory.
+  MoveTo(PathBuf)
+}
+
+
+/// Rules controlling which files [`watch_dir()`] sends and how.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchRules {
+  /// Channel files are sent on.
+  pub ch: Channel,
+
+  /// Message command number.
+  pub cmd: u32,
+
+  /// Only send files with one of these extensions (compared
+  /// case-insensitively, without the leading `.`).  If empty, files are sent
+  /// regardless of their extension.
+  pub extensions: Vec<String>,
+
+  /// How often the directory is scanned.
+  pub poll_interval: Duration,
+
+  /// Scan the directory as soon as the platform reports changes in it, in
+  /// addition to polling it.
+  pub notify: bool,
+
+  /// How long a file's size and modification time must remain unchanged
+  /// before it is sent.
+  pub settle: Duration,
+
+  /// What to do with files the server has accepted.
+  pub after_send: AfterSend,
+
+  /// Directory files the server rejects are moved into.  If not set, or if a
+  /// file can't be moved into it, rejected files are left in place and are
+  /// not retried unless they are modified.
+  pub rejected: Option<PathBuf>
+}
+
+impl WatchRules {
+  /// Send all files on channel `ch`, deleting them once accepted.
+  pub fn new<C: Into<Channel>>(ch: C) -> Self {
+    WatchRules {
+      ch: ch.into(),
+      cmd: 0,
+      extensions: Vec::new(),
+      poll_interval: Duration::from_secs(1),
+      notify: true,
+      settle: Duration::from_secs(2),
+      after_send: AfterSend::Delete,
+      rejected: None
+    }
+  }
+
+  fn accepts(&self, fname: &Path) -> bool {
+    if self.extensions.is_empty() {
+      return true;
+    }
+    match fname.extension().and_then(|ext| ext.to_str()) {
+      Some(ext) => self.extensions.iter().any(|e| e.eq_ignore_ascii_case(ext)),
+      None => false
+    }
+  }
+}
+
+
+/// State of a file seen in the watched directory.
+struct Candidate {
+  len: u64,
+  modified: Option<SystemTime>,
+  since: Instant,
+  rejected: bool
+}
+
+
+/// Wakes the watcher when the platform reports changes in a directory.
+struct Notifier {
+  _watcher: RecommendedWatcher,
+  rx: mpsc::UnboundedReceiver<()>
+}
+
+impl Notifier {
+  /// Returns `None` if notifications aren't available for `dir`, in which
+  /// case it can only be polled.
+  fn new(dir: &Path) -> Option<Self> {
+    let (tx, rx) = mpsc::unbounded();
+    // Errors are passed on as well, since a rescan is the only recovery.
+    let mut watcher = notify::recommended_watcher(move |_| {
+      let _ = tx.unbounded_send(());
+    })
+    .ok()?;
+    watcher.watch(dir, RecursiveMode::NonRecursive).ok()?;
+    Some(Notifier {
+      _watcher: watcher,
+      rx
+    })
+  }
+
+  /// Wait for changes, consuming all the changes reported so far.
+  async fn changed(&mut self) {
+    if self.rx.next().await.is_none() {
+      // The watcher has given up; carry on polling.
+      return future::pending().await;
+    }
+    while self.rx.try_recv().is_ok() {}
+  }
+}
+
+
+/// Send files which appear in `dir` over `conn`, according to `rules`.
+///
+/// Files are sent one at a time, in the order they were found, and only
+/// regular files directly in `dir` are considered.  A file is handed off
+/// once the server has replied to its payload, at which point it is deleted
+/// or moved according to [`WatchRules::after_send`].  If the server rejects
+/// a file it is handled according to [`WatchRules::rejected`], and the
+/// watcher carries on.
+///
+/// Runs until an error other than a rejection occurs, such as the directory
+/// becoming unreadable or the connection failing.  To stop watching, drop
+/// the returned future.  A file which was being sent when the future was
+/// dropped, or when the connection failed, remains in the directory and is
+/// sent again by the next watcher; files are therefore delivered at least
+/// once.
+pub async fn watch_dir<T, P>(
+  dir: P,
+  rules: &WatchRules,
+  conn: &mut Framed<T, blather::Codec>
+) -> Result<(), Error>
+where
+  T: AsyncRead + AsyncWrite + Unpin,
+  P: AsRef<Path>
+{
+  let dir = dir.as_ref();
+  let xfer = Transport {
+    ch: rules.ch.clone()
+  };
+  let mut seen: HashMap<PathBuf, Candidate> = HashMap::new();
+
+  let mut interval = tokio::time::interval(rules.poll_interval);
+  interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+  let mut notifier = if rules.notify {
+    Notifier::new(dir)
+  } else {
+    None
+  };
+
+  loop {
+    wait(&mut interval, notifier.as_mut(), &seen, rules.settle).await;
+    for fname in scan(dir, rules, &mut seen).await? {
+      let modified = seen[&fname].modified;
+      match handoff(conn, &xfer, rules, &fname, modified).await {
+        Ok(()) => {
+          seen.remove(&fname);
+        }
+        Err(e) if e.is_server_error() => {
+          let moved = match rules.rejected {
+            Some(ref rejdir) => match move_into(&fname, rejdir).await {
+              Ok(()) => true,
+              Err(_e) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(
+                  file = %fname.display(),
+                  error = %_e,
+                  "unable to move rejected file"
+                );
+                false
+              }
+            },
+            None => false
+          };
+          if moved {
+            seen.remove(&fname);
+          } else if let Some(cand) = seen.get_mut(&fname) {
+            cand.rejected = true;
+          }
+        }
+        Err(e) => return Err(e)
+      }
+    }
+  }
+}
+
+
+/// Wait until the directory should be scanned again: when the poll interval
+/// has passed or, if notifications are available, when the directory has
+/// changed or a file is due to have settled.
+async fn wait(
+  interval: &mut Interval,
+  notifier: Option<&mut Notifier>,
+  seen: &HashMap<PathBuf, Candidate>,
+  settle: Duration
+) {
+  let tick = pin!(interval.tick());
+  let notifier = match notifier {
+    Some(notifier) => notifier,
+    None => {
+      tick.await;
+      return;
+    }
+  };
+
+  let settled = seen
+    .values()
+    .filter(|cand| !cand.rejected)
+    .map(|cand| cand.since + settle)
+    .min();
+  let settled = pin!(async move {
+    match settled {
+      Some(at) => tokio::time::sleep_until(at.into()).await,
+      None => future::pending().await
+    }
+  });
+  let changed = pin!(notifier.changed());
+
+  future::select(tick, future::select(changed, settled)).await;
+}
+
+
+/// Scan the directory, updating the state of the files in it, and return
+/// the files which are ready to be sent.
+async fn scan(
+  dir: &Path,
+  rules: &WatchRules,
+  seen: &mut HashMap<PathBuf, Candidate>
+) -> Result<Vec<PathBuf>, Error> {
+  let now = Instant::now();
+  let mut present = HashSet::new();
+  let mut ready = Vec::new();
+
+  let local = |e| Error::local_io(dir, e);
+  let mut entries = tokio::fs::read_dir(dir).await.map_err(local)?;
+  while let Some(entry) = entries.next_entry().await.map_err(local)? {
+    let fname = entry.path();
+    let hidden = entry
+      .file_name()
+      .to_str()
+      .is_none_or(|s| s.starts_with('.'));
+    if hidden || !rules.accepts(&fname) {
+      continue;
+    }
+
+    // The file may have been removed since the directory was read.
+    let md = match entry.metadata().await {
+      Ok(md) if md.is_file() => md,
+      _ => continue
+    };
+    let len = md.len();
+    let modified = md.modified().ok();
+
+    match seen.get_mut(&fname) {
+      Some(cand) if cand.len == len && cand.modified == modified => {
+        if !cand.rejected && now.duration_since(cand.since) >= rules.settle {
+          ready.push(fname.clone());
+        }
+      }
+      _ => {
+        seen.insert(
+          fname.clone(),
+          Candidate {
+            len,
+            modified,
+            since: now,
+            rejected: false
+          }
+        );
+      }
+    }
+    present.insert(fname);
+  }
+
+  // Forget files which have disappeared.
+  seen.retain(|fname, _| present.contains(fname));
+
+  Ok(ready)
+}
+
+
+async fn handoff<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  xfer: &Transport,
+  rules: &WatchRules,
+  fname: &Path,
+  modified: Option<SystemTime>
+) -> Result<(), Error> {
+  let meta = MsgMeta {
+    filename: fname
+      .file_name()
+      .and_then(|s| s.to_str())
+      .map(|s| s.to_string()),
+    created: modified,
+    ..Default::default()
+  };
+  let mi = MsgInfo::builder()
+    .cmd(rules.cmd)
+    .meta_params(meta.to_params()?)
+    .payload_file(fname)
+    .build()?;
+
+  super::send(conn, xfer, &mi).await?;
+
+  match rules.after_send {
+    AfterSend::Delete => tokio::fs::remove_file(fname)
+      .await
+      .map_err(|e| Error::local_io(fname, e))?,
+    AfterSend::MoveTo(ref dir) => move_into(fname, dir).await?
+  }
+  Ok(())
+}
+
+
+/// Move a file into a directory, keeping its name.  Falls back to copying the
+/// file if it can't be renamed, for instance because the directory is on
+/// another file system.
+async fn move_into(fname: &Path, dir: &Path) -> Result<(), Error> {
+  let target = match fname.file_name() {
+    Some(name) => dir.join(name),
+    None => {
+      return Err(Error::bad_format(
+        "file name",
+        format!("invalid file name '{}'", fname.display())
+      ))
+    }
+  };
+  if tokio::fs::rename(fname, &target).await.is_err() {
+    tokio::fs::copy(fname, &target)
+      .await
+      .map_err(|e| Error::local_io(&target, e))?;
+    tokio::fs::remove_file(fname)
+      .await
+      .map_err(|e| Error::local_io(fname, e))?;
+  }
+  Ok(())
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
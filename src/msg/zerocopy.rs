@@ -0,0 +1,138 @@
+//! Zero-copy transmission of file contents using `sendfile()`.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::{AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::err::Error;
+
+/// Largest number of bytes Linux will transfer in a single `sendfile()` call.
+const MAX_CHUNK: u64 = 0x7fff_f000;
+
+
+/// How long the transfer thread waits for the socket to become writable
+/// before checking whether it has been told to stop.
+const POLL_INTERVAL_MS: libc::c_int = 100;
+
+
+/// Write the first `size` bytes of the file `fname` to the socket `sock`.
+///
+/// The socket is nonblocking (it is owned by the runtime), so the transfer
+/// runs on a blocking thread which waits for the socket to become writable
+/// whenever the kernel's send buffer is full.
+///
+/// The thread works on its own duplicate of the socket, so the socket isn't
+/// closed under it if this future is dropped and the connection closed.
+/// Dropping the future also tells the thread to stop; until it has, it may
+/// still write to the socket.
+///
+/// The caller must make sure that `sock` is open when this function is
+/// called, and isn't written to by anyone else until it returns.
+pub(super) async fn sendfile(
+  sock: RawFd,
+  fname: &Path,
+  size: u64
+) -> Result<(), Error> {
+  // SAFETY: The caller guarantees that `sock` is open, and it is only
+  // borrowed long enough to duplicate it.
+  let sock = unsafe { BorrowedFd::borrow_raw(sock) }.try_clone_to_owned()?;
+  let fname: PathBuf = fname.to_path_buf();
+  let stop = StopOnDrop(Arc::new(AtomicBool::new(false)));
+  let stopped = stop.0.clone();
+  let res = crate::task::spawn_blocking("ddmw-sendfile", move || {
+    sendfile_blocking(&sock, &fname, size, &stopped)
+  })?
+  .await;
+  drop(stop);
+  match res {
+    Ok(res) => Ok(res?),
+    Err(e) => Err(Error::IO(e.into()))
+  }
+}
+
+
+/// Tells the transfer thread to stop when dropped.
+struct StopOnDrop(Arc<AtomicBool>);
+
+impl Drop for StopOnDrop {
+  fn drop(&mut self) {
+    self.0.store(true, Ordering::Relaxed);
+  }
+}
+
+
+fn sendfile_blocking(
+  sock: &OwnedFd,
+  fname: &Path,
+  size: u64,
+  stopped: &AtomicBool
+) -> Result<(), Error> {
+  let local = |e| Error::local_io(fname, e);
+  let f = File::open(fname).map_err(local)?;
+  let mut remain = size;
+  let mut offset: libc::off_t = 0;
+
+  while remain > 0 {
+    if stopped.load(Ordering::Relaxed) {
+      return Err(cancelled().into());
+    }
+    let count = remain.min(MAX_CHUNK) as usize;
+    let n = unsafe {
+      libc::sendfile(sock.as_raw_fd(), f.as_raw_fd(), &mut offset, count)
+    };
+    if n < 0 {
+      let err = io::Error::last_os_error();
+      match err.kind() {
+        io::ErrorKind::WouldBlock => wait_writable(sock, stopped)?,
+        io::ErrorKind::Interrupted => {}
+        _ => return Err(err.into())
+      }
+      continue;
+    }
+    if n == 0 {
+      return Err(local(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "File was truncated while being sent"
+      )));
+    }
+    remain -= n as u64;
+  }
+
+  Ok(())
+}
+
+
+/// Block until the socket is writable, or the transfer has been told to
+/// stop.
+fn wait_writable(sock: &OwnedFd, stopped: &AtomicBool) -> io::Result<()> {
+  let mut pfd = libc::pollfd {
+    fd: sock.as_raw_fd(),
+    events: libc::POLLOUT,
+    revents: 0
+  };
+  loop {
+    if stopped.load(Ordering::Relaxed) {
+      return Err(cancelled());
+    }
+    let n = unsafe { libc::poll(&mut pfd, 1, POLL_INTERVAL_MS) };
+    if n > 0 {
+      return Ok(());
+    }
+    if n < 0 {
+      let err = io::Error::last_os_error();
+      if err.kind() != io::ErrorKind::Interrupted {
+        return Err(err);
+      }
+    }
+  }
+}
+
+
+fn cancelled() -> io::Error {
+  io::Error::new(io::ErrorKind::Interrupted, "Transfer was cancelled")
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,324 @@
+//! Typed accessors for [`Params`].
+//!
+//! The accessors `blather` provides report missing keys and unparsable
+//! values as `blather` errors.  [`ParamsExt`] adds accessors for the value
+//! types replies commonly carry -- durations, timestamps, sizes and
+//! enumerations -- which report a missing key as `Error::MissingData`
+//! naming the key, and come in required and optional (`_opt`) variants.
+//!
+//! The parsers are also available as free functions, for values from other
+//! sources, such as configuration files.
+
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use blather::Params;
+
+use crate::err::Error;
+
+
+/// Typed accessors for reading reply parameters.
+pub trait ParamsExt {
+  /// Get the value of `key`, if it is set.
+  fn get_value(&self, key: &str) -> Option<&str>;
+
+  /// Get the value of `key`, failing with `Error::MissingData` if it isn't
+  /// set.
+  fn require_str(&self, key: &str) -> Result<&str, Error> {
+    self.get_value(key).ok_or_else(|| missing(key))
+  }
+
+  /// Get the value of `key` parsed as `T`, failing with `Error::BadFormat`
+  /// if it can't be parsed.
+  fn get_parsed<T: FromStr>(&self, key: &str) -> Result<T, Error> {
+    self.get_parsed_opt(key)?.ok_or_else(|| missing(key))
+  }
+
+  /// Like [`ParamsExt::get_parsed()`], but returns `Ok(None)` if `key` isn't
+  /// set.
+  fn get_parsed_opt<T: FromStr>(&self, key: &str) -> Result<Option<T>, Error> {
+    self
+      .get_value(key)
+      .map(|v| {
+        v.parse::<T>().map_err(|_| {
+          Error::bad_format(format!("value of '{}'", key), "unable to parse")
+        })
+      })
+      .transpose()
+  }
+
+  /// Get the value of `key` parsed as one of the values of the enumeration
+  /// `T`, failing with `Error::UnknownData` if it isn't one of them.
+  fn get_enum<T: FromStr>(&self, key: &str) -> Result<T, Error> {
+    self.get_enum_opt(key)?.ok_or_else(|| missing(key))
+  }
+
+  /// Like [`ParamsExt::get_enum()`], but returns `Ok(None)` if `key` isn't
+  /// set.
+  fn get_enum_opt<T: FromStr>(&self, key: &str) -> Result<Option<T>, Error> {
+    self
+      .get_value(key)
+      .map(|v| {
+        v.parse::<T>().map_err(|_| Error::UnknownData {
+          what: format!("value of '{}'", key),
+          value: v.to_string()
+        })
+      })
+      .transpose()
+  }
+
+  /// Get the value of `key` as a duration.  See [`parse_duration()`].
+  fn get_duration(&self, key: &str) -> Result<Duration, Error> {
+    self.get_duration_opt(key)?.ok_or_else(|| missing(key))
+  }
+
+  /// Like [`ParamsExt::get_duration()`], but returns `Ok(None)` if `key`
+  /// isn't set.
+  fn get_duration_opt(&self, key: &str) -> Result<Option<Duration>, Error> {
+    self
+      .get_value(key)
+      .map(|v| parse_duration(v).map_err(|e| in_key(e, key)))
+      .transpose()
+  }
+
+  /// Get the value of `key` as a point in time.  See [`parse_timestamp()`].
+  fn get_timestamp(&self, key: &str) -> Result<SystemTime, Error> {
+    self.get_timestamp_opt(key)?.ok_or_else(|| missing(key))
+  }
+
+  /// Like [`ParamsExt::get_timestamp()`], but returns `Ok(None)` if `key`
+  /// isn't set.
+  fn get_timestamp_opt(&self, key: &str) -> Result<Option<SystemTime>, Error> {
+    self
+      .get_value(key)
+      .map(|v| parse_timestamp(v).map_err(|e| in_key(e, key)))
+      .transpose()
+  }
+
+  /// Get the value of `key` as a number of bytes.  See [`parse_size()`].
+  fn get_size_bytes(&self, key: &str) -> Result<u64, Error> {
+    self.get_size_bytes_opt(key)?.ok_or_else(|| missing(key))
+  }
+
+  /// Like [`ParamsExt::get_size_bytes()`], but returns `Ok(None)` if `key`
+  /// isn't set.
+  fn get_size_bytes_opt(&self, key: &str) -> Result<Option<u64>, Error> {
+    self
+      .get_value(key)
+      .map(|v| parse_size(v).map_err(|e| in_key(e, key)))
+      .transpose()
+  }
+}
+
+impl ParamsExt for Params {
+  fn get_value(&self, key: &str) -> Option<&str> {
+    self.get_str(key)
+  }
+}
+
+
+fn missing(key: &str) -> Error {
+  Error::missing(key)
+}
+
+fn in_key(e: Error, key: &str) -> Error {
+  match e {
+    Error::BadFormat { what, reason } => Error::BadFormat {
+      what: format!("{} in '{}'", what, key),
+      reason
+    },
+    e => e
+  }
+}
+
+
+/// Split `s` into a leading number and the unit following it.
+fn split_unit(s: &str) -> (&str, &str) {
+  let idx = s
+    .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+    .unwrap_or(s.len());
+  (&s[..idx], s[idx..].trim_start())
+}
+
+
+/// Parse a duration, such as `10s`, `250ms` or `1h30m`.
+///
+/// The value is one or more numbers, each followed by one of the units `ns`,
+/// `us`, `ms`, `s`, `m`, `h` and `d`.  A plain number is taken to be a number
+/// of seconds.  Numbers may have fractions.
+pub fn parse_duration(s: &str) -> Result<Duration, Error> {
+  let invalid =
+    || Error::bad_format("duration", format!("invalid duration '{}'", s));
+
+  let mut rest = s.trim();
+  if rest.is_empty() {
+    return Err(invalid());
+  }
+  if let Ok(secs) = rest.parse::<f64>() {
+    return Duration::try_from_secs_f64(secs).map_err(|_| invalid());
+  }
+
+  let mut total = Duration::ZERO;
+  while !rest.is_empty() {
+    let (num, tail) = split_unit(rest);
+    let idx = tail
+      .find(|c: char| c.is_ascii_digit() || c.is_whitespace())
+      .unwrap_or(tail.len());
+    let (unit, tail) = tail.split_at(idx);
+    let scale = match unit {
+      "ns" => 1e-9,
+      "us" | "µs" => 1e-6,
+      "ms" => 1e-3,
+      "s" => 1.0,
+      "m" => 60.0,
+      "h" => 3600.0,
+      "d" => 86400.0,
+      _ => return Err(invalid())
+    };
+    let n = num.parse::<f64>().map_err(|_| invalid())?;
+    let d = Duration::try_from_secs_f64(n * scale).map_err(|_| invalid())?;
+    total = total.checked_add(d).ok_or_else(invalid)?;
+    rest = tail.trim_start();
+  }
+  Ok(total)
+}
+
+
+/// Parse a point in time, either as a number of seconds since the Unix
+/// epoch, or in the RFC 3339 format, such as `2024-01-01T12:00:00Z` or
+/// `2024-01-01T14:00:00.5+02:00`.  A date on its own, such as
+/// `2024-01-01`, is taken to mean midnight UTC.  Years are limited to
+/// `0000` through `9999`, and UTC offsets to less than 24 hours.
+pub fn parse_timestamp(s: &str) -> Result<SystemTime, Error> {
+  let s = s.trim();
+  let invalid =
+    || Error::bad_format("timestamp", format!("invalid timestamp '{}'", s));
+
+  if let Ok(secs) = s.parse::<u64>() {
+    return UNIX_EPOCH
+      .checked_add(Duration::from_secs(secs))
+      .ok_or_else(invalid);
+  }
+
+  let num = |s: &str| -> Result<i64, Error> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+      return Err(invalid());
+    }
+    s.parse::<i64>().map_err(|_| invalid())
+  };
+
+  let (date, time) = match s.find(['T', 't', ' ']) {
+    Some(idx) => (&s[..idx], Some(&s[idx + 1..])),
+    None => (s, None)
+  };
+
+  let mut parts = date.splitn(3, '-');
+  let year = num(parts.next().unwrap_or_default())?;
+  let month = num(parts.next().ok_or_else(invalid)?)?;
+  let day = num(parts.next().ok_or_else(invalid)?)?;
+  if year > 9999 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+    return Err(invalid());
+  }
+  let mut secs = days_from_civil(year, month, day)
+    .checked_mul(86400)
+    .ok_or_else(invalid)?;
+  let mut nanos = 0u32;
+
+  if let Some(time) = time {
+    // Split off the UTC offset.
+    let (clock, offset) = if let Some(clock) = time.strip_suffix(['Z', 'z']) {
+      (clock, 0)
+    } else {
+      let idx = time.rfind(['+', '-']).ok_or_else(invalid)?;
+      let (clock, offs) = time.split_at(idx);
+      let sign = if offs.starts_with('-') { -1 } else { 1 };
+      let (h, m) = offs[1..].split_once(':').ok_or_else(invalid)?;
+      let (h, m) = (num(h)?, num(m)?);
+      if h > 23 || m > 59 {
+        return Err(invalid());
+      }
+      (clock, sign * (h * 3600 + m * 60))
+    };
+
+    let (clock, frac) = match clock.split_once('.') {
+      Some((clock, frac)) => (clock, Some(frac)),
+      None => (clock, None)
+    };
+    let mut hms = clock.splitn(3, ':');
+    let h = num(hms.next().unwrap_or_default())?;
+    let m = num(hms.next().ok_or_else(invalid)?)?;
+    let sec = num(hms.next().ok_or_else(invalid)?)?;
+    if h > 23 || m > 59 || sec > 60 {
+      return Err(invalid());
+    }
+    if let Some(frac) = frac {
+      num(frac)?;
+      let digits: String = frac.chars().chain("000000000".chars()).collect();
+      nanos = digits[..9].parse::<u32>().map_err(|_| invalid())?;
+    }
+    secs = secs
+      .checked_add(h * 3600 + m * 60 + sec - offset)
+      .ok_or_else(invalid)?;
+  }
+
+  let ts = if secs >= 0 {
+    UNIX_EPOCH.checked_add(Duration::new(secs as u64, nanos))
+  } else {
+    UNIX_EPOCH
+      .checked_sub(Duration::from_secs(secs.unsigned_abs()))
+      .and_then(|t| t.checked_add(Duration::new(0, nanos)))
+  };
+  ts.ok_or_else(invalid)
+}
+
+
+/// Number of days from 1970-01-01 to the given date in the proleptic
+/// Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+  let y = if month <= 2 { year - 1 } else { year };
+  let era = y.div_euclid(400);
+  let yoe = y - era * 400;
+  let mp = (month + 9) % 12;
+  let doy = (153 * mp + 2) / 5 + day - 1;
+  let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+  era * 146097 + doe - 719468
+}
+
+
+/// Parse a size in bytes, such as `512`, `10kB` or `5MiB`.
+///
+/// The units `k`, `M`, `G` and `T`, optionally followed by `B`, are powers of
+/// 1000, and `Ki`, `Mi`, `Gi` and `Ti`, also optionally followed by `B`, are
+/// powers of 1024.  Units are case insensitive.  A plain number, or a number
+/// followed by `B`, is a number of bytes.
+pub fn parse_size(s: &str) -> Result<u64, Error> {
+  let invalid = || Error::bad_format("size", format!("invalid size '{}'", s));
+
+  let (num, unit) = split_unit(s.trim());
+  let unit = unit.to_ascii_lowercase();
+  let unit = unit.strip_suffix('b').unwrap_or(&unit);
+  let scale: u64 = match unit {
+    "" => 1,
+    "k" => 1000,
+    "m" => 1000 * 1000,
+    "g" => 1000 * 1000 * 1000,
+    "t" => 1000 * 1000 * 1000 * 1000,
+    "ki" => 1 << 10,
+    "mi" => 1 << 20,
+    "gi" => 1 << 30,
+    "ti" => 1 << 40,
+    _ => return Err(invalid())
+  };
+
+  if let Ok(n) = num.parse::<u64>() {
+    return n.checked_mul(scale).ok_or_else(invalid);
+  }
+  let n = num.parse::<f64>().map_err(|_| invalid())?;
+  let bytes = (n * scale as f64).round();
+  if !bytes.is_finite() || bytes >= u64::MAX as f64 {
+    return Err(invalid());
+  }
+  Ok(bytes as u64)
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,29 @@
+//! Commonly used types and helpers.
+//!
+//! ```
+//! use tokio_ddmw::prelude::*;
+//! ```
+//!
+//! Includes the `blather` types the helpers operate on, so integrations
+//! don't need to depend on a matching version of `blather` themselves.
+
+pub use blather::{Codec, Params, Telegram};
+
+pub use tokio_util::codec::Framed;
+
+pub use crate::auth::{authenticate, AuthInfo, Token};
+#[cfg(feature = "tokio-net")]
+pub use crate::client::Client;
+pub use crate::clntif::ClntIfFramed;
+pub use crate::err::Error;
+pub use crate::framed::DdmwFramedExt;
+#[cfg(feature = "msg")]
+pub use crate::msg::{
+  Channel, Conn, ConnTransport, Endpoint, MsgInfo, Transport
+};
+pub use crate::{
+  expect_okfail, get_nodeinfo, sendrecv, DdmwFramed, NodeInfo, ObjRef,
+  OptObjRef
+};
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
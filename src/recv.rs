@@ -0,0 +1,720 @@
+//! Helpers for receiver integrations.
+//!
+//! Messages are pushed to the application over a client interface
+//! connection.  Each message is announced by a `Msg` telegram containing the
+//! channel (`_Ch`), the transfer identifier (`XferId`), the optional command
+//! (`Cmd`) and the metadata and payload lengths (`MetaLen` and `Len`).  The
+//! metadata, which must be a `Params` block, and the payload follow the
+//! telegram, in that order.  Once the application has processed a message it
+//! acknowledges it by replying with an `Ok` telegram (or a `Fail` telegram if
+//! it could not process it) containing the message's transfer identifier.
+
+use std::convert::TryFrom;
+use std::future::Future;
+#[cfg(feature = "msg")]
+use std::path::Path;
+use std::pin::pin;
+use std::task::Poll;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::time::Instant;
+
+use tokio_util::codec::Framed;
+
+use futures::future::{self, Either};
+use futures::sink::SinkExt;
+use futures::stream::{FuturesUnordered, StreamExt};
+
+use bytes::BytesMut;
+
+use blather::{codec, Params, Telegram};
+
+#[cfg(feature = "msg")]
+use crate::clntif::{self, ClntIfFramed};
+use crate::err::Error;
+use crate::meta::TagFilter;
+
+
+/// A received message.
+#[derive(Debug, Clone)]
+pub struct Msg {
+  pub ch: u8,
+  pub xferid: String,
+  pub cmd: u32,
+  pub meta: Option<Params>,
+  pub payload: Option<BytesMut>
+}
+
+
+/// The contents of a `Msg` telegram announcing an incoming message.
+struct MsgHdr {
+  ch: u8,
+  xferid: String,
+  cmd: u32,
+  metalen: u64,
+  len: u64
+}
+
+impl MsgHdr {
+  fn report_received(&self) {
+    crate::metrics::global()
+      .message_received(self.ch, self.metalen.saturating_add(self.len));
+  }
+
+  /// The payload length, failing with `Error::TooLarge` if it exceeds
+  /// `limit` or can't be addressed on this platform.
+  fn payload_len(&self, limit: u64) -> Result<usize, Error> {
+    let too_large = || Error::TooLarge {
+      what: format!("Payload of message {}", self.xferid),
+      size: Some(self.len),
+      limit
+    };
+    if self.len > limit {
+      return Err(too_large());
+    }
+    usize::try_from(self.len).map_err(|_| too_large())
+  }
+}
+
+
+/// The largest payload [`next()`] and [`consume()`] receive into memory by
+/// default.
+pub const DEFAULT_MAX_PAYLOAD: u64 = 64 * 1024 * 1024;
+
+
+/// Wait for the next message to arrive and receive it into memory.
+///
+/// Payloads larger than [`DEFAULT_MAX_PAYLOAD`] are rejected; see
+/// [`next_limited()`].
+///
+/// Returns `Ok(None)` if the connection was closed.
+pub async fn next<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>
+) -> Result<Option<Msg>, Error> {
+  next_limited(conn, DEFAULT_MAX_PAYLOAD).await
+}
+
+
+/// Like [`next()`], but reject payloads larger than `max_payload` bytes.
+///
+/// A message whose payload is too large is skipped and reported to the
+/// server as failed, and `Error::TooLarge`, naming its transfer identifier,
+/// is returned.  The connection remains usable.
+pub async fn next_limited<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  max_payload: u64
+) -> Result<Option<Msg>, Error> {
+  let hdr = match parse_hdr(conn.next().await)? {
+    Some(hdr) => hdr,
+    None => return Ok(None)
+  };
+  let meta = recv_meta(conn, &hdr).await?;
+  let payload = match recv_payload(conn, &hdr, max_payload).await? {
+    Ok(payload) => payload,
+    Err(e) => {
+      fail(conn, &hdr.xferid, &e.to_string()).await?;
+      return Err(e);
+    }
+  };
+  hdr.report_received();
+
+  Ok(Some(Msg {
+    ch: hdr.ch,
+    xferid: hdr.xferid,
+    cmd: hdr.cmd,
+    meta,
+    payload
+  }))
+}
+
+
+/// Acknowledge that a message has been processed.
+pub async fn ack<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  xferid: &str
+) -> Result<(), Error> {
+  conn.send(&ack_tg(xferid)?).await?;
+  Ok(())
+}
+
+
+/// Report that a message could not be processed.
+pub async fn fail<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  xferid: &str,
+  reason: &str
+) -> Result<(), Error> {
+  conn.send(&fail_tg(xferid, reason)?).await?;
+  Ok(())
+}
+
+
+fn ack_tg(xferid: &str) -> Result<Telegram, Error> {
+  let mut tg = Telegram::new_topic("Ok")?;
+  tg.add_str("XferId", xferid)?;
+  Ok(tg)
+}
+
+
+fn fail_tg(xferid: &str, reason: &str) -> Result<Telegram, Error> {
+  let mut tg = Telegram::new_topic("Fail")?;
+  tg.add_str("XferId", xferid)?;
+  tg.add_str("Err", reason)?;
+  Ok(tg)
+}
+
+
+/// Pacing options for [`consume()`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Pacing {
+  /// Maximum number of messages to pull off the connection per second.
+  pub max_rate: Option<f64>,
+
+  /// Maximum number of messages being processed concurrently.  Defaults to
+  /// one, meaning messages are processed one at a time.
+  pub max_concurrent: Option<usize>,
+
+  /// Largest payload to receive into memory.  Defaults to
+  /// [`DEFAULT_MAX_PAYLOAD`].  Messages with larger payloads are skipped and
+  /// reported to the server as failed.
+  pub max_payload: Option<u64>
+}
+
+
+/// Receive messages and pass them to a handler until the connection is
+/// closed.
+///
+/// Messages whose metadata does not pass the tag `filter` are acknowledged
+/// without being passed to the handler, and their payloads are skipped rather
+/// than loaded into memory.
+///
+/// The next message is not read off the connection until the `pacing`
+/// constraints allow it, so a slow handler causes the backlog to remain on
+/// the server rather than accumulate in memory.  Running handlers keep making
+/// progress while the next message is being received.
+///
+/// Each message is acknowledged once its handler completes; if the handler
+/// returns an error the message is reported as failed.
+pub async fn consume<T, F, Fut>(
+  conn: &mut Framed<T, blather::Codec>,
+  filter: &TagFilter,
+  pacing: &Pacing,
+  mut handler: F
+) -> Result<(), Error>
+where
+  T: AsyncRead + AsyncWrite + Unpin,
+  F: FnMut(Msg) -> Fut,
+  Fut: Future<Output = Result<(), Error>>
+{
+  let max_concurrent = pacing.max_concurrent.unwrap_or(1).max(1);
+  let max_payload = pacing.max_payload.unwrap_or(DEFAULT_MAX_PAYLOAD);
+  let interval = match pacing.max_rate {
+    Some(rate) if rate > 0.0 => Some(Duration::from_secs_f64(1.0 / rate)),
+    _ => None
+  };
+  let mut next_at = Instant::now();
+  let mut handlers = Handlers {
+    inflight: FuturesUnordered::new(),
+    done: Vec::new()
+  };
+
+  loop {
+    // Wait for a free handler slot, acknowledging messages as their handlers
+    // complete.
+    handlers.reply_done(conn).await?;
+    while handlers.inflight.len() >= max_concurrent {
+      if let Some(outcome) = handlers.inflight.next().await {
+        handlers.done.push(outcome);
+      }
+      handlers.reply_done(conn).await?;
+    }
+
+    if let Some(interval) = interval {
+      handlers.drive(tokio::time::sleep_until(next_at)).await;
+      next_at = Instant::now() + interval;
+    }
+
+    // Wait for the next message announcement while acknowledging completed
+    // handlers.  Only the telegram reception is raced, which is safe to
+    // abandon since the Framed buffers partially received frames.
+    let hdr = loop {
+      handlers.reply_done(conn).await?;
+      if handlers.inflight.is_empty() {
+        break parse_hdr(conn.next().await)?;
+      }
+      match future::select(conn.next(), handlers.inflight.next()).await {
+        Either::Left((input, _)) => break parse_hdr(input)?,
+        Either::Right((Some(outcome), _)) => handlers.done.push(outcome),
+        Either::Right((None, _)) => {}
+      }
+    };
+
+    let hdr = match hdr {
+      Some(hdr) => hdr,
+      None => {
+        // Disconnected; let running handlers finish, but there's no way to
+        // acknowledge them.
+        while handlers.inflight.next().await.is_some() {}
+        return Ok(());
+      }
+    };
+
+    let meta = handlers.drive(recv_meta(conn, &hdr)).await?;
+
+    let wanted = match meta {
+      Some(ref meta) => filter.matches(meta),
+      None => filter.matches(&Params::new())
+    };
+    if !wanted {
+      handlers.drive(skip_payload(conn, &hdr)).await?;
+      handlers.drive(ack(conn, &hdr.xferid)).await?;
+      continue;
+    }
+
+    let payload = match handlers
+      .drive(recv_payload(conn, &hdr, max_payload))
+      .await?
+    {
+      Ok(payload) => payload,
+      Err(e) => {
+        handlers
+          .drive(fail(conn, &hdr.xferid, &e.to_string()))
+          .await?;
+        continue;
+      }
+    };
+    hdr.report_received();
+
+    let xferid = hdr.xferid.clone();
+    let fut = handler(Msg {
+      ch: hdr.ch,
+      xferid: hdr.xferid,
+      cmd: hdr.cmd,
+      meta,
+      payload
+    });
+    handlers.inflight.push(async move { (xferid, fut.await) });
+  }
+}
+
+
+/// The transfer identifier of a message passed to a [`consume()`] handler,
+/// and the handler's result.
+type Outcome = (String, Result<(), Error>);
+
+
+/// The handlers [`consume()`] is running, and the outcomes of those which
+/// have completed but have not been reported to the server yet.
+///
+/// Handlers only make progress while they are polled, so `consume()` waits
+/// through [`drive()`](Self::drive), which polls them alongside whatever is
+/// being waited for.
+struct Handlers<H> {
+  inflight: FuturesUnordered<H>,
+  done: Vec<Outcome>
+}
+
+impl<H: Future<Output = Outcome>> Handlers<H> {
+  /// Wait for `fut` while running the handlers.
+  async fn drive<F: Future>(&mut self, fut: F) -> F::Output {
+    let inflight = &mut self.inflight;
+    let done = &mut self.done;
+    let mut fut = pin!(fut);
+    future::poll_fn(|cx| {
+      while let Poll::Ready(Some(outcome)) = inflight.poll_next_unpin(cx) {
+        done.push(outcome);
+      }
+      fut.as_mut().poll(cx)
+    })
+    .await
+  }
+
+  /// Acknowledge, or report as failed, the messages whose handlers have
+  /// completed.
+  async fn reply_done<T: AsyncRead + AsyncWrite + Unpin>(
+    &mut self,
+    conn: &mut Framed<T, blather::Codec>
+  ) -> Result<(), Error> {
+    while !self.done.is_empty() {
+      for (xferid, res) in std::mem::take(&mut self.done) {
+        self.drive(reply(conn, &xferid, res)).await?;
+      }
+    }
+    Ok(())
+  }
+}
+
+
+/// What to do when a file being delivered by [`deliver_to_dir()`] has the
+/// same name as an existing file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Collision {
+  /// Replace the existing file.
+  Overwrite,
+
+  /// Add a numeric suffix to the file name, such as `report-1.pdf`, choosing
+  /// the first name which isn't taken.
+  #[default]
+  Rename,
+
+  /// Report the message as failed, leaving the existing file in place.
+  Fail
+}
+
+
+/// How [`deliver_to_dir()`] names delivered files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NamingPolicy {
+  /// Use the file name stored in the message metadata, under
+  /// [`meta::FILENAME_KEY`](crate::meta::FILENAME_KEY), if it has one.
+  /// Otherwise, or if the stored name isn't a plain file name, the file is
+  /// named after the message's transfer identifier.
+  pub use_filename: bool,
+
+  /// What to do if a file by the same name already exists.
+  pub collision: Collision
+}
+
+impl Default for NamingPolicy {
+  fn default() -> Self {
+    NamingPolicy {
+      use_filename: true,
+      collision: Collision::default()
+    }
+  }
+}
+
+#[cfg(feature = "msg")]
+impl NamingPolicy {
+  fn name_for(&self, meta: Option<&Params>, xferid: &str) -> String {
+    let fname = if self.use_filename {
+      meta.and_then(|m| m.get_str(crate::meta::FILENAME_KEY))
+    } else {
+      None
+    };
+    match fname {
+      Some(fname) if is_plain_filename(fname) => fname.to_string(),
+      _ => xferid.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+    }
+  }
+}
+
+
+/// Returns `true` if `fname` names a file without referring to any other
+/// directory, and isn't a hidden file.
+#[cfg(feature = "msg")]
+fn is_plain_filename(fname: &str) -> bool {
+  let mut comps = Path::new(fname).components();
+  matches!(
+    (comps.next(), comps.next()),
+    (Some(std::path::Component::Normal(_)), None)
+  ) && !fname.starts_with('.')
+    && !fname.contains(['/', '\\'])
+}
+
+
+/// Receive messages and store their payloads as files in `dir` until the
+/// connection is closed.
+///
+/// Files are named according to `naming`.  Each payload is written to a
+/// hidden temporary file in `dir`, synced to disk and then renamed into
+/// place, so other processes watching the directory never see partially
+/// written files.  The message is acknowledged once its file is in place.
+///
+/// Payloads are written by the codec's background file writer, so slow
+/// disks don't hold up other tasks, and the codec's binary transfer
+/// timeouts apply.  The codec is set to [remove partial
+/// files](clntif::Codec::remove_partial_files).
+///
+/// If a message can't be delivered because of a name collision (see
+/// [`Collision::Fail`]) it is reported as failed and delivery carries on.
+/// Errors writing to the directory are reported to the server as failures
+/// for the affected message, and are then returned, since later messages
+/// would likely fail in the same way.
+///
+/// Only one receiver should deliver into any given directory.
+///
+/// Requires the `msg` feature.
+#[cfg(feature = "msg")]
+pub async fn deliver_to_dir<T, P>(
+  conn: &mut ClntIfFramed<T>,
+  dir: P,
+  naming: &NamingPolicy
+) -> Result<(), Error>
+where
+  T: AsyncRead + AsyncWrite + Unpin,
+  P: AsRef<Path>
+{
+  let dir = dir.as_ref();
+  conn.codec_mut().remove_partial_files(true);
+
+  loop {
+    let hdr = match clntif::util::next_input(conn).await {
+      Some(Ok(clntif::Input::Telegram(tg))) => parse_msg_tg(tg)?,
+      Some(Ok(_)) => return Err(Error::bad_state("a telegram")),
+      Some(Err(e)) => return Err(e),
+      None => return Ok(())
+    };
+    let meta = if hdr.metalen > 0 {
+      conn.codec_mut().expect_params();
+      match next_clntif(conn).await? {
+        clntif::Input::Params(params) => Some(params),
+        _ => return Err(Error::bad_state("message metadata"))
+      }
+    } else {
+      None
+    };
+    let name = naming.name_for(meta.as_ref(), &hdr.xferid);
+    let tmpname = dir.join(format!(".{}.part", name));
+
+    if let Err(e) = recv_to_file(conn, &hdr, &tmpname).await? {
+      let _ = tokio::fs::remove_file(&tmpname).await;
+      conn.send(&fail_tg(&hdr.xferid, &e.to_string())?).await?;
+      return Err(e);
+    }
+    hdr.report_received();
+
+    match place(&tmpname, dir, &name, naming.collision).await {
+      Ok(true) => conn.send(&ack_tg(&hdr.xferid)?).await?,
+      Ok(false) => {
+        let _ = tokio::fs::remove_file(&tmpname).await;
+        let reason = format!("File '{}' already exists", name);
+        conn.send(&fail_tg(&hdr.xferid, &reason)?).await?;
+      }
+      Err(e) => {
+        let _ = tokio::fs::remove_file(&tmpname).await;
+        conn.send(&fail_tg(&hdr.xferid, &e.to_string())?).await?;
+        return Err(e);
+      }
+    }
+  }
+}
+
+
+/// Receive a message's payload into the file `fname`, and sync it to disk.
+///
+/// The outer error is returned if the connection can no longer be used, and
+/// the inner one if only the message is affected.  If the file can't be
+/// created the payload is skipped, so the connection remains usable.
+#[cfg(feature = "msg")]
+async fn recv_to_file<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut ClntIfFramed<T>,
+  hdr: &MsgHdr,
+  fname: &Path
+) -> Result<Result<(), Error>, Error> {
+  if hdr.len == 0 {
+    return Ok(create_synced(fname).await);
+  }
+  let len = hdr.payload_len(u64::MAX)?;
+  if let Err(e) = conn.codec_mut().expect_file(fname, len) {
+    conn.codec_mut().skip(len)?;
+    return match next_clntif(conn).await? {
+      clntif::Input::SkipDone => Ok(Err(e)),
+      _ => Err(Error::bad_state("skipped payload"))
+    };
+  }
+  match next_clntif(conn).await? {
+    clntif::Input::File(_) => {}
+    _ => return Err(Error::bad_state("message payload"))
+  }
+  Ok(sync(fname).await)
+}
+
+
+/// Wait for the next input on a `clntif` connection, waiting for the file
+/// writer to catch up whenever the codec pauses, and treating a closed
+/// connection as an error.
+#[cfg(feature = "msg")]
+async fn next_clntif<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut ClntIfFramed<T>
+) -> Result<clntif::Input, Error> {
+  loop {
+    match clntif::util::next_input(conn).await {
+      Some(Ok(clntif::Input::Paused)) => conn.codec().resumed().await,
+      Some(input) => return input,
+      None => return Err(Error::Disconnected)
+    }
+  }
+}
+
+
+#[cfg(feature = "msg")]
+async fn create_synced(fname: &Path) -> Result<(), Error> {
+  let local = |e| Error::local_io(fname, e);
+  let f = tokio::fs::File::create(fname).await.map_err(local)?;
+  f.sync_all().await.map_err(local)
+}
+
+
+#[cfg(feature = "msg")]
+async fn sync(fname: &Path) -> Result<(), Error> {
+  let local = |e| Error::local_io(fname, e);
+  let f = tokio::fs::File::open(fname).await.map_err(local)?;
+  f.sync_all().await.map_err(local)
+}
+
+
+/// Rename the temporary file `tmpname` to `name` in `dir`, resolving
+/// collisions according to `collision`.
+///
+/// Returns `Ok(false)` if the file was not placed because of a collision.
+#[cfg(feature = "msg")]
+async fn place(
+  tmpname: &Path,
+  dir: &Path,
+  name: &str,
+  collision: Collision
+) -> Result<bool, Error> {
+  let mut target = dir.join(name);
+  if collision != Collision::Overwrite && exists(&target).await? {
+    if collision == Collision::Fail {
+      return Ok(false);
+    }
+    let (stem, ext) = match name.rsplit_once('.') {
+      Some((stem, ext)) if !stem.is_empty() => (stem, Some(ext)),
+      _ => (name, None)
+    };
+    for n in 1u64.. {
+      target = match ext {
+        Some(ext) => dir.join(format!("{}-{}.{}", stem, n, ext)),
+        None => dir.join(format!("{}-{}", stem, n))
+      };
+      if !exists(&target).await? {
+        break;
+      }
+    }
+  }
+  tokio::fs::rename(tmpname, &target)
+    .await
+    .map_err(|e| Error::local_io(&target, e))?;
+  Ok(true)
+}
+
+
+#[cfg(feature = "msg")]
+async fn exists(fname: &Path) -> Result<bool, Error> {
+  tokio::fs::try_exists(fname)
+    .await
+    .map_err(|e| Error::local_io(fname, e))
+}
+
+
+async fn reply<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  xferid: &str,
+  res: Result<(), Error>
+) -> Result<(), Error> {
+  match res {
+    Ok(_) => ack(conn, xferid).await,
+    Err(e) => fail(conn, xferid, &e.to_string()).await
+  }
+}
+
+
+fn parse_hdr(
+  input: Option<Result<codec::Input, blather::Error>>
+) -> Result<Option<MsgHdr>, Error> {
+  let tg = match input {
+    Some(Ok(codec::Input::Telegram(tg))) => tg,
+    Some(Ok(_)) => return Err(Error::bad_state("a telegram")),
+    Some(Err(e)) => return Err(e.into()),
+    None => return Ok(None)
+  };
+  parse_msg_tg(tg).map(Some)
+}
+
+
+/// Parse a `Msg` telegram announcing an incoming message.
+fn parse_msg_tg(tg: Telegram) -> Result<MsgHdr, Error> {
+  if tg.get_topic() != Some("Msg") {
+    return Err(Error::BadState {
+      expected: "a message announcement".to_string(),
+      found: tg.get_topic().map(|t| format!("'{}'", t))
+    });
+  }
+
+  let xferid = match tg.get_str("XferId") {
+    Some(xferid) => xferid.to_string(),
+    None => return Err(Error::missing("XferId"))
+  };
+
+  Ok(MsgHdr {
+    ch: tg.get_int::<u8>("_Ch")?,
+    xferid,
+    cmd: tg.get_int_def::<u32>("Cmd", 0)?,
+    metalen: tg.get_int_def::<u64>("MetaLen", 0)?,
+    len: tg.get_int_def::<u64>("Len", 0)?
+  })
+}
+
+
+/// Wait for the next frame, treating a closed connection as an error.
+async fn next_input<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>
+) -> Result<codec::Input, Error> {
+  match conn.next().await {
+    Some(input) => Ok(input?),
+    None => Err(Error::Disconnected)
+  }
+}
+
+
+async fn recv_meta<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  hdr: &MsgHdr
+) -> Result<Option<Params>, Error> {
+  if hdr.metalen == 0 {
+    return Ok(None);
+  }
+  conn.codec_mut().expect_params();
+  match next_input(conn).await? {
+    codec::Input::Params(params) => Ok(Some(params)),
+    _ => Err(Error::bad_state("message metadata"))
+  }
+}
+
+
+/// Receive a message's payload into memory.
+///
+/// The outer error is returned if the connection can no longer be used, and
+/// the inner one if the payload was larger than `limit`, in which case it
+/// has been skipped.
+async fn recv_payload<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  hdr: &MsgHdr,
+  limit: u64
+) -> Result<Result<Option<BytesMut>, Error>, Error> {
+  if hdr.len == 0 {
+    return Ok(Ok(None));
+  }
+  let len = match hdr.payload_len(limit) {
+    Ok(len) => len,
+    Err(e) => {
+      skip_payload(conn, hdr).await?;
+      return Ok(Err(e));
+    }
+  };
+  conn.codec_mut().expect_buf(len)?;
+  match next_input(conn).await? {
+    codec::Input::Buf(buf) => Ok(Ok(Some(buf))),
+    _ => Err(Error::bad_state("message payload"))
+  }
+}
+
+
+async fn skip_payload<T: AsyncRead + AsyncWrite + Unpin>(
+  conn: &mut Framed<T, blather::Codec>,
+  hdr: &MsgHdr
+) -> Result<(), Error> {
+  if hdr.len == 0 {
+    return Ok(());
+  }
+  conn.codec_mut().skip(hdr.payload_len(u64::MAX)?)?;
+  match next_input(conn).await? {
+    codec::Input::SkipDone => Ok(()),
+    _ => Err(Error::bad_state("skipped payload"))
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
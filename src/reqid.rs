@@ -0,0 +1,112 @@
+//! Request identifiers.
+//!
+//! When enabled, every request sent using [`sendrecv()`](crate::sendrecv)
+//! (or its [`clntif`](crate::clntif::util::sendrecv) counterpart) is stamped
+//! with a request identifier, in the [`PARAM`] parameter, unless it already
+//! has one.  The identifier is included in the request's tracing span and in
+//! any [`Error::Command`](crate::Error::Command) the request results in, so
+//! a transfer can be correlated across the application's, the sender node's
+//! and the receiver's logs.
+//!
+//! Identifiers are generated by [`generate()`] unless an application
+//! supplies its own generator, for instance to use the trace identifier of
+//! the current OpenTelemetry context:
+//!
+//! ```
+//! tokio_ddmw::reqid::set_generator(|| "my-trace-id".to_string());
+//! ```
+
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use blather::Telegram;
+
+
+/// Name of the parameter carrying the request identifier.
+pub const PARAM: &str = "_ReqId";
+
+
+type Generator = Arc<dyn Fn() -> String + Send + Sync>;
+
+fn generator_cell() -> &'static RwLock<Option<Generator>> {
+  static GENERATOR: OnceLock<RwLock<Option<Generator>>> = OnceLock::new();
+  GENERATOR.get_or_init(|| RwLock::new(None))
+}
+
+fn set(gen: Option<Generator>) {
+  let mut cell = match generator_cell().write() {
+    Ok(cell) => cell,
+    Err(poisoned) => poisoned.into_inner()
+  };
+  *cell = gen;
+}
+
+fn get() -> Option<Generator> {
+  match generator_cell().read() {
+    Ok(cell) => cell.clone(),
+    Err(poisoned) => poisoned.into_inner().clone()
+  }
+}
+
+
+/// Stamp requests with identifiers produced by [`generate()`].
+pub fn enable() {
+  set(Some(Arc::new(generate)));
+}
+
+/// Stamp requests with identifiers produced by `gen`.
+pub fn set_generator<F>(gen: F)
+where
+  F: Fn() -> String + Send + Sync + 'static
+{
+  set(Some(Arc::new(gen)));
+}
+
+/// Stop stamping requests with identifiers.
+pub fn disable() {
+  set(None);
+}
+
+/// Returns `true` if requests are stamped with identifiers.
+pub fn is_enabled() -> bool {
+  get().is_some()
+}
+
+
+/// Generate a request identifier which is unique within the process, and
+/// unlikely to collide with those of other processes.
+///
+/// The identifier consists of the process' start time and a sequence
+/// number, both in hexadecimal.
+pub fn generate() -> String {
+  static START: OnceLock<u128> = OnceLock::new();
+  static SEQ: AtomicU64 = AtomicU64::new(0);
+
+  let start = START.get_or_init(|| {
+    SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|d| d.as_nanos())
+      .unwrap_or_default()
+  });
+  let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+  format!("{:x}-{:x}", start, seq)
+}
+
+
+/// Add a request identifier to `tg`, if identifiers are enabled and it
+/// doesn't already have one.
+pub(crate) fn stamp(tg: &Telegram) -> Cow<'_, Telegram> {
+  let gen = match get() {
+    Some(gen) if !tg.have_param(PARAM) => gen,
+    _ => return Cow::Borrowed(tg)
+  };
+  let mut tg = tg.clone();
+  // The generator's output is validated by add_param(); an unusable
+  // identifier is dropped rather than failing the request.
+  let _ = tg.add_param(PARAM, gen());
+  Cow::Owned(tg)
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,270 @@
+//! Retry policies.
+//!
+//! A [`Policy`] describes how long to wait between attempts, how many
+//! attempts to make and which errors are worth retrying.  It is used by the
+//! helpers in this crate which retry operations, such as
+//! [`Client`](crate::client::Client) when (re)establishing its connection,
+//! and can be used for application operations through [`run()`]:
+#![cfg_attr(feature = "tokio-net", doc = "```no_run")]
+#![cfg_attr(not(feature = "tokio-net"), doc = "```ignore")]
+//! # async fn example() -> Result<(), tokio_ddmw::Error> {
+//! use std::time::Duration;
+//! use tokio_ddmw::msg::{self, ConnTransport, MsgInfo};
+//! use tokio_ddmw::retry::{self, Policy};
+//!
+//! # let xfer: ConnTransport = todo!();
+//! # let mi: MsgInfo = todo!();
+//! let policy =
+//!   Policy::exponential(Duration::from_millis(100), Duration::from_secs(10))
+//!     .max_attempts(8)
+//!     .budget(Duration::from_secs(60));
+//! let report =
+//!   retry::run(&policy, || msg::connsend(xfer.clone(), &mi)).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::err::Error;
+
+
+/// How the delay between attempts develops.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backoff {
+  /// Wait the same amount of time between all attempts.
+  Fixed(Duration),
+
+  /// Start at `initial` and multiply the delay by `multiplier` after each
+  /// attempt, up to `max`.
+  Exponential {
+    initial: Duration,
+    max: Duration,
+    multiplier: f64
+  }
+}
+
+
+/// Decides whether an error is worth retrying.
+type Classifier = Arc<dyn Fn(&Error) -> bool + Send + Sync>;
+
+
+/// A retry policy.
+///
+/// By default errors are retried if [`Error::is_retryable()`] says they
+/// should be; use [`Policy::retry_if()`] to choose differently.
+#[derive(Clone)]
+pub struct Policy {
+  backoff: Backoff,
+  jitter: f64,
+  max_attempts: Option<u32>,
+  budget: Option<Duration>,
+  retry_if: Option<Classifier>
+}
+
+impl Policy {
+  /// Make a single attempt.
+  pub fn none() -> Self {
+    Policy::fixed(Duration::ZERO).max_attempts(1)
+  }
+
+  /// Retry indefinitely, waiting `delay` between attempts.
+  pub fn fixed(delay: Duration) -> Self {
+    Policy {
+      backoff: Backoff::Fixed(delay),
+      jitter: 0.0,
+      max_attempts: None,
+      budget: None,
+      retry_if: None
+    }
+  }
+
+  /// Retry indefinitely, doubling the delay between attempts from `initial`
+  /// up to `max`, with each delay randomly reduced by up to half.
+  pub fn exponential(initial: Duration, max: Duration) -> Self {
+    Policy {
+      backoff: Backoff::Exponential {
+        initial,
+        max,
+        multiplier: 2.0
+      },
+      jitter: 0.5,
+      max_attempts: None,
+      budget: None,
+      retry_if: None
+    }
+  }
+
+  /// Replace the backoff.
+  pub fn backoff(mut self, backoff: Backoff) -> Self {
+    self.backoff = backoff;
+    self
+  }
+
+  /// Randomly reduce each delay by up to `fraction` of it, so clients which
+  /// failed at the same time don't retry in lockstep.  `fraction` is clamped
+  /// to `0.0..=1.0`; `0.0` disables jitter.
+  pub fn jitter(mut self, fraction: f64) -> Self {
+    self.jitter = fraction.clamp(0.0, 1.0);
+    self
+  }
+
+  /// Give up after `n` attempts, including the first one.
+  pub fn max_attempts(mut self, n: u32) -> Self {
+    self.max_attempts = Some(n.max(1));
+    self
+  }
+
+  /// Give up rather than wait for another attempt if doing so would exceed
+  /// `budget`, counted from the first attempt.
+  pub fn budget(mut self, budget: Duration) -> Self {
+    self.budget = Some(budget);
+    self
+  }
+
+  /// Only retry errors for which `f` returns `true`.
+  pub fn retry_if<F>(mut self, f: F) -> Self
+  where
+    F: Fn(&Error) -> bool + Send + Sync + 'static
+  {
+    self.retry_if = Some(Arc::new(f));
+    self
+  }
+
+  /// Returns `true` if the policy considers `err` worth retrying.
+  pub fn should_retry(&self, err: &Error) -> bool {
+    match self.retry_if {
+      Some(ref f) => f(err),
+      None => err.is_retryable()
+    }
+  }
+
+  /// Begin tracking attempts of an operation.
+  pub fn start(&self) -> Attempts<'_> {
+    Attempts {
+      policy: self,
+      started: Instant::now(),
+      attempt: 1,
+      delay: match self.backoff {
+        Backoff::Fixed(delay) => delay,
+        Backoff::Exponential { initial, .. } => initial
+      },
+      rand: RandomState::new()
+    }
+  }
+}
+
+impl Default for Policy {
+  /// Exponential backoff from 100ms up to 30s, for at most 10 attempts.
+  fn default() -> Self {
+    Policy::exponential(Duration::from_millis(100), Duration::from_secs(30))
+      .max_attempts(10)
+  }
+}
+
+impl fmt::Debug for Policy {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("Policy")
+      .field("backoff", &self.backoff)
+      .field("jitter", &self.jitter)
+      .field("max_attempts", &self.max_attempts)
+      .field("budget", &self.budget)
+      .field("retry_if", &self.retry_if.as_ref().map(|_| ".."))
+      .finish()
+  }
+}
+
+
+/// The attempts made so far of an operation, as returned by
+/// [`Policy::start()`].
+pub struct Attempts<'a> {
+  policy: &'a Policy,
+  started: Instant,
+  attempt: u32,
+  delay: Duration,
+  rand: RandomState
+}
+
+impl Attempts<'_> {
+  /// The number of the current attempt, starting at one.
+  pub fn attempt(&self) -> u32 {
+    self.attempt
+  }
+
+  /// The time passed since the first attempt.
+  pub fn elapsed(&self) -> Duration {
+    self.started.elapsed()
+  }
+
+  /// Report that the current attempt failed with `err`.
+  ///
+  /// Returns the time to wait before the next attempt, or `None` if the
+  /// operation should be given up, because the error isn't worth retrying
+  /// or the policy's attempts or budget have been used up.
+  pub fn next_delay(&mut self, err: &Error) -> Option<Duration> {
+    if !self.policy.should_retry(err) {
+      return None;
+    }
+    if let Some(max) = self.policy.max_attempts {
+      if self.attempt >= max {
+        return None;
+      }
+    }
+
+    let delay = self.jittered(self.delay);
+    if let Some(budget) = self.policy.budget {
+      if self.elapsed() + delay > budget {
+        return None;
+      }
+    }
+
+    self.attempt += 1;
+    if let Backoff::Exponential {
+      max, multiplier, ..
+    } = self.policy.backoff
+    {
+      self.delay = self.delay.mul_f64(multiplier.max(1.0)).min(max);
+    }
+    Some(delay)
+  }
+
+  fn jittered(&self, delay: Duration) -> Duration {
+    if self.policy.jitter == 0.0 {
+      return delay;
+    }
+    let mut h = self.rand.build_hasher();
+    h.write_u32(self.attempt);
+    let r = (h.finish() >> 11) as f64 / (1u64 << 53) as f64;
+    delay.mul_f64(1.0 - self.policy.jitter * r)
+  }
+}
+
+
+/// Run `op` until it succeeds or `policy` gives up, waiting between
+/// attempts as the policy dictates.
+///
+/// Returns the error of the last attempt if the policy gives up.
+pub async fn run<T, F, Fut>(policy: &Policy, mut op: F) -> Result<T, Error>
+where
+  F: FnMut() -> Fut,
+  Fut: Future<Output = Result<T, Error>>
+{
+  let mut attempts = policy.start();
+  loop {
+    let err = match op().await {
+      Ok(v) => return Ok(v),
+      Err(e) => e
+    };
+    match attempts.next_delay(&err) {
+      Some(delay) => tokio::time::sleep(delay).await,
+      None => return Err(err)
+    }
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
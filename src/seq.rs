@@ -0,0 +1,351 @@
+//! Message sequence numbering.
+//!
+//! The link between the sender and receiver nodes is one-way, so the
+//! receiving integration has no way to ask for a message it did not get.
+//! Stamping each message with a per-channel sequence number in its metadata
+//! lets the receiver detect reordering and loss.
+
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use futures::channel::mpsc;
+
+use blather::Params;
+
+use crate::err::Error;
+
+/// Metadata key used to store a message's sequence number.
+pub const SEQ_KEY: &str = "_Seq";
+
+
+/// Generator of per-channel, monotonically increasing, sequence numbers.
+///
+/// If the sequencer was created using [`Sequencer::load()`] the next
+/// sequence number for each channel is written back to the state file each
+/// time a number is handed out, so numbering continues where it left off
+/// after a restart.
+pub struct Sequencer {
+  fname: Option<PathBuf>,
+  next: HashMap<u8, u64>
+}
+
+impl Default for Sequencer {
+  fn default() -> Self {
+    Sequencer::new()
+  }
+}
+
+impl Sequencer {
+  /// Create a sequencer which only keeps its state in memory.
+  pub fn new() -> Self {
+    Sequencer {
+      fname: None,
+      next: HashMap::new()
+    }
+  }
+
+  /// Create a sequencer which persists its state in the file `fname`.
+  ///
+  /// If the file exists its state will be loaded.  Each line in the file
+  /// consists of a channel number and the next sequence number for that
+  /// channel, separated by a space.
+  pub fn load<P: Into<PathBuf>>(fname: P) -> Result<Self, Error> {
+    let fname = fname.into();
+    let mut next = HashMap::new();
+
+    if fname.exists() {
+      let buf =
+        fs::read_to_string(&fname).map_err(|e| Error::local_io(&fname, e))?;
+      for line in buf.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+          continue;
+        }
+        let mut it = line.splitn(2, ' ');
+        let ch = it.next().and_then(|s| s.parse::<u8>().ok());
+        let seq = it.next().and_then(|s| s.trim().parse::<u64>().ok());
+        match (ch, seq) {
+          (Some(ch), Some(seq)) => {
+            next.insert(ch, seq);
+          }
+          _ => {
+            return Err(Error::bad_format(
+              "sequence state",
+              format!("invalid line '{}'", line)
+            ));
+          }
+        }
+      }
+    }
+
+    Ok(Sequencer {
+      fname: Some(fname),
+      next
+    })
+  }
+
+  /// Return the sequence number the next message on channel `ch` will be
+  /// assigned, without consuming it.
+  pub fn peek(&self, ch: u8) -> u64 {
+    *self.next.get(&ch).unwrap_or(&0)
+  }
+
+  /// Allocate the next sequence number for channel `ch`.
+  ///
+  /// If the sequencer has a state file the number is only handed out once
+  /// the new state has been written to it.
+  pub async fn next(&mut self, ch: u8) -> Result<u64, Error> {
+    let seq = self.peek(ch);
+    let following = seq.checked_add(1).ok_or_else(|| Error::TooLarge {
+      what: format!("Sequence number of channel {}", ch),
+      size: Some(seq),
+      limit: u64::MAX - 1
+    })?;
+    // Only consume the number once the new state has been persisted, so a
+    // failed (or abandoned) save doesn't leave a gap in the numbering.
+    let mut next = self.next.clone();
+    next.insert(ch, following);
+    self.save(&next).await?;
+    self.next = next;
+    Ok(seq)
+  }
+
+  /// Allocate the next sequence number for channel `ch` and store it in the
+  /// metadata `meta`.
+  pub async fn stamp(
+    &mut self,
+    ch: u8,
+    meta: &mut Params
+  ) -> Result<u64, Error> {
+    let seq = self.next(ch).await?;
+    meta.add_param(SEQ_KEY, seq)?;
+    Ok(seq)
+  }
+
+  /// Write the state `next` to the state file (if there is one).
+  async fn save(&self, next: &HashMap<u8, u64>) -> Result<(), Error> {
+    let fname = match &self.fname {
+      Some(fname) => fname,
+      None => return Ok(())
+    };
+
+    let mut chans: Vec<_> = next.iter().collect();
+    chans.sort();
+    let buf: String = chans
+      .into_iter()
+      .map(|(ch, seq)| format!("{} {}\n", ch, seq))
+      .collect();
+
+    write_state(fname, buf).await
+  }
+}
+
+
+/// Name of the temporary file the state file `fname` is written to before
+/// being renamed into place, so a crash can't leave a truncated state file
+/// behind.
+fn tmp_name(fname: &Path) -> PathBuf {
+  let mut tmpname = fname.as_os_str().to_os_string();
+  tmpname.push(".tmp");
+  PathBuf::from(tmpname)
+}
+
+/// Replace the contents of the state file `fname` with `buf`.
+#[cfg(feature = "msg")]
+async fn write_state(fname: &Path, buf: String) -> Result<(), Error> {
+  use tokio::io::AsyncWriteExt;
+
+  let tmpname = tmp_name(fname);
+  let local = |e| Error::local_io(&tmpname, e);
+  let mut f = tokio::fs::File::create(&tmpname).await.map_err(local)?;
+  f.write_all(buf.as_bytes()).await.map_err(local)?;
+  f.sync_all().await.map_err(local)?;
+  tokio::fs::rename(&tmpname, fname)
+    .await
+    .map_err(|e| Error::local_io(fname, e))?;
+
+  Ok(())
+}
+
+/// Replace the contents of the state file `fname` with `buf`.
+///
+/// The file is written on the blocking thread pool if the `rt` feature is
+/// enabled and there is a runtime to use.
+#[cfg(not(feature = "msg"))]
+async fn write_state(fname: &Path, buf: String) -> Result<(), Error> {
+  #[cfg(feature = "rt")]
+  if tokio::runtime::Handle::try_current().is_ok() {
+    let path = fname.to_path_buf();
+    let task = crate::task::spawn_blocking("ddmw-seq", move || {
+      write_state_sync(&path, &buf)
+    })
+    .map_err(|e| Error::local_io(fname, e))?;
+    return match task.await {
+      Ok(res) => res,
+      Err(e) => Err(Error::local_io(fname, e.into()))
+    };
+  }
+  write_state_sync(fname, &buf)
+}
+
+#[cfg(not(feature = "msg"))]
+fn write_state_sync(fname: &Path, buf: &str) -> Result<(), Error> {
+  use std::io::Write;
+
+  let tmpname = tmp_name(fname);
+  let local = |e| Error::local_io(&tmpname, e);
+  let mut f = fs::File::create(&tmpname).map_err(local)?;
+  f.write_all(buf.as_bytes()).map_err(local)?;
+  f.sync_all().map_err(local)?;
+  fs::rename(&tmpname, fname).map_err(|e| Error::local_io(fname, e))?;
+
+  Ok(())
+}
+
+
+/// Extract the sequence number from a message's metadata.
+///
+/// Returns `Ok(None)` if the metadata has not been stamped with a sequence
+/// number.  `u64::MAX`, which a [`Sequencer`] never hands out, is rejected
+/// as malformed.
+pub fn get_seq(meta: &Params) -> Result<Option<u64>, Error> {
+  match meta.get_str(SEQ_KEY) {
+    Some(s) => match s.parse::<u64>() {
+      Ok(seq) if seq < u64::MAX => Ok(Some(seq)),
+      _ => Err(Error::bad_format(
+        "sequence number",
+        format!("invalid value '{}'", s)
+      ))
+    },
+    None => Ok(None)
+  }
+}
+
+
+/// Events reported by a [`GapDetector`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum SeqEvent {
+  /// The sequence numbers in `missing` were skipped on channel `ch`.
+  Gap { ch: u8, missing: Range<u64> },
+
+  /// A sequence number which was previously reported missing arrived late.
+  Recovered { ch: u8, seq: u64 },
+
+  /// A sequence number which had already been received arrived again.
+  Duplicate { ch: u8, seq: u64 }
+}
+
+
+#[derive(Default)]
+struct ChanState {
+  /// The next expected sequence number (one past the high-water mark).
+  next: u64,
+
+  /// Ranges of sequence numbers below `next` which have not been received.
+  missing: Vec<Range<u64>>
+}
+
+
+/// Receiver-side tracker of the sequence numbers stamped by a [`Sequencer`].
+///
+/// Keeps a per-channel high-water mark and a list of missing ranges.  Events
+/// are returned from [`observe()`](Self::observe) and are also sent to any
+/// streams acquired using [`subscribe()`](Self::subscribe).
+#[derive(Default)]
+pub struct GapDetector {
+  chans: HashMap<u8, ChanState>,
+  subscribers: Vec<mpsc::UnboundedSender<SeqEvent>>
+}
+
+impl GapDetector {
+  pub fn new() -> Self {
+    GapDetector::default()
+  }
+
+  /// Return a stream of the events generated by this detector.
+  pub fn subscribe(&mut self) -> mpsc::UnboundedReceiver<SeqEvent> {
+    let (tx, rx) = mpsc::unbounded();
+    self.subscribers.push(tx);
+    rx
+  }
+
+  /// Return the ranges of sequence numbers on channel `ch` that have been
+  /// skipped and not (yet) received.
+  pub fn missing(&self, ch: u8) -> &[Range<u64>] {
+    match self.chans.get(&ch) {
+      Some(cs) => &cs.missing,
+      None => &[]
+    }
+  }
+
+  /// Return the highest sequence number that has been received on channel
+  /// `ch`, if any.
+  pub fn high_water(&self, ch: u8) -> Option<u64> {
+    match self.chans.get(&ch) {
+      Some(cs) if cs.next > 0 => Some(cs.next - 1),
+      _ => None
+    }
+  }
+
+  /// Register the arrival of sequence number `seq` on channel `ch`.
+  ///
+  /// `u64::MAX`, which a [`Sequencer`] never hands out, is malformed and
+  /// ignored.
+  pub fn observe(&mut self, ch: u8, seq: u64) -> Option<SeqEvent> {
+    let following = seq.checked_add(1)?;
+    let cs = self.chans.entry(ch).or_default();
+
+    let ev = if seq >= cs.next {
+      let ev = if seq > cs.next {
+        let missing = cs.next..seq;
+        cs.missing.push(missing.clone());
+        Some(SeqEvent::Gap { ch, missing })
+      } else {
+        None
+      };
+      cs.next = following;
+      ev
+    } else if let Some(idx) = cs.missing.iter().position(|r| r.contains(&seq))
+    {
+      // Split the missing range around the late arrival
+      let r = cs.missing.remove(idx);
+      if following < r.end {
+        cs.missing.insert(idx, following..r.end);
+      }
+      if r.start < seq {
+        cs.missing.insert(idx, r.start..seq);
+      }
+      Some(SeqEvent::Recovered { ch, seq })
+    } else {
+      Some(SeqEvent::Duplicate { ch, seq })
+    };
+
+    if let Some(ref ev) = ev {
+      // Drop subscribers which have gone away
+      self
+        .subscribers
+        .retain(|tx| tx.unbounded_send(ev.clone()).is_ok());
+    }
+
+    ev
+  }
+
+  /// Register the arrival of a message on channel `ch`, using the sequence
+  /// number stored in its metadata.
+  ///
+  /// Messages without a sequence number are ignored.
+  pub fn observe_meta(
+    &mut self,
+    ch: u8,
+    meta: &Params
+  ) -> Result<Option<SeqEvent>, Error> {
+    match get_seq(meta)? {
+      Some(seq) => Ok(self.observe(ch, seq)),
+      None => Ok(None)
+    }
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,71 @@
+//! Spawning of the crate's internal tasks.
+//!
+//! Tasks are given names describing what they do.  If the `console` feature
+//! is enabled, and the crate is built with `--cfg tokio_unstable`, the names
+//! are passed on to the runtime so they show up in tools like tokio-console.
+//! If the `tracing` feature is enabled the tasks run within a `ddmw.task`
+//! span carrying the name.
+
+#[cfg(feature = "testing")]
+use std::future::Future;
+use std::io;
+
+use tokio::task::JoinHandle;
+
+
+/// Spawn `fut` as a new task.
+#[cfg(feature = "testing")]
+pub(crate) fn spawn<F>(
+  name: &'static str,
+  fut: F
+) -> io::Result<JoinHandle<F::Output>>
+where
+  F: Future + Send + 'static,
+  F::Output: Send + 'static
+{
+  #[cfg(feature = "tracing")]
+  let fut = {
+    use tracing::Instrument;
+    fut.instrument(tracing::debug_span!("ddmw.task", name))
+  };
+
+  #[cfg(all(tokio_unstable, feature = "console"))]
+  return tokio::task::Builder::new().name(name).spawn(fut);
+
+  #[cfg(not(all(tokio_unstable, feature = "console")))]
+  {
+    let _ = name;
+    Ok(tokio::task::spawn(fut))
+  }
+}
+
+
+/// Run the blocking function `f` on the runtime's blocking thread pool.
+pub(crate) fn spawn_blocking<F, R>(
+  name: &'static str,
+  f: F
+) -> io::Result<JoinHandle<R>>
+where
+  F: FnOnce() -> R + Send + 'static,
+  R: Send + 'static
+{
+  #[cfg(feature = "tracing")]
+  let f = {
+    let span = tracing::debug_span!("ddmw.task", name);
+    move || {
+      let _enter = span.enter();
+      f()
+    }
+  };
+
+  #[cfg(all(tokio_unstable, feature = "console"))]
+  return tokio::task::Builder::new().name(name).spawn_blocking(f);
+
+  #[cfg(not(all(tokio_unstable, feature = "console")))]
+  {
+    let _ = name;
+    Ok(tokio::task::spawn_blocking(f))
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
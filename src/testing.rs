@@ -0,0 +1,64 @@
+//! Utilities for testing integrations without a running middleware.
+//!
+//! Enabled by the `testing` feature.  [`MockServer`] implements enough of the
+//! client interface for the helpers in this crate to run against it, with
+//! scriptable replies and a record of the requests it has received:
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), tokio_ddmw::Error> {
+//! use tokio_ddmw::testing::{Match, MockServer, Reply};
+//!
+//! let server = MockServer::bind_tcp().await?;
+//! server.reply("Auth", Reply::fail_with("AuthExpired"));
+//!
+//! let mut conn = server.endpoint().connect().await?;
+//! // .. run the code under test against `conn` ..
+//!
+//! assert_eq!(server.requests_for("Auth").len(), 1);
+//! server.assert_received(&Match::new().topic("Auth").param("Name", "test"));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Tests which need control over each exchange, or over how data reaches the
+//! client, can use [`pair()`] instead.  It connects a client over an
+//! in-memory pipe to a [`ServerEnd`] which the test drives itself.
+//!
+//! Sessions against a real middleware can be recorded using a [`Recorder`]
+//! and served back by a [`Replay`], so that tests of complex flows can run
+//! without network access:
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), tokio_ddmw::Error> {
+//! use tokio_util::codec::Framed;
+//! use tokio_ddmw::testing::Replay;
+//!
+//! let replay = Replay::load("tests/cassettes/auth.cassette")?;
+//! let mut conn = Framed::new(replay, blather::Codec::new());
+//! // .. run the code under test against `conn` ..
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Any of these transports can be wrapped in [`Faulty`] to inject delays,
+//! fragmentation, truncation, corruption or disconnects at given offsets.
+//!
+//! [`Loopback`] serves preloaded replies without a peer, and is used by the
+//! crate's benchmarks.
+
+mod assert;
+mod cassette;
+mod duplex;
+mod fault;
+mod loopback;
+mod server;
+
+pub use crate::assert_telegram;
+pub use assert::{Inspect, Match};
+pub use cassette::{Cassette, Recorder, Replay};
+pub use duplex::{pair, pair_with, ServerEnd, DEFAULT_BUF_SIZE};
+pub use fault::{Fault, Faulty};
+pub use loopback::Loopback;
+pub use server::{MockServer, Reply, Request};
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
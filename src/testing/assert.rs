@@ -0,0 +1,187 @@
+//! Assertions on telegrams and parameters.
+
+use std::fmt::Debug;
+
+use blather::{Params, Telegram};
+
+use super::server::Request;
+
+
+/// Something with a topic and parameters which a [`Match`] can be applied
+/// to.
+pub trait Inspect: Debug {
+  /// The topic, if there is one.
+  fn topic(&self) -> Option<&str>;
+
+  /// The value of the parameter `key`, if it is set.
+  fn param(&self, key: &str) -> Option<&str>;
+}
+
+impl Inspect for Telegram {
+  fn topic(&self) -> Option<&str> {
+    self.get_topic()
+  }
+
+  fn param(&self, key: &str) -> Option<&str> {
+    self.get_str(key)
+  }
+}
+
+impl Inspect for Params {
+  fn topic(&self) -> Option<&str> {
+    None
+  }
+
+  fn param(&self, key: &str) -> Option<&str> {
+    self.get_str(key)
+  }
+}
+
+impl Inspect for Request {
+  fn topic(&self) -> Option<&str> {
+    Some(&self.topic)
+  }
+
+  fn param(&self, key: &str) -> Option<&str> {
+    self.params.get_str(key)
+  }
+}
+
+impl<T: Inspect + ?Sized> Inspect for &T {
+  fn topic(&self) -> Option<&str> {
+    (**self).topic()
+  }
+
+  fn param(&self, key: &str) -> Option<&str> {
+    (**self).param(key)
+  }
+}
+
+
+/// Expected topic and parameters.
+///
+/// Parameters which aren't mentioned are ignored, so a match only needs to
+/// list what a test cares about:
+///
+/// ```
+/// use blather::Telegram;
+/// use tokio_ddmw::testing::Match;
+///
+/// let mut tg = Telegram::new_topic("Msg").unwrap();
+/// tg.add_param("_Ch", 2).unwrap();
+/// tg.add_param("Len", 4).unwrap();
+///
+/// assert!(Match::new().topic("Msg").param("_Ch", 2).matches(&tg));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Match {
+  topic: Option<String>,
+  params: Vec<(String, String)>
+}
+
+impl Match {
+  /// A match which accepts anything.
+  pub fn new() -> Self {
+    Match::default()
+  }
+
+  /// Require the topic to be `topic`.
+  pub fn topic(mut self, topic: &str) -> Self {
+    self.topic = Some(topic.to_string());
+    self
+  }
+
+  /// Require the parameter `key` to be set to the string form of `value`.
+  pub fn param<V: ToString>(mut self, key: &str, value: V) -> Self {
+    self.params.push((key.to_string(), value.to_string()));
+    self
+  }
+
+  /// Returns `true` if `t` has the expected topic and parameters.
+  pub fn matches<T: Inspect + ?Sized>(&self, t: &T) -> bool {
+    self.mismatch(t).is_none()
+  }
+
+  /// Describe the first way in which `t` differs from the expected topic and
+  /// parameters, or return `None` if it matches.
+  pub fn mismatch<T: Inspect + ?Sized>(&self, t: &T) -> Option<String> {
+    if let Some(topic) = &self.topic {
+      if t.topic() != Some(topic.as_str()) {
+        return Some(format!(
+          "expected topic '{}', got '{}'",
+          topic,
+          t.topic().unwrap_or_default()
+        ));
+      }
+    }
+    for (key, value) in &self.params {
+      match t.param(key) {
+        Some(v) if v == value => {}
+        Some(v) => {
+          return Some(format!(
+            "expected parameter '{}' to be '{}', got '{}'",
+            key, value, v
+          ))
+        }
+        None => {
+          return Some(format!(
+            "expected parameter '{}' to be '{}', but it is not set",
+            key, value
+          ))
+        }
+      }
+    }
+    None
+  }
+
+  /// Panic, describing the difference, unless `t` matches.
+  #[track_caller]
+  pub fn assert<T: Inspect + ?Sized>(&self, t: &T) {
+    if let Some(msg) = self.mismatch(t) {
+      panic!("assertion failed: {}\n  in: {:?}", msg, t);
+    }
+  }
+}
+
+
+/// Assert that a telegram, a set of parameters or a request received by a
+/// [`MockServer`](super::MockServer) has the given topic and parameters.
+///
+/// Parameter values may be anything implementing `ToString`, and are
+/// compared in their string form.  Parameters which aren't listed are
+/// ignored.
+///
+/// ```
+/// use blather::Telegram;
+/// use tokio_ddmw::testing::assert_telegram;
+///
+/// let mut tg = Telegram::new_topic("Msg").unwrap();
+/// tg.add_param("_Ch", 2).unwrap();
+/// tg.add_param("Len", 4).unwrap();
+///
+/// assert_telegram!(tg, topic = "Msg", params = { "_Ch" => 2, "Len" => "4" });
+/// assert_telegram!(tg, topic = "Msg");
+/// assert_telegram!(tg.get_params(), params = { "Len" => 4 });
+/// ```
+#[macro_export]
+macro_rules! assert_telegram {
+  (
+    $tg:expr, topic = $topic:expr,
+    params = { $($k:expr => $v:expr),* $(,)? } $(,)?
+  ) => {
+    $crate::testing::Match::new()
+      .topic($topic)
+      $(.param($k, $v))*
+      .assert(&$tg)
+  };
+  ($tg:expr, topic = $topic:expr $(,)?) => {
+    $crate::testing::Match::new().topic($topic).assert(&$tg)
+  };
+  ($tg:expr, params = { $($k:expr => $v:expr),* $(,)? } $(,)?) => {
+    $crate::testing::Match::new()
+      $(.param($k, $v))*
+      .assert(&$tg)
+  };
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
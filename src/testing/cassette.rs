@@ -0,0 +1,388 @@
+//! Recording of sessions, and deterministic replay of them.
+//!
+//! A [`Recorder`] wraps the transport of a real session and appends
+//! everything passing through it to a cassette file.  A [`Replay`] loads the
+//! file and acts as a transport which serves the recorded session back,
+//! without any network access.
+//!
+//! The cassette contains the session exactly as it was seen on the wire,
+//! including any credentials, so sessions which are to be stored alongside
+//! tests should be recorded using test accounts.
+//!
+//! # File format
+//! The file is UTF-8 text.  The first line identifies the format and its
+//! version:
+//!
+//! ```text
+//! ddmw-cassette 1
+//! ```
+//!
+//! Each following line is a record consisting of a direction, a space and
+//! the data as lowercase hex digits.  The direction is `>` for data sent by
+//! the client and `<` for data received by it.  How the data is split into
+//! records carries no meaning; consecutive records in the same direction are
+//! merged when the cassette is loaded.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::clntif::Direction;
+use crate::err::Error;
+
+
+/// Version of the cassette file format.
+pub const FORMAT_VERSION: u32 = 1;
+
+const HEADER: &str = "ddmw-cassette";
+
+
+/// A recorded session.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Cassette {
+  records: Vec<(Direction, Vec<u8>)>
+}
+
+impl Cassette {
+  /// Load the cassette file `path`.
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+    Self::parse(&std::fs::read_to_string(path)?)
+  }
+
+  /// Parse the contents of a cassette file.
+  pub fn parse(s: &str) -> Result<Self, Error> {
+    let mut lines = s.lines();
+    let header = lines.next().unwrap_or_default();
+    if header != format!("{} {}", HEADER, FORMAT_VERSION) {
+      return Err(Error::bad_format(
+        "cassette",
+        format!("unsupported header '{}'", header)
+      ));
+    }
+
+    let mut cassette = Cassette::default();
+    for line in lines.filter(|l| !l.is_empty()) {
+      let (dir, data) = match line.split_once(' ') {
+        Some((">", data)) => (Direction::Outbound, data),
+        Some(("<", data)) => (Direction::Inbound, data),
+        _ => {
+          return Err(Error::bad_format(
+            "cassette",
+            format!("invalid record '{}'", line)
+          ))
+        }
+      };
+      cassette.push(dir, &parse_hex(data)?);
+    }
+    Ok(cassette)
+  }
+
+  /// Write the cassette to the file `path`, replacing it if it exists.
+  pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "{} {}", HEADER, FORMAT_VERSION)?;
+    for (dir, data) in &self.records {
+      write_record(&mut out, *dir, data)?;
+    }
+    out.flush()?;
+    Ok(())
+  }
+
+  /// Append data to the cassette, merging it with the last record if it has
+  /// the same direction.
+  pub fn push(&mut self, dir: Direction, data: &[u8]) {
+    if data.is_empty() {
+      return;
+    }
+    match self.records.last_mut() {
+      Some((d, buf)) if *d == dir => buf.extend_from_slice(data),
+      _ => self.records.push((dir, data.to_vec()))
+    }
+  }
+
+  /// Get all data the client sent during the session.
+  pub fn sent(&self) -> Vec<u8> {
+    self.data(Direction::Outbound)
+  }
+
+  /// Get all data the client received during the session.
+  pub fn received(&self) -> Vec<u8> {
+    self.data(Direction::Inbound)
+  }
+
+  fn data(&self, dir: Direction) -> Vec<u8> {
+    self
+      .records
+      .iter()
+      .filter(|(d, _)| *d == dir)
+      .flat_map(|(_, data)| data.iter().copied())
+      .collect()
+  }
+}
+
+
+fn write_record<W: Write>(
+  out: &mut W,
+  dir: Direction,
+  data: &[u8]
+) -> io::Result<()> {
+  let dir = match dir {
+    Direction::Outbound => '>',
+    Direction::Inbound => '<'
+  };
+  let mut hex = String::with_capacity(data.len() * 2);
+  for b in data {
+    let _ = write!(hex, "{:02x}", b);
+  }
+  writeln!(out, "{} {}", dir, hex)
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>, Error> {
+  if s.len() % 2 == 1 || !s.is_ascii() {
+    return Err(Error::bad_format("cassette", "invalid hex data"));
+  }
+  (0..s.len())
+    .step_by(2)
+    .map(|i| {
+      u8::from_str_radix(&s[i..i + 2], 16)
+        .map_err(|_| Error::bad_format("cassette", "invalid hex data"))
+    })
+    .collect()
+}
+
+
+/// Transport which records everything passing through it to a cassette
+/// file.
+///
+/// Each piece of data is written to the file as soon as it has been
+/// observed, so the recording survives a session which is cut short.
+pub struct Recorder<T> {
+  inner: T,
+  out: BufWriter<File>
+}
+
+impl<T> Recorder<T> {
+  /// Record the session on the transport `inner` to the file `path`,
+  /// replacing it if it exists.
+  pub fn create<P: AsRef<Path>>(inner: T, path: P) -> Result<Self, Error> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "{} {}", HEADER, FORMAT_VERSION)?;
+    out.flush()?;
+    Ok(Recorder { inner, out })
+  }
+
+  pub fn get_ref(&self) -> &T {
+    &self.inner
+  }
+
+  pub fn get_mut(&mut self) -> &mut T {
+    &mut self.inner
+  }
+
+  /// Stop recording and get the wrapped transport.
+  pub fn into_inner(self) -> T {
+    self.inner
+  }
+
+  fn record(&mut self, dir: Direction, data: &[u8]) -> io::Result<()> {
+    if data.is_empty() {
+      return Ok(());
+    }
+    write_record(&mut self.out, dir, data)?;
+    self.out.flush()
+  }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Recorder<T> {
+  fn poll_read(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>
+  ) -> Poll<io::Result<()>> {
+    let start = buf.filled().len();
+    let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+    if let Poll::Ready(Ok(())) = res {
+      self.record(Direction::Inbound, &buf.filled()[start..])?;
+    }
+    res
+  }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Recorder<T> {
+  fn poll_write(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &[u8]
+  ) -> Poll<io::Result<usize>> {
+    let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+    if let Poll::Ready(Ok(n)) = res {
+      self.record(Direction::Outbound, &buf[..n])?;
+    }
+    res
+  }
+
+  fn poll_flush(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>
+  ) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.inner).poll_flush(cx)
+  }
+
+  fn poll_shutdown(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>
+  ) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.inner).poll_shutdown(cx)
+  }
+}
+
+
+/// Transport which serves a recorded session back to the client.
+///
+/// Reads return the data the client received during the recorded session,
+/// once the client has sent everything it sent before receiving it.  By
+/// default the data the client writes must match the recording exactly;
+/// anything else fails the write with an [`io::ErrorKind::InvalidData`]
+/// error.  Sessions which are expected to differ in what the client sends,
+/// such as ones stamped with request identifiers, can disable the check
+/// using [`verify_writes()`](Self::verify_writes).
+///
+/// Once the recording has been played back reads return end-of-file.
+pub struct Replay {
+  records: VecDeque<(Direction, Vec<u8>)>,
+  pos: usize,
+  verify: bool
+}
+
+impl Replay {
+  pub fn new(cassette: Cassette) -> Self {
+    Replay {
+      records: cassette.records.into(),
+      pos: 0,
+      verify: true
+    }
+  }
+
+  /// Load the cassette file `path` and replay it.
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+    Ok(Self::new(Cassette::load(path)?))
+  }
+
+  /// Choose whether the data written by the client must match the
+  /// recording.
+  ///
+  /// If disabled, everything the client writes is accepted, and each write
+  /// skips the remainder of the data the client sent before the next piece
+  /// of received data.
+  pub fn verify_writes(mut self, verify: bool) -> Self {
+    self.verify = verify;
+    self
+  }
+
+  /// Returns `true` if the whole recording has been played back.
+  pub fn is_done(&self) -> bool {
+    self.records.is_empty()
+  }
+
+  fn advance(&mut self, n: usize) {
+    self.pos += n;
+    if self
+      .records
+      .front()
+      .is_some_and(|(_, d)| self.pos >= d.len())
+    {
+      self.records.pop_front();
+      self.pos = 0;
+    }
+  }
+}
+
+fn mismatch(msg: String) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl AsyncRead for Replay {
+  fn poll_read(
+    mut self: Pin<&mut Self>,
+    _cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>
+  ) -> Poll<io::Result<()>> {
+    let n = match self.records.front() {
+      None => return Poll::Ready(Ok(())),
+      Some((Direction::Outbound, data)) => {
+        return Poll::Ready(Err(mismatch(format!(
+          "Client read while the recording expects it to send {} more bytes",
+          data.len() - self.pos
+        ))))
+      }
+      Some((Direction::Inbound, data)) => {
+        let n = buf.remaining().min(data.len() - self.pos);
+        buf.put_slice(&data[self.pos..self.pos + n]);
+        n
+      }
+    };
+    self.advance(n);
+    Poll::Ready(Ok(()))
+  }
+}
+
+impl AsyncWrite for Replay {
+  fn poll_write(
+    mut self: Pin<&mut Self>,
+    _cx: &mut Context<'_>,
+    buf: &[u8]
+  ) -> Poll<io::Result<usize>> {
+    let verify = self.verify;
+    let pos = self.pos;
+    let n = match self.records.front() {
+      Some((Direction::Outbound, data)) if !verify => data.len() - pos,
+      Some((Direction::Outbound, data)) => {
+        let n = buf.len().min(data.len() - pos);
+        if buf[..n] != data[pos..pos + n] {
+          return Poll::Ready(Err(mismatch(format!(
+            "Client sent data which differs from the recording at offset {}",
+            pos
+          ))));
+        }
+        n
+      }
+      _ if !verify => return Poll::Ready(Ok(buf.len())),
+      Some((Direction::Inbound, data)) => {
+        return Poll::Ready(Err(mismatch(format!(
+          "Client sent data while the recording expects it to receive {} \
+           more bytes",
+          data.len() - pos
+        ))))
+      }
+      None => {
+        return Poll::Ready(Err(mismatch(
+          "Client sent data after the end of the recording".to_string()
+        )))
+      }
+    };
+    self.advance(n);
+    Poll::Ready(Ok(if verify { n } else { buf.len() }))
+  }
+
+  fn poll_flush(
+    self: Pin<&mut Self>,
+    _cx: &mut Context<'_>
+  ) -> Poll<io::Result<()>> {
+    Poll::Ready(Ok(()))
+  }
+
+  fn poll_shutdown(
+    self: Pin<&mut Self>,
+    _cx: &mut Context<'_>
+  ) -> Poll<io::Result<()>> {
+    Poll::Ready(Ok(()))
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,118 @@
+//! In-memory client/server connection pairs.
+
+use futures::sink::SinkExt;
+
+use tokio::io::{duplex, AsyncWriteExt, DuplexStream};
+
+use tokio_stream::StreamExt;
+
+use tokio_util::codec::Framed;
+
+use bytes::Bytes;
+
+use blather::Telegram;
+
+use super::server::{recv_buf, Reply};
+use crate::clntif::{Codec, Input};
+use crate::err::Error;
+
+
+/// Size of the in-memory pipe used by [`pair()`].
+///
+/// Kept small so that anything but the smallest transfers reach the peer in
+/// several pieces, like they would over a real socket.
+pub const DEFAULT_BUF_SIZE: usize = 256;
+
+
+/// Create a connected client and server pair, communicating over an
+/// in-memory pipe of [`DEFAULT_BUF_SIZE`] bytes.
+///
+/// The client end is framed the way the crate's helpers expect, and the
+/// server end is driven by the test.
+pub fn pair() -> (Framed<DuplexStream, blather::Codec>, ServerEnd) {
+  pair_with(blather::Codec::new(), DEFAULT_BUF_SIZE)
+}
+
+
+/// Create a connected client and server pair, with the client end framed
+/// using `codec`, communicating over an in-memory pipe of `buf_size` bytes.
+///
+/// Writes block once `buf_size` bytes are waiting to be read, so small
+/// values force the peer to receive data in small pieces.
+pub fn pair_with<C>(
+  codec: C,
+  buf_size: usize
+) -> (Framed<DuplexStream, C>, ServerEnd) {
+  let (client, server) = duplex(buf_size);
+  let server = ServerEnd {
+    conn: Framed::new(server, Codec::new())
+  };
+  (Framed::new(client, codec), server)
+}
+
+
+/// The server end of a connection created by [`pair()`].
+pub struct ServerEnd {
+  conn: Framed<DuplexStream, Codec>
+}
+
+impl ServerEnd {
+  /// Receive the next request.
+  pub async fn recv(&mut self) -> Result<Telegram, Error> {
+    match self.conn.next().await {
+      Some(Ok(Input::Telegram(tg))) => Ok(tg),
+      Some(Ok(_)) => Err(Error::bad_state("a telegram")),
+      Some(Err(e)) => Err(e),
+      None => Err(Error::Disconnected)
+    }
+  }
+
+  /// Receive the next request and make sure it has the topic `topic`.
+  pub async fn expect(&mut self, topic: &str) -> Result<Telegram, Error> {
+    let tg = self.recv().await?;
+    match tg.get_topic() {
+      Some(t) if t == topic => Ok(tg),
+      t => Err(Error::BadState {
+        expected: format!("a '{}' request", topic),
+        found: Some(format!("'{}'", t.unwrap_or_default()))
+      })
+    }
+  }
+
+  /// Receive `len` bytes of binary data, such as a message's content.
+  pub async fn recv_buf(&mut self, len: usize) -> Result<Bytes, Error> {
+    recv_buf(&mut self.conn, len).await
+  }
+
+  /// Send a reply to the client.
+  pub async fn reply(&mut self, reply: Reply) -> Result<(), Error> {
+    self.send(&reply.to_telegram()?).await
+  }
+
+  /// Send a telegram to the client.
+  pub async fn send(&mut self, tg: &Telegram) -> Result<(), Error> {
+    self.conn.send(tg).await
+  }
+
+  /// Send raw data to the client, such as the contents of a binary
+  /// transfer.
+  ///
+  /// The data is written as-is, without any framing.
+  pub async fn send_raw(&mut self, data: &[u8]) -> Result<(), Error> {
+    self.conn.get_mut().write_all(data).await?;
+    Ok(())
+  }
+
+  /// Close the server end of the connection.
+  pub async fn close(mut self) -> Result<(), Error> {
+    self.conn.get_mut().shutdown().await?;
+    Ok(())
+  }
+
+  /// Get the framed server end of the connection.
+  pub fn framed(&mut self) -> &mut Framed<DuplexStream, Codec> {
+    &mut self.conn
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
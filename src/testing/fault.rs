@@ -0,0 +1,284 @@
+//! Transport with scripted faults.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Sleep;
+
+
+/// A fault injected by [`Faulty`] once a given number of bytes has passed in
+/// one direction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+  /// Stall for the given duration before passing on any more data.
+  Delay(Duration),
+
+  /// Pass on at most the given number of bytes per read or write from here
+  /// on.  `Fragment(0)` stops fragmenting.
+  Fragment(usize),
+
+  /// Don't pass on any more data.  Reads return end-of-file, and writes
+  /// claim to succeed but discard the data.
+  Truncate,
+
+  /// Fail all further reads or writes with a
+  /// [`ConnectionReset`](io::ErrorKind::ConnectionReset) error.
+  Disconnect,
+
+  /// Flip the bits set in the given mask of the byte at the fault's offset.
+  Corrupt(u8)
+}
+
+
+enum State {
+  Open,
+  Truncated,
+  Disconnected
+}
+
+enum Step {
+  Eof,
+  Go { len: usize, corrupt: Option<u8> }
+}
+
+/// The faults scripted for one direction, and the progress through them.
+struct Script {
+  faults: VecDeque<(u64, Fault)>,
+  pos: u64,
+  fragment: Option<usize>,
+  sleep: Option<Pin<Box<Sleep>>>,
+  state: State
+}
+
+impl Script {
+  fn new() -> Self {
+    Script {
+      faults: VecDeque::new(),
+      pos: 0,
+      fragment: None,
+      sleep: None,
+      state: State::Open
+    }
+  }
+
+  fn add(&mut self, offset: u64, fault: Fault) {
+    // Faults at the same offset fire in the order they were added.
+    let idx = self.faults.partition_point(|(off, _)| *off <= offset);
+    self.faults.insert(idx, (offset, fault));
+  }
+
+  /// Fire the faults which are due, and determine how much data may be
+  /// passed on by the next read or write of up to `want` bytes.
+  fn poll_step(
+    &mut self,
+    cx: &mut Context<'_>,
+    want: usize
+  ) -> Poll<io::Result<Step>> {
+    loop {
+      if let Some(sleep) = &mut self.sleep {
+        ready!(sleep.as_mut().poll(cx));
+        self.sleep = None;
+        self.faults.pop_front();
+        continue;
+      }
+      match self.state {
+        State::Open => {}
+        State::Truncated => return Poll::Ready(Ok(Step::Eof)),
+        State::Disconnected => {
+          return Poll::Ready(Err(io::Error::new(
+            io::ErrorKind::ConnectionReset,
+            "Injected disconnect"
+          )))
+        }
+      }
+      let fault = match self.faults.front() {
+        Some((off, fault)) if *off <= self.pos => *fault,
+        _ => break
+      };
+      match fault {
+        Fault::Delay(d) => {
+          self.sleep = Some(Box::pin(tokio::time::sleep(d)));
+          continue;
+        }
+        Fault::Fragment(n) => self.fragment = Some(n).filter(|n| *n != 0),
+        Fault::Truncate => self.state = State::Truncated,
+        Fault::Disconnect => self.state = State::Disconnected,
+        // Applied to the first byte of the next read or write.
+        Fault::Corrupt(_) => break
+      }
+      self.faults.pop_front();
+    }
+
+    let corrupt = match self.faults.front() {
+      Some((off, Fault::Corrupt(mask))) if *off <= self.pos => Some(*mask),
+      _ => None
+    };
+    let mut len = want;
+    if let Some(n) = self.fragment {
+      len = len.min(n);
+    }
+    // Stop short of the next fault, so it fires at its exact offset.
+    if let Some((off, _)) = self.faults.iter().find(|(off, _)| *off > self.pos)
+    {
+      len = len.min((off - self.pos) as usize);
+    }
+    Poll::Ready(Ok(Step::Go { len, corrupt }))
+  }
+
+  fn advance(&mut self, n: usize, corrupted: bool) {
+    if n != 0 && corrupted {
+      self.faults.pop_front();
+    }
+    self.pos += n as u64;
+  }
+}
+
+
+/// Transport wrapper which injects faults at given offsets into the data
+/// passing through it.
+///
+/// Faults are scripted separately for the data read from and written to the
+/// wrapped transport, at offsets counted from the start of the connection in
+/// each direction.  Reads and writes never cross the offset of a pending
+/// fault, so each fault fires exactly at its offset.  This allows the error
+/// paths of decoders and reconnect logic to be exercised deterministically:
+///
+/// ```no_run
+/// # async fn example() -> Result<(), tokio_ddmw::Error> {
+/// use tokio_util::codec::Framed;
+/// use tokio_ddmw::testing::{Fault, Faulty, Replay};
+///
+/// // Cut the connection 100 bytes into the recorded session's replies.
+/// let io = Faulty::new(Replay::load("tests/cassettes/recv.cassette")?)
+///   .on_read(0, Fault::Fragment(7))
+///   .on_read(100, Fault::Disconnect);
+/// let mut conn = Framed::new(io, blather::Codec::new());
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Fault::Delay`] requires a tokio runtime with the time driver enabled.
+pub struct Faulty<T> {
+  inner: T,
+  read: Script,
+  write: Script
+}
+
+impl<T> Faulty<T> {
+  /// Wrap `inner`, without any faults scripted.
+  pub fn new(inner: T) -> Self {
+    Faulty {
+      inner,
+      read: Script::new(),
+      write: Script::new()
+    }
+  }
+
+  /// Inject `fault` once `offset` bytes have been read from the wrapped
+  /// transport.
+  pub fn on_read(mut self, offset: u64, fault: Fault) -> Self {
+    self.read.add(offset, fault);
+    self
+  }
+
+  /// Inject `fault` once `offset` bytes have been written to the wrapped
+  /// transport.
+  pub fn on_write(mut self, offset: u64, fault: Fault) -> Self {
+    self.write.add(offset, fault);
+    self
+  }
+
+  /// Number of bytes read through the wrapper so far.
+  pub fn bytes_read(&self) -> u64 {
+    self.read.pos
+  }
+
+  /// Number of bytes written through the wrapper so far.
+  pub fn bytes_written(&self) -> u64 {
+    self.write.pos
+  }
+
+  pub fn get_ref(&self) -> &T {
+    &self.inner
+  }
+
+  pub fn get_mut(&mut self) -> &mut T {
+    &mut self.inner
+  }
+
+  pub fn into_inner(self) -> T {
+    self.inner
+  }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Faulty<T> {
+  fn poll_read(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>
+  ) -> Poll<io::Result<()>> {
+    let me = self.get_mut();
+    let (len, corrupt) = match ready!(me.read.poll_step(cx, buf.remaining()))?
+    {
+      Step::Eof => return Poll::Ready(Ok(())),
+      Step::Go { len, corrupt } => (len, corrupt)
+    };
+
+    let mut tmp = vec![0u8; len];
+    let mut rb = ReadBuf::new(&mut tmp);
+    ready!(Pin::new(&mut me.inner).poll_read(cx, &mut rb))?;
+    let n = rb.filled().len();
+    if let (Some(mask), true) = (corrupt, n != 0) {
+      tmp[0] ^= mask;
+    }
+    buf.put_slice(&tmp[..n]);
+    me.read.advance(n, corrupt.is_some());
+    Poll::Ready(Ok(()))
+  }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Faulty<T> {
+  fn poll_write(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+    buf: &[u8]
+  ) -> Poll<io::Result<usize>> {
+    let me = self.get_mut();
+    let (len, corrupt) = match ready!(me.write.poll_step(cx, buf.len()))? {
+      Step::Eof => return Poll::Ready(Ok(buf.len())),
+      Step::Go { len, corrupt } => (len, corrupt)
+    };
+
+    let n = match corrupt {
+      Some(mask) if len != 0 => {
+        let mut data = buf[..len].to_vec();
+        data[0] ^= mask;
+        ready!(Pin::new(&mut me.inner).poll_write(cx, &data))?
+      }
+      _ => ready!(Pin::new(&mut me.inner).poll_write(cx, &buf[..len]))?
+    };
+    me.write.advance(n, corrupt.is_some());
+    Poll::Ready(Ok(n))
+  }
+
+  fn poll_flush(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>
+  ) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.inner).poll_flush(cx)
+  }
+
+  fn poll_shutdown(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>
+  ) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.inner).poll_shutdown(cx)
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,105 @@
+//! In-memory transport for benchmarks.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use bytes::Bytes;
+
+
+/// Transport which serves preloaded input to reads and discards everything
+/// written to it.
+///
+/// Unlike a socket or [`pair()`](super::pair) there's no peer, no system
+/// calls and no task switching involved, so benchmarks running against it
+/// measure the cost of the encoding and decoding itself.  Since writes never
+/// need to be answered, the input may contain all the replies a client is
+/// going to read up front:
+///
+/// ```
+/// use tokio_ddmw::testing::Loopback;
+///
+/// // Replies to a `Msg` request followed by its payload.
+/// let io = Loopback::new(&b"Ok\nXferId 1\n\nOk\n\n"[..]);
+/// ```
+///
+/// Once the input has been read, reads return end-of-file.
+#[derive(Clone, Debug)]
+pub struct Loopback {
+  input: Bytes,
+  read_size: usize,
+  written: u64
+}
+
+impl Loopback {
+  pub fn new<B: Into<Bytes>>(input: B) -> Self {
+    Loopback {
+      input: input.into(),
+      read_size: usize::MAX,
+      written: 0
+    }
+  }
+
+  /// Return at most `size` bytes from each read, to model the reads of a
+  /// socket with a receive buffer of `size` bytes.
+  ///
+  /// # Panics
+  /// If `size` is zero.
+  pub fn read_size(mut self, size: usize) -> Self {
+    assert!(size != 0, "The read size must not be zero");
+    self.read_size = size;
+    self
+  }
+
+  /// Number of input bytes which have not been read yet.
+  pub fn remaining(&self) -> usize {
+    self.input.len()
+  }
+
+  /// Number of bytes written, and discarded, so far.
+  pub fn written(&self) -> u64 {
+    self.written
+  }
+}
+
+impl AsyncRead for Loopback {
+  fn poll_read(
+    mut self: Pin<&mut Self>,
+    _cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>
+  ) -> Poll<io::Result<()>> {
+    let n = buf.remaining().min(self.read_size).min(self.input.len());
+    let data = self.input.split_to(n);
+    buf.put_slice(&data);
+    Poll::Ready(Ok(()))
+  }
+}
+
+impl AsyncWrite for Loopback {
+  fn poll_write(
+    mut self: Pin<&mut Self>,
+    _cx: &mut Context<'_>,
+    buf: &[u8]
+  ) -> Poll<io::Result<usize>> {
+    self.written += buf.len() as u64;
+    Poll::Ready(Ok(buf.len()))
+  }
+
+  fn poll_flush(
+    self: Pin<&mut Self>,
+    _cx: &mut Context<'_>
+  ) -> Poll<io::Result<()>> {
+    Poll::Ready(Ok(()))
+  }
+
+  fn poll_shutdown(
+    self: Pin<&mut Self>,
+    _cx: &mut Context<'_>
+  ) -> Poll<io::Result<()>> {
+    Poll::Ready(Ok(()))
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,385 @@
+//! Mock client interface server.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[cfg(unix)]
+use std::path::PathBuf;
+
+use futures::sink::SinkExt;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+use tokio_stream::StreamExt;
+
+use tokio_util::codec::Framed;
+
+use bytes::Bytes;
+
+use blather::{Params, Telegram};
+
+use super::assert::Match;
+use crate::clntif::{Codec, Input};
+use crate::err::Error;
+use crate::msg::Endpoint;
+
+
+/// A reply the mock server sends in response to a request.
+#[derive(Clone, Debug)]
+pub enum Reply {
+  Ok(Params),
+  Fail(Params)
+}
+
+impl Reply {
+  /// An `Ok` reply without any parameters.
+  pub fn ok() -> Self {
+    Reply::Ok(Params::new())
+  }
+
+  /// A `Fail` reply without any parameters.
+  pub fn fail() -> Self {
+    Reply::Fail(Params::new())
+  }
+
+  /// A `Fail` reply with the error code `code`, which the client maps using
+  /// [`Error::from_fail()`].
+  pub fn fail_with(code: &str) -> Self {
+    let mut params = Params::new();
+    // Error codes are plain identifiers; an invalid one simply isn't added.
+    let _ = params.add_str("ErrCode", code);
+    Reply::Fail(params)
+  }
+
+  fn is_ok(&self) -> bool {
+    matches!(self, Reply::Ok(_))
+  }
+
+  pub(super) fn to_telegram(&self) -> Result<Telegram, Error> {
+    let (topic, params) = match self {
+      Reply::Ok(params) => ("Ok", params),
+      Reply::Fail(params) => ("Fail", params)
+    };
+    let mut tg = Telegram::new_topic(topic)?;
+    for (k, v) in params.get_inner() {
+      tg.add_str(k, v)?;
+    }
+    Ok(tg)
+  }
+}
+
+
+/// A request received by the mock server.
+#[derive(Clone, Debug)]
+pub struct Request {
+  pub topic: String,
+  pub params: Params,
+
+  /// Message metadata, for `Msg` requests which carried any.
+  pub meta: Option<Bytes>,
+
+  /// Message payload, for `Msg` requests which carried any.
+  pub payload: Option<Bytes>
+}
+
+
+#[derive(Default)]
+pub(super) struct Shared {
+  queued: Mutex<HashMap<String, VecDeque<Reply>>>,
+  always: Mutex<HashMap<String, Reply>>,
+  requests: Mutex<Vec<Request>>,
+  next_xferid: AtomicU64
+}
+
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+  match m.lock() {
+    Ok(guard) => guard,
+    Err(poisoned) => poisoned.into_inner()
+  }
+}
+
+impl Shared {
+  /// Get the scripted reply to a request, or its default reply.
+  fn reply_to(&self, tg: &Telegram) -> Result<Reply, Error> {
+    let topic = tg.get_topic().unwrap_or_default();
+    if let Some(reply) = lock(&self.queued)
+      .get_mut(topic)
+      .and_then(VecDeque::pop_front)
+    {
+      return Ok(reply);
+    }
+    if let Some(reply) = lock(&self.always).get(topic) {
+      return Ok(reply.clone());
+    }
+    self.default_reply(tg)
+  }
+
+  fn default_reply(&self, tg: &Telegram) -> Result<Reply, Error> {
+    let mut params = Params::new();
+    match tg.get_topic().unwrap_or_default() {
+      "Auth" if tg.get_bool_def("ReqTkn", false)? => {
+        params.add_str("Tkn", "mocktoken")?;
+      }
+      "GetNodeInfo" => {
+        params.add_str("ddmw.node", "sender")?;
+        params.add_str("ddmw.version", "0.0.0")?;
+        params.add_str("os.name", "mock")?;
+        params.add_str("ddmw.ddlink.engine", "mock")?;
+        params.add_str("ddmw.ddlink.protocol", "udp")?;
+        params.add_str("ddmw.ddlink.protimpl", "generic")?;
+      }
+      "RdAcc" => {
+        params.add_param("Id", tg.get_int_def::<i64>("Id", 1)?)?;
+        params.add_str("Name", tg.get_str_def("Name", "mock"))?;
+        params.add_bool("Lock", false)?;
+        params.add_str("Perms", "")?;
+      }
+      "RdCh" => {
+        let id = tg.get_int_def::<u8>("Id", 1)?;
+        params.add_param("Id", id)?;
+        match tg.get_str("Name") {
+          Some(name) => params.add_str("Name", name)?,
+          None => params.add_param("Name", format!("ch{}", id))?
+        }
+      }
+      "Msg" => {
+        let n = self.next_xferid.fetch_add(1, Ordering::Relaxed) + 1;
+        params.add_param("XferId", format!("mock-{}", n))?;
+      }
+      _ => {}
+    }
+    Ok(Reply::Ok(params))
+  }
+}
+
+
+/// Serve a single client connection until it is closed.
+pub(super) async fn serve<T>(io: T, shared: Arc<Shared>) -> Result<(), Error>
+where
+  T: AsyncRead + AsyncWrite + Unpin
+{
+  let mut conn = Framed::new(io, Codec::new());
+  while let Some(input) = conn.next().await {
+    let tg = match input? {
+      Input::Telegram(tg) => tg,
+      _ => {
+        return Err(Error::BadState {
+          expected: "a telegram".to_string(),
+          found: Some("other input".to_string())
+        })
+      }
+    };
+    let reply = shared.reply_to(&tg)?;
+    let mut req = Request {
+      topic: tg.get_topic().unwrap_or_default().to_string(),
+      params: tg.get_params().clone(),
+      meta: None,
+      payload: None
+    };
+
+    if req.topic == "Msg" && reply.is_ok() {
+      conn.send(&reply.to_telegram()?).await?;
+      let metalen = tg.get_int_def::<usize>("MetaLen", 0)?;
+      let len = tg.get_int_def::<usize>("Len", 0)?;
+      if metalen != 0 {
+        req.meta = Some(recv_buf(&mut conn, metalen).await?);
+      }
+      if metalen != 0 && len != 0 {
+        conn.send(&Reply::ok().to_telegram()?).await?;
+      }
+      if len != 0 {
+        req.payload = Some(recv_buf(&mut conn, len).await?);
+      }
+      // Record the request before the final reply, so it's visible to the
+      // client as soon as its request completes.
+      lock(&shared.requests).push(req);
+      if metalen != 0 || len != 0 {
+        conn.send(&Reply::ok().to_telegram()?).await?;
+      }
+    } else {
+      lock(&shared.requests).push(req);
+      conn.send(&reply.to_telegram()?).await?;
+    }
+  }
+  Ok(())
+}
+
+pub(super) async fn recv_buf<T>(
+  conn: &mut Framed<T, Codec>,
+  len: usize
+) -> Result<Bytes, Error>
+where
+  T: AsyncRead + AsyncWrite + Unpin
+{
+  conn.codec_mut().expect_buf(len)?;
+  match conn.next().await {
+    Some(Ok(Input::Buf(buf))) => Ok(buf.freeze()),
+    Some(Ok(_)) => Err(Error::bad_state("a buffer")),
+    Some(Err(e)) => Err(e),
+    None => Err(Error::Disconnected)
+  }
+}
+
+
+/// A mock client interface server, listening on an ephemeral socket.
+///
+/// The server understands enough of `Auth`, `GetNodeInfo`, `RdAcc`, `RdCh`
+/// and `Msg` to reply to them with plausible defaults; any other request is
+/// replied to with an empty `Ok`.  Replies can be scripted per topic using
+/// [`reply()`](Self::reply) and [`reply_always()`](Self::reply_always).
+///
+/// The server stops accepting connections when dropped.  Requires a tokio
+/// runtime.
+pub struct MockServer {
+  endpoint: MockEndpoint,
+  shared: Arc<Shared>,
+  task: JoinHandle<()>
+}
+
+enum MockEndpoint {
+  Tcp(SocketAddr),
+  #[cfg(unix)]
+  Uds(PathBuf)
+}
+
+impl MockServer {
+  /// Start a server listening on an ephemeral TCP port on the loopback
+  /// interface.
+  pub async fn bind_tcp() -> Result<Self, Error> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let shared = Arc::new(Shared::default());
+    let sh = Arc::clone(&shared);
+    let task = crate::task::spawn("ddmw-mock-server", async move {
+      while let Ok((sock, _)) = listener.accept().await {
+        spawn_conn(sock, Arc::clone(&sh));
+      }
+    })?;
+    Ok(MockServer {
+      endpoint: MockEndpoint::Tcp(addr),
+      shared,
+      task
+    })
+  }
+
+  /// Start a server listening on the unix domain socket `path`.
+  ///
+  /// The socket file is removed when the server is dropped.
+  #[cfg(unix)]
+  pub async fn bind_uds<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
+    let path = path.into();
+    let listener = UnixListener::bind(&path)?;
+    let shared = Arc::new(Shared::default());
+    let sh = Arc::clone(&shared);
+    let task = crate::task::spawn("ddmw-mock-server", async move {
+      while let Ok((sock, _)) = listener.accept().await {
+        spawn_conn(sock, Arc::clone(&sh));
+      }
+    })?;
+    Ok(MockServer {
+      endpoint: MockEndpoint::Uds(path),
+      shared,
+      task
+    })
+  }
+
+  /// Get an endpoint which connects to the server.
+  pub fn endpoint(&self) -> Endpoint {
+    match &self.endpoint {
+      MockEndpoint::Tcp(addr) => Endpoint::TcpSockAddr(addr.to_string()),
+      #[cfg(unix)]
+      MockEndpoint::Uds(path) => Endpoint::UdsPath(path.clone())
+    }
+  }
+
+  /// Reply to the next request with the topic `topic` with `reply`.
+  ///
+  /// Replies queued for the same topic are used in the order they were
+  /// queued, before any reply set using
+  /// [`reply_always()`](Self::reply_always).
+  pub fn reply(&self, topic: &str, reply: Reply) {
+    lock(&self.shared.queued)
+      .entry(topic.to_string())
+      .or_default()
+      .push_back(reply);
+  }
+
+  /// Reply to all requests with the topic `topic` with `reply`, unless a
+  /// reply has been queued using [`reply()`](Self::reply).
+  pub fn reply_always(&self, topic: &str, reply: Reply) {
+    lock(&self.shared.always).insert(topic.to_string(), reply);
+  }
+
+  /// Get all requests received so far, in the order they were received.
+  pub fn requests(&self) -> Vec<Request> {
+    lock(&self.shared.requests).clone()
+  }
+
+  /// Get all requests with the topic `topic` received so far.
+  pub fn requests_for(&self, topic: &str) -> Vec<Request> {
+    lock(&self.shared.requests)
+      .iter()
+      .filter(|req| req.topic == topic)
+      .cloned()
+      .collect()
+  }
+
+  /// Get all requests received so far which match `m`.
+  pub fn requests_matching(&self, m: &Match) -> Vec<Request> {
+    lock(&self.shared.requests)
+      .iter()
+      .filter(|req| m.matches(*req))
+      .cloned()
+      .collect()
+  }
+
+  /// Panic unless at least one request matching `m` has been received.
+  #[track_caller]
+  pub fn assert_received(&self, m: &Match) {
+    let requests = lock(&self.shared.requests);
+    if !requests.iter().any(|req| m.matches(req)) {
+      let topics: Vec<&str> =
+        requests.iter().map(|req| req.topic.as_str()).collect();
+      panic!(
+        "assertion failed: no request matching {:?} received; received \
+         topics: {:?}",
+        m, topics
+      );
+    }
+  }
+
+  /// Forget all requests received so far.
+  pub fn clear_requests(&self) {
+    lock(&self.shared.requests).clear();
+  }
+}
+
+impl Drop for MockServer {
+  fn drop(&mut self) {
+    self.task.abort();
+    #[cfg(unix)]
+    if let MockEndpoint::Uds(path) = &self.endpoint {
+      let _ = std::fs::remove_file(path);
+    }
+  }
+}
+
+
+fn spawn_conn<T>(io: T, shared: Arc<Shared>)
+where
+  T: AsyncRead + AsyncWrite + Unpin + Send + 'static
+{
+  // Failing connections are dropped; the client sees the disconnect.
+  let _ = crate::task::spawn("ddmw-mock-conn", async move {
+    let _ = serve(io, shared).await;
+  });
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
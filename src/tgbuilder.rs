@@ -0,0 +1,117 @@
+//! Fluent construction of telegrams.
+//!
+//! Building a telegram using `Telegram::add_param()` requires every call to
+//! be checked for errors.  [`TelegramBuilder`] instead records any errors
+//! and reports them once the telegram is built, and the [`tg!`](crate::tg)
+//! macro wraps it for the common case of a fixed set of parameters:
+//!
+//! ```
+//! # fn example(ch: &str, cmd: u32) -> Result<(), tokio_ddmw::Error> {
+//! use tokio_ddmw::tg;
+//!
+//! let tg = tg!("Msg", "_Ch" => ch, "Cmd" => cmd)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use blather::Telegram;
+
+use crate::err::Error;
+
+
+/// Builder for [`Telegram`]s which defers error checking to
+/// [`TelegramBuilder::build()`].
+pub struct TelegramBuilder {
+  tg: Telegram,
+  errors: Vec<Error>
+}
+
+impl TelegramBuilder {
+  /// Begin building a telegram with the topic `topic`.
+  pub fn new(topic: &str) -> Self {
+    let mut errors = Vec::new();
+    let tg = match Telegram::new_topic(topic) {
+      Ok(tg) => tg,
+      Err(e) => {
+        errors.push(e.into());
+        Telegram::new()
+      }
+    };
+    TelegramBuilder { tg, errors }
+  }
+
+  /// Add a parameter.
+  pub fn param<K: ToString, V: ToString>(mut self, key: K, value: V) -> Self {
+    if let Err(e) = self.tg.add_param(key, value) {
+      self.errors.push(e.into());
+    }
+    self
+  }
+
+  /// Add a parameter if `value` is `Some`.
+  pub fn param_opt<K: ToString, V: ToString>(
+    self,
+    key: K,
+    value: Option<V>
+  ) -> Self {
+    match value {
+      Some(value) => self.param(key, value),
+      None => self
+    }
+  }
+
+  /// Add a parameter if `cond` is `true`.
+  pub fn param_if<K: ToString, V: ToString>(
+    self,
+    cond: bool,
+    key: K,
+    value: V
+  ) -> Self {
+    if cond {
+      self.param(key, value)
+    } else {
+      self
+    }
+  }
+
+  /// Add a boolean parameter, encoded the way the server expects.
+  pub fn bool<K: ToString>(mut self, key: K, value: bool) -> Self {
+    if let Err(e) = self.tg.add_bool(key, value) {
+      self.errors.push(e.into());
+    }
+    self
+  }
+
+  /// Return the telegram, or the errors encountered while building it.
+  ///
+  /// A single error is returned as-is.  If several parameters failed, their
+  /// errors are combined into an `Error::BadFormat`.
+  pub fn build(mut self) -> Result<Telegram, Error> {
+    match self.errors.len() {
+      0 => Ok(self.tg),
+      1 => Err(self.errors.remove(0)),
+      _ => {
+        let msgs: Vec<String> =
+          self.errors.iter().map(|e| e.to_string()).collect();
+        Err(Error::bad_format("telegram", msgs.join("; ")))
+      }
+    }
+  }
+}
+
+
+/// Build a [`Telegram`](blather::Telegram) from a topic and a list of
+/// `key => value` parameters.
+///
+/// Evaluates to a `Result<Telegram, Error>`.  See
+/// [`TelegramBuilder`](crate::tgbuilder::TelegramBuilder).
+#[macro_export]
+macro_rules! tg {
+  ($topic:expr $(, $key:expr => $value:expr)* $(,)?) => {
+    $crate::tgbuilder::TelegramBuilder::new($topic)
+      $(.param($key, $value))*
+      .build()
+  };
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
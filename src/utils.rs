@@ -1,44 +1,100 @@
 use std::fs::File;
-use std::io::BufRead;
+use std::io::{self, BufRead, ErrorKind};
 use std::path::Path;
 
-/// Read the first line of a file.
-/// Returns `None` on any problems (file not found, unable to open, file has
-/// no lines, etc).
-/// Returns `Some(String)` on success.
+use crate::auth::{SecretError, Trim};
+use crate::err::Error;
+
+/// Read a secret from the first line of a file.
 ///
-/// # Notes
-/// - This file will trim the end of the string, and thus it is unsuitable for
-///   reading strings which may end with whitespaces.
-pub(crate) fn read_single_line<P>(fname: P) -> Option<String>
+/// Fails with `Error::Secret` if the file doesn't exist, can't be read, or
+/// its first line is empty once `trim` has been applied.
+pub(crate) fn read_secret_line<P>(
+  fname: P,
+  trim: Trim
+) -> Result<String, Error>
 where
   P: AsRef<Path>
 {
-  if let Ok(mut lines) = read_lines(fname.as_ref()) {
-    if let Some(line) = lines.next() {
-      if let Ok(l) = line {
-        Some(l.trim_end().to_string())
-      } else {
-        None
-      }
-    } else {
-      None
-    }
-  } else {
-    None
-  }
+  let fname = fname.as_ref();
+  let mut line = String::new();
+  let res = File::open(fname)
+    .map(io::BufReader::new)
+    .and_then(|mut r| r.read_line(&mut line));
+  secret_from(fname, res.map(|_| line), trim)
 }
 
-
-/// Return a buffered line iterator for reading a file line-by-line.
-pub(crate) fn read_lines<P>(
-  filename: P
-) -> std::io::Result<std::io::Lines<std::io::BufReader<File>>>
+/// Asynchronous version of [`read_secret_line()`].
+///
+/// The file is read using `tokio::fs` if the `msg` feature is enabled, and
+/// otherwise on the blocking thread pool, if the `rt` feature is enabled
+/// and there is a runtime to use.
+pub(crate) async fn read_secret_line_async<P>(
+  fname: P,
+  trim: Trim
+) -> Result<String, Error>
 where
   P: AsRef<Path>
 {
-  let file = std::fs::File::open(filename)?;
-  Ok(std::io::BufReader::new(file).lines())
+  let fname = fname.as_ref();
+
+  #[cfg(feature = "msg")]
+  {
+    use tokio::io::AsyncBufReadExt;
+
+    let mut line = String::new();
+    let res = match tokio::fs::File::open(fname).await {
+      Ok(f) => tokio::io::BufReader::new(f).read_line(&mut line).await,
+      Err(e) => Err(e)
+    };
+    secret_from(fname, res.map(|_| line), trim)
+  }
+
+  #[cfg(not(feature = "msg"))]
+  {
+    #[cfg(feature = "rt")]
+    if tokio::runtime::Handle::try_current().is_ok() {
+      let path = fname.to_path_buf();
+      let task = crate::task::spawn_blocking("ddmw-secret", move || {
+        read_secret_line(path, trim)
+      });
+      return match task {
+        Ok(task) => match task.await {
+          Ok(res) => res,
+          Err(e) => secret_from(fname, Err(e.into()), trim)
+        },
+        Err(e) => secret_from(fname, Err(e), trim)
+      };
+    }
+    read_secret_line(fname, trim)
+  }
+}
+
+/// Turn the result of reading the first line of the secret file `fname`
+/// into the secret.
+fn secret_from(
+  fname: &Path,
+  res: io::Result<String>,
+  trim: Trim
+) -> Result<String, Error> {
+  let fail = |error| Error::Secret {
+    path: fname.to_path_buf(),
+    error
+  };
+
+  let line = res.map_err(|e| {
+    fail(match e.kind() {
+      ErrorKind::NotFound => SecretError::NotFound,
+      ErrorKind::PermissionDenied => SecretError::PermissionDenied,
+      _ => SecretError::IO(e)
+    })
+  })?;
+
+  let secret = trim.apply(&line);
+  if secret.is_empty() {
+    return Err(fail(SecretError::Empty));
+  }
+  Ok(secret.to_string())
 }
 
 // vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
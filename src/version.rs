@@ -0,0 +1,166 @@
+//! Semantic version numbers, as reported by servers.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use crate::err::Error;
+
+
+/// A semantic version number.
+///
+/// Parsing is lenient in the ways server version strings tend to vary: a
+/// leading `v` is accepted, a missing minor or patch number is taken to be
+/// zero, and build metadata (anything following a `+`) is ignored.
+///
+/// Versions are ordered by semantic versioning precedence, so a pre-release
+/// version orders before the corresponding release.  Versions which only
+/// differ in how numeric pre-release identifiers are written, such as
+/// `rc.01` and `rc.1`, are equal.
+///
+/// ```
+/// use tokio_ddmw::version::Version;
+///
+/// let v: Version = "2.1.0-rc.1".parse().unwrap();
+/// assert!(v > Version::new(2, 0, 9));
+/// assert!(v < Version::new(2, 1, 0));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Version {
+  pub major: u64,
+  pub minor: u64,
+  pub patch: u64,
+
+  /// Pre-release identifiers, such as `rc.1`.
+  pub pre: Option<String>
+}
+
+impl Version {
+  pub fn new(major: u64, minor: u64, patch: u64) -> Self {
+    Version {
+      major,
+      minor,
+      patch,
+      pre: None
+    }
+  }
+
+  /// Returns `true` if this is version `major.minor.0` or later.
+  pub fn at_least(&self, major: u64, minor: u64) -> bool {
+    *self >= Version::new(major, minor, 0)
+  }
+}
+
+impl FromStr for Version {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let bad =
+      || Error::bad_format("version", format!("invalid version '{}'", s));
+
+    let v = s.trim();
+    let v = v.strip_prefix('v').unwrap_or(v);
+    let v = v.split('+').next().unwrap_or_default();
+    let (nums, pre) = match v.split_once('-') {
+      Some((nums, pre)) if !pre.is_empty() => (nums, Some(pre.to_string())),
+      Some(_) => return Err(bad()),
+      None => (v, None)
+    };
+
+    let mut parts = [0u64; 3];
+    for (n, part) in nums.split('.').enumerate() {
+      if n == parts.len() {
+        return Err(bad());
+      }
+      parts[n] = part.parse().map_err(|_| bad())?;
+    }
+
+    Ok(Version {
+      major: parts[0],
+      minor: parts[1],
+      patch: parts[2],
+      pre
+    })
+  }
+}
+
+impl fmt::Display for Version {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+    if let Some(pre) = &self.pre {
+      write!(f, "-{}", pre)?;
+    }
+    Ok(())
+  }
+}
+
+impl PartialEq for Version {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl Eq for Version {}
+
+/// Hashed consistently with the ordering, so numeric pre-release
+/// identifiers are hashed by value.
+impl Hash for Version {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    (self.major, self.minor, self.patch).hash(state);
+    if let Some(pre) = &self.pre {
+      for ident in pre.split('.') {
+        match ident.parse::<u64>() {
+          Ok(n) => (0u8, n).hash(state),
+          Err(_) => (1u8, ident).hash(state)
+        }
+      }
+    }
+    self.pre.is_some().hash(state);
+  }
+}
+
+impl Ord for Version {
+  fn cmp(&self, other: &Self) -> Ordering {
+    (self.major, self.minor, self.patch)
+      .cmp(&(other.major, other.minor, other.patch))
+      .then_with(|| match (&self.pre, &other.pre) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Greater,
+        (Some(_), None) => Ordering::Less,
+        (Some(a), Some(b)) => cmp_pre(a, b)
+      })
+  }
+}
+
+impl PartialOrd for Version {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+/// Compare pre-release identifiers: numeric identifiers are compared
+/// numerically and order before alphanumeric ones, and a shorter list of
+/// otherwise equal identifiers orders first.
+fn cmp_pre(a: &str, b: &str) -> Ordering {
+  let mut a = a.split('.');
+  let mut b = b.split('.');
+  loop {
+    let ord = match (a.next(), b.next()) {
+      (None, None) => return Ordering::Equal,
+      (None, Some(_)) => return Ordering::Less,
+      (Some(_), None) => return Ordering::Greater,
+      (Some(x), Some(y)) => match (x.parse::<u64>(), y.parse::<u64>()) {
+        (Ok(x), Ok(y)) => x.cmp(&y),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => x.cmp(y)
+      }
+    };
+    if ord != Ordering::Equal {
+      return ord;
+    }
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
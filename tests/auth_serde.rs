@@ -0,0 +1,34 @@
+#![cfg(feature = "serde_json")]
+
+use tokio_ddmw::auth::{AuthInfo, Token};
+
+#[test]
+fn redacted_secrets_are_rejected() {
+  let ai = AuthInfo {
+    accpass: Some(("acc".to_string(), "secret".to_string())),
+    itkn: Some(Token::Buf("token".to_string())),
+    otkn: None
+  };
+  let json = serde_json::to_string(&ai).unwrap();
+  assert!(!json.contains("secret") && !json.contains("\"token\""));
+
+  let err = serde_json::from_str::<AuthInfo>(&json).unwrap_err();
+  assert!(err.to_string().contains("redacted"), "{}", err);
+
+  let json = r#"{"itkn":{"Buf":"<redacted>"},"otkn":null}"#;
+  let err = serde_json::from_str::<AuthInfo>(json).unwrap_err();
+  assert!(err.to_string().contains("redacted"), "{}", err);
+}
+
+#[test]
+fn real_secrets_are_accepted() {
+  let json = r#"{"accpass":["acc","secret"],"itkn":{"Buf":"t"},"otkn":null}"#;
+  let ai: AuthInfo = serde_json::from_str(json).unwrap();
+  assert_eq!(ai.accpass, Some(("acc".to_string(), "secret".to_string())));
+  assert_eq!(ai.itkn, Some(Token::Buf("t".to_string())));
+
+  let ai: AuthInfo = serde_json::from_str(r#"{"otkn":null}"#).unwrap();
+  assert_eq!(ai.accpass, None);
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
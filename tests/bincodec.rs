@@ -0,0 +1,95 @@
+use bytes::{BufMut, Bytes, BytesMut};
+
+use tokio::io::AsyncWriteExt;
+
+use tokio_stream::StreamExt;
+
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
+use blather::Telegram;
+
+use tokio_ddmw::clntif::util::{negotiate_v2, Negotiated};
+use tokio_ddmw::clntif::{BinCodec, Codec, Frame, Input};
+use tokio_ddmw::Error;
+
+/// Frames are only returned once all of their payload has arrived.
+#[test]
+fn roundtrip() {
+  let mut codec = BinCodec::new();
+  let mut tg = Telegram::new_topic("Hello").unwrap();
+  tg.add_str("Name", "World").unwrap();
+  let mut buf = BytesMut::new();
+  codec.encode(&tg, &mut buf).unwrap();
+  codec.encode(Bytes::from_static(b"data"), &mut buf).unwrap();
+
+  let mut input = buf.split_to(3);
+  assert!(codec.decode(&mut input).unwrap().is_none());
+  input.unsplit(buf);
+  match codec.decode(&mut input).unwrap() {
+    Some(Frame::Telegram(tg)) => {
+      assert_eq!(tg.get_topic(), Some("Hello"));
+      assert_eq!(tg.get_str("Name"), Some("World"));
+    }
+    _ => panic!("Expected a telegram")
+  }
+  match codec.decode(&mut input).unwrap() {
+    Some(Frame::Data(data)) => assert_eq!(&data[..], b"data"),
+    _ => panic!("Expected data")
+  }
+  assert!(input.is_empty());
+}
+
+/// Oversized and unknown frames are rejected based on their header alone.
+#[test]
+fn bad_frames() {
+  let mut codec = BinCodec::new();
+  codec.set_max_frame_size(4);
+  let mut buf = BytesMut::new();
+  buf.put_u8(2);
+  buf.put_u32(5);
+  assert!(matches!(
+    codec.decode(&mut buf),
+    Err(Error::TooLarge {
+      size: Some(5),
+      limit: 4,
+      ..
+    })
+  ));
+
+  let mut buf = BytesMut::new();
+  buf.put_u8(9);
+  buf.put_u32(1);
+  buf.put_u8(0);
+  assert!(matches!(
+    codec.decode(&mut buf),
+    Err(Error::UnknownData { .. })
+  ));
+}
+
+/// Connections stay line based if the server doesn't support protocol v2.
+#[tokio::test]
+async fn negotiate() {
+  for (reply, v2) in [(&b"Fail\n\n"[..], false), (b"Ok\n\n", true)] {
+    let (client, server) = tokio::io::duplex(1024);
+    let mut server = Framed::new(server, Codec::new());
+    let server = async move {
+      match server.next().await {
+        Some(Ok(Input::Telegram(tg))) => {
+          assert_eq!(tg.get_topic(), Some("Proto"));
+          assert_eq!(tg.get_str("Version"), Some("2"));
+        }
+        _ => panic!("Expected a Proto request")
+      }
+      server.get_mut().write_all(reply).await.unwrap();
+      server
+    };
+    let client = negotiate_v2(Framed::new(client, Codec::new()));
+    let (res, _server) = tokio::join!(client, server);
+    match res.unwrap() {
+      Negotiated::V1(_) => assert!(!v2),
+      Negotiated::V2(_) => assert!(v2)
+    }
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
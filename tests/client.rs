@@ -0,0 +1,304 @@
+#![cfg(feature = "testing")]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::{SinkExt, StreamExt};
+
+use tokio::net::TcpListener;
+
+use tokio_util::codec::Framed;
+
+use blather::{Params, Telegram};
+
+use tokio_ddmw::auth::{AuthInfo, Token};
+use tokio_ddmw::client::Client;
+use tokio_ddmw::msg::{self, Channel, Endpoint, MsgInfo, Transport};
+use tokio_ddmw::retry::Policy;
+use tokio_ddmw::testing::{pair, Match, MockServer, Reply};
+use tokio_ddmw::Error;
+
+fn ch_reply(id: u8, name: &str) -> Reply {
+  let mut params = Params::new();
+  params.add_param("Id", id).unwrap();
+  params.add_str("Name", name).unwrap();
+  Reply::Ok(params)
+}
+
+#[tokio::test]
+async fn send_message() {
+  let server = MockServer::bind_tcp().await.unwrap();
+  let mut client = Client::connect(server.endpoint(), None).await.unwrap();
+
+  let xfer = Transport { ch: Channel::Id(2) };
+  let mi = MsgInfo::builder()
+    .cmd(7)
+    .payload_buf(b"hello".to_vec())
+    .build()
+    .unwrap();
+  let report = client.send(&xfer, &mi).await.unwrap();
+
+  assert_eq!(report.xferid, "mock-1");
+  assert_eq!(report.bytes_payload, 5);
+  server.assert_received(&Match::new().topic("Msg").param("_Ch", 2));
+  let reqs = server.requests_for("Msg");
+  assert_eq!(reqs[0].payload.as_deref(), Some(&b"hello"[..]));
+  assert_eq!(reqs[0].params.get_str("Cmd"), Some("7"));
+}
+
+#[tokio::test]
+async fn receive_message() {
+  let (mut conn, mut server) = pair();
+
+  let mut tg = Telegram::new_topic("Msg").unwrap();
+  tg.add_param("_Ch", 4).unwrap();
+  tg.add_str("XferId", "x-1").unwrap();
+  tg.add_param("Len", 5).unwrap();
+  server.send(&tg).await.unwrap();
+  server.send_raw(b"world").await.unwrap();
+
+  let m = tokio_ddmw::recv::next(&mut conn).await.unwrap().unwrap();
+  assert_eq!(m.ch, 4);
+  assert_eq!(m.xferid, "x-1");
+  assert_eq!(m.payload.as_deref(), Some(&b"world"[..]));
+
+  tokio_ddmw::recv::ack(&mut conn, &m.xferid).await.unwrap();
+  let ack = server.expect("Ok").await.unwrap();
+  assert_eq!(ack.get_str("XferId"), Some("x-1"));
+}
+
+/// Channel names are cached per endpoint, so the same name can resolve to
+/// different channels on different servers.
+#[tokio::test]
+async fn channel_cache_per_endpoint() {
+  let a = MockServer::bind_tcp().await.unwrap();
+  let b = MockServer::bind_tcp().await.unwrap();
+  a.reply_always("RdCh", ch_reply(3, "reports"));
+  b.reply_always("RdCh", ch_reply(7, "reports"));
+
+  let xfer = Transport {
+    ch: Channel::from("reports")
+  };
+  let mi = MsgInfo::builder()
+    .payload_buf(b"data".to_vec())
+    .build()
+    .unwrap();
+
+  let mut client_a = Client::connect(a.endpoint(), None).await.unwrap();
+  client_a.send(&xfer, &mi).await.unwrap();
+  client_a.send(&xfer, &mi).await.unwrap();
+  assert_eq!(a.requests_for("RdCh").len(), 1);
+  assert_eq!(a.requests_matching(&Match::new().param("_Ch", 3)).len(), 2);
+
+  let mut client_b = Client::connect(b.endpoint(), None).await.unwrap();
+  client_b.send(&xfer, &mi).await.unwrap();
+  assert_eq!(b.requests_for("RdCh").len(), 1);
+  b.assert_received(&Match::new().topic("Msg").param("_Ch", 7));
+}
+
+/// A rejected token falls back to authenticating with the passphrase.
+#[tokio::test]
+async fn auth_falls_back_to_passphrase() {
+  let server = MockServer::bind_tcp().await.unwrap();
+  server.reply("Auth", Reply::fail_with("AuthExpired"));
+
+  let ai = AuthInfo {
+    accpass: Some(("acc".to_string(), "secret".to_string())),
+    itkn: Some(Token::Buf("stale".to_string())),
+    otkn: None
+  };
+  Client::connect(server.endpoint(), Some(ai)).await.unwrap();
+
+  let reqs = server.requests_for("Auth");
+  assert_eq!(reqs.len(), 2);
+  assert_eq!(reqs[0].params.get_str("Tkn"), Some("stale"));
+  assert_eq!(reqs[1].params.get_str("AccName"), Some("acc"));
+  assert_eq!(reqs[1].params.get_str("Pass"), Some("secret"));
+}
+
+#[tokio::test]
+async fn auth_without_fallback_fails() {
+  let server = MockServer::bind_tcp().await.unwrap();
+  server.reply("Auth", Reply::fail_with("AuthExpired"));
+
+  let ai = AuthInfo {
+    accpass: None,
+    itkn: Some(Token::Buf("stale".to_string())),
+    otkn: None
+  };
+  let err = Client::connect(server.endpoint(), Some(ai)).await.err();
+  assert!(matches!(err, Some(Error::InvalidCredentials)));
+}
+
+/// Tokens read from files are cut to 32 bytes without splitting characters.
+#[tokio::test]
+async fn long_token_file() {
+  let server = MockServer::bind_tcp().await.unwrap();
+  let mut fname = std::env::temp_dir();
+  fname.push(format!("tokio-ddmw-{}-token", std::process::id()));
+  std::fs::write(&fname, format!("{}\u{e9}tail\n", "a".repeat(31))).unwrap();
+
+  let ai = AuthInfo {
+    accpass: None,
+    itkn: Some(Token::File(fname.clone())),
+    otkn: None
+  };
+  Client::connect(server.endpoint(), Some(ai)).await.unwrap();
+  std::fs::remove_file(&fname).unwrap();
+
+  let reqs = server.requests_for("Auth");
+  assert_eq!(reqs[0].params.get_str("Tkn"), Some(&*"a".repeat(31)));
+}
+
+#[tokio::test]
+async fn send_to_multiple_channels() {
+  let server = MockServer::bind_tcp().await.unwrap();
+  let mut client = Client::connect(server.endpoint(), None).await.unwrap();
+  let mut params = Params::new();
+  params.add_str("XferId", "first").unwrap();
+  server.reply("Msg", Reply::Ok(params));
+  server.reply("Msg", Reply::fail_with("PermissionDenied"));
+
+  let mi = MsgInfo::builder()
+    .payload_buf(b"multi".to_vec())
+    .build()
+    .unwrap();
+  let channels = [Channel::Id(1), Channel::Id(2), Channel::Id(3)];
+  let res = msg::send_multi(client.conn(), &channels, &mi)
+    .await
+    .unwrap();
+
+  assert_eq!(res.len(), 3);
+  assert_eq!(res[0].1.as_deref().ok(), Some("first"));
+  assert!(matches!(res[1].1, Err(ref e) if e.is_server_error()));
+  assert_eq!(res[2].1.as_deref().ok(), Some("mock-1"));
+  let sent = server.requests_matching(&Match::new().topic("Msg"));
+  assert_eq!(sent.len(), 3);
+  assert!(sent[2].payload.as_deref() == Some(&b"multi"[..]));
+}
+
+/// A file which disappears before it is sent is a local error, which isn't
+/// worth retrying.
+#[tokio::test]
+async fn send_retry_gives_up_on_local_errors() {
+  let server = MockServer::bind_tcp().await.unwrap();
+  let mut client = Client::builder(server.endpoint())
+    .retry(Policy::fixed(Duration::from_secs(5)).max_attempts(3))
+    .connect()
+    .await
+    .unwrap();
+
+  let mut fname = std::env::temp_dir();
+  fname.push(format!("tokio-ddmw-{}-vanished", std::process::id()));
+  std::fs::write(&fname, b"gone").unwrap();
+  let mi = MsgInfo::builder().payload_file(&fname).build().unwrap();
+  std::fs::remove_file(&fname).unwrap();
+
+  let start = Instant::now();
+  let xfer = Transport { ch: Channel::Id(1) };
+  let err = client.send_retry(&xfer, &mi).await.unwrap_err();
+  assert!(matches!(err, Error::LocalIO { .. }));
+  assert!(!err.is_retryable());
+  assert!(start.elapsed() < Duration::from_secs(5));
+  assert!(server.requests_for("Msg").is_empty());
+}
+
+/// A partial ddlink block isn't parsed, so it's kept in `extra` along with
+/// any other unknown parameters.
+#[tokio::test]
+async fn nodeinfo_extra() {
+  for full in [false, true] {
+    let (mut conn, mut server) = pair();
+
+    let mut params = Params::new();
+    params.add_str("ddmw.node", "sender").unwrap();
+    params.add_str("ddmw.version", "1.2.3").unwrap();
+    params.add_str("ddmw.ddlink.engine", "x").unwrap();
+    params.add_str("ddmw.ddlink.protocol", "udp").unwrap();
+    if full {
+      params.add_str("ddmw.ddlink.protimpl", "generic").unwrap();
+    }
+    params.add_str("site", "lab").unwrap();
+    let reply = async {
+      server.expect("GetNodeInfo").await.unwrap();
+      server.reply(Reply::Ok(params)).await.unwrap();
+    };
+    let (ni, _) = tokio::join!(tokio_ddmw::get_nodeinfo(&mut conn), reply);
+    let ni = ni.unwrap();
+
+    assert_eq!(ni.ddlnk.is_some(), full);
+    assert_eq!(ni.extra.get_str("site"), Some("lab"));
+    assert_eq!(ni.extra.get_str("ddmw.node"), None);
+    if full {
+      assert_eq!(ni.extra.get_str("ddmw.ddlink.engine"), None);
+    } else {
+      assert_eq!(ni.extra.get_str("ddmw.ddlink.engine"), Some("x"));
+      assert_eq!(ni.extra.get_str("ddmw.ddlink.protocol"), Some("udp"));
+    }
+  }
+}
+
+/// A server which answers `Slow` requests after `delay`, and other requests
+/// at once, echoing their topics.  Returns its endpoint and the number of
+/// connections it has accepted.
+async fn slow_server(delay: Duration) -> (Endpoint, Arc<AtomicUsize>) {
+  let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+  let endpoint =
+    Endpoint::TcpSockAddr(listener.local_addr().unwrap().to_string());
+  let conns = Arc::new(AtomicUsize::new(0));
+  let count = conns.clone();
+  tokio::spawn(async move {
+    while let Ok((stream, _)) = listener.accept().await {
+      count.fetch_add(1, Ordering::SeqCst);
+      tokio::spawn(async move {
+        let mut conn = Framed::new(stream, blather::Codec::new());
+        while let Some(Ok(blather::codec::Input::Telegram(tg))) =
+          conn.next().await
+        {
+          let topic = tg.get_topic().unwrap_or_default().to_string();
+          let mut reply = Telegram::new_topic("Ok").unwrap();
+          if topic == "GetNodeInfo" {
+            reply.add_str("ddmw.node", "sender").unwrap();
+            reply.add_str("ddmw.version", "1.0.0").unwrap();
+          } else {
+            if topic == "Slow" {
+              tokio::time::sleep(delay).await;
+            }
+            reply.add_str("Topic", &topic).unwrap();
+          }
+          if conn.send(&reply).await.is_err() {
+            break;
+          }
+        }
+      });
+    }
+  });
+  (endpoint, conns)
+}
+
+/// A request which times out leaves its reply in the connection, so the
+/// client reconnects before the next request rather than reading it.
+#[tokio::test]
+async fn request_timeout_reconnects() {
+  let (endpoint, conns) = slow_server(Duration::from_millis(200)).await;
+  let mut client = Client::builder(endpoint)
+    .request_timeout(Duration::from_millis(50))
+    .connect()
+    .await
+    .unwrap();
+
+  let tg = Telegram::new_topic("Slow").unwrap();
+  let err = client.sendrecv(&tg).await.unwrap_err();
+  assert!(matches!(err, Error::DeadlineExceeded { .. }), "{:?}", err);
+  assert_eq!(conns.load(Ordering::SeqCst), 1);
+
+  // Wait for the late reply to arrive on the old connection.
+  tokio::time::sleep(Duration::from_millis(250)).await;
+  let tg = Telegram::new_topic("Fast").unwrap();
+  let params = client.sendrecv(&tg).await.unwrap();
+  assert_eq!(params.get_str("Topic"), Some("Fast"));
+  assert_eq!(conns.load(Ordering::SeqCst), 2);
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+use futures::sink::SinkExt;
+
+use tokio::io::{AsyncWriteExt, DuplexStream};
+
+use tokio_stream::StreamExt;
+
+use tokio_util::codec::Framed;
+
+use blather::Telegram;
+
+use tokio_ddmw::clntif::util::{
+  next_input, send_frame_and_body, send_params_batched
+};
+use tokio_ddmw::clntif::{ClntIfFramed, Codec, Input};
+use tokio_ddmw::Error;
+
+fn pair() -> (ClntIfFramed<DuplexStream>, ClntIfFramed<DuplexStream>) {
+  let (a, b) = tokio::io::duplex(64 * 1024);
+  (Framed::new(a, Codec::new()), Framed::new(b, Codec::new()))
+}
+
+async fn expect_topic(conn: &mut ClntIfFramed<DuplexStream>, topic: &str) {
+  match conn.next().await {
+    Some(Ok(Input::Telegram(tg))) => assert_eq!(tg.get_topic(), Some(topic)),
+    _ => panic!("Expected a '{}' telegram", topic)
+  }
+}
+
+/// The body follows the telegram directly, after anything which was already
+/// queued on the connection.
+#[tokio::test]
+async fn frame_and_body() {
+  let (mut client, mut server) = pair();
+
+  let first = Telegram::new_topic("First").unwrap();
+  client.feed(&first).await.unwrap();
+  let mut tg = Telegram::new_topic("Data").unwrap();
+  tg.add_param("Len", 10).unwrap();
+  let body = bytes::Buf::chain(&b"01234"[..], &b"56789"[..]);
+  send_frame_and_body(&mut client, &tg, body).await.unwrap();
+
+  expect_topic(&mut server, "First").await;
+  expect_topic(&mut server, "Data").await;
+  server.codec_mut().expect_buf(10).unwrap();
+  match server.next().await {
+    Some(Ok(Input::Buf(buf))) => assert_eq!(&buf[..], b"0123456789"),
+    _ => panic!("Expected a buffer")
+  }
+}
+
+/// Parameter blocks larger than a batch arrive as a single block.
+#[tokio::test]
+async fn params_batched() {
+  let (mut client, mut server) = pair();
+  let entries = (0..10_000).map(|i| (format!("Key{}", i), "x".repeat(16)));
+
+  let client = async move {
+    send_params_batched(&mut client, entries).await.unwrap();
+    client
+  };
+  let server = async move {
+    server.codec_mut().expect_params();
+    server.next().await
+  };
+  let (_client, res) = tokio::join!(client, server);
+  match res {
+    Some(Ok(Input::Params(params))) => {
+      let params = params.into_inner();
+      assert_eq!(params.len(), 10_000);
+      assert_eq!(params["Key9999"], "x".repeat(16));
+    }
+    _ => panic!("Expected parameters")
+  }
+}
+
+/// Entries which can't be represented on the wire are rejected.
+#[tokio::test]
+async fn params_batched_invalid() {
+  let (mut client, _server) = pair();
+  for entry in [("Bad Key", "value"), ("Key", "two\nlines")] {
+    assert!(matches!(
+      send_params_batched(&mut client, [entry]).await,
+      Err(Error::BadFormat { .. })
+    ));
+  }
+}
+
+/// The overall transfer timeout applies even while data keeps arriving.
+#[tokio::test]
+async fn transfer_deadline() {
+  let (mut client, mut server) = pair();
+  client
+    .codec_mut()
+    .set_bin_timeout(Some(Duration::from_millis(100)));
+  assert!(client.codec().receive_deadline().is_none());
+  client.codec_mut().expect_buf(100).unwrap();
+  assert!(client.codec().receive_deadline().is_some());
+
+  let server = async move {
+    for _ in 0..20 {
+      server.get_mut().write_all(b"x").await.unwrap();
+      tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    server
+  };
+  let client = async move {
+    let res = next_input(&mut client).await;
+    (res, client)
+  };
+  let ((res, client), _server) = tokio::join!(client, server);
+  match res {
+    Some(Err(Error::TransferTimeout { remaining })) => {
+      assert!(remaining > 0)
+    }
+    _ => panic!("Expected a transfer timeout")
+  }
+  assert!(client.codec().receive_deadline().is_none());
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
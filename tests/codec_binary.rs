@@ -0,0 +1,447 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use bytes::BytesMut;
+
+use futures::executor::block_on;
+
+use tokio::io::AsyncWrite;
+
+use tokio_util::codec::Decoder;
+
+use tokio_ddmw::checksum::Checksum;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+use tokio_ddmw::clntif::Compression;
+use tokio_ddmw::clntif::{Codec, Expect, Input, State};
+use tokio_ddmw::Error;
+
+/// An asynchronous writer which appends to a buffer shared with the test.
+#[derive(Clone, Default)]
+struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuf {
+  fn contents(&self) -> Vec<u8> {
+    self.0.lock().unwrap().clone()
+  }
+}
+
+impl AsyncWrite for SharedBuf {
+  fn poll_write(
+    self: Pin<&mut Self>,
+    _cx: &mut Context<'_>,
+    buf: &[u8]
+  ) -> Poll<io::Result<usize>> {
+    self.0.lock().unwrap().extend_from_slice(buf);
+    Poll::Ready(Ok(buf.len()))
+  }
+
+  fn poll_flush(
+    self: Pin<&mut Self>,
+    _cx: &mut Context<'_>
+  ) -> Poll<io::Result<()>> {
+    Poll::Ready(Ok(()))
+  }
+
+  fn poll_shutdown(
+    self: Pin<&mut Self>,
+    _cx: &mut Context<'_>
+  ) -> Poll<io::Result<()>> {
+    Poll::Ready(Ok(()))
+  }
+}
+
+impl io::Write for SharedBuf {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    self.0.lock().unwrap().extend_from_slice(buf);
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// A writer which always fails.
+struct Broken;
+
+impl io::Write for Broken {
+  fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+    Err(io::Error::other("broken"))
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+/// Decode the next entity, waiting for the writer whenever the decoder
+/// pauses.
+fn next(
+  codec: &mut Codec,
+  buf: &mut BytesMut
+) -> Result<Option<Input>, Error> {
+  loop {
+    match codec.decode(buf)? {
+      Some(Input::Paused) => block_on(codec.resumed()),
+      input => return Ok(input)
+    }
+  }
+}
+
+/// Data handed to an asynchronous writer is written by the returned future,
+/// which completes once all of it has been written.
+#[test]
+fn async_writer() {
+  let mut codec = Codec::new();
+  let out = SharedBuf::default();
+  let fut = codec.expect_async_writer(out.clone(), 10).unwrap();
+
+  let mut buf = BytesMut::from(&b"01234"[..]);
+  assert!(codec.decode(&mut buf).unwrap().is_none());
+  buf.extend_from_slice(b"56789Next\n\n");
+  assert!(matches!(codec.decode(&mut buf), Ok(Some(Input::WriteDone))));
+
+  block_on(fut).unwrap();
+  assert_eq!(out.contents(), b"0123456789");
+  assert!(matches!(
+    codec.decode(&mut buf),
+    Ok(Some(Input::Telegram(_)))
+  ));
+}
+
+/// The writer future fails if the transfer is abandoned before all data has
+/// been received.
+#[test]
+fn async_writer_abandoned() {
+  let mut codec = Codec::new();
+  let fut = codec.expect_async_writer(SharedBuf::default(), 10).unwrap();
+
+  let mut buf = BytesMut::from(&b"01234"[..]);
+  assert!(codec.decode(&mut buf).unwrap().is_none());
+  codec.reset();
+
+  assert!(matches!(block_on(fut), Err(Error::BadState { .. })));
+}
+
+/// Transfers larger than the configured maximum are refused before any data
+/// is received.
+#[test]
+fn max_bin_size() {
+  let mut codec = Codec::new();
+  codec.set_max_bin_size(8);
+  assert!(matches!(
+    codec.expect_buf(9),
+    Err(Error::TooLarge {
+      size: Some(9),
+      limit: 8,
+      ..
+    })
+  ));
+  assert!(matches!(
+    codec.expect_chunks(9),
+    Err(Error::TooLarge { .. })
+  ));
+  assert!(matches!(
+    codec.expect_writer(Vec::new(), 9),
+    Err(Error::TooLarge { .. })
+  ));
+  assert_eq!(codec.expecting(), Expect::Telegram);
+
+  codec.expect_buf(8).unwrap();
+  let mut buf = BytesMut::from(&b"01234567"[..]);
+  assert!(matches!(codec.decode(&mut buf), Ok(Some(Input::Buf(_)))));
+}
+
+/// A binary transfer can be abandoned, after which the decoder expects a
+/// telegram again.
+#[test]
+fn reset_abandons_transfer() {
+  let mut codec = Codec::new();
+  codec.expect_buf(10).unwrap();
+  let mut buf = BytesMut::from(&b"0123"[..]);
+  assert!(codec.decode(&mut buf).unwrap().is_none());
+  assert_eq!(codec.expecting(), Expect::Buf(6));
+
+  codec.reset();
+  assert_eq!(codec.expecting(), Expect::Telegram);
+  buf.extend_from_slice(b"Next\n\n");
+  match codec.decode(&mut buf).unwrap() {
+    Some(Input::Telegram(tg)) => assert_eq!(tg.get_topic(), Some("Next")),
+    _ => panic!("Expected a telegram")
+  }
+}
+
+/// Received buffers are only returned if their checksum matches the expected
+/// one.
+#[test]
+fn buf_checksum() {
+  let mut codec = Codec::new();
+  codec
+    .expect_buf_verified(4, Checksum::sha256(b"0123"))
+    .unwrap();
+  let mut buf = BytesMut::from(&b"0123"[..]);
+  match codec.decode(&mut buf).unwrap() {
+    Some(Input::Buf(b)) => assert_eq!(&b[..], b"0123"),
+    _ => panic!("Expected a buffer")
+  }
+
+  codec
+    .expect_buf_verified(4, Checksum::sha256(b"0123"))
+    .unwrap();
+  let mut buf = BytesMut::from(&b"0124Next\n\n"[..]);
+  assert!(matches!(
+    codec.decode(&mut buf),
+    Err(Error::ChecksumMismatch { .. })
+  ));
+  assert!(matches!(
+    codec.decode(&mut buf),
+    Ok(Some(Input::Telegram(_)))
+  ));
+}
+
+#[cfg(feature = "gzip")]
+fn gzip(data: &[u8]) -> Vec<u8> {
+  use std::io::Write;
+  let mut enc =
+    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+  enc.write_all(data).unwrap();
+  enc.finish().unwrap()
+}
+
+/// Compressed data is decompressed as it's received, and the decompressed
+/// size is subject to the maximum binary size.
+#[cfg(feature = "gzip")]
+#[test]
+fn gzip_decompression() {
+  let data = b"0123456789".repeat(100);
+  let compressed = gzip(&data);
+
+  let mut codec = Codec::new();
+  codec.decompress_next(Compression::Gzip);
+  codec.expect_buf(compressed.len()).unwrap();
+  let (head, tail) = compressed.split_at(compressed.len() / 2);
+  let mut buf = BytesMut::from(head);
+  assert!(codec.decode(&mut buf).unwrap().is_none());
+  buf.extend_from_slice(tail);
+  match codec.decode(&mut buf).unwrap() {
+    Some(Input::Buf(b)) => assert_eq!(&b[..], &data[..]),
+    _ => panic!("Expected a buffer")
+  }
+
+  codec.set_max_bin_size(data.len() - 1);
+  codec.decompress_next(Compression::Gzip);
+  codec.expect_buf(compressed.len()).unwrap();
+  let mut buf = BytesMut::from(&compressed[..]);
+  assert!(matches!(
+    codec.decode(&mut buf),
+    Err(Error::TooLarge { .. })
+  ));
+  assert_eq!(codec.expecting(), Expect::Telegram);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd_decompression() {
+  let data = b"0123456789".repeat(100);
+  let compressed = zstd::encode_all(&data[..], 0).unwrap();
+
+  let mut codec = Codec::new();
+  codec.decompress_next(Compression::Zstd);
+  let out = SharedBuf::default();
+  let fut = codec
+    .expect_async_writer(out.clone(), compressed.len())
+    .unwrap();
+  let mut buf = BytesMut::from(&compressed[..]);
+  assert!(matches!(codec.decode(&mut buf), Ok(Some(Input::WriteDone))));
+  block_on(fut).unwrap();
+  assert_eq!(out.contents(), data);
+}
+
+/// Chunks handed back to the codec are reused for later chunks.
+#[test]
+fn chunk_pool() {
+  let mut codec = Codec::new();
+  codec.set_chunk_pool(1);
+  codec.expect_chunks(8).unwrap();
+
+  let mut buf = BytesMut::from(&b"0123"[..]);
+  let chunk = match codec.decode(&mut buf).unwrap() {
+    Some(Input::Chunk(chunk, 4)) => chunk,
+    _ => panic!("Expected a chunk")
+  };
+  assert_eq!(&chunk[..], b"0123");
+  let ptr = chunk.as_ptr();
+  codec.recycle(chunk);
+
+  buf.extend_from_slice(b"4567");
+  match codec.decode(&mut buf).unwrap() {
+    Some(Input::Chunk(chunk, 0)) => {
+      assert_eq!(&chunk[..], b"4567");
+      assert_eq!(chunk.as_ptr(), ptr);
+    }
+    _ => panic!("Expected a chunk")
+  }
+}
+
+/// Data received in one go is split into chunks of at most the maximum
+/// chunk size.
+#[test]
+fn max_chunk_size() {
+  let mut codec = Codec::new();
+  codec.set_max_chunk_size(4);
+  codec.expect_chunks(10).unwrap();
+
+  let mut buf = BytesMut::from(&b"0123456789Next\n\n"[..]);
+  for (data, remain) in [(&b"0123"[..], 6), (b"4567", 2), (b"89", 0)] {
+    match codec.decode(&mut buf).unwrap() {
+      Some(Input::Chunk(chunk, r)) => {
+        assert_eq!(&chunk[..], data);
+        assert_eq!(r, remain);
+      }
+      _ => panic!("Expected a chunk")
+    }
+  }
+  assert!(matches!(
+    codec.decode(&mut buf),
+    Ok(Some(Input::Telegram(_)))
+  ));
+}
+
+/// The state hook is called when the decoder switches between kinds of
+/// states, but not as binary data is received.
+#[test]
+fn state_changes() {
+  let seen = Arc::new(Mutex::new(Vec::new()));
+  let mut codec = Codec::new();
+  let hooked = Arc::clone(&seen);
+  codec.on_state_change(move |prev, new| {
+    hooked.lock().unwrap().push((prev, new));
+  });
+
+  codec.expect_buf(4).unwrap();
+  let mut buf = BytesMut::from(&b"01"[..]);
+  assert!(codec.decode(&mut buf).unwrap().is_none());
+  assert_eq!(codec.state(), State::Binary { remaining: 2 });
+  buf.extend_from_slice(b"23");
+  assert!(codec.decode(&mut buf).unwrap().is_some());
+  codec.expect_params();
+
+  assert_eq!(
+    *seen.lock().unwrap(),
+    [
+      (State::Telegram, State::Binary { remaining: 4 }),
+      (State::Binary { remaining: 0 }, State::Telegram),
+      (State::Telegram, State::Params)
+    ]
+  );
+}
+
+/// Progress is reported relative to each transfer, as data arrives.
+#[test]
+fn progress() {
+  let seen = Arc::new(Mutex::new(Vec::new()));
+  let mut codec = Codec::new();
+  let hooked = Arc::clone(&seen);
+  codec.on_progress(move |done, total| {
+    hooked.lock().unwrap().push((done, total));
+  });
+
+  codec.expect_buf(6).unwrap();
+  let mut buf = BytesMut::from(&b"012"[..]);
+  assert!(codec.decode(&mut buf).unwrap().is_none());
+  buf.extend_from_slice(b"345");
+  assert!(codec.decode(&mut buf).unwrap().is_some());
+
+  // A new transfer of the same kind starts over, even if the previous one
+  // was abandoned while the decoder was still in the same state.
+  codec.expect_buf(4).unwrap();
+  let mut buf = BytesMut::from(&b"01"[..]);
+  assert!(codec.decode(&mut buf).unwrap().is_none());
+  codec.expect_buf(2).unwrap();
+  buf.extend_from_slice(b"01");
+  assert!(codec.decode(&mut buf).unwrap().is_some());
+
+  assert_eq!(*seen.lock().unwrap(), [(3, 6), (6, 6), (2, 4), (2, 2)]);
+}
+
+/// Data which was received but couldn't be written is still accounted for.
+#[test]
+fn progress_on_write_error() {
+  let seen = Arc::new(Mutex::new(Vec::new()));
+  let mut codec = Codec::new();
+  let hooked = Arc::clone(&seen);
+  codec.on_progress(move |done, total| {
+    hooked.lock().unwrap().push((done, total));
+  });
+
+  let fut = codec.expect_async_writer(SharedBuf::default(), 10).unwrap();
+  drop(fut);
+  let mut buf = BytesMut::from(&b"0123"[..]);
+  assert!(codec.decode(&mut buf).is_err());
+  assert_eq!(codec.expecting(), Expect::Writer(6));
+  assert_eq!(*seen.lock().unwrap(), [(4, 10)]);
+}
+
+/// The decoder pauses while an asynchronous writer has too much data queued,
+/// and resumes once the writer has caught up.
+#[test]
+fn async_writer_backlog() {
+  let mut codec = Codec::new();
+  codec.set_async_writer_backlog(4);
+  let out = SharedBuf::default();
+  let mut fut = Box::pin(codec.expect_async_writer(out.clone(), 10).unwrap());
+
+  let mut buf = BytesMut::from(&b"012345"[..]);
+  assert!(codec.decode(&mut buf).unwrap().is_none());
+  buf.extend_from_slice(b"6789");
+  assert!(matches!(codec.decode(&mut buf), Ok(Some(Input::Paused))));
+  assert!(codec.is_paused());
+  assert_eq!(buf.len(), 4);
+
+  // Let the writer catch up with the queued data.
+  block_on(async {
+    assert!(futures::poll!(&mut fut).is_pending());
+    codec.resumed().await;
+  });
+  assert_eq!(out.contents(), b"012345");
+  assert!(!codec.is_paused());
+
+  assert!(matches!(codec.decode(&mut buf), Ok(Some(Input::WriteDone))));
+  block_on(fut).unwrap();
+  assert_eq!(out.contents(), b"0123456789");
+}
+
+/// Data is written to a blocking writer in the background, and the
+/// transfer completes once it has all been written.
+#[test]
+fn writer() {
+  let mut codec = Codec::new();
+  let out = SharedBuf::default();
+  codec.expect_writer(out.clone(), 10).unwrap();
+
+  let mut buf = BytesMut::from(&b"01234"[..]);
+  assert!(next(&mut codec, &mut buf).unwrap().is_none());
+  buf.extend_from_slice(b"56789Next\n\n");
+  assert!(matches!(
+    next(&mut codec, &mut buf),
+    Ok(Some(Input::WriteDone))
+  ));
+  assert_eq!(out.contents(), b"0123456789");
+  assert!(matches!(
+    next(&mut codec, &mut buf),
+    Ok(Some(Input::Telegram(_)))
+  ));
+}
+
+/// A failing writer fails the transfer.
+#[test]
+fn writer_error() {
+  let mut codec = Codec::new();
+  codec.expect_writer(Broken, 4).unwrap();
+  let mut buf = BytesMut::from(&b"0123"[..]);
+  assert!(next(&mut codec, &mut buf).is_err());
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,146 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bytes::{Bytes, BytesMut};
+
+use tokio_util::codec::{Decoder, Encoder};
+
+use blather::{KVLines, Params, Telegram};
+
+use tokio_ddmw::checksum::Checksum;
+use tokio_ddmw::clntif::{Codec, CodecMetrics, Direction, TapData};
+use tokio_ddmw::metrics::Metrics;
+
+/// Raw buffers of any of the supported types are appended to the write
+/// buffer as-is.
+#[test]
+fn byte_buffers() {
+  let mut codec = Codec::new();
+  let mut buf = BytesMut::new();
+  codec.encode(BytesMut::from(&b"01"[..]), &mut buf).unwrap();
+  codec.encode(&b"23"[..], &mut buf).unwrap();
+  codec.encode(b"45".to_vec(), &mut buf).unwrap();
+  codec.encode(Bytes::from_static(b"67"), &mut buf).unwrap();
+  codec.encode(BytesMut::from(&b"89"[..]), &mut buf).unwrap();
+  assert_eq!(&buf[..], b"0123456789");
+}
+
+/// The tap sees complete line based entities as-is, and binary data as a
+/// summary.
+#[test]
+fn tap() {
+  let seen = Arc::new(Mutex::new(Vec::new()));
+  let mut codec = Codec::new();
+  let tapped = Arc::clone(&seen);
+  codec.set_tap(move |dir, data| {
+    let data = match data {
+      TapData::Lines(lines) => String::from_utf8(lines.to_vec()).unwrap(),
+      TapData::Binary { len, digest } => format!("{} {}", len, digest)
+    };
+    tapped.lock().unwrap().push((dir, data));
+  });
+
+  let mut buf = BytesMut::new();
+  let tg = Telegram::new_topic("Out").unwrap();
+  codec.encode(&tg, &mut buf).unwrap();
+  codec.encode(&b"data"[..], &mut buf).unwrap();
+
+  let mut buf = BytesMut::from(&b"In\nKey "[..]);
+  assert!(codec.decode(&mut buf).unwrap().is_none());
+  buf.extend_from_slice(b"value\n\n");
+  assert!(codec.decode(&mut buf).unwrap().is_some());
+  codec.expect_buf(4).unwrap();
+  buf.extend_from_slice(b"data");
+  assert!(codec.decode(&mut buf).unwrap().is_some());
+
+  let digest = Checksum::sha256(b"data");
+  assert_eq!(
+    *seen.lock().unwrap(),
+    [
+      (Direction::Outbound, "Out\n\n".to_string()),
+      (Direction::Outbound, format!("4 {}", digest)),
+      (Direction::Inbound, "In\nKey value\n\n".to_string()),
+      (Direction::Inbound, format!("4 {}", digest))
+    ]
+  );
+}
+
+/// Owned entities are encoded exactly like borrowed ones.
+#[test]
+fn owned_entities() {
+  let mut tg = Telegram::new_topic("Topic").unwrap();
+  tg.add_str("Key", "value").unwrap();
+  let mut params = Params::new();
+  params.add_str("Key", "value").unwrap();
+  let mut kvlines = KVLines::new();
+  kvlines.append("Key", "value");
+
+  let mut codec = Codec::new();
+  let mut borrowed = BytesMut::new();
+  codec.encode(&tg, &mut borrowed).unwrap();
+  codec.encode(&params, &mut borrowed).unwrap();
+  codec.encode(&kvlines, &mut borrowed).unwrap();
+
+  let mut owned = BytesMut::new();
+  codec.encode(tg, &mut owned).unwrap();
+  codec.encode(params, &mut owned).unwrap();
+  codec.encode(kvlines, &mut owned).unwrap();
+  assert_eq!(owned, borrowed);
+}
+
+#[derive(Default)]
+struct ByteCounts {
+  sent: AtomicU64,
+  received: AtomicU64
+}
+
+impl Metrics for ByteCounts {
+  fn bytes_sent(&self, n: u64) {
+    self.sent.fetch_add(n, Ordering::Relaxed);
+  }
+
+  fn bytes_received(&self, n: u64) {
+    self.received.fetch_add(n, Ordering::Relaxed);
+  }
+}
+
+/// The codec counts what passes through it, and reports the number of bytes
+/// to a metrics sink.
+#[test]
+fn metrics() {
+  let sink = Arc::new(ByteCounts::default());
+  let mut codec = Codec::new();
+  codec.set_metrics_sink(Arc::clone(&sink) as Arc<dyn Metrics>);
+
+  let mut out = BytesMut::new();
+  codec
+    .encode(&Telegram::new_topic("Out").unwrap(), &mut out)
+    .unwrap();
+  codec.encode(&b"data"[..], &mut out).unwrap();
+
+  let mut buf = BytesMut::from(&b"In\n\nKey value\n\n0123Bad\xff\n"[..]);
+  assert!(codec.decode(&mut buf).unwrap().is_some());
+  codec.expect_params();
+  assert!(codec.decode(&mut buf).unwrap().is_some());
+  codec.expect_buf(4).unwrap();
+  assert!(codec.decode(&mut buf).unwrap().is_some());
+  assert!(codec.decode(&mut buf).is_err());
+
+  let expected = CodecMetrics {
+    telegrams: 1,
+    params: 1,
+    kvlines: 0,
+    bin_transfers: 1,
+    bytes_in: 24,
+    bytes_out: 9,
+    decode_errors: 1
+  };
+  assert_eq!(*codec.metrics(), expected);
+  assert_eq!(sink.sent.load(Ordering::Relaxed), 9);
+  assert_eq!(sink.received.load(Ordering::Relaxed), 24);
+
+  codec.reset_metrics();
+  assert_eq!(*codec.metrics(), CodecMetrics::default());
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,100 @@
+#![cfg(feature = "testing")]
+
+use std::time::Duration;
+
+use tokio::io::{duplex, AsyncWriteExt, DuplexStream};
+
+use tokio_stream::StreamExt;
+
+use tokio_util::codec::Framed;
+
+use tokio_ddmw::clntif::{util, ClntIfFramed, Codec, Input};
+use tokio_ddmw::testing::{Fault, Faulty};
+use tokio_ddmw::Error;
+
+fn framed(
+  faulty: impl FnOnce(Faulty<DuplexStream>) -> Faulty<DuplexStream>
+) -> (ClntIfFramed<Faulty<DuplexStream>>, DuplexStream) {
+  let (client, server) = duplex(4096);
+  let conn = Framed::new(faulty(Faulty::new(client)), Codec::new());
+  (conn, server)
+}
+
+async fn next_telegram(conn: &mut ClntIfFramed<Faulty<DuplexStream>>) {
+  match conn.next().await {
+    Some(Ok(Input::Telegram(_))) => {}
+    _ => panic!("Expected a telegram")
+  }
+}
+
+/// A connection which closes part way through a binary transfer reports
+/// how much of it arrived.
+#[tokio::test]
+async fn truncated_transfer() {
+  let (mut conn, mut server) = framed(|f| f.on_read(20, Fault::Truncate));
+  server
+    .write_all(b"Msg\nLen 10\n\n0123456789")
+    .await
+    .unwrap();
+
+  next_telegram(&mut conn).await;
+  conn.codec_mut().expect_buf(10).unwrap();
+  match conn.next().await {
+    Some(Err(Error::TruncatedTransfer { expected, received })) => {
+      assert_eq!(expected, 10);
+      assert_eq!(received, 8);
+    }
+    _ => panic!("Expected a truncated transfer")
+  }
+}
+
+/// Lines which arrive in fragments are reassembled, and are still subject
+/// to the line length limit.
+#[tokio::test]
+async fn fragmented_line_limit() {
+  let (mut conn, mut server) = framed(|f| f.on_read(0, Fault::Fragment(3)));
+  conn.codec_mut().set_max_line_length(16);
+  server.write_all(b"Short\n\n").await.unwrap();
+  next_telegram(&mut conn).await;
+
+  server
+    .write_all(b"ThisTopicIsFarTooLongForTheLimit\n\n")
+    .await
+    .unwrap();
+  assert!(matches!(
+    conn.next().await,
+    Some(Err(Error::BadFormat { .. }))
+  ));
+}
+
+/// A stalled binary transfer times out and leaves the codec ready for the
+/// next telegram.
+#[tokio::test]
+async fn stalled_transfer_times_out() {
+  let (mut conn, mut server) =
+    framed(|f| f.on_read(17, Fault::Delay(Duration::from_secs(60))));
+  conn
+    .codec_mut()
+    .set_bin_idle_timeout(Some(Duration::from_millis(50)));
+  server.write_all(b"Msg\nLen 10\n\n01234").await.unwrap();
+
+  next_telegram(&mut conn).await;
+  conn.codec_mut().expect_buf(10).unwrap();
+  match util::next_input(&mut conn).await {
+    Some(Err(Error::TransferTimeout { remaining })) => {
+      assert_eq!(remaining, 5)
+    }
+    _ => panic!("Expected a transfer timeout")
+  }
+  assert!(conn.codec().receive_deadline().is_none());
+}
+
+/// Corrupted telegram lines are reported as format errors.
+#[tokio::test]
+async fn corrupted_line() {
+  let (mut conn, mut server) = framed(|f| f.on_read(4, Fault::Corrupt(0x80)));
+  server.write_all(b"Msg\nLen 10\n\n").await.unwrap();
+  assert!(matches!(conn.next().await, Some(Err(_))));
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,266 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bytes::BytesMut;
+
+use futures::executor::block_on;
+
+use tokio_util::codec::Decoder;
+
+use tokio_ddmw::checksum::Checksum;
+use tokio_ddmw::clntif::{Codec, FileOpts, Input};
+use tokio_ddmw::Error;
+
+/// Create an empty directory for a test in the temporary directory.
+fn tmp_dir(name: &str) -> PathBuf {
+  let mut dir = std::env::temp_dir();
+  dir.push(format!("tokio-ddmw-{}-{}", std::process::id(), name));
+  let _ = std::fs::remove_dir_all(&dir);
+  std::fs::create_dir_all(&dir).unwrap();
+  dir
+}
+
+/// Decode the next entity, waiting for the file writer whenever the decoder
+/// pauses.
+fn next(
+  codec: &mut Codec,
+  buf: &mut BytesMut
+) -> Result<Option<Input>, Error> {
+  loop {
+    match codec.decode(buf)? {
+      Some(Input::Paused) => block_on(codec.resumed()),
+      input => return Ok(input)
+    }
+  }
+}
+
+/// File data is handed to the writer as it arrives, and the file is
+/// reported once the writer has finished with it.
+#[test]
+fn file_received_in_pieces() {
+  let dir = tmp_dir("file_received_in_pieces");
+  let fname = dir.join("data");
+  let mut codec = Codec::new();
+  codec.expect_file(&fname, 10).unwrap();
+
+  let mut buf = BytesMut::from(&b"0123"[..]);
+  assert!(next(&mut codec, &mut buf).unwrap().is_none());
+  buf.extend_from_slice(b"456789");
+  match next(&mut codec, &mut buf).unwrap() {
+    Some(Input::File(path)) => assert_eq!(path, fname),
+    _ => panic!("Expected a file")
+  }
+  assert_eq!(std::fs::read(&fname).unwrap(), b"0123456789");
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn part_path(fname: &Path) -> PathBuf {
+  let mut s = fname.as_os_str().to_owned();
+  s.push(".part");
+  PathBuf::from(s)
+}
+
+/// Wait for the file writer, which runs in the background, to remove a file.
+fn wait_removed(fname: &Path) -> bool {
+  for _ in 0..200 {
+    if !fname.exists() {
+      return true;
+    }
+    std::thread::sleep(Duration::from_millis(10));
+  }
+  false
+}
+
+/// Data is only written to a temporary file, which replaces the target once
+/// the transfer is complete.
+#[test]
+fn file_moved_into_place_when_complete() {
+  let dir = tmp_dir("file_moved_into_place_when_complete");
+  let fname = dir.join("data");
+  std::fs::write(&fname, b"old").unwrap();
+  let mut codec = Codec::new();
+  codec.expect_file(&fname, 6).unwrap();
+
+  let mut buf = BytesMut::from(&b"new"[..]);
+  assert!(next(&mut codec, &mut buf).unwrap().is_none());
+  assert_eq!(std::fs::read(&fname).unwrap(), b"old");
+  assert!(part_path(&fname).exists());
+
+  buf.extend_from_slice(b"new");
+  assert!(matches!(
+    next(&mut codec, &mut buf),
+    Ok(Some(Input::File(_)))
+  ));
+  assert_eq!(std::fs::read(&fname).unwrap(), b"newnew");
+  assert!(!part_path(&fname).exists());
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// The temporary file of an interrupted transfer is kept, unless the codec
+/// has been told to remove partial files.
+#[test]
+fn interrupted_file_transfer() {
+  let dir = tmp_dir("interrupted_file_transfer");
+  for remove in [false, true] {
+    let fname = dir.join(format!("data-{}", remove));
+    let mut codec = Codec::new();
+    codec.remove_partial_files(remove);
+    codec.expect_file(&fname, 10).unwrap();
+
+    let mut buf = BytesMut::from(&b"0123"[..]);
+    assert!(next(&mut codec, &mut buf).unwrap().is_none());
+    drop(codec);
+
+    if remove {
+      assert!(wait_removed(&part_path(&fname)));
+    } else {
+      // Give the writer a chance to (wrongly) remove the file.
+      std::thread::sleep(Duration::from_millis(50));
+      assert_eq!(std::fs::read(part_path(&fname)).unwrap(), b"0123");
+    }
+    assert!(!fname.exists());
+  }
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// An interrupted transfer continues where the partial file ends.
+#[test]
+fn resume_interrupted_file() {
+  let dir = tmp_dir("resume_interrupted_file");
+  let fname = dir.join("data");
+  let mut codec = Codec::new();
+  codec.expect_file(&fname, 10).unwrap();
+  let mut buf = BytesMut::from(&b"0123"[..]);
+  assert!(next(&mut codec, &mut buf).unwrap().is_none());
+  drop(codec);
+
+  // The writer finishes the queued data in the background.
+  let mut offset = None;
+  for _ in 0..200 {
+    offset = Codec::partial_file_size(&fname);
+    if offset == Some(4) {
+      break;
+    }
+    std::thread::sleep(Duration::from_millis(10));
+  }
+  assert_eq!(offset, Some(4));
+
+  let mut codec = Codec::new();
+  codec.expect_file_at(&fname, 4, 6).unwrap();
+  let mut buf = BytesMut::from(&b"456789"[..]);
+  assert!(matches!(
+    next(&mut codec, &mut buf),
+    Ok(Some(Input::File(_)))
+  ));
+  assert_eq!(std::fs::read(&fname).unwrap(), b"0123456789");
+  assert_eq!(Codec::partial_file_size(&fname), None);
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Resuming fails if the partial file has less data than the peer assumes.
+#[test]
+fn resume_beyond_partial_file() {
+  let dir = tmp_dir("resume_beyond_partial_file");
+  let fname = dir.join("data");
+  std::fs::write(part_path(&fname), b"01").unwrap();
+  let mut codec = Codec::new();
+  assert!(matches!(
+    codec.expect_file_at(&fname, 4, 6),
+    Err(Error::InvalidSize { .. })
+  ));
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Parent directories are created on request, and the requested permissions
+/// are applied regardless of the umask.
+#[test]
+fn file_opts_create_dirs_and_mode() {
+  let dir = tmp_dir("file_opts_create_dirs_and_mode");
+  let fname = dir.join("a").join("b").join("data");
+  let mut codec = Codec::new();
+  let opts = FileOpts {
+    create_dirs: false,
+    ..Default::default()
+  };
+  assert!(codec.expect_file_with(&fname, 4, &opts).is_err());
+
+  let opts = FileOpts {
+    #[cfg(unix)]
+    mode: Some(0o604),
+    create_dirs: true,
+    ..Default::default()
+  };
+  codec.expect_file_with(&fname, 4, &opts).unwrap();
+  let mut buf = BytesMut::from(&b"0123"[..]);
+  assert!(matches!(
+    next(&mut codec, &mut buf),
+    Ok(Some(Input::File(_)))
+  ));
+  assert_eq!(std::fs::read(&fname).unwrap(), b"0123");
+
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    let md = std::fs::metadata(&fname).unwrap();
+    assert_eq!(md.permissions().mode() & 0o777, 0o604);
+  }
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Without `overwrite` an existing file is never replaced, whether it exists
+/// when the transfer starts or appears before it completes.
+#[test]
+fn file_opts_no_overwrite() {
+  let dir = tmp_dir("file_opts_no_overwrite");
+  let fname = dir.join("data");
+  let opts = FileOpts {
+    overwrite: false,
+    ..Default::default()
+  };
+
+  std::fs::write(&fname, b"old").unwrap();
+  let mut codec = Codec::new();
+  assert!(codec.expect_file_with(&fname, 4, &opts).is_err());
+  std::fs::remove_file(&fname).unwrap();
+
+  codec.expect_file_with(&fname, 4, &opts).unwrap();
+  let mut buf = BytesMut::from(&b"01"[..]);
+  assert!(next(&mut codec, &mut buf).unwrap().is_none());
+  std::fs::write(&fname, b"old").unwrap();
+  buf.extend_from_slice(b"23");
+  assert!(next(&mut codec, &mut buf).is_err());
+  assert_eq!(std::fs::read(&fname).unwrap(), b"old");
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// A received file whose checksum doesn't match the expected one is never
+/// moved into place.
+#[test]
+fn file_checksum_mismatch() {
+  let dir = tmp_dir("file_checksum_mismatch");
+  let fname = dir.join("data");
+  let mut codec = Codec::new();
+  codec
+    .expect_file_verified(&fname, 4, Checksum::sha256(b"0123"))
+    .unwrap();
+  let mut buf = BytesMut::from(&b"0124"[..]);
+  assert!(matches!(
+    next(&mut codec, &mut buf),
+    Err(Error::ChecksumMismatch { .. })
+  ));
+  assert!(!fname.exists());
+  assert!(!part_path(&fname).exists());
+
+  codec
+    .expect_file_verified(&fname, 4, Checksum::sha256(b"0123"))
+    .unwrap();
+  let mut buf = BytesMut::from(&b"0123"[..]);
+  assert!(matches!(
+    next(&mut codec, &mut buf),
+    Ok(Some(Input::File(_)))
+  ));
+  assert_eq!(std::fs::read(&fname).unwrap(), b"0123");
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,285 @@
+use bytes::BytesMut;
+
+use tokio_util::codec::Decoder;
+
+use tokio_ddmw::clntif::{
+  Codec, DuplicateParams, Input, InvalidUtf8, LineLimits, LineMode
+};
+use tokio_ddmw::Error;
+
+/// Lowering the line length limit while a partial line is buffered must
+/// fail the line rather than panic.
+#[test]
+fn lowered_limit_with_partial_line() {
+  let mut codec = Codec::new();
+  let mut buf =
+    BytesMut::from(&b"ThisIsAVeryLongTelegramTopicWithoutNewline_"[..]);
+  assert!(codec.decode(&mut buf).unwrap().is_none());
+
+  codec.set_max_line_length(5);
+  assert!(codec.decode(&mut buf).is_err());
+}
+
+/// Raising the limit again lets the rest of a partial line through.
+#[test]
+fn raised_limit_with_partial_line() {
+  let mut codec = Codec::new();
+  codec.set_max_line_length(8);
+  let mut buf = BytesMut::from(&b"Topic"[..]);
+  assert!(codec.decode(&mut buf).unwrap().is_none());
+
+  codec.set_max_line_length(64);
+  buf.extend_from_slice(b"WithALongerName\n\n");
+  match codec.decode(&mut buf).unwrap() {
+    Some(Input::Telegram(tg)) => {
+      assert_eq!(tg.get_topic(), Some("TopicWithALongerName"))
+    }
+    _ => panic!("Expected a telegram")
+  }
+}
+
+/// Each kind of line based entity is subject to its own limits.
+#[test]
+fn per_state_line_limits() {
+  let mut codec = Codec::new();
+  codec.set_line_limits(
+    LineMode::Telegram,
+    LineLimits {
+      max_line_length: 16,
+      ..Default::default()
+    }
+  );
+  codec.set_line_limits(
+    LineMode::Params,
+    LineLimits {
+      max_line_length: 64,
+      ..Default::default()
+    }
+  );
+  let line = b"Key ThisValueIsLongerThanSixteen\n\n";
+
+  codec.expect_params();
+  let mut buf = BytesMut::from(&line[..]);
+  match codec.decode(&mut buf).unwrap() {
+    Some(Input::Params(params)) => {
+      assert_eq!(params.get_str("Key"), Some("ThisValueIsLongerThanSixteen"))
+    }
+    _ => panic!("Expected parameters")
+  }
+
+  let mut buf = BytesMut::from(&b"Topic\n"[..]);
+  buf.extend_from_slice(line);
+  assert!(codec.decode(&mut buf).is_err());
+}
+
+/// Decode errors tell where in the stream the offending data was found.
+#[test]
+fn error_context() {
+  let mut codec = Codec::new();
+  let mut buf = BytesMut::from(&b"Good\n\nBad\xff\n\n"[..]);
+  assert!(matches!(
+    codec.decode(&mut buf),
+    Ok(Some(Input::Telegram(_)))
+  ));
+  assert_eq!(codec.offset(), 6);
+
+  let msg = match codec.decode(&mut buf) {
+    Err(e) => e.to_string(),
+    _ => panic!("Expected a decode error")
+  };
+  assert!(msg.contains("offset: 9"), "{}", msg);
+  assert!(msg.contains("data: [ff]"), "{}", msg);
+}
+
+/// Entities with too many parameters, or which are too large overall, are
+/// rejected.
+#[test]
+fn param_count_and_size_limits() {
+  let mut codec = Codec::new();
+  codec.set_line_limits(
+    LineMode::Telegram,
+    LineLimits {
+      max_params: 2,
+      max_size: 32,
+      ..Default::default()
+    }
+  );
+
+  let mut buf = BytesMut::from(&b"Topic\nA 1\nB 2\n\n"[..]);
+  assert!(matches!(
+    codec.decode(&mut buf),
+    Ok(Some(Input::Telegram(_)))
+  ));
+
+  let mut buf = BytesMut::from(&b"Topic\nA 1\nB 2\nC 3\n\n"[..]);
+  assert!(matches!(
+    codec.decode(&mut buf),
+    Err(Error::TooLarge { limit: 2, .. })
+  ));
+
+  let mut codec = Codec::new();
+  codec.set_line_limits(
+    LineMode::Telegram,
+    LineLimits {
+      max_size: 16,
+      ..Default::default()
+    }
+  );
+  let mut buf = BytesMut::from(&b"Topic\nKey 0123456789\n\n"[..]);
+  assert!(matches!(
+    codec.decode(&mut buf),
+    Err(Error::TooLarge { limit: 16, .. })
+  ));
+}
+
+/// Values which aren't valid UTF-8 can be accepted, optionally keeping their
+/// original bytes.
+#[test]
+fn invalid_utf8_values() {
+  let input = &b"Topic\nKey a\xffb\n\n"[..];
+
+  let mut codec = Codec::new();
+  assert!(codec.decode(&mut BytesMut::from(input)).is_err());
+
+  for mode in [InvalidUtf8::Replace, InvalidUtf8::Preserve] {
+    let mut codec = Codec::new();
+    codec.set_invalid_utf8(mode);
+    match codec.decode(&mut BytesMut::from(input)).unwrap() {
+      Some(Input::Telegram(tg)) => {
+        assert_eq!(tg.get_str("Key"), Some("a\u{fffd}b"))
+      }
+      _ => panic!("Expected a telegram")
+    }
+    let raw = codec.raw_values().get("Key").map(|v| &v[..]);
+    if mode == InvalidUtf8::Preserve {
+      assert_eq!(raw, Some(&b"a\xffb"[..]));
+    } else {
+      assert_eq!(raw, None);
+    }
+  }
+
+  // Keys must always be valid UTF-8.
+  let mut codec = Codec::new();
+  codec.set_invalid_utf8(InvalidUtf8::Replace);
+  let mut buf = BytesMut::from(&b"Topic\nK\xffy a\n\n"[..]);
+  assert!(codec.decode(&mut buf).is_err());
+}
+
+/// Telegrams with a registered push topic are told apart from replies.
+#[test]
+fn push_topics() {
+  let mut codec = Codec::new();
+  codec.add_push_topic("Event");
+  let mut buf = BytesMut::from(&b"Event\nId 1\n\nOk\n\n"[..]);
+  match codec.decode(&mut buf).unwrap() {
+    Some(Input::Push(tg)) => assert_eq!(tg.get_str("Id"), Some("1")),
+    _ => panic!("Expected a push telegram")
+  }
+  assert!(matches!(
+    codec.decode(&mut buf),
+    Ok(Some(Input::Telegram(_)))
+  ));
+
+  assert!(codec.remove_push_topic("Event"));
+  let mut buf = BytesMut::from(&b"Event\n\n"[..]);
+  assert!(matches!(
+    codec.decode(&mut buf),
+    Ok(Some(Input::Telegram(_)))
+  ));
+}
+
+/// Parameter lines without a separator are skipped, unless the codec is
+/// strict.
+#[test]
+fn strict_param_lines() {
+  let input = &b"Topic\nA 1\nNoSeparator\nB 2\n\n"[..];
+
+  let mut codec = Codec::new();
+  match codec.decode(&mut BytesMut::from(input)).unwrap() {
+    Some(Input::Telegram(tg)) => {
+      assert_eq!(tg.get_str("A"), Some("1"));
+      assert_eq!(tg.get_str("B"), Some("2"));
+      assert_eq!(tg.get_str("NoSeparator"), None);
+    }
+    _ => panic!("Expected a telegram")
+  }
+
+  let mut codec = Codec::new();
+  codec.set_strict(true);
+  let msg = match codec.decode(&mut BytesMut::from(input)) {
+    Err(Error::BadFormat { reason, .. }) => reason,
+    _ => panic!("Expected Error::BadFormat")
+  };
+  assert!(msg.contains("offset: 10"), "{}", msg);
+}
+
+/// Repeated keys are handled according to the duplicate parameter policy.
+#[test]
+fn duplicate_params() {
+  let input = &b"Topic\nKey 1\nKey 2\nOther 3\n\n"[..];
+  for (policy, value) in [
+    (DuplicateParams::LastWins, "2"),
+    (DuplicateParams::FirstWins, "1"),
+    (DuplicateParams::Collect, "2")
+  ] {
+    let mut codec = Codec::new();
+    codec.set_duplicate_params(policy);
+    match codec.decode(&mut BytesMut::from(input)).unwrap() {
+      Some(Input::Telegram(tg)) => {
+        assert_eq!(tg.get_str("Key"), Some(value));
+        assert_eq!(tg.get_str("Other"), Some("3"));
+      }
+      _ => panic!("Expected a telegram")
+    }
+    if policy == DuplicateParams::Collect {
+      assert_eq!(codec.duplicates().len(), 1);
+      assert_eq!(codec.duplicates()["Key"], ["1", "2"]);
+    } else {
+      assert!(codec.duplicates().is_empty());
+    }
+  }
+
+  let mut codec = Codec::new();
+  codec.set_duplicate_params(DuplicateParams::Error);
+  assert!(matches!(
+    codec.decode(&mut BytesMut::from(input)),
+    Err(Error::BadFormat { .. })
+  ));
+
+  // The policy applies to parameter blocks as well.
+  codec.expect_params();
+  let mut buf = BytesMut::from(&b"Key 1\nKey 2\n\n"[..]);
+  assert!(matches!(
+    codec.decode(&mut buf),
+    Err(Error::BadFormat { .. })
+  ));
+}
+
+/// After a malformed entity the decoder can skip ahead to the next one.
+#[test]
+fn resync() {
+  let input = &b"Bad\xff\nKey value\n\nGood\n\n"[..];
+
+  let mut codec = Codec::new();
+  let mut buf = BytesMut::from(input);
+  assert!(codec.decode(&mut buf).is_err());
+  codec.resync();
+  match codec.decode(&mut buf).unwrap() {
+    Some(Input::Telegram(tg)) => assert_eq!(tg.get_topic(), Some("Good")),
+    _ => panic!("Expected a telegram")
+  }
+
+  // The rest of the malformed entity may arrive later.
+  let mut codec = Codec::new();
+  codec.set_auto_resync(true);
+  let mut buf = BytesMut::from(&input[..10]);
+  assert!(codec.decode(&mut buf).is_err());
+  assert!(codec.decode(&mut buf).unwrap().is_none());
+  buf.extend_from_slice(&input[10..]);
+  match codec.decode(&mut buf).unwrap() {
+    Some(Input::Telegram(tg)) => assert_eq!(tg.get_topic(), Some("Good")),
+    _ => panic!("Expected a telegram")
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,38 @@
+#![cfg(feature = "config")]
+
+use std::time::Duration;
+
+use tokio_ddmw::config::IntegrationConfig;
+use tokio_ddmw::msg::Channel;
+
+/// Environment variables override the configuration file.  The environment
+/// is process wide, so this is the only test in this binary.
+#[test]
+fn env_overrides() {
+  let mut cfg = IntegrationConfig::parse(
+    "msgif = \"127.0.0.1:5000\"\nchannel = 3\n\n[timeouts]\nconnect = 10\n"
+  )
+  .unwrap();
+  assert_eq!(cfg.channel, Some(Channel::Id(3)));
+
+  std::env::set_var("DDMW_CHANNEL", "reports");
+  std::env::set_var("DDMW_AUTH_NAME", "reporter");
+  std::env::set_var("DDMW_MGMTIF", "127.0.0.1:5001");
+  cfg.apply_env().unwrap();
+
+  assert!(cfg.msgif.is_some());
+  assert_eq!(cfg.channel, Some(Channel::Name("reports".to_string())));
+  let auth = cfg.auth.as_ref().unwrap();
+  assert_eq!(auth.name.as_deref(), Some("reporter"));
+  assert_eq!(cfg.timeouts.connect, Some(Duration::from_secs(10)));
+  #[cfg(feature = "tokio-net")]
+  {
+    assert!(cfg.client_builder().is_ok());
+    assert!(cfg.mgmt_client_builder().is_ok());
+  }
+
+  std::env::set_var("DDMW_CONNECT_TIMEOUT", "soon");
+  assert!(cfg.apply_env().is_err());
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
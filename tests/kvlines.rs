@@ -0,0 +1,44 @@
+use blather::KVLines;
+
+use tokio_ddmw::kvlines::KVLinesExt;
+
+fn sample() -> KVLines {
+  let mut kvlines = KVLines::new();
+  kvlines.append("Id", "1");
+  kvlines.append("Name", "first entry");
+  kvlines.append("Id", "2");
+  kvlines
+}
+
+#[test]
+fn pairs_and_values() {
+  let kvlines = sample();
+  assert_eq!(
+    kvlines.to_pairs(),
+    [
+      ("Id".to_string(), "1".to_string()),
+      ("Name".to_string(), "first entry".to_string()),
+      ("Id".to_string(), "2".to_string())
+    ]
+  );
+  assert_eq!(kvlines.values_of("Id").collect::<Vec<_>>(), ["1", "2"]);
+  assert_eq!(kvlines.get_first::<u32>("Id").unwrap(), Some(1));
+  assert_eq!(kvlines.get_first::<u32>("Missing").unwrap(), None);
+  assert_eq!(kvlines.get_all::<u32>("Id").unwrap(), [1, 2]);
+  assert!(kvlines.get_all::<u32>("Name").is_err());
+}
+
+#[cfg(feature = "indexmap")]
+#[test]
+fn maps() {
+  let kvlines = sample();
+  let map = kvlines.to_indexmap();
+  assert_eq!(map.keys().collect::<Vec<_>>(), ["Id", "Name"]);
+  assert_eq!(map["Id"], "2");
+
+  let multi = kvlines.to_multimap();
+  assert_eq!(multi["Id"], ["1", "2"]);
+  assert_eq!(multi["Name"], ["first entry"]);
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,137 @@
+#![cfg(feature = "testing")]
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use blather::Params;
+
+use tokio_ddmw::msg::{self, Channel, InputType, MsgInfo, Transport};
+use tokio_ddmw::testing::{pair, Reply};
+use tokio_ddmw::Error;
+
+fn tmp_file(name: &str, data: &[u8]) -> PathBuf {
+  let mut fname = std::env::temp_dir();
+  fname.push(format!("tokio-ddmw-{}-{}", std::process::id(), name));
+  std::fs::write(&fname, data).unwrap();
+  fname
+}
+
+fn accept(xferid: &str) -> Reply {
+  let mut params = Params::new();
+  params.add_str("XferId", xferid).unwrap();
+  Reply::Ok(params)
+}
+
+/// Send the file `fname` as a payload, and call `change` on it once the
+/// server has received the announcement, before the payload is sent.
+///
+/// Returns the result of the send and the payload the server received, if
+/// the send succeeded and nothing followed the announced payload.
+async fn send_changed(
+  fname: &Path,
+  payload: InputType,
+  change: impl FnOnce(&Path)
+) -> Result<Vec<u8>, Error> {
+  let (mut conn, mut server) = pair();
+  let xfer = Transport { ch: Channel::Id(1) };
+  let mi = MsgInfo::builder().payload(payload).build().unwrap();
+
+  // The connection is closed once the send completes, so the server can
+  // tell whether anything followed the payload.
+  let client = async move {
+    let res = msg::send(&mut conn, &xfer, &mi).await;
+    drop(conn);
+    res
+  };
+  let server = async {
+    let tg = server.expect("Msg").await.unwrap();
+    let len = tg.get_int::<usize>("Len").unwrap();
+    change(fname);
+    server.reply(accept("x-1")).await.unwrap();
+    let res = server.recv_buf(len).await;
+    if res.is_ok() {
+      server.reply(Reply::ok()).await.unwrap();
+    }
+    (res, server)
+  };
+  let (res, (received, mut server)) = tokio::join!(client, server);
+  res?;
+  assert!(server.recv_buf(1).await.is_err());
+  Ok(received.unwrap().to_vec())
+}
+
+fn append(fname: &Path) {
+  let mut f = OpenOptions::new().append(true).open(fname).unwrap();
+  f.write_all(b" world").unwrap();
+}
+
+fn truncate(fname: &Path) {
+  let f = OpenOptions::new().write(true).open(fname).unwrap();
+  f.set_len(2).unwrap();
+}
+
+#[tokio::test]
+async fn grown_file_sends_announced_size() {
+  for (name, mmap) in [("grown", false), ("grown-mmap", true)] {
+    let fname = tmp_file(name, b"hello");
+    let payload = if mmap {
+      InputType::Mmap(fname.clone())
+    } else {
+      InputType::File(fname.clone())
+    };
+    let res = send_changed(&fname, payload, append).await;
+    std::fs::remove_file(&fname).unwrap();
+    assert_eq!(res.unwrap(), b"hello");
+  }
+}
+
+#[tokio::test]
+async fn truncated_file_fails() {
+  for (name, mmap) in [("truncated", false), ("truncated-mmap", true)] {
+    let fname = tmp_file(name, b"hello");
+    let payload = if mmap {
+      InputType::Mmap(fname.clone())
+    } else {
+      InputType::File(fname.clone())
+    };
+    let res = send_changed(&fname, payload, truncate).await;
+    std::fs::remove_file(&fname).unwrap();
+    match res {
+      Err(Error::InvalidSize { .. }) => {}
+      res => panic!("Expected Error::InvalidSize, got {:?}", res)
+    }
+  }
+}
+
+/// A message the server reports as a duplicate is sent without its payload,
+/// and the report says so.
+#[tokio::test]
+async fn duplicate_reported() {
+  let (mut conn, mut server) = pair();
+  let xfer = Transport { ch: Channel::Id(1) };
+  let mi = MsgInfo::builder()
+    .dedup_key("k-1")
+    .payload_buf(b"hello".to_vec())
+    .build()
+    .unwrap();
+
+  let server = async {
+    let tg = server.expect("Msg").await.unwrap();
+    assert_eq!(tg.get_str("DedupKey"), Some("k-1"));
+    let mut params = Params::new();
+    params.add_str("XferId", "x-1").unwrap();
+    params.add_str("Dup", "true").unwrap();
+    server.reply(Reply::Ok(params)).await.unwrap();
+  };
+  let (report, _) =
+    tokio::join!(msg::send_dedup(&mut conn, &xfer, &mi), server);
+  let report = report.unwrap();
+
+  assert_eq!(report.xferid, "x-1");
+  assert!(report.duplicate);
+  assert_eq!(report.bytes_payload, 0);
+  assert!(report.to_string().ends_with(" (duplicate)"));
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
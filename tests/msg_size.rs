@@ -0,0 +1,45 @@
+#![cfg(feature = "msg")]
+
+use std::fs::File;
+use std::path::PathBuf;
+
+use tokio_ddmw::msg::{MsgInfo, MAX_META_SIZE};
+use tokio_ddmw::Error;
+
+/// Create a sparse file of the requested size in the temporary directory.
+fn sparse_file(name: &str, size: u64) -> PathBuf {
+  let mut fname = std::env::temp_dir();
+  fname.push(format!("tokio-ddmw-{}-{}", std::process::id(), name));
+  let f = File::create(&fname).unwrap();
+  f.set_len(size).unwrap();
+  fname
+}
+
+#[test]
+fn meta_at_limit() {
+  let fname = sparse_file("meta_at_limit", MAX_META_SIZE);
+  let res = MsgInfo::builder().meta_file(&fname).build();
+  std::fs::remove_file(&fname).unwrap();
+  assert!(res.is_ok());
+}
+
+#[test]
+fn meta_above_limit() {
+  let fname = sparse_file("meta_above_limit", MAX_META_SIZE + 1);
+  let res = MsgInfo::builder().meta_file(&fname).build();
+  std::fs::remove_file(&fname).unwrap();
+  match res {
+    Err(Error::TooLarge { .. }) => {}
+    _ => panic!("Expected Error::TooLarge")
+  }
+}
+
+#[test]
+fn payload_above_4g() {
+  let fname = sparse_file("payload_above_4g", (1 << 32) + 1);
+  let res = MsgInfo::builder().payload_file(&fname).build();
+  std::fs::remove_file(&fname).unwrap();
+  assert!(res.is_ok());
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
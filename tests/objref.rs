@@ -0,0 +1,16 @@
+use tokio_ddmw::{ObjRef, OptObjRef};
+
+#[test]
+fn display_round_trips() {
+  for r in [
+    OptObjRef::Current,
+    OptObjRef::Id(-4),
+    OptObjRef::Name("admin".to_string())
+  ] {
+    assert_eq!(r.to_string().parse::<OptObjRef>().unwrap(), r);
+  }
+  assert!("".parse::<OptObjRef>().is_err());
+  assert_eq!("7".parse::<ObjRef>().unwrap(), ObjRef::Id(7));
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
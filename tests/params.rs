@@ -0,0 +1,108 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use blather::Params;
+
+use tokio_ddmw::params::{
+  parse_duration, parse_size, parse_timestamp, ParamsExt
+};
+use tokio_ddmw::Error;
+
+#[test]
+fn durations() {
+  let ok = [
+    ("10", Duration::from_secs(10)),
+    ("1.5", Duration::from_millis(1500)),
+    ("250ms", Duration::from_millis(250)),
+    ("1h30m", Duration::from_secs(5400)),
+    ("2d 1s", Duration::from_secs(172_801)),
+    ("10us", Duration::from_micros(10)),
+    ("7ns", Duration::from_nanos(7))
+  ];
+  for (s, d) in ok {
+    assert_eq!(parse_duration(s).unwrap(), d, "{:?}", s);
+  }
+  for s in ["", "-1", "10x", "ms", "1e400", "99999999999999999999d"] {
+    assert!(parse_duration(s).is_err(), "{:?} parsed", s);
+  }
+}
+
+#[test]
+fn sizes() {
+  let ok = [
+    ("512", 512),
+    ("512B", 512),
+    ("10kB", 10_000),
+    ("10k", 10_000),
+    ("5MiB", 5 << 20),
+    ("1.5 KiB", 1536),
+    ("2t", 2_000_000_000_000)
+  ];
+  for (s, n) in ok {
+    assert_eq!(parse_size(s).unwrap(), n, "{:?}", s);
+  }
+  for s in ["", "kB", "10x", "-1", "99999999999TiB", "1e30"] {
+    assert!(parse_size(s).is_err(), "{:?} parsed", s);
+  }
+}
+
+#[test]
+fn timestamps() {
+  let ok: [(&str, i64); 7] = [
+    ("0", 0),
+    ("1700000000", 1_700_000_000),
+    ("1970-01-01", 0),
+    ("2024-01-01T12:00:00Z", 1_704_110_400),
+    ("2024-01-01t14:00:00+02:00", 1_704_110_400),
+    ("2024-01-01 11:30:00-00:30", 1_704_110_400),
+    ("1969-12-31T23:59:59Z", -1)
+  ];
+  for (s, secs) in ok {
+    let ts = parse_timestamp(s).unwrap();
+    let expected = if secs >= 0 {
+      UNIX_EPOCH + Duration::from_secs(secs as u64)
+    } else {
+      UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+    };
+    assert_eq!(ts, expected, "{:?}", s);
+  }
+
+  let ts = parse_timestamp("2024-01-01T12:00:00.25Z").unwrap();
+  assert_eq!(
+    ts.duration_since(UNIX_EPOCH).unwrap(),
+    Duration::new(1_704_110_400, 250_000_000)
+  );
+
+  for s in [
+    "",
+    "2024-13-01",
+    "2024-01-32",
+    "2024-01-01T24:00:00Z",
+    "2024-01-01T12:00:00",
+    "2024-01-01T12:00:00+24:00",
+    "99999999999999-01-01",
+    "2024-01-01T00:00:00+99999999999999999:00",
+    "2024-01-01T00:00:00+00:99999999999999999"
+  ] {
+    assert!(parse_timestamp(s).is_err(), "{:?} parsed", s);
+  }
+}
+
+/// Errors identify the offending key.
+#[test]
+fn error_fields() {
+  let mut params = Params::new();
+  params.add_str("Timeout", "soon").unwrap();
+
+  match params.get_duration("Missing") {
+    Err(Error::MissingData { key }) => assert_eq!(key, "Missing"),
+    res => panic!("Expected Error::MissingData, got {:?}", res)
+  }
+  match params.get_duration("Timeout") {
+    Err(Error::BadFormat { what, .. }) => {
+      assert_eq!(what, "duration in 'Timeout'")
+    }
+    res => panic!("Expected Error::BadFormat, got {:?}", res)
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,105 @@
+#![cfg(feature = "testing")]
+
+use std::time::Duration;
+
+use futures::channel::mpsc;
+use futures::StreamExt;
+
+use blather::Telegram;
+
+use tokio_ddmw::clntif::Codec;
+use tokio_ddmw::meta::TagFilter;
+use tokio_ddmw::recv::{consume, deliver_to_dir, NamingPolicy, Pacing};
+use tokio_ddmw::testing::{pair, pair_with};
+use tokio_ddmw::Error;
+
+fn announce(xferid: &str, len: u64) -> Telegram {
+  let mut tg = Telegram::new_topic("Msg").unwrap();
+  tg.add_param("_Ch", 1).unwrap();
+  tg.add_str("XferId", xferid).unwrap();
+  tg.add_param("Len", len).unwrap();
+  tg
+}
+
+#[tokio::test]
+async fn slow_handler_runs_while_next_message_is_received() {
+  let (mut conn, mut server) = pair();
+  let (tx, mut finished) = mpsc::unbounded();
+
+  let server = async move {
+    server.send(&announce("1", 4)).await?;
+    server.send_raw(b"abcd").await?;
+
+    // Hold back the second payload until the first handler has finished,
+    // which it only does if it's run while the payload is being waited for.
+    server.send(&announce("2", 4)).await?;
+    finished.next().await;
+    server.send_raw(b"efgh").await?;
+
+    for xferid in ["1", "2"] {
+      let tg = server.expect("Ok").await?;
+      assert_eq!(tg.get_str("XferId"), Some(xferid));
+    }
+    server.close().await
+  };
+
+  let filter = TagFilter::default();
+  let pacing = Pacing {
+    max_concurrent: Some(2),
+    ..Default::default()
+  };
+  let client = consume(&mut conn, &filter, &pacing, |msg| {
+    let tx = tx.clone();
+    async move {
+      if msg.xferid == "1" {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        tx.unbounded_send(()).unwrap();
+      }
+      Ok::<_, Error>(())
+    }
+  });
+
+  let res = tokio::time::timeout(Duration::from_secs(5), async {
+    tokio::join!(client, server)
+  })
+  .await
+  .expect("the first handler stalled while the next message was received");
+  res.0.unwrap();
+  res.1.unwrap();
+}
+
+/// Payloads are received into files through the `clntif` codec, and renamed
+/// into place once complete.
+#[tokio::test]
+async fn deliver_files() {
+  let mut dir = std::env::temp_dir();
+  dir.push(format!("tokio-ddmw-{}-deliver_files", std::process::id()));
+  let _ = std::fs::remove_dir_all(&dir);
+  std::fs::create_dir_all(&dir).unwrap();
+
+  // A small pipe makes the payload arrive in several pieces.
+  let (mut conn, mut server) = pair_with(Codec::new(), 64);
+  let payload = vec![b'x'; 1000];
+  let server = async {
+    for xferid in ["a-1", "a-2"] {
+      server.send(&announce(xferid, payload.len() as u64)).await?;
+      server.send_raw(&payload).await?;
+      let tg = server.expect("Ok").await?;
+      assert_eq!(tg.get_str("XferId"), Some(xferid));
+    }
+    server.close().await
+  };
+  let naming = NamingPolicy::default();
+  let client = deliver_to_dir(&mut conn, &dir, &naming);
+
+  let (res, server) = tokio::join!(client, server);
+  res.unwrap();
+  server.unwrap();
+  for name in ["a_1", "a_2"] {
+    assert_eq!(std::fs::read(dir.join(name)).unwrap(), payload);
+  }
+  assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
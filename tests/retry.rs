@@ -0,0 +1,91 @@
+use std::cell::Cell;
+use std::time::Duration;
+
+use tokio_ddmw::deadline::Deadline;
+use tokio_ddmw::retry::{self, Policy};
+use tokio_ddmw::Error;
+
+fn retryable() -> Error {
+  Error::TransferTimeout { remaining: 1 }
+}
+
+#[tokio::test]
+async fn retries_until_success() {
+  let policy = Policy::fixed(Duration::from_millis(1)).max_attempts(5);
+  let calls = Cell::new(0);
+  let res = retry::run(&policy, || {
+    calls.set(calls.get() + 1);
+    let n = calls.get();
+    async move {
+      if n < 3 {
+        Err(retryable())
+      } else {
+        Ok(n)
+      }
+    }
+  })
+  .await;
+  assert_eq!(res.unwrap(), 3);
+}
+
+#[tokio::test]
+async fn gives_up_after_max_attempts() {
+  let policy = Policy::fixed(Duration::from_millis(1)).max_attempts(4);
+  let calls = Cell::new(0);
+  let res: Result<(), _> = retry::run(&policy, || {
+    calls.set(calls.get() + 1);
+    async { Err(retryable()) }
+  })
+  .await;
+  assert!(matches!(res, Err(Error::TransferTimeout { .. })));
+  assert_eq!(calls.get(), 4);
+}
+
+#[tokio::test]
+async fn stops_on_errors_not_worth_retrying() {
+  let policy = Policy::fixed(Duration::from_millis(1)).max_attempts(4);
+  let calls = Cell::new(0);
+  let res: Result<(), _> = retry::run(&policy, || {
+    calls.set(calls.get() + 1);
+    async { Err(Error::InvalidCredentials) }
+  })
+  .await;
+  assert!(matches!(res, Err(Error::InvalidCredentials)));
+  assert_eq!(calls.get(), 1);
+}
+
+#[test]
+fn exponential_backoff_is_capped() {
+  let policy =
+    Policy::exponential(Duration::from_millis(10), Duration::from_millis(25))
+      .jitter(0.0);
+  let mut attempts = policy.start();
+  let delays: Vec<_> = (0..4)
+    .map(|_| attempts.next_delay(&retryable()).unwrap())
+    .collect();
+  assert_eq!(delays, [10, 20, 25, 25].map(Duration::from_millis).to_vec());
+  assert_eq!(attempts.attempt(), 5);
+}
+
+#[tokio::test]
+async fn deadline_names_the_step() {
+  let deadline = Deadline::after(Duration::from_millis(20));
+  deadline.run("connect", async { Ok(()) }).await.unwrap();
+
+  let res: Result<(), _> = deadline
+    .run("send", async {
+      tokio::time::sleep(Duration::from_secs(60)).await;
+      Ok(())
+    })
+    .await;
+  match res {
+    Err(Error::DeadlineExceeded { step, budget, .. }) => {
+      assert_eq!(step, "send");
+      assert_eq!(budget, Duration::from_millis(20));
+    }
+    _ => panic!("Expected the deadline to pass")
+  }
+  assert!(deadline.is_expired());
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,38 @@
+use tokio_ddmw::auth::{read_secret_line, read_secret_line_with, Trim};
+use tokio_ddmw::Error;
+
+fn secret_file(name: &str, content: &str) -> std::path::PathBuf {
+  let mut fname = std::env::temp_dir();
+  fname.push(format!("tokio-ddmw-{}-{}", std::process::id(), name));
+  std::fs::write(&fname, content).unwrap();
+  fname
+}
+
+#[tokio::test]
+async fn first_line_is_trimmed() {
+  let fname = secret_file("secret", "  s3cret \r\nsecond line\n");
+  assert_eq!(read_secret_line(&fname).await.unwrap(), "  s3cret");
+  assert_eq!(
+    read_secret_line_with(&fname, Trim::Both).await.unwrap(),
+    "s3cret"
+  );
+  assert_eq!(
+    read_secret_line_with(&fname, Trim::None).await.unwrap(),
+    "  s3cret "
+  );
+  std::fs::remove_file(&fname).unwrap();
+}
+
+#[tokio::test]
+async fn missing_and_empty_files() {
+  let fname = secret_file("empty", " \n");
+  let err = read_secret_line(&fname).await.unwrap_err();
+  assert!(matches!(err, Error::Secret { .. }));
+  assert!(err.to_string().contains("empty"), "{}", err);
+
+  std::fs::remove_file(&fname).unwrap();
+  let err = read_secret_line(&fname).await.unwrap_err();
+  assert!(err.to_string().contains("not found"), "{}", err);
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
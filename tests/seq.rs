@@ -0,0 +1,80 @@
+use blather::Params;
+
+use futures::StreamExt;
+
+use tokio_ddmw::seq::{get_seq, GapDetector, SeqEvent, Sequencer};
+
+#[tokio::test]
+async fn stamped_numbers_are_per_channel() {
+  let mut seq = Sequencer::new();
+  let mut meta = Params::new();
+  assert_eq!(seq.stamp(1, &mut meta).await.unwrap(), 0);
+  assert_eq!(get_seq(&meta).unwrap(), Some(0));
+  assert_eq!(seq.next(1).await.unwrap(), 1);
+  assert_eq!(seq.next(2).await.unwrap(), 0);
+  assert_eq!(seq.peek(1), 2);
+}
+
+#[tokio::test]
+async fn state_survives_reload() {
+  let mut fname = std::env::temp_dir();
+  fname.push(format!("tokio-ddmw-{}-seq", std::process::id()));
+  let _ = std::fs::remove_file(&fname);
+
+  let mut seq = Sequencer::load(&fname).unwrap();
+  seq.next(3).await.unwrap();
+  seq.next(3).await.unwrap();
+  drop(seq);
+
+  let mut seq = Sequencer::load(&fname).unwrap();
+  assert_eq!(seq.next(3).await.unwrap(), 2);
+  std::fs::remove_file(&fname).unwrap();
+}
+
+#[test]
+fn gaps_recoveries_and_duplicates() {
+  let mut gd = GapDetector::new();
+  let mut events = gd.subscribe();
+
+  assert_eq!(gd.observe(1, 0), None);
+  assert_eq!(
+    gd.observe(1, 4),
+    Some(SeqEvent::Gap {
+      ch: 1,
+      missing: 1..4
+    })
+  );
+  assert_eq!(gd.high_water(1), Some(4));
+  assert_eq!(gd.missing(1).to_vec(), vec![1..4]);
+
+  assert_eq!(
+    gd.observe(1, 2),
+    Some(SeqEvent::Recovered { ch: 1, seq: 2 })
+  );
+  assert_eq!(gd.missing(1), &[1..2, 3..4]);
+  assert_eq!(
+    gd.observe(1, 2),
+    Some(SeqEvent::Duplicate { ch: 1, seq: 2 })
+  );
+  assert_eq!(gd.observe(1, u64::MAX), None);
+
+  // Channels are tracked independently
+  assert_eq!(gd.observe(2, 0), None);
+  assert!(gd.missing(2).is_empty());
+
+  drop(gd);
+  let events: Vec<_> = futures::executor::block_on(events.by_ref().collect());
+  assert_eq!(events.len(), 3);
+}
+
+#[test]
+fn meta_without_sequence_number() {
+  let mut gd = GapDetector::new();
+  assert_eq!(gd.observe_meta(1, &Params::new()).unwrap(), None);
+
+  let mut meta = Params::new();
+  meta.add_str("_Seq", "bogus").unwrap();
+  assert!(gd.observe_meta(1, &meta).is_err());
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
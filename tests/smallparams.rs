@@ -0,0 +1,60 @@
+use bytes::BytesMut;
+
+use tokio_util::codec::{Decoder, Encoder};
+
+use tokio_ddmw::clntif::{Codec, Input, SmallParams, SmallTelegram};
+
+#[test]
+fn add_and_get() {
+  let mut params = SmallParams::new();
+  params.add("Id", 42).unwrap();
+  params.add("Name", "foo").unwrap();
+  params.add("Id", 43).unwrap();
+
+  assert_eq!(params.len(), 3);
+  assert_eq!(params.get_str("Name"), Some("foo"));
+  assert_eq!(params.get_param::<u32>("Id").unwrap(), 43);
+  assert!(params.get_param::<u32>("Missing").is_err());
+  assert!(params.add("Bad Key", 1).is_err());
+  assert!(params.add("Key", "two\nlines").is_err());
+  assert_eq!(params.len(), 3);
+
+  let keys: Vec<_> = params.iter().map(|(k, _)| k).collect();
+  assert_eq!(keys, ["Id", "Name", "Id"]);
+}
+
+/// A telegram encoded from a `SmallTelegram` decodes to the same parameters,
+/// and its precalculated size matches the encoded size.
+#[test]
+fn roundtrip() {
+  let mut tg = SmallTelegram::new("Progress");
+  tg.add("Done", 10).unwrap();
+  tg.add("Total", 100).unwrap();
+
+  let mut codec = Codec::new();
+  let mut buf = BytesMut::new();
+  codec.encode(&tg, &mut buf).unwrap();
+  assert_eq!(buf.len(), tg.calc_buf_size());
+
+  match codec.decode(&mut buf).unwrap() {
+    Some(Input::Telegram(decoded)) => {
+      assert_eq!(decoded.get_topic(), Some("Progress"));
+      assert_eq!(decoded.get_int::<u32>("Done").unwrap(), 10);
+      assert_eq!(decoded.get_int::<u32>("Total").unwrap(), 100);
+    }
+    _ => panic!("Expected a telegram")
+  }
+
+  codec.expect_small_params();
+  let mut buf = BytesMut::new();
+  codec.encode(&tg.params, &mut buf).unwrap();
+  match codec.decode(&mut buf).unwrap() {
+    Some(Input::SmallParams(params)) => {
+      assert_eq!(params.get_str("Done"), Some("10"));
+      assert_eq!(params.get_str("Total"), Some("100"));
+    }
+    _ => panic!("Expected parameters")
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
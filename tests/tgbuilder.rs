@@ -0,0 +1,55 @@
+use tokio_ddmw::tg;
+use tokio_ddmw::tgbuilder::TelegramBuilder;
+use tokio_ddmw::Error;
+
+#[test]
+fn build() {
+  let tg = TelegramBuilder::new("Msg")
+    .param("_Ch", "log")
+    .param_opt("Cmd", Some(7))
+    .param_opt::<_, u32>("Missing", None)
+    .param_if(true, "Yes", 1)
+    .param_if(false, "No", 1)
+    .bool("Flag", true)
+    .build()
+    .unwrap();
+  assert_eq!(tg.get_topic(), Some("Msg"));
+  assert_eq!(tg.get_str("_Ch"), Some("log"));
+  assert_eq!(tg.get_int::<u32>("Cmd").unwrap(), 7);
+  assert_eq!(tg.get_str("Missing"), None);
+  assert_eq!(tg.get_str("Yes"), Some("1"));
+  assert_eq!(tg.get_str("No"), None);
+  assert!(tg.get_bool("Flag").unwrap());
+
+  let tg = tg!("Msg", "_Ch" => "log", "Cmd" => 7).unwrap();
+  assert_eq!(tg.get_str("_Ch"), Some("log"));
+  assert_eq!(tg.get_int::<u32>("Cmd").unwrap(), 7);
+}
+
+/// Errors are reported once the telegram is built, and all of them are
+/// included.
+#[test]
+fn errors() {
+  assert!(TelegramBuilder::new("Bad Topic").build().is_err());
+
+  let single = TelegramBuilder::new("Msg")
+    .param("Bad Key", 1)
+    .build()
+    .unwrap_err()
+    .to_string();
+  let err = TelegramBuilder::new("Msg")
+    .param("Bad Key", 1)
+    .param("Good", 2)
+    .param("Also Bad", 3)
+    .build()
+    .unwrap_err();
+  match err {
+    Error::BadFormat { what, reason } => {
+      assert_eq!(what, "telegram");
+      assert_eq!(reason, format!("{}; {}", single, single))
+    }
+    e => panic!("Expected Error::BadFormat, got {:?}", e)
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
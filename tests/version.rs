@@ -0,0 +1,58 @@
+use std::collections::HashSet;
+
+use tokio_ddmw::version::Version;
+
+#[test]
+fn lenient_parsing() {
+  let v: Version = "v2.1".parse().unwrap();
+  assert_eq!(v, Version::new(2, 1, 0));
+
+  let v: Version = " 3.0.4-rc.2+build.7 ".parse().unwrap();
+  assert_eq!((v.major, v.minor, v.patch), (3, 0, 4));
+  assert_eq!(v.pre.as_deref(), Some("rc.2"));
+  assert_eq!(v.to_string(), "3.0.4-rc.2");
+}
+
+#[test]
+fn invalid_versions() {
+  for s in ["", "1.2.3.4", "1.x", "1.2.3-", "-rc.1"] {
+    assert!(s.parse::<Version>().is_err(), "{:?} parsed", s);
+  }
+}
+
+#[test]
+fn precedence() {
+  let order = [
+    "1.0.0-alpha",
+    "1.0.0-alpha.1",
+    "1.0.0-alpha.beta",
+    "1.0.0-beta.2",
+    "1.0.0-beta.11",
+    "1.0.0-rc.1",
+    "1.0.0",
+    "1.2.0"
+  ];
+  let vers: Vec<Version> = order.iter().map(|s| s.parse().unwrap()).collect();
+  for pair in vers.windows(2) {
+    assert!(pair[0] < pair[1], "{} < {}", pair[0], pair[1]);
+  }
+  assert!(vers[7].at_least(1, 2));
+  assert!(!vers[6].at_least(1, 1));
+}
+
+#[test]
+fn equality_matches_ordering() {
+  let a: Version = "1.0.0-rc.01".parse().unwrap();
+  let b: Version = "1.0.0-rc.1".parse().unwrap();
+  assert_eq!(a, b);
+  assert_eq!(a.cmp(&b), std::cmp::Ordering::Equal);
+
+  let set: HashSet<Version> = vec![a, b].into_iter().collect();
+  assert_eq!(set.len(), 1);
+  assert_ne!(
+    "1.0.0-rc.1".parse::<Version>().unwrap(),
+    "1.0.0-rc".parse::<Version>().unwrap()
+  );
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
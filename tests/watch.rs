@@ -0,0 +1,72 @@
+#![cfg(all(feature = "watch", feature = "testing"))]
+
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::pin;
+use std::time::Duration;
+
+use futures::future::{self, Either};
+
+use tokio_ddmw::client::Client;
+use tokio_ddmw::msg::{watch_dir, Channel, WatchRules};
+use tokio_ddmw::testing::{MockServer, Reply};
+
+fn tempdir(name: &str) -> PathBuf {
+  let mut dir = std::env::temp_dir();
+  dir.push(format!("tokio-ddmw-{}-{}", std::process::id(), name));
+  let _ = std::fs::remove_dir_all(&dir);
+  std::fs::create_dir(&dir).unwrap();
+  dir
+}
+
+async fn until<F: Fn() -> bool>(cond: F) {
+  while !cond() {
+    tokio::time::sleep(Duration::from_millis(10)).await;
+  }
+}
+
+/// Run the watcher alongside `check`, failing if the watcher stops first.
+async fn with_watcher<W, C>(watch: W, check: C)
+where
+  W: Future<Output = Result<(), tokio_ddmw::Error>>,
+  C: Future<Output = ()>
+{
+  let (watch, check) = (pin!(watch), pin!(check));
+  let both = future::select(watch, check);
+  match tokio::time::timeout(Duration::from_secs(10), both).await {
+    Ok(Either::Right(_)) => {}
+    Ok(Either::Left((res, _))) => panic!("Watcher stopped: {:?}", res),
+    Err(_) => panic!("Timed out")
+  }
+}
+
+/// A rejected file which can't be moved into the rejected directory is left
+/// in place, and the watcher carries on.  The poll interval is far longer
+/// than the test, so files are only found through notifications.
+#[tokio::test]
+async fn unmovable_rejected_file() {
+  let dir = tempdir("watch");
+  std::fs::write(dir.join("a.txt"), b"first").unwrap();
+
+  let server = MockServer::bind_tcp().await.unwrap();
+  server.reply("Msg", Reply::fail_with("PermissionDenied"));
+  let mut client = Client::connect(server.endpoint(), None).await.unwrap();
+
+  let mut rules = WatchRules::new(Channel::Id(1));
+  rules.poll_interval = Duration::from_secs(3600);
+  rules.settle = Duration::ZERO;
+  rules.rejected = Some(dir.join("missing").join("rejected"));
+
+  let check = async {
+    until(|| server.requests_for("Msg").len() == 1).await;
+    std::fs::write(dir.join("b.txt"), b"second").unwrap();
+    until(|| !dir.join("b.txt").exists()).await;
+  };
+  with_watcher(watch_dir(&dir, &rules, client.conn()), check).await;
+
+  assert!(dir.join("a.txt").exists());
+  assert_eq!(server.requests_for("Msg").len(), 2);
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :